@@ -0,0 +1,805 @@
+use druid::Point;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+use crate::data::{AppData, MyGtfs};
+use crate::geometry::{haversine_distance, to_local_metres};
+use crate::layers::{features_from_file, LayerFeature, LayerGeometry};
+
+/// catchments are rasterised into square cells this fraction of the walk radius wide
+const CELLS_PER_RADIUS: f64 = 4.;
+/// column names recognised as coordinates in census CSVs
+const CSV_LAT_COLUMNS: [&str; 2] = ["lat", "latitude"];
+const CSV_LONG_COLUMNS: [&str; 4] = ["lon", "lng", "long", "longitude"];
+
+/// Population and jobs counts for areas (GeoJSON polygons) or points (GeoJSON points or CSV rows with lat/lon columns). The counts are read from the features' properties when the analysis is run, so the field names can be changed without reloading.
+pub struct Census {
+    /// the file name
+    pub name: String,
+    pub features: Vec<LayerFeature>,
+}
+impl Census {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Census, Box<dyn Error>> {
+        let path = path.as_ref();
+        let is_csv = path
+            .extension()
+            .map(|extension| extension.eq_ignore_ascii_case("csv"))
+            .unwrap_or(false);
+        let features = if is_csv {
+            census_csv_features(path)?
+        } else {
+            features_from_file(path)?
+        };
+        Ok(Census {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "census".to_string()),
+            features,
+        })
+    }
+
+    /// the sum of a field over all features, or None if no feature has a numeric value for it
+    fn total(&self, field: &str) -> Option<f64> {
+        let values = self
+            .features
+            .iter()
+            .filter_map(|feature| property_f64(feature, field))
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.iter().sum())
+        }
+    }
+}
+
+fn census_csv_features(path: &Path) -> Result<Vec<LayerFeature>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let column = |names: &[&str]| {
+        headers
+            .iter()
+            .position(|header| names.iter().any(|name| header.eq_ignore_ascii_case(name)))
+    };
+    let lat_column = column(&CSV_LAT_COLUMNS).ok_or("census CSV has no lat or latitude column")?;
+    let long_column =
+        column(&CSV_LONG_COLUMNS).ok_or("census CSV has no lon or longitude column")?;
+
+    let mut features = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let coord = match (
+            record.get(long_column).and_then(|x| x.parse::<f64>().ok()),
+            record.get(lat_column).and_then(|y| y.parse::<f64>().ok()),
+        ) {
+            (Some(long), Some(lat)) => Point::new(long, lat),
+            // skip rows without a location rather than failing the whole file
+            _ => continue,
+        };
+        features.push(LayerFeature {
+            geometries: vec![LayerGeometry::Point(coord)],
+            properties: headers
+                .iter()
+                .zip(record.iter())
+                .map(|(header, value)| (header.to_string(), value.to_string()))
+                .collect(),
+        });
+    }
+    Ok(features)
+}
+
+/// matches field names case insensitively, since census exports are inconsistent about it
+fn property_f64(feature: &LayerFeature, field: &str) -> Option<f64> {
+    feature
+        .properties
+        .iter()
+        .find(|(key, _value)| key.eq_ignore_ascii_case(field))
+        .and_then(|(_key, value)| value.trim().parse::<f64>().ok())
+}
+
+/// The stops and stop patterns of a dataset, either as it was loaded or as it has been edited
+pub struct NetworkSnapshot {
+    /// (long, lat) of each stop id
+    stops: HashMap<String, Point>,
+    /// the distinct sequences of stop ids of each route's trips
+    route_patterns: HashMap<String, Vec<Vec<String>>>,
+}
+impl NetworkSnapshot {
+    /// the dataset as originally loaded
+    pub fn from_gtfs(gtfs: &MyGtfs) -> NetworkSnapshot {
+        let stops = gtfs
+            .stops
+            .iter()
+            .filter_map(|stop| match (stop.longitude, stop.latitude) {
+                (Some(long), Some(lat)) => Some((stop.id.clone(), Point::new(long, lat))),
+                _ => None,
+            })
+            .collect();
+        let trip_routes = gtfs
+            .trips
+            .iter()
+            .map(|trip| (&trip.id, &trip.route_id))
+            .collect::<HashMap<_, _>>();
+
+        // stop_times are sorted by trip and then stop_sequence
+        let mut snapshot = NetworkSnapshot {
+            stops,
+            route_patterns: HashMap::new(),
+        };
+        let mut pattern: Vec<String> = Vec::new();
+        for (i, stop_time) in gtfs.stop_times.iter().enumerate() {
+            pattern.push(stop_time.stop_id.clone());
+            let last_of_trip = gtfs
+                .stop_times
+                .get(i + 1)
+                .map(|next| next.trip_id != stop_time.trip_id)
+                .unwrap_or(true);
+            if last_of_trip {
+                if let Some(route_id) = trip_routes.get(&stop_time.trip_id) {
+                    snapshot.add_pattern(route_id, pattern.clone());
+                }
+                pattern.clear();
+            }
+        }
+        snapshot
+    }
+
+    /// the dataset with edits applied, ignoring deleted items
+    pub fn from_app_data(data: &AppData) -> NetworkSnapshot {
+        let stops = data
+            .stops
+            .iter()
+            .filter(|stop| stop.live)
+            .map(|stop| (stop.id.clone(), stop.latlong))
+            .collect::<HashMap<_, _>>();
+        let mut snapshot = NetworkSnapshot {
+            stops,
+            route_patterns: HashMap::new(),
+        };
+        for trip in data.trips.iter().filter(|trip| trip.live) {
+            if let Some((start, end)) = data.stop_time_range_from_trip_id.get(&trip.id) {
+                let pattern = (start..end)
+                    .filter_map(|i| data.stop_times.get(i))
+                    .filter(|stop_time| {
+                        stop_time.live && snapshot.stops.contains_key(&stop_time.stop_id)
+                    })
+                    .map(|stop_time| stop_time.stop_id.clone())
+                    .collect::<Vec<_>>();
+                snapshot.add_pattern(&trip.route_id, pattern);
+            }
+        }
+        snapshot
+    }
+
+    fn add_pattern(&mut self, route_id: &str, pattern: Vec<String>) {
+        let patterns = self
+            .route_patterns
+            .entry(route_id.to_string())
+            .or_insert_with(Vec::new);
+        if !pattern.is_empty() && !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
+    }
+
+    /// spacing between consecutive stops of each route, counting each pair of stops once however many trips serve it
+    fn route_spacing(&self) -> HashMap<String, RouteSpacing> {
+        let mut route_spacing = HashMap::new();
+        for (route_id, patterns) in &self.route_patterns {
+            let mut pairs = HashSet::new();
+            let mut route_stops = HashSet::new();
+            for pattern in patterns {
+                route_stops.extend(pattern.iter());
+                for pair in pattern.windows(2) {
+                    if pair[0] != pair[1] {
+                        pairs.insert((&pair[0], &pair[1]));
+                    }
+                }
+            }
+            let mut distances = pairs
+                .iter()
+                .filter_map(|(a, b)| match (self.stops.get(*a), self.stops.get(*b)) {
+                    (Some(a), Some(b)) => Some(haversine_distance(*a, *b)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            if distances.is_empty() {
+                continue;
+            }
+            distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            route_spacing.insert(
+                route_id.clone(),
+                RouteSpacing {
+                    n_stops: route_stops.len(),
+                    mean: distances.iter().sum::<f64>() / distances.len() as f64,
+                    median: distances[distances.len() / 2],
+                    min: distances[0],
+                    max: distances[distances.len() - 1],
+                },
+            );
+        }
+        route_spacing
+    }
+
+    /// only stops served by a trip count towards coverage
+    fn served_stops(&self) -> Vec<Point> {
+        let served = self
+            .route_patterns
+            .values()
+            .flatten()
+            .flatten()
+            .collect::<HashSet<_>>();
+        served
+            .into_iter()
+            .filter_map(|stop_id| self.stops.get(stop_id).cloned())
+            .collect()
+    }
+}
+
+/// The union of the walk radius circles around a set of stops, rasterised into cells in local metres around origin
+struct Catchment {
+    origin: Point,
+    cell_size: f64,
+    cells: HashSet<(i64, i64)>,
+}
+impl Catchment {
+    fn new(stops: &[Point], origin: Point, radius: f64) -> Catchment {
+        let cell_size = radius / CELLS_PER_RADIUS;
+        let mut cells = HashSet::new();
+        let reach = CELLS_PER_RADIUS as i64 + 1;
+        for stop in stops {
+            let centre = to_local_metres(*stop, origin);
+            let (i0, j0) = cell_of(centre, cell_size);
+            for i in i0 - reach..=i0 + reach {
+                for j in j0 - reach..=j0 + reach {
+                    let cell_centre =
+                        Point::new((i as f64 + 0.5) * cell_size, (j as f64 + 0.5) * cell_size);
+                    if cell_centre.distance(centre) <= radius {
+                        cells.insert((i, j));
+                    }
+                }
+            }
+        }
+        Catchment {
+            origin,
+            cell_size,
+            cells,
+        }
+    }
+
+    fn area_km2(&self) -> f64 {
+        self.cells.len() as f64 * self.cell_size * self.cell_size / 1_000_000.
+    }
+
+    fn covers(&self, coord: Point) -> bool {
+        self.cells.contains(&cell_of(
+            to_local_metres(coord, self.origin),
+            self.cell_size,
+        ))
+    }
+
+    /// the proportion of a feature that is within the catchment. Polygons are sampled at the centre of each cell they contain
+    fn covered_fraction(&self, feature: &LayerFeature) -> f64 {
+        let mut samples = 0;
+        let mut covered = 0;
+        for geometry in &feature.geometries {
+            match geometry {
+                LayerGeometry::Point(coord) => {
+                    samples += 1;
+                    if self.covers(*coord) {
+                        covered += 1;
+                    }
+                }
+                LayerGeometry::Polygon(rings) => {
+                    let rings = rings
+                        .iter()
+                        .map(|ring| {
+                            ring.iter()
+                                .map(|coord| to_local_metres(*coord, self.origin))
+                                .collect::<Vec<_>>()
+                        })
+                        .collect::<Vec<_>>();
+                    let exterior = match rings.get(0) {
+                        Some(exterior) if !exterior.is_empty() => exterior,
+                        _ => continue,
+                    };
+                    let (i0, j0) = cell_of(
+                        Point::new(
+                            exterior.iter().map(|p| p.x).fold(f64::INFINITY, f64::min),
+                            exterior.iter().map(|p| p.y).fold(f64::INFINITY, f64::min),
+                        ),
+                        self.cell_size,
+                    );
+                    let (i1, j1) = cell_of(
+                        Point::new(
+                            exterior
+                                .iter()
+                                .map(|p| p.x)
+                                .fold(f64::NEG_INFINITY, f64::max),
+                            exterior
+                                .iter()
+                                .map(|p| p.y)
+                                .fold(f64::NEG_INFINITY, f64::max),
+                        ),
+                        self.cell_size,
+                    );
+                    let samples_before = samples;
+                    for i in i0..=i1 {
+                        for j in j0..=j1 {
+                            let cell_centre = Point::new(
+                                (i as f64 + 0.5) * self.cell_size,
+                                (j as f64 + 0.5) * self.cell_size,
+                            );
+                            if inside_rings(&rings, cell_centre) {
+                                samples += 1;
+                                if self.cells.contains(&(i, j)) {
+                                    covered += 1;
+                                }
+                            }
+                        }
+                    }
+                    // polygons smaller than a cell are treated as a point at their first vertex
+                    if samples == samples_before {
+                        samples += 1;
+                        if self.cells.contains(&cell_of(exterior[0], self.cell_size)) {
+                            covered += 1;
+                        }
+                    }
+                }
+                // counts aren't attached to lines
+                LayerGeometry::LineString(_) => {}
+            }
+        }
+        if samples == 0 {
+            0.
+        } else {
+            covered as f64 / samples as f64
+        }
+    }
+}
+
+fn cell_of(point: Point, cell_size: f64) -> (i64, i64) {
+    (
+        (point.x / cell_size).floor() as i64,
+        (point.y / cell_size).floor() as i64,
+    )
+}
+
+/// even-odd rule, so holes are excluded whichever way their rings are wound
+fn inside_rings(rings: &[Vec<Point>], point: Point) -> bool {
+    let mut inside = false;
+    for ring in rings {
+        for i in 0..ring.len() {
+            let a = ring[i];
+            let b = ring[(i + 1) % ring.len()];
+            if (a.y > point.y) != (b.y > point.y)
+                && point.x < a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y)
+            {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteSpacing {
+    pub n_stops: usize,
+    /// metres
+    pub mean: f64,
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteSpacingComparison {
+    pub route_id: String,
+    pub route_name: String,
+    pub before: Option<RouteSpacing>,
+    pub after: Option<RouteSpacing>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageTotals {
+    pub served_stops: usize,
+    pub catchment_km2: f64,
+    /// None if there is no census or it has no values for the field
+    pub population: Option<f64>,
+    pub jobs: Option<f64>,
+}
+
+/// Stop spacing and walk catchment coverage of the dataset as loaded (before) and as edited (after)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalysisReport {
+    /// walk radius around stops in metres
+    pub radius: f64,
+    pub census_name: Option<String>,
+    pub census_population: Option<f64>,
+    pub census_jobs: Option<f64>,
+    pub before: CoverageTotals,
+    pub after: CoverageTotals,
+    /// sorted by route name
+    pub routes: Vec<RouteSpacingComparison>,
+}
+impl AnalysisReport {
+    pub fn new(
+        data: &AppData,
+        census: Option<&Census>,
+        radius: f64,
+        population_field: &str,
+        jobs_field: &str,
+    ) -> AnalysisReport {
+        myprint!("start analysis");
+        let before = NetworkSnapshot::from_gtfs(&data.gtfs);
+        let after = NetworkSnapshot::from_app_data(data);
+
+        // both catchments need the same origin so their cells line up
+        let after_stops = after.served_stops();
+        let before_stops = before.served_stops();
+        let all_stops = after_stops.iter().chain(before_stops.iter());
+        let n_stops = after_stops.len() + before_stops.len();
+        let origin = if n_stops == 0 {
+            Point::ZERO
+        } else {
+            let sum = all_stops.fold(Point::ZERO, |sum, stop| sum + stop.to_vec2());
+            Point::new(sum.x / n_stops as f64, sum.y / n_stops as f64)
+        };
+
+        let coverage = |stops: &[Point]| {
+            let catchment = Catchment::new(stops, origin, radius);
+            let covered = |field: &str| {
+                let census = census?;
+                census.total(field)?;
+                Some(
+                    census
+                        .features
+                        .iter()
+                        .filter_map(|feature| {
+                            property_f64(feature, field)
+                                .map(|count| count * catchment.covered_fraction(feature))
+                        })
+                        .sum::<f64>(),
+                )
+            };
+            CoverageTotals {
+                served_stops: stops.len(),
+                catchment_km2: catchment.area_km2(),
+                population: covered(population_field),
+                jobs: covered(jobs_field),
+            }
+        };
+
+        let before_spacing = before.route_spacing();
+        let after_spacing = after.route_spacing();
+        let mut routes = data
+            .routes
+            .iter()
+            .filter(|route| {
+                before_spacing.contains_key(&route.id) || after_spacing.contains_key(&route.id)
+            })
+            .map(|route| RouteSpacingComparison {
+                route_id: route.id.clone(),
+                route_name: format!("{} {}", route.short_name, route.long_name)
+                    .trim()
+                    .to_string(),
+                before: before_spacing.get(&route.id).cloned(),
+                after: after_spacing.get(&route.id).cloned(),
+            })
+            .collect::<Vec<_>>();
+        routes.sort_by(|a, b| a.route_name.cmp(&b.route_name));
+
+        let report = AnalysisReport {
+            radius,
+            census_name: census.map(|census| census.name.clone()),
+            census_population: census.and_then(|census| census.total(population_field)),
+            census_jobs: census.and_then(|census| census.total(jobs_field)),
+            before: coverage(&before_stops),
+            after: coverage(&after_stops),
+            routes,
+        };
+        myprint!("finish analysis");
+        report
+    }
+}
+
+fn format_delta(before: f64, after: f64) -> String {
+    format!("{:+.0}", after - before)
+}
+
+impl fmt::Display for AnalysisReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "walk radius {}m", self.radius)?;
+        writeln!(
+            f,
+            "{:<24}{:>12}{:>12}{:>12}",
+            "", "before", "after", "change"
+        )?;
+        writeln!(
+            f,
+            "{:<24}{:>12}{:>12}{:>12}",
+            "served stops",
+            self.before.served_stops,
+            self.after.served_stops,
+            format_delta(
+                self.before.served_stops as f64,
+                self.after.served_stops as f64
+            )
+        )?;
+        writeln!(
+            f,
+            "{:<24}{:>12.2}{:>12.2}{:>+12.2}",
+            "catchment km²",
+            self.before.catchment_km2,
+            self.after.catchment_km2,
+            self.after.catchment_km2 - self.before.catchment_km2
+        )?;
+        for (name, before, after, total) in [
+            (
+                "population covered",
+                self.before.population,
+                self.after.population,
+                self.census_population,
+            ),
+            (
+                "jobs covered",
+                self.before.jobs,
+                self.after.jobs,
+                self.census_jobs,
+            ),
+        ] {
+            if let (Some(before), Some(after), Some(total)) = (before, after, total) {
+                writeln!(
+                    f,
+                    "{:<24}{:>12.0}{:>12.0}{:>12}  of {:.0} ({:.1}% after)",
+                    name,
+                    before,
+                    after,
+                    format_delta(before, after),
+                    total,
+                    if total > 0. { after / total * 100. } else { 0. }
+                )?;
+            }
+        }
+        match &self.census_name {
+            Some(census_name) if self.census_population.is_none() && self.census_jobs.is_none() => {
+                writeln!(
+                    f,
+                    "{} has no values for the population or jobs fields",
+                    census_name
+                )?
+            }
+            Some(census_name) => writeln!(f, "census: {}", census_name)?,
+            None => writeln!(f, "load a census file for population and jobs coverage")?,
+        }
+
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<32}{:>8}{:>14}{:>14}{:>10}",
+            "route stop spacing (m)", "stops", "mean before", "mean after", "median"
+        )?;
+        for route in &self.routes {
+            let mean = |spacing: &Option<RouteSpacing>| match spacing {
+                Some(spacing) => format!("{:.0}", spacing.mean),
+                None => "-".to_string(),
+            };
+            let mut name = route.route_name.clone();
+            if name.chars().count() > 30 {
+                name = name.chars().take(29).collect::<String>() + "…";
+            }
+            writeln!(
+                f,
+                "{:<32}{:>8}{:>14}{:>14}{:>10}",
+                name,
+                route
+                    .after
+                    .as_ref()
+                    .or(route.before.as_ref())
+                    .map(|spacing| spacing.n_stops)
+                    .unwrap_or(0),
+                mean(&route.before),
+                mean(&route.after),
+                route
+                    .after
+                    .as_ref()
+                    .map(|spacing| format!("{:.0}", spacing.median))
+                    .unwrap_or_else(|| "-".to_string()),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(stops: &[(&str, f64, f64)], patterns: &[(&str, &[&str])]) -> NetworkSnapshot {
+        let mut snapshot = NetworkSnapshot {
+            stops: stops
+                .iter()
+                .map(|(id, long, lat)| (id.to_string(), Point::new(*long, *lat)))
+                .collect(),
+            route_patterns: HashMap::new(),
+        };
+        for (route_id, pattern) in patterns {
+            snapshot.add_pattern(route_id, pattern.iter().map(|id| id.to_string()).collect());
+        }
+        snapshot
+    }
+
+    fn point_feature(long: f64, lat: f64, properties: &[(&str, &str)]) -> LayerFeature {
+        LayerFeature {
+            geometries: vec![LayerGeometry::Point(Point::new(long, lat))],
+            properties: properties
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    fn square(centre: Point, half_width: f64) -> Vec<Point> {
+        vec![
+            Point::new(centre.x - half_width, centre.y - half_width),
+            Point::new(centre.x + half_width, centre.y - half_width),
+            Point::new(centre.x + half_width, centre.y + half_width),
+            Point::new(centre.x - half_width, centre.y + half_width),
+        ]
+    }
+
+    #[test]
+    fn route_spacing_counts_each_pair_of_stops_once() {
+        let snapshot = snapshot(
+            &[("a", 0., 0.), ("b", 0.001, 0.), ("c", 0.003, 0.)],
+            &[("route", &["a", "b", "c"]), ("route", &["a", "b"])],
+        );
+        let spacing = snapshot.route_spacing().remove("route").unwrap();
+        let ab = haversine_distance(Point::new(0., 0.), Point::new(0.001, 0.));
+        let bc = haversine_distance(Point::new(0.001, 0.), Point::new(0.003, 0.));
+        assert_eq!(spacing.n_stops, 3);
+        assert!((spacing.mean - (ab + bc) / 2.).abs() < 1e-6);
+        assert_eq!(spacing.median, bc);
+        assert_eq!(spacing.min, ab);
+        assert_eq!(spacing.max, bc);
+    }
+
+    #[test]
+    fn patterns_are_only_added_once() {
+        let snapshot = snapshot(
+            &[("a", 0., 0.), ("b", 0.001, 0.)],
+            &[
+                ("route", &["a", "b"]),
+                ("route", &["a", "b"]),
+                ("route", &[]),
+            ],
+        );
+        assert_eq!(snapshot.route_patterns["route"].len(), 1);
+    }
+
+    #[test]
+    fn routes_without_located_stops_have_no_spacing() {
+        let snapshot = snapshot(&[("a", 0., 0.)], &[("route", &["a", "missing"])]);
+        assert!(snapshot.route_spacing().is_empty());
+    }
+
+    #[test]
+    fn only_served_stops_count_towards_coverage() {
+        let snapshot = snapshot(
+            &[("a", 0., 0.), ("b", 0.001, 0.), ("unserved", 0.002, 0.)],
+            &[("route", &["a", "b"]), ("other", &["b", "a"])],
+        );
+        let mut served = snapshot.served_stops();
+        served.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        assert_eq!(served, vec![Point::new(0., 0.), Point::new(0.001, 0.)]);
+    }
+
+    #[test]
+    fn catchment_is_about_a_circle_around_each_stop() {
+        let catchment = Catchment::new(&[Point::ZERO], Point::ZERO, 100.);
+        // pi * 100m^2
+        assert!((catchment.area_km2() - 0.0314).abs() < 0.005);
+        // about 55m and 220m east
+        assert!(catchment.covers(Point::new(0.0005, 0.)));
+        assert!(!catchment.covers(Point::new(0.002, 0.)));
+
+        // overlapping circles aren't counted twice
+        let overlapping = Catchment::new(&[Point::ZERO, Point::ZERO], Point::ZERO, 100.);
+        assert_eq!(overlapping.area_km2(), catchment.area_km2());
+    }
+
+    #[test]
+    fn covered_fraction_of_points_and_polygons() {
+        let catchment = Catchment::new(&[Point::ZERO], Point::ZERO, 100.);
+        assert_eq!(
+            catchment.covered_fraction(&point_feature(0.0005, 0., &[])),
+            1.
+        );
+        assert_eq!(
+            catchment.covered_fraction(&point_feature(0.01, 0., &[])),
+            0.
+        );
+
+        let polygon = |centre: Point, half_width: f64| LayerFeature {
+            geometries: vec![LayerGeometry::Polygon(vec![square(centre, half_width)])],
+            properties: Vec::new(),
+        };
+        // about 45m across, so inside the catchment
+        assert_eq!(
+            catchment.covered_fraction(&polygon(Point::ZERO, 0.0002)),
+            1.
+        );
+        assert_eq!(
+            catchment.covered_fraction(&polygon(Point::new(0.1, 0.1), 0.001)),
+            0.
+        );
+        // about 2.2km across, so mostly outside the catchment
+        let fraction = catchment.covered_fraction(&polygon(Point::ZERO, 0.01));
+        assert!(fraction > 0. && fraction < 0.1);
+    }
+
+    #[test]
+    fn holes_are_outside_polygons() {
+        let rings = vec![square(Point::ZERO, 10.), square(Point::ZERO, 5.)];
+        assert!(inside_rings(&rings, Point::new(7., 0.)));
+        assert!(!inside_rings(&rings, Point::ZERO));
+        assert!(!inside_rings(&rings, Point::new(20., 0.)));
+    }
+
+    #[test]
+    fn census_fields_are_matched_case_insensitively() {
+        let census = Census {
+            name: "census".to_string(),
+            features: vec![
+                point_feature(0., 0., &[("Population", " 10 ")]),
+                point_feature(0., 0., &[("population", "5.5"), ("jobs", "n/a")]),
+            ],
+        };
+        assert_eq!(census.total("POPULATION"), Some(15.5));
+        assert_eq!(census.total("jobs"), None);
+    }
+
+    #[test]
+    fn census_csv_rows_without_a_location_are_skipped() {
+        let path = std::env::temp_dir().join(format!(
+            "gtfs_manager_census_test_{}.csv",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "Latitude,lon,population\n-23.5,-46.6,100\n,-46.7,200\n-23.6,-46.8,300\n",
+        )
+        .unwrap();
+        let census = Census::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(census.features.len(), 2);
+        assert_eq!(
+            census.features[0].geometries,
+            vec![LayerGeometry::Point(Point::new(-46.6, -23.5))]
+        );
+        assert_eq!(census.total("population"), Some(400.));
+    }
+
+    #[test]
+    fn census_csv_needs_coordinate_columns() {
+        let path = std::env::temp_dir().join(format!(
+            "gtfs_manager_census_no_coords_test_{}.csv",
+            std::process::id()
+        ));
+        std::fs::write(&path, "x,y,population\n1,2,3\n").unwrap();
+        let result = Census::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn deltas_are_signed() {
+        assert_eq!(format_delta(10., 15.), "+5");
+        assert_eq!(format_delta(10., 7.), "-3");
+        assert_eq!(format_delta(10., 10.), "+0");
+    }
+}
@@ -1,10 +1,29 @@
-use std::collections::HashMap;
-
+use druid::commands::{CLOSE_WINDOW, QUIT_APP, SHOW_SAVE_PANEL};
 use druid::im::Vector;
 use druid::keyboard_types::Key;
-use druid::{AppDelegate, Env, Event, Point, Selector};
+use druid::{AppDelegate, Application, DelegateCtx, Env, Event, FileInfo, Point, Selector};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use uuid::Uuid;
 
+use crate::analysis::{AnalysisReport, Census};
 use crate::data::*;
+use crate::export::{geojson_export, gpx_export, kml_export};
+use crate::import::{Import, ImportKind};
+use crate::layers::GeoJsonLayer;
+use crate::library::{update_library, write_library, LibraryUpdate};
+use crate::loading::{
+    load_feed_in_background, load_osm_in_background, LoadedFeed, Loading, LoadingFinished,
+    LOADING_FINISHED, LOADING_PROGRESS, OSM_LOADED,
+};
+use crate::menu::save_project_dialog;
+use crate::osm::NetworkMode;
+use crate::project::{Project, PROJECT_EXTENSION};
+use crate::recent::remember_recent_file;
+use crate::source::is_stdin;
 // use crate::my_trip_derived_lenses::route_id;
 
 // command selectors
@@ -18,11 +37,84 @@ pub const EDIT_DELETE: Selector<usize> = Selector::new("edit.delete");
 pub const EDIT_STOP_TIME_CHOOSE: Selector = Selector::new("edit.stop_time.choose");
 pub const EDIT_STOP_TIME_UPDATE: Selector<String> = Selector::new("edit.stop_time.update");
 pub const NEW_STOP: Selector<Point> = Selector::new("new.stop");
+/// Selector<(stop id, new (long, lat) coord)>
+pub const MOVE_STOP: Selector<(String, Point)> = Selector::new("move.stop");
+/// Selector<(stop id, new (long, lat) coord)> for stops moved together, which are undone together
+pub const MOVE_STOPS: Selector<Vec<(String, Point)>> = Selector::new("move.stops");
+/// Selector<(shape id, new (long, lat) coords of the shape's points)>
+pub const UPDATE_SHAPE: Selector<(String, Vec<Point>)> = Selector::new("update.shape");
+/// Selector<trip id> creates a new shape for the trip from it's stop coords
+pub const CREATE_SHAPE: Selector<String> = Selector::new("create.shape");
+/// Selector<(trip id, shape id)> gives the trip a shape which already exists
+pub const ASSIGN_SHAPE: Selector<(String, String)> = Selector::new("assign.shape");
+/// Selector<trip id> routes between the trip's stops along the OSM network to make a shape preview
+pub const GENERATE_SHAPE: Selector<String> = Selector::new("generate.shape");
+pub const ACCEPT_SHAPE_PREVIEW: Selector = Selector::new("accept.shape_preview");
+pub const DISCARD_SHAPE_PREVIEW: Selector = Selector::new("discard.shape_preview");
+pub const LOAD_OSM: Selector<FileInfo> = Selector::new("load.osm");
+pub const LOAD_GEOJSON_LAYER: Selector<FileInfo> = Selector::new("load.geojson_layer");
+/// Selector<layer id>
+pub const REMOVE_LAYER: Selector<String> = Selector::new("remove.layer");
+/// a GeoJSON or CSV file of population and jobs counts for the coverage analysis
+pub const LOAD_CENSUS: Selector<FileInfo> = Selector::new("load.census");
+/// compares stop spacing and coverage of the edited dataset with the dataset as loaded
+pub const RUN_ANALYSIS: Selector = Selector::new("run.analysis");
+/// fills shape_dist_traveled for all shapes and stop_times
+pub const COMPUTE_SHAPE_DIST: Selector = Selector::new("compute.shape_dist");
+/// reverts the most recent action
+pub const UNDO_ACTION: Selector = Selector::new("undo.action");
+/// sets AppData.live to false for the stops and trips selected on the map
+pub const DELETE_SELECTION: Selector = Selector::new("delete.selection");
+/// sets AppData.selection_field to AppData.selection_value for the stops and trips selected on the map
+pub const SET_SELECTION_FIELD: Selector = Selector::new("set.selection_field");
+pub const EXPORT_SELECTION: Selector<FileInfo> = Selector::new("export.selection");
+/// handled by MapWidget, since it holds the paths and view to draw
+pub const EXPORT_MAP_PNG: Selector<FileInfo> = Selector::new("export.map.png");
+/// handled by MapWidget, since it holds the paths and view to draw
+pub const EXPORT_MAP_SVG: Selector<FileInfo> = Selector::new("export.map.svg");
+/// writes AppData.geojson_export_layer as a GeoJSON FeatureCollection
+pub const EXPORT_GEOJSON: Selector<FileInfo> = Selector::new("export.geojson");
+/// writes the routes and stops, or only those selected, as KML
+pub const EXPORT_KML: Selector<FileInfo> = Selector::new("export.kml");
+/// writes the routes and stops, or only those selected, as GPX tracks and waypoints
+pub const EXPORT_GPX: Selector<FileInfo> = Selector::new("export.gpx");
+/// reads a CSV or GeoJSON file of stops into AppData.import so its fields can be mapped
+pub const IMPORT_STOPS: Selector<FileInfo> = Selector::new("import.stops");
+/// reads a GeoJSON file of LineStrings into AppData.import so its fields can be mapped
+pub const IMPORT_SHAPES: Selector<FileInfo> = Selector::new("import.shapes");
+/// adds AppData.import's features to the feed as one action
+pub const APPLY_IMPORT: Selector = Selector::new("apply.import");
+/// handled by MapWidget. Animates the map to fit the selection, or the whole feed if true
+pub const FIT_MAP: Selector<bool> = Selector::new("fit.map");
+/// saves the edits and view to a project file, separately from the feed
+pub const SAVE_PROJECT: Selector<FileInfo> = Selector::new("save.project");
+/// reloads the project's feed in the background then applies its edits
+pub const OPEN_PROJECT: Selector<FileInfo> = Selector::new("open.project");
+/// a GTFS zip or directory
+pub const OPEN_FEED: Selector<FileInfo> = Selector::new("open.feed");
+/// a GTFS zip or directory to copy into the library and open
+pub const IMPORT_FEED: Selector<FileInfo> = Selector::new("import.feed");
+/// Selector<path of a feed or project>
+pub const OPEN_RECENT: Selector<String> = Selector::new("open.recent");
+/// saves to the current project, or asks for a path if there isn't one
+pub const SAVE: Selector = Selector::new("save");
+/// replaces the feed with nothing, showing the start screen
+pub const CLOSE_FEED: Selector = Selector::new("close.feed");
+/// continues with AppData.pending_action without saving
+pub const DISCARD_CHANGES: Selector = Selector::new("discard.changes");
+/// Selector<library feed id>
+pub const OPEN_LIBRARY_FEED: Selector<String> = Selector::new("open.library_feed");
+/// Selector<library feed id>, deletes the library's copy of the feed but keeps its record
+pub const REMOVE_LIBRARY_DATA: Selector<String> = Selector::new("remove.library_data");
+/// Selector<library feed id>, copies the feed from where it was originally opened again
+pub const REIMPORT_LIBRARY_FEED: Selector<String> = Selector::new("reimport.library_feed");
 
 /// Selector(trip_id, stop_sequence, before) so before: true, after: false
 pub const ADD_STOP_TIME_CHOOSE: Selector<bool> = Selector::new("add.stop_time.choose");
 /// Selector<route id>
 pub const ADD_TRIP: Selector<String> = Selector::new("add.trip");
+/// sent when an agency's id is edited, to rebuild AppData.agency_index_from_id
+pub const AGENCY_ID_CHANGED: Selector = Selector::new("agency.id.changed");
 /// Selector<agency id>
 pub const ADD_ROUTE: Selector<Option<String>> = Selector::new("add.route");
 // pub const ADD_STOP_TIME_UPDATE: Selector<String> = Selector::new("add.stop_time.update");
@@ -46,19 +138,24 @@ impl AppDelegate<AppData> for Delegate {
         _ctx: &mut druid::DelegateCtx,
         _window_id: druid::WindowId,
         event: Event,
-        _data: &mut AppData,
+        data: &mut AppData,
         _env: &Env,
     ) -> Option<Event> {
         match &event {
+            // the title bar's close button sends this rather than CLOSE_WINDOW, so it needs the same prompt
+            Event::WindowCloseRequested if has_unsaved_changes(data) => {
+                data.pending_action = Some(PendingAction::Quit);
+                return None;
+            }
             Event::KeyDown(key_event) => {
                 // not firing for some reason
-                println!("keydown");
+                myprint!("keydown");
                 match key_event.key {
                     Key::ArrowUp => {
-                        println!("arrowup");
+                        myprint!("arrowup");
                     }
                     Key::ArrowDown => {
-                        println!("arrowdown");
+                        myprint!("arrowdown");
                     }
                     _ => {}
                 }
@@ -69,7 +166,7 @@ impl AppDelegate<AppData> for Delegate {
     }
     fn command(
         &mut self,
-        _ctx: &mut druid::DelegateCtx,
+        ctx: &mut druid::DelegateCtx,
         _target: druid::Target,
         cmd: &druid::Command,
         data: &mut AppData,
@@ -77,10 +174,10 @@ impl AppDelegate<AppData> for Delegate {
     ) -> druid::Handled {
         myprint!("got cmd");
         if let Some(item_delete) = cmd.get(ITEM_DELETE) {
-            dbg!(item_delete);
+            myprint!("handle ITEM_DELETE command: ", format!("{:?}", item_delete));
             druid::Handled::Yes
         } else if let Some(item_update) = cmd.get(ITEM_UPDATE) {
-            dbg!(item_update);
+            myprint!("handle ITEM_UPDATE command: ", format!("{:?}", item_update));
             druid::Handled::Yes
 
             // delete edits
@@ -89,29 +186,418 @@ impl AppDelegate<AppData> for Delegate {
             let (item_type, parent_id) = item;
 
             // data.edits.clear();
-            for agency in data.agencies.iter_mut() {
-                if item_type == "agency" && &agency.id() == parent_id {
-                    // agency.new_child();
-                    data.actions.push_back(Action {
-                        id: data.actions.len(),
-                        edit_type: EditType::Create,
-                        // todo is the item type route? or should it be a trip?
-                        item_type: "route".to_string(),
-                        item_id: agency.id(),
-                        // item_data: Some(Rc::new(agency.clone())),
-                    });
-                }
+            let agency_ids = data
+                .agencies
+                .iter()
+                .filter(|agency| item_type == "agency" && &agency.id() == parent_id)
+                .map(|agency| agency.id())
+                .collect::<Vec<_>>();
+            for agency_id in agency_ids {
+                // agency.new_child();
+                // todo is the item type route? or should it be a trip?
+                data.push_action(EditType::Create, "route", agency_id, ActionChange::None);
             }
             // druid::Handled::No
             druid::Handled::Yes
         } else if let Some(edit_id) = cmd.get(EDIT_DELETE) {
-            dbg!(edit_id);
-            let _edit = data.actions.get(*edit_id).unwrap();
+            myprint!("delete action ", edit_id);
             data.actions.retain(|edit| edit.id != *edit_id);
             druid::Handled::Yes
+        } else if let Some((stop_id, latlong)) = cmd.get(MOVE_STOP) {
+            myprint!("handle MOVE_STOP command");
+            if let Some(previous) = data.set_stop_position(stop_id, *latlong) {
+                data.push_action(
+                    EditType::Update,
+                    "stop",
+                    stop_id.clone(),
+                    ActionChange::StopPosition {
+                        stop_id: stop_id.clone(),
+                        before: (previous.x, previous.y),
+                        after: (latlong.x, latlong.y),
+                    },
+                );
+            }
+            druid::Handled::Yes
+        } else if let Some(stop_latlongs) = cmd.get(MOVE_STOPS) {
+            myprint!("handle MOVE_STOPS command");
+            let changes = stop_latlongs
+                .iter()
+                .filter_map(|(stop_id, latlong)| {
+                    data.set_stop_position(stop_id, *latlong).map(|previous| {
+                        ActionChange::StopPosition {
+                            stop_id: stop_id.clone(),
+                            before: (previous.x, previous.y),
+                            after: (latlong.x, latlong.y),
+                        }
+                    })
+                })
+                .collect::<Vector<_>>();
+            push_group_action(data, EditType::Update, changes);
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(DELETE_SELECTION) {
+            myprint!("handle DELETE_SELECTION command");
+            let changes = data.delete_selection();
+            push_group_action(data, EditType::Delete, changes);
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(SET_SELECTION_FIELD) {
+            myprint!("handle SET_SELECTION_FIELD command");
+            let field = data.selection_field.clone();
+            let value = data.selection_value.clone();
+            match data.set_selection_field(&field, &value) {
+                Ok(changes) => push_group_action(data, EditType::Update, changes),
+                Err(error) => data.show_error("failed to set field", error),
+            }
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(EXPORT_SELECTION) {
+            myprint!("handle EXPORT_SELECTION command");
+            let result = data
+                .selection_subset_json()
+                .map_err(|error| error.to_string())
+                .and_then(|json| {
+                    std::fs::write(file_info.path(), json).map_err(|error| error.to_string())
+                });
+            if let Err(error) = result {
+                data.show_error("failed to export selection", error);
+            }
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(EXPORT_GEOJSON) {
+            myprint!("handle EXPORT_GEOJSON command");
+            let result = geojson_export(data, &data.geojson_export_layer, data.export_selection)
+                .and_then(|geojson| Ok(serde_json::to_string(&geojson)?))
+                .and_then(|json| Ok(fs::write(file_info.path(), json)?));
+            if let Err(error) = result {
+                data.show_error("failed to export GeoJSON", error);
+            }
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(EXPORT_KML) {
+            myprint!("handle EXPORT_KML command");
+            let kml = kml_export(data, data.export_selection);
+            if let Err(error) = fs::write(file_info.path(), kml) {
+                data.show_error("failed to export KML", error);
+            }
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(EXPORT_GPX) {
+            myprint!("handle EXPORT_GPX command");
+            let gpx = gpx_export(data, data.export_selection);
+            if let Err(error) = fs::write(file_info.path(), gpx) {
+                data.show_error("failed to export GPX", error);
+            }
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(IMPORT_STOPS) {
+            myprint!("handle IMPORT_STOPS command");
+            match Import::from_file(file_info.path(), ImportKind::Stops) {
+                Ok(import) => data.import = Some(import),
+                Err(error) => data.show_error("failed to read stops to import", error),
+            }
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(IMPORT_SHAPES) {
+            myprint!("handle IMPORT_SHAPES command");
+            match Import::from_file(file_info.path(), ImportKind::Shapes) {
+                Ok(import) => data.import = Some(import),
+                Err(error) => data.show_error("failed to read shapes to import", error),
+            }
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(APPLY_IMPORT) {
+            myprint!("handle APPLY_IMPORT command");
+            if let Some(mut import) = data.import.take() {
+                match import.apply(data) {
+                    Ok(changes) => data.push_action(
+                        EditType::Create,
+                        "import",
+                        format!("{} ({} changes)", import.name, changes.len()),
+                        ActionChange::Group(changes),
+                    ),
+                    // keep the import open so the mapping can be fixed
+                    Err(error) => {
+                        import.error = Some(error);
+                        data.import = Some(import);
+                    }
+                }
+            }
+            druid::Handled::Yes
+        } else if let Some((shape_id, coords)) = cmd.get(UPDATE_SHAPE) {
+            myprint!("handle UPDATE_SHAPE command");
+            let before = data.set_shape_points(shape_id, coords);
+            let after = data.edited_shapes.get(shape_id).unwrap().clone();
+            data.push_action(
+                EditType::Update,
+                "shape",
+                shape_id.clone(),
+                ActionChange::ShapePoints {
+                    shape_id: shape_id.clone(),
+                    before,
+                    after,
+                },
+            );
+            druid::Handled::Yes
+        } else if let Some(trip_id) = cmd.get(CREATE_SHAPE) {
+            myprint!("handle CREATE_SHAPE command");
+            let trip = data.trip(trip_id).unwrap();
+            let coords = data.trip_stop_coords(trip);
+            create_shape_for_trips(data, &[trip_id.clone()], &coords);
+            druid::Handled::Yes
+        } else if let Some((trip_id, shape_id)) = cmd.get(ASSIGN_SHAPE) {
+            myprint!("handle ASSIGN_SHAPE command");
+            if !data.has_shape(shape_id) {
+                data.show_error(
+                    "can't assign shape",
+                    format!("no shape with id {}", shape_id),
+                );
+            } else if let Some(previous_shape_id) =
+                data.set_trip_shape_id(trip_id, Some(shape_id.clone()))
+            {
+                data.push_action(
+                    EditType::Update,
+                    "trip",
+                    trip_id.clone(),
+                    ActionChange::TripShape {
+                        trip_id: trip_id.clone(),
+                        before: previous_shape_id,
+                        after: Some(shape_id.clone()),
+                    },
+                );
+            }
+            druid::Handled::Yes
+        } else if let Some(trip_id) = cmd.get(GENERATE_SHAPE) {
+            myprint!("handle GENERATE_SHAPE command");
+            if let Some(road_network) = data.road_network.clone() {
+                let trip = data.trip(trip_id).unwrap();
+                let route = data.route(&trip.route_id).unwrap();
+                if let Some(mode) = NetworkMode::from_route_type(&route.route_type) {
+                    let routed_shape =
+                        road_network.route_through_stops(&data.trip_stop_coords(trip), mode);
+                    data.shape_preview = Some(ShapePreview {
+                        trip_id: trip_id.clone(),
+                        coords: routed_shape.coords.into_iter().collect::<Vector<_>>(),
+                        failed_legs: routed_shape.failed_legs,
+                    });
+                } else {
+                    let route_type = format!("{:?}", route.route_type.0);
+                    data.show_error(
+                        "can't snap shape",
+                        format!("no OSM ways for {}", route_type),
+                    );
+                }
+            } else if data.osm_loading.is_some() {
+                data.show_error("can't snap shape", "the OSM network is still being read");
+            } else {
+                data.show_error("can't snap shape", "no OSM network loaded");
+            }
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(ACCEPT_SHAPE_PREVIEW) {
+            if let Some(shape_preview) = data.shape_preview.take() {
+                // use the shape for all the trips with the same stops which don't have a shape yet
+                let trip_ids = data.trips_with_same_pattern(&shape_preview.trip_id);
+                let coords = shape_preview.coords.iter().cloned().collect::<Vec<_>>();
+                create_shape_for_trips(data, &trip_ids, &coords);
+            }
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(DISCARD_SHAPE_PREVIEW) {
+            data.shape_preview = None;
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(SAVE_PROJECT) {
+            myprint!("handle SAVE_PROJECT command");
+            match save_project(data, file_info.path()) {
+                Ok(()) => continue_pending_action(ctx, data),
+                Err(error) => data.show_error("failed to save project", error),
+            }
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(SAVE) {
+            myprint!("handle SAVE command");
+            match data.project_path.clone() {
+                Some(project_path) => match save_project(data, Path::new(&project_path)) {
+                    Ok(()) => continue_pending_action(ctx, data),
+                    Err(error) => data.show_error("failed to save project", error),
+                },
+                // the edits haven't been saved before so ask where to
+                None => ctx.submit_command(SHOW_SAVE_PANEL.with(save_project_dialog())),
+            }
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(OPEN_PROJECT) {
+            let path = file_info.path().to_string_lossy().to_string();
+            request_action(ctx, data, PendingAction::OpenProject(path));
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(OPEN_FEED) {
+            let path = file_info.path().to_string_lossy().to_string();
+            request_action(ctx, data, PendingAction::OpenFeed(path));
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(IMPORT_FEED) {
+            let path = file_info.path().to_string_lossy().to_string();
+            request_action(ctx, data, PendingAction::ImportFeed(path));
+            druid::Handled::Yes
+        } else if let Some(path) = cmd.get(OPEN_RECENT) {
+            let action = if Path::new(path).extension() == Some(OsStr::new(PROJECT_EXTENSION)) {
+                PendingAction::OpenProject(path.clone())
+            } else {
+                PendingAction::OpenFeed(path.clone())
+            };
+            request_action(ctx, data, action);
+            druid::Handled::Yes
+        } else if let Some(id) = cmd.get(OPEN_LIBRARY_FEED) {
+            request_action(ctx, data, PendingAction::OpenLibraryFeed(id.clone()));
+            druid::Handled::Yes
+        } else if let Some(id) = cmd.get(REIMPORT_LIBRARY_FEED) {
+            request_action(ctx, data, PendingAction::ReimportLibraryFeed(id.clone()));
+            druid::Handled::Yes
+        } else if let Some(id) = cmd.get(REMOVE_LIBRARY_DATA) {
+            myprint!("handle REMOVE_LIBRARY_DATA command");
+            if let Some(feed) = data.library.iter_mut().find(|feed| &feed.id == id) {
+                let result = feed.remove_data();
+                write_library(&data.library);
+                if let Err(error) = result {
+                    data.show_error("failed to remove library data", error);
+                }
+            }
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(CLOSE_FEED) {
+            request_action(ctx, data, PendingAction::Close);
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(DISCARD_CHANGES) {
+            myprint!("discard unsaved changes");
+            continue_pending_action(ctx, data);
+            druid::Handled::Yes
+        } else if cmd.is(CLOSE_WINDOW) || cmd.is(QUIT_APP) {
+            // let druid close the window once the changes have been dealt with
+            if has_unsaved_changes(data) {
+                data.pending_action = Some(PendingAction::Quit);
+                druid::Handled::Yes
+            } else {
+                druid::Handled::No
+            }
+        } else if let Some(progress) = cmd.get(LOADING_PROGRESS) {
+            if let Some(loading) = &mut data.loading {
+                if loading.id == progress.load_id {
+                    loading.apply(progress);
+                }
+            }
+            druid::Handled::Yes
+        } else if let Some(finished) = cmd.get(LOADING_FINISHED) {
+            myprint!("handle LOADING_FINISHED command");
+            let current_load_id = data.loading.as_ref().map(|loading| loading.id);
+            let cancelled = data.loading.as_ref().map_or(false, Loading::is_cancelled);
+            match finished.take() {
+                // another feed has been opened since, or the loading screen was left
+                Some(LoadingFinished { load_id, .. }) if Some(load_id) != current_load_id => {
+                    myprint!("ignoring superseded load ", load_id);
+                }
+                Some(LoadingFinished {
+                    result: Ok(LoadedFeed(mut loaded, library_feed)),
+                    ..
+                }) => {
+                    // the OSM network may have been loaded before the feed finished
+                    loaded.road_network = data.road_network.take();
+                    loaded.osm_loading = data.osm_loading.take();
+                    // an error from importing the feed to the library is newer than the one already shown
+                    if loaded.error_message.is_none() {
+                        loaded.error_message = data.error_message.take();
+                    }
+                    loaded.recent_files = data.recent_files.clone();
+                    loaded.library = data.library.clone();
+                    if let Some(library_feed) = library_feed {
+                        update_library(&mut loaded.library, library_feed);
+                    }
+                    let opened_path = loaded.project_path.as_ref().or(loaded.source_path.as_ref());
+                    // stdin can't be opened again
+                    if let Some(path) = opened_path
+                        .filter(|path| !is_stdin(Path::new(path)))
+                        .cloned()
+                    {
+                        remember_recent_file(&mut loaded.recent_files, &path);
+                    }
+                    *data = loaded;
+                }
+                // back to the feed which was already open, or the start screen
+                Some(LoadingFinished { result: Err(_), .. }) if cancelled => {
+                    data.loading = None;
+                }
+                // go back to the feed which was already open
+                Some(LoadingFinished {
+                    result: Err(error), ..
+                }) if data.source_path.is_some() => {
+                    data.show_error("failed to load feed, keeping the current one", error);
+                    data.loading = None;
+                }
+                Some(LoadingFinished {
+                    result: Err(error), ..
+                }) => {
+                    if let Some(loading) = &mut data.loading {
+                        loading.error = Some(error);
+                    }
+                }
+                None => {}
+            }
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(LOAD_OSM) {
+            myprint!("handle LOAD_OSM command");
+            data.osm_loading = Some(file_info.path().to_string_lossy().to_string());
+            load_osm_in_background(file_info.path().to_path_buf(), ctx.get_external_handle());
+            druid::Handled::Yes
+        } else if let Some(result) = cmd.get(OSM_LOADED) {
+            myprint!("handle OSM_LOADED command");
+            data.osm_loading = None;
+            match result.take() {
+                Some(Ok(road_network)) => data.road_network = Some(Arc::new(road_network)),
+                Some(Err(error)) => data.show_error("failed to load OSM", error),
+                None => {}
+            }
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(LOAD_GEOJSON_LAYER) {
+            myprint!("handle LOAD_GEOJSON_LAYER command");
+            match GeoJsonLayer::from_file(file_info.path(), data.map_layers.len()) {
+                Ok(layer) => data.map_layers.push_back(layer),
+                Err(error) => data.show_error("failed to load GeoJSON layer", error),
+            }
+            druid::Handled::Yes
+        } else if let Some(layer_id) = cmd.get(REMOVE_LAYER) {
+            data.map_layers.retain(|layer| &layer.id != layer_id);
+            druid::Handled::Yes
+        } else if let Some(file_info) = cmd.get(LOAD_CENSUS) {
+            myprint!("handle LOAD_CENSUS command");
+            match Census::from_file(file_info.path()) {
+                Ok(census) => data.census = Some(Arc::new(census)),
+                Err(error) => data.show_error("failed to load census", error),
+            }
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(RUN_ANALYSIS) {
+            let report = AnalysisReport::new(
+                data,
+                data.census.as_deref(),
+                data.analysis_radius as f64,
+                &data.analysis_population_field,
+                &data.analysis_jobs_field,
+            );
+            data.analysis_report = Some(Arc::new(report));
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(COMPUTE_SHAPE_DIST) {
+            let (warnings, changes) = data.compute_shape_dist_traveled();
+            data.shape_dist_warnings = warnings;
+            if !changes.is_empty() {
+                data.push_action(
+                    EditType::Update,
+                    "shape_dist_traveled",
+                    format!("{} items", changes.len()),
+                    ActionChange::Group(changes),
+                );
+            }
+            druid::Handled::Yes
+        } else if let Some(_) = cmd.get(UNDO_ACTION) {
+            // actions which recorded no change, eg ITEM_NEW_CHILD, can't be reverted so are skipped and left in the list
+            let revertible = data
+                .actions
+                .iter()
+                .enumerate()
+                .rev()
+                .find(|(_index, action)| action.change != ActionChange::None)
+                .map(|(index, _action)| index);
+            if let Some(index) = revertible {
+                myprint!("undo action");
+                let action = data.actions.remove(index);
+                data.revert_change(&action.change);
+            }
+            druid::Handled::Yes
         } else if let Some(agency_id) = cmd.get(ADD_ROUTE) {
             let new_route = MyRoute::new(agency_id.clone());
-            data.routes.push_front(new_route);
+            data.push_route(new_route);
             druid::Handled::Yes
         } else if let Some(_route_id) = cmd.get(ADD_TRIP) {
             data.map_stop_selection_mode = true;
@@ -123,19 +609,12 @@ impl AppDelegate<AppData> for Delegate {
             let new_stop = MyStop::new(*latlong);
 
             // need to resort stops? no, stops are not sorted
-            data.stops.push_back(new_stop.clone());
-
-            // let mut stop_index_from_id = HashMap::new();
-            // data.stops.iter().enumerate().for_each(|(i, stop)| {
-            //     stop_index_from_id.insert(stop.id.clone(), i);
-            // });
-            data.stop_index_from_id
-                .insert(new_stop.id.clone(), data.stops.len() - 1);
+            data.push_stop(new_stop.clone());
 
             // first we need to determine whether we are adding to an existing trip, or creating a new trip by looking at whether a stop_time is selected or only route, else panic
 
             // update existing trip
-            if let Some((trip_id, stop_sequence)) = &data.selected_stop_time_id {
+            if let Some((trip_id, stop_sequence)) = data.selected_stop_time_id.clone() {
                 // determine whether stop selected is for updating a stop_time or creating a new one
 
                 // create new stop_time
@@ -144,29 +623,17 @@ impl AppDelegate<AppData> for Delegate {
                     // stop_times.sort_by(|stop1, stop2| stop1.stop_sequence.cmp(&stop2.stop_sequence));
                     // stop_times.sort_by(|x1, x2| x1.trip_id.cmp(&x2.trip_id));
 
-                    // need to insert the new stop_time, update all the stop_sequences for the other stop_times in that trip, then resort stop_times (not actually necessary), and recreate stop_time_range_from_trip_id (might be avoidable if we store actual stop_times in a HashMap)
+                    // need to insert the new stop_time, update all the stop_sequences for the other stop_times in that trip, then resort stop_times (not actually necessary)
 
                     // could maybe use data.stop_times.insert_ord(item) ???
-                    let (selected_stop_time_index, _) = data
-                        .stop_times
-                        .iter()
-                        .enumerate()
-                        .find(|(_index, stop_time)| {
-                            &stop_time.trip_id == trip_id
-                                && &stop_time.stop_sequence == stop_sequence
-                        })
-                        .unwrap();
+                    let selected_stop_time_index =
+                        data.stop_time_index(&trip_id, stop_sequence).unwrap();
 
                     // recalcuate n_stops for MyTrip
-                    let trip = data
-                        .trips
-                        .iter_mut()
-                        .find(|trip| &trip.id == trip_id)
-                        .unwrap();
-                    trip.n_stops += 1;
-
-                    // insert new stop_time
-                    data.stop_times.insert(
+                    data.trip_mut(&trip_id).unwrap().n_stops += 1;
+
+                    // insert new stop_time, which also udpates stop_time_range_from_trip_id (important to do this first to get correct range to update stop_sequences)
+                    data.insert_stop_time(
                         if insert_stop_time_before {
                             selected_stop_time_index
                         } else {
@@ -174,14 +641,9 @@ impl AppDelegate<AppData> for Delegate {
                         },
                         MyStopTime::new(trip_id.clone(), new_stop.id.clone(), 99),
                     );
-                    // udpate stop_time_range_from_trip_id (important to do this first to get correct range to update stop_sequences)
-
-                    // data.stop_time_range_from_trip_id = stop_time_range_from_trip_id;
-                    data.stop_time_range_from_trip_id =
-                        make_stop_time_range_from_trip_id(&data.stop_times);
 
                     // udpate all stop_sequences for that trip
-                    let range = data.stop_time_range_from_trip_id.get(trip_id).unwrap();
+                    let range = data.stop_time_range_from_trip_id.get(&trip_id).unwrap();
                     let mut stop_sequence_inc = 1;
                     for i in range.0..range.1 {
                         let stop_time = data.stop_times.get_mut(i).unwrap();
@@ -193,14 +655,10 @@ impl AppDelegate<AppData> for Delegate {
 
                     // update existing stop_time
                 } else {
-                    let selected_stop_time = data
-                        .stop_times
-                        .iter_mut()
-                        .find(|stop_time| {
-                            &stop_time.trip_id == trip_id
-                                && &stop_time.stop_sequence == stop_sequence
-                        })
-                        .unwrap();
+                    let selected_stop_time_index =
+                        data.stop_time_index(&trip_id, stop_sequence).unwrap();
+                    let selected_stop_time =
+                        data.stop_times.get_mut(selected_stop_time_index).unwrap();
                     selected_stop_time.stop_id = new_stop.id.clone();
                     selected_stop_time.edited = true;
                 }
@@ -210,18 +668,13 @@ impl AppDelegate<AppData> for Delegate {
                 // data.map_stop_selection_mode = true;
                 let mut new_trip = MyTrip::new(selected_route_id.clone());
                 new_trip.n_stops = 1;
-                data.trips.push_front(new_trip.clone());
+                data.push_trip(new_trip.clone());
 
                 // insert new stop_time
-                data.stop_times.push_back(MyStopTime::new(
-                    new_trip.id.clone(),
-                    new_stop.id.clone(),
-                    1,
-                ));
-                // udpate stop_time_range_from_trip_id (important to do this first to get correct range to update stop_sequences)
-
-                data.stop_time_range_from_trip_id =
-                    make_stop_time_range_from_trip_id(&data.stop_times);
+                data.insert_stop_time(
+                    data.stop_times.len(),
+                    MyStopTime::new(new_trip.id.clone(), new_stop.id.clone(), 1),
+                );
             } else {
                 panic!("shouldn't be able to select a stop here ");
             }
@@ -236,38 +689,26 @@ impl AppDelegate<AppData> for Delegate {
             myprint!("cmd.get(EDIT_STOP_TIME_UPDATE)");
             // first we need to determine whether we are adding to an existing trip, or creating a new trip by looking at whether a stop_time is selected or only route, else panic
 
-            if let Some((trip_id, stop_sequence)) = &data.selected_stop_time_id {
+            if let Some((trip_id, stop_sequence)) = data.selected_stop_time_id.clone() {
                 // determine whether stop selected is for updating a stop_time or creating a new one
 
                 // data.stop_times is sorted and it's order is assumed fixed by stop_time_range_from_trip_id
                 // stop_times.sort_by(|stop1, stop2| stop1.stop_sequence.cmp(&stop2.stop_sequence));
                 // stop_times.sort_by(|x1, x2| x1.trip_id.cmp(&x2.trip_id));
 
-                // need to insert the new stop_time, update all the stop_sequences for the other stop_times in that trip, then resort stop_times (not actually necessary), and recreate stop_time_range_from_trip_id (might be avoidable if we store actual stop_times in a HashMap)
+                // need to insert the new stop_time, update all the stop_sequences for the other stop_times in that trip, then resort stop_times (not actually necessary)
 
                 // insert new stop_time before or after selected stop_time
                 if let Some(insert_stop_time_before) = data.insert_stop_time_before {
                     // could maybe use data.stop_times.insert_ord(item) ???
-                    let (selected_stop_time_index, _) = data
-                        .stop_times
-                        .iter()
-                        .enumerate()
-                        .find(|(_index, stop_time)| {
-                            &stop_time.trip_id == trip_id
-                                && &stop_time.stop_sequence == stop_sequence
-                        })
-                        .unwrap();
+                    let selected_stop_time_index =
+                        data.stop_time_index(&trip_id, stop_sequence).unwrap();
 
                     // recalcuate n_stops for MyTrip
-                    let trip = data
-                        .trips
-                        .iter_mut()
-                        .find(|trip| &trip.id == trip_id)
-                        .unwrap();
-                    trip.n_stops += 1;
-
-                    // insert new stop_time
-                    data.stop_times.insert(
+                    data.trip_mut(&trip_id).unwrap().n_stops += 1;
+
+                    // insert new stop_time, which also udpates stop_time_range_from_trip_id (important to do this first to get correct range to update stop_sequences)
+                    data.insert_stop_time(
                         if insert_stop_time_before {
                             selected_stop_time_index
                         } else {
@@ -275,14 +716,9 @@ impl AppDelegate<AppData> for Delegate {
                         },
                         MyStopTime::new(trip_id.clone(), stop_id.clone(), 99),
                     );
-                    // udpate stop_time_range_from_trip_id (important to do this first to get correct range to update stop_sequences)
-
-                    // data.stop_time_range_from_trip_id = stop_time_range_from_trip_id;
-                    data.stop_time_range_from_trip_id =
-                        make_stop_time_range_from_trip_id(&data.stop_times);
 
                     // udpate all stop_sequences for that trip
-                    let range = data.stop_time_range_from_trip_id.get(trip_id).unwrap();
+                    let range = data.stop_time_range_from_trip_id.get(&trip_id).unwrap();
                     let mut stop_sequence_inc = 1;
                     for i in range.0..range.1 {
                         let stop_time = data.stop_times.get_mut(i).unwrap();
@@ -294,18 +730,16 @@ impl AppDelegate<AppData> for Delegate {
                 // edit existing stop time
                 } else {
                     // set the new stop id
-                    println!(
-                        "update stop_time {:?} to stop_id: {}",
-                        data.selected_stop_time_id, stop_id
+                    myprint!(
+                        "update stop_time ",
+                        format!("{:?}", data.selected_stop_time_id),
+                        " to stop_id: ",
+                        stop_id
                     );
-                    let selected_stop_time = data
-                        .stop_times
-                        .iter_mut()
-                        .find(|stop_time| {
-                            &stop_time.trip_id == trip_id
-                                && &stop_time.stop_sequence == stop_sequence
-                        })
-                        .unwrap();
+                    let selected_stop_time_index =
+                        data.stop_time_index(&trip_id, stop_sequence).unwrap();
+                    let selected_stop_time =
+                        data.stop_times.get_mut(selected_stop_time_index).unwrap();
                     selected_stop_time.stop_id = stop_id.clone();
                     selected_stop_time.edited = true;
                 }
@@ -313,15 +747,13 @@ impl AppDelegate<AppData> for Delegate {
                 // data.map_stop_selection_mode = true;
                 let mut new_trip = MyTrip::new(selected_route_id.clone());
                 new_trip.n_stops = 1;
-                data.trips.push_front(new_trip.clone());
+                data.push_trip(new_trip.clone());
 
                 // insert new stop_time
-                data.stop_times
-                    .push_back(MyStopTime::new(new_trip.id.clone(), stop_id.clone(), 1));
-                // udpate stop_time_range_from_trip_id (important to do this first to get correct range to update stop_sequences)
-
-                data.stop_time_range_from_trip_id =
-                    make_stop_time_range_from_trip_id(&data.stop_times);
+                data.insert_stop_time(
+                    data.stop_times.len(),
+                    MyStopTime::new(new_trip.id.clone(), stop_id.clone(), 1),
+                );
             } else {
                 panic!("shouldn't be able to select a stop here ");
             }
@@ -368,6 +800,9 @@ impl AppDelegate<AppData> for Delegate {
             // data.selected_trip_id = None;
             // data.selected_stop_time_id = None;
             druid::Handled::Yes
+        } else if cmd.is(AGENCY_ID_CHANGED) {
+            data.rebuild_agency_index();
+            druid::Handled::Yes
         } else if let Some(agency_id) = cmd.get(SELECT_AGENCY) {
             // TODO why is the if statement needed?
             if data.selected_agency_id != Some(agency_id.clone()) {
@@ -416,15 +851,7 @@ impl AppDelegate<AppData> for Delegate {
             druid::Handled::Yes
         } else if let Some(trip_id) = cmd.get(SELECT_TRIP) {
             myprint!("select trip");
-            let trip_index = data
-                .trips
-                .iter()
-                .enumerate()
-                .find(|(_index, trip)| &trip.id == trip_id)
-                .unwrap()
-                .0;
-            data.selected_trip_id = Some((trip_index, trip_id.clone()));
-            dbg!(&data.selected_trip_id);
+            data.selected_trip_id = Some(trip_id.clone());
             data.selected_stop_time_id = None;
             data.selected_stop_id = None;
 
@@ -462,7 +889,6 @@ impl AppDelegate<AppData> for Delegate {
 
             // let (trip_id, stop_sequence) = stop_time_pk;
             // set the new stop id
-            println!("hover stop_time {:?}", data.selected_stop_time_id);
             let previous_hovered_stop_time_id = data.hovered_stop_time_id.clone();
             data.hovered_stop_time_id = stop_time_id.clone();
 
@@ -494,10 +920,6 @@ impl AppDelegate<AppData> for Delegate {
             data.selected_stop_time_id = Some(stop_time_pk.clone());
             // These below will already be set when navigating the list, but won't necessarily be when selecting the map?? No...
             // data.selected_route_id
-            dbg!(&data.selected_stop_time_id);
-            dbg!(&data.selected_trip_id);
-            dbg!(&data.selected_route_id);
-            dbg!(&data.selected_agency_id);
 
             data.selected_stop_id = None;
 
@@ -516,25 +938,169 @@ impl AppDelegate<AppData> for Delegate {
     }
 }
 
-fn make_stop_time_range_from_trip_id(
-    stop_times: &Vector<MyStopTime>,
-) -> HashMap<String, (usize, usize)> {
-    let mut stop_time_range_from_trip_id = HashMap::new();
-    let mut trip_start_index = 0;
-    let mut trip_end_index = 0;
-    let mut current_trip = stop_times[0].trip_id.clone();
-    // let stop_times2 = data.gtfs.stop_times.clone();
-    for stop_time in stop_times {
-        // when we arrive at a new section of trip_id's insert the index range into to map, update the current trip, and reset the range start index
-        if current_trip != stop_time.trip_id {
-            stop_time_range_from_trip_id
-                .insert(current_trip.clone(), (trip_start_index, trip_end_index));
-            current_trip = stop_time.trip_id.clone();
-            trip_start_index = trip_end_index;
+/// creates a new shape from (long, lat) coords and assigns it to the trips, as one action so it is undone in one go
+fn create_shape_for_trips(data: &mut AppData, trip_ids: &[String], coords: &[Point]) {
+    let shape_id = Uuid::new_v4().to_string();
+    let before = data.set_shape_points(&shape_id, coords);
+    let after = data.edited_shapes.get(&shape_id).unwrap().clone();
+    let mut changes = Vector::new();
+    changes.push_back(ActionChange::ShapePoints {
+        shape_id: shape_id.clone(),
+        before,
+        after,
+    });
+    for trip_id in trip_ids {
+        if let Some(previous_shape_id) = data.set_trip_shape_id(trip_id, Some(shape_id.clone())) {
+            changes.push_back(ActionChange::TripShape {
+                trip_id: trip_id.clone(),
+                before: previous_shape_id,
+                after: Some(shape_id.clone()),
+            });
+        }
+    }
+    data.push_action(
+        EditType::Create,
+        "shape",
+        shape_id,
+        ActionChange::Group(changes),
+    );
+}
+
+/// records changes made together to the selection as one action, so they are undone together
+fn push_group_action(data: &mut AppData, edit_type: EditType, changes: Vector<ActionChange>) {
+    if changes.is_empty() {
+        return;
+    }
+    data.push_action(
+        edit_type,
+        "selection",
+        format!("{} items", changes.len()),
+        ActionChange::Group(changes),
+    );
+}
+
+fn has_unsaved_changes(data: &AppData) -> bool {
+    data.source_path.is_some()
+        && Project::new(data, PathBuf::new()).edits_fingerprint() != data.saved_edits
+}
+
+/// does the action straight away if there's nothing to lose, otherwise asks whether to save first
+fn request_action(ctx: &mut DelegateCtx, data: &mut AppData, action: PendingAction) {
+    if has_unsaved_changes(data) {
+        data.pending_action = Some(action);
+    } else {
+        perform_action(ctx, data, action);
+    }
+}
+
+fn continue_pending_action(ctx: &mut DelegateCtx, data: &mut AppData) {
+    if let Some(action) = data.pending_action.take() {
+        perform_action(ctx, data, action);
+    }
+}
+
+fn perform_action(ctx: &mut DelegateCtx, data: &mut AppData, action: PendingAction) {
+    match action {
+        PendingAction::Close => {
+            if let Some(loading) = &data.loading {
+                loading.cancel();
+            }
+            let mut closed = make_initial_data(MyGtfs::default());
+            closed.road_network = data.road_network.take();
+            closed.recent_files = data.recent_files.clone();
+            closed.library = data.library.clone();
+            *data = closed;
+        }
+        PendingAction::OpenFeed(path) => {
+            let library = LibraryUpdate::for_feed(&data.library, Path::new(&path));
+            start_loading(ctx, data, PathBuf::from(path), None, library)
+        }
+        PendingAction::ImportFeed(path) => {
+            let library = LibraryUpdate::for_import(&data.library, Path::new(&path));
+            start_loading(ctx, data, PathBuf::from(path), None, library)
+        }
+        PendingAction::OpenProject(path) => match Project::from_file(Path::new(&path)) {
+            Ok(project) => {
+                let library = LibraryUpdate::for_project(&data.library, &project.source);
+                start_loading(ctx, data, project.source.clone(), Some(project), library)
+            }
+            Err(error) => data.show_error("failed to open project", error),
+        },
+        PendingAction::OpenLibraryFeed(id) => {
+            let feed = data.library.iter().find(|feed| feed.id == id).cloned();
+            match feed {
+                Some(feed) => match feed.data_path.clone() {
+                    Some(data_path) => start_loading(
+                        ctx,
+                        data,
+                        PathBuf::from(data_path),
+                        None,
+                        LibraryUpdate::Opened(feed),
+                    ),
+                    None => data.show_error(
+                        "failed to open library feed",
+                        format!("{} has no data, reimport it first", feed.name),
+                    ),
+                },
+                None => data.show_error(
+                    "failed to open library feed",
+                    format!("no feed with id {}", id),
+                ),
+            }
+        }
+        PendingAction::ReimportLibraryFeed(id) => {
+            let feed = data.library.iter().find(|feed| feed.id == id).cloned();
+            match feed {
+                // feeds imported from a URL are downloaded again
+                Some(feed) => start_loading(
+                    ctx,
+                    data,
+                    PathBuf::from(&feed.source),
+                    None,
+                    LibraryUpdate::Import(Some(feed)),
+                ),
+                None => data.show_error(
+                    "failed to reimport library feed",
+                    format!("no feed with id {}", id),
+                ),
+            }
         }
-        trip_end_index += 1;
+        PendingAction::Quit => Application::global().quit(),
+    }
+}
+
+/// shows the loading screen while the feed is read in the background, it replaces the current one when LOADING_FINISHED arrives
+fn start_loading(
+    ctx: &mut DelegateCtx,
+    data: &mut AppData,
+    source: PathBuf,
+    project: Option<Project>,
+    library: LibraryUpdate,
+) {
+    // its result would replace this load's when it arrived
+    if let Some(previous) = &data.loading {
+        previous.cancel();
     }
-    // insert final trip id
-    stop_time_range_from_trip_id.insert(current_trip.clone(), (trip_start_index, trip_end_index));
-    stop_time_range_from_trip_id
+    let loading = Loading::new(source.to_string_lossy().to_string());
+    load_feed_in_background(
+        source,
+        project,
+        library,
+        ctx.get_external_handle(),
+        &loading,
+    );
+    data.loading = Some(loading);
+}
+
+fn save_project(data: &mut AppData, path: &Path) -> Result<(), Box<dyn Error>> {
+    let source_path = data.source_path.as_ref().ok_or("no feed is open")?;
+    // so the project can be opened from any directory
+    let source = fs::canonicalize(source_path).unwrap_or_else(|_| PathBuf::from(source_path));
+    let project = Project::new(data, source);
+    project.write(path)?;
+    data.saved_edits = project.edits_fingerprint();
+    let path = path.to_string_lossy().to_string();
+    remember_recent_file(&mut data.recent_files, &path);
+    data.project_path = Some(path);
+    Ok(())
 }
@@ -0,0 +1,401 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::data::{FeedIndex, MyGtfs};
+use crate::source::{feed_directory, is_feed_file};
+
+/// bump whenever MyGtfs, the gtfs_structures records it holds, or FeedIndex change shape, so that caches written by older versions are ignored
+const CACHE_VERSION: u32 = 5;
+const CACHE_MAGIC: &[u8; 8] = b"GTFSMCAC";
+/// older caches are deleted whenever a new one is written, see prune()
+const MAX_CACHED_FEEDS: usize = 10;
+
+/// Snapshot of a parsed feed, its tables already sorted, along with the FeedIndex built from them, stored in the user's cache directory under the hash of the source, so reopening an unchanged feed skips parsing the CSVs and indexing them. The items shown in the app are still made from the cached records by make_indexed_data, so a large feed still takes a moment to reopen.
+/// Each cache has a .used stamp next to it holding when it was last read or written, so that prune() keeps the caches of feeds which are opened often, not just recently changed. Setting the modification time of the cache itself would do, but needs a newer rust than this crate supports
+/// The records are written as MessagePack rather than bincode since gtfs_structures' serde implementations are written for CSV and don't always serialize to the same shape they deserialize from, which bincode can't read back but a self describing format can. Structs are written with their field names, so a record gaining or reordering fields in a gtfs_structures update can't be misread as another field
+pub struct FeedCache {
+    path: PathBuf,
+    hash: String,
+}
+impl FeedCache {
+    /// hashes the zip or XML file at source, or the .txt and .xml files if source is a directory. The hash is remembered against the files' sizes and modification times, so an unchanged feed is only read in full once. None if there is nowhere to keep a cache
+    pub fn for_source(source: &Path) -> Result<Option<FeedCache>, Box<dyn Error>> {
+        match cache_dir() {
+            Some(dir) => Ok(Some(FeedCache::in_dir(&dir, source)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn in_dir(dir: &Path, source: &Path) -> Result<FeedCache, Box<dyn Error>> {
+        let files = if source.is_dir() {
+            let mut files = fs::read_dir(feed_directory(source))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            files.retain(|path| is_feed_file(path));
+            files.sort();
+            files
+        } else {
+            vec![source.to_path_buf()]
+        };
+
+        let mut fingerprint = Sha256::new();
+        for path in &files {
+            let metadata = fs::metadata(path)?;
+            let modified = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let path = path.canonicalize().unwrap_or_else(|_| path.clone());
+            fingerprint.update(
+                format!(
+                    "{}\n{}\n{}\n",
+                    path.to_string_lossy(),
+                    metadata.len(),
+                    modified.as_nanos()
+                )
+                .as_bytes(),
+            );
+        }
+        let fingerprint_path = dir.join(format!("{:x}.source", fingerprint.finalize()));
+        let hash = match fs::read_to_string(&fingerprint_path) {
+            Ok(hash) => hash.trim().to_string(),
+            Err(_) => {
+                let hash = content_hash(source, &files)?;
+                fs::create_dir_all(dir)?;
+                // the hash is only remembered to save time, so failing to is no reason to stop
+                if let Err(error) = fs::write(&fingerprint_path, &hash) {
+                    myprint!("failed to remember feed hash: ", error);
+                }
+                hash
+            }
+        };
+        Ok(FeedCache {
+            path: dir.join(format!("{}.bin", hash)),
+            hash,
+        })
+    }
+
+    /// hex SHA-256 of the source's contents, the same for copies of the feed wherever they are
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// the cached feed and its index, or None if there isn't one for this source or it was written by a different version
+    pub fn read(&self) -> Result<Option<(MyGtfs, FeedIndex)>, Box<dyn Error>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+        let mut reader = BufReader::new(file);
+        let mut magic = [0; 8];
+        let mut version = [0; 4];
+        reader.read_exact(&mut magic)?;
+        reader.read_exact(&mut version)?;
+        if &magic != CACHE_MAGIC || u32::from_le_bytes(version) != CACHE_VERSION {
+            return Ok(None);
+        }
+        let cached = rmp_serde::from_read(reader)?;
+        if let Err(error) = self.mark_used() {
+            myprint!("failed to mark cache as used: ", error);
+        }
+        Ok(Some(cached))
+    }
+
+    /// gtfs must already have been sorted by index_feed, which made index
+    pub fn write(&self, gtfs: &MyGtfs, index: &FeedIndex) -> Result<(), Box<dyn Error>> {
+        let dir = self.path.parent().unwrap_or_else(|| Path::new("."));
+        fs::create_dir_all(dir)?;
+        // write to a temporary file first so an interrupted write never leaves a truncated cache behind
+        let temp_path = self.path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        writer.write_all(CACHE_MAGIC)?;
+        writer.write_all(&CACHE_VERSION.to_le_bytes())?;
+        rmp_serde::encode::write_named(&mut writer, &(gtfs, index))?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(temp_path, &self.path)?;
+        self.mark_used()?;
+        // the new cache is written, so failing to tidy up the old ones only wastes some space
+        if let Err(error) = prune(dir) {
+            myprint!("failed to remove old caches: ", error);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self) -> Result<(), Box<dyn Error>> {
+        for path in [&self.path, &used_path(&self.path)] {
+            match fs::remove_file(path) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn mark_used(&self) -> io::Result<()> {
+        write_used(&self.path, SystemTime::now())
+    }
+}
+
+/// the stamp recording when the cache at path was last used
+fn used_path(path: &Path) -> PathBuf {
+    path.with_extension("used")
+}
+
+/// the stamp holds the time as seconds since the epoch
+fn write_used(path: &Path, time: SystemTime) -> io::Result<()> {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    fs::write(used_path(path), seconds.to_string())
+}
+
+/// when the cache at path was last read or written, falling back to when it was written if its stamp is missing or unreadable
+fn last_used(path: &Path) -> io::Result<SystemTime> {
+    let stamped = fs::read_to_string(used_path(path))
+        .ok()
+        .and_then(|seconds| seconds.trim().parse().ok())
+        .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds));
+    match stamped {
+        Some(time) => Ok(time),
+        None => fs::metadata(path)?.modified(),
+    }
+}
+
+/// the name of each file is included for directories, so renaming a file also invalidates the cache, but not for single files, so copies of a zip share a cache
+fn content_hash(source: &Path, files: &[PathBuf]) -> Result<String, Box<dyn Error>> {
+    let mut hasher = Sha256::new();
+    for path in files {
+        if source.is_dir() {
+            hasher.update(path.file_name().unwrap().to_string_lossy().as_bytes());
+        }
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// deletes all but the MAX_CACHED_FEEDS most recently used caches and their stamps, along with the remembered hashes of sources which no longer have a cache
+fn prune(dir: &Path) -> Result<(), Box<dyn Error>> {
+    let mut caches = Vec::new();
+    let mut fingerprints = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("bin") => caches.push((last_used(&path)?, path)),
+            Some("source") => fingerprints.push(path),
+            _ => {}
+        }
+    }
+    // most recent first
+    caches.sort_by(|(modified1, _), (modified2, _)| modified2.cmp(modified1));
+    for (_modified, path) in caches.iter().skip(MAX_CACHED_FEEDS) {
+        fs::remove_file(path)?;
+        match fs::remove_file(used_path(path)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+    }
+    let kept_hashes = caches
+        .iter()
+        .take(MAX_CACHED_FEEDS)
+        .filter_map(|(_modified, path)| path.file_stem())
+        .map(|hash| hash.to_string_lossy().to_string())
+        .collect::<HashSet<_>>();
+    for path in fingerprints {
+        if !kept_hashes.contains(fs::read_to_string(&path)?.trim()) {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// $XDG_CACHE_HOME/gtfs_manager, falling back to ~/.cache/gtfs_manager, or %LOCALAPPDATA%\gtfs_manager on windows
+fn cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    base.map(|base| base.join("gtfs_manager"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::index_feed;
+    use serde::de::DeserializeOwned;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "gtfs_manager_cache_test_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn records<T: DeserializeOwned>(csv: &str) -> Vec<T> {
+        csv::Reader::from_reader(csv.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn feed() -> MyGtfs {
+        MyGtfs {
+            agencies: records(
+                "agency_id,agency_name,agency_url,agency_timezone\n\
+                 a,Agency,https://example.com,America/Sao_Paulo\n",
+            ),
+            routes: records(
+                "route_id,agency_id,route_short_name,route_long_name,route_type,route_color,route_text_color\n\
+                 r,a,1,Route,3,FF0000,FFFFFF\n",
+            ),
+            trips: records(
+                "route_id,service_id,trip_id,direction_id,shape_id\n\
+                 r,weekdays,t,1,s\n",
+            ),
+            stop_times: records(
+                "trip_id,arrival_time,departure_time,stop_id,stop_sequence,pickup_type,shape_dist_traveled\n\
+                 t,08:00:00,08:00:30,s1,1,0,0\n\
+                 t,25:10:00,25:10:00,s2,2,,1.5\n",
+            ),
+            stops: records(
+                "stop_id,stop_name,stop_lat,stop_lon,location_type,wheelchair_boarding\n\
+                 s1,First,-23.5,-46.6,0,1\n\
+                 s2,Second,-23.6,-46.7,,\n",
+            ),
+            shapes: Some(records(
+                "shape_id,shape_pt_lat,shape_pt_lon,shape_pt_sequence\n\
+                 s,-23.5,-46.6,1\n\
+                 s,-23.6,-46.7,2\n",
+            )),
+            calendar: Some(records(
+                "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+                 weekdays,1,1,1,1,1,0,0,20220101,20221231\n",
+            )),
+        }
+    }
+
+    #[test]
+    fn cached_feeds_read_back_unchanged() {
+        let dir = temp_dir("round_trip");
+        let cache = FeedCache {
+            path: dir.join("feed.bin"),
+            hash: "feed".to_string(),
+        };
+        let mut gtfs = feed();
+        let index = index_feed(&mut gtfs);
+        cache.write(&gtfs, &index).unwrap();
+        let (cached, cached_index) = cache.read().unwrap().unwrap();
+        // the records don't implement PartialEq, and serializing them is what the cache relies on anyway
+        assert_eq!(
+            serde_json::to_value(&cached).unwrap(),
+            serde_json::to_value(&gtfs).unwrap()
+        );
+        assert_eq!(
+            cached_index
+                .stop_time_range_from_trip_id
+                .iter()
+                .collect::<Vec<_>>(),
+            index
+                .stop_time_range_from_trip_id
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            cached_index.agency_index_from_id,
+            index.agency_index_from_id
+        );
+        assert_eq!(cached_index.n_trips_of_routes, index.n_trips_of_routes);
+        assert!(dir.join("feed.used").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn caches_from_other_versions_are_ignored() {
+        let dir = temp_dir("version");
+        let path = dir.join("feed.bin");
+        let mut contents = CACHE_MAGIC.to_vec();
+        contents.extend((CACHE_VERSION - 1).to_le_bytes());
+        contents.extend(rmp_serde::to_vec_named(&feed()).unwrap());
+        fs::write(&path, contents).unwrap();
+        let cache = FeedCache {
+            path,
+            hash: "feed".to_string(),
+        };
+        assert!(cache.read().unwrap().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hashes_follow_the_contents_of_the_source() {
+        let dir = temp_dir("hash");
+        let cache_dir = dir.join("cache");
+        let feed_dir = dir.join("feed");
+        fs::create_dir_all(&feed_dir).unwrap();
+        fs::write(feed_dir.join("stops.txt"), "stop_id\ns1\n").unwrap();
+        fs::write(feed_dir.join("notes.md"), "ignored").unwrap();
+        let zip_path = dir.join("feed.zip");
+        fs::write(&zip_path, "not really a zip").unwrap();
+        let copy_path = dir.join("copy.zip");
+        fs::copy(&zip_path, &copy_path).unwrap();
+
+        let first = FeedCache::in_dir(&cache_dir, &feed_dir).unwrap();
+        // the second time the hash is remembered rather than calculated
+        assert_eq!(
+            FeedCache::in_dir(&cache_dir, &feed_dir).unwrap().hash(),
+            first.hash()
+        );
+        fs::write(feed_dir.join("notes.md"), "still ignored").unwrap();
+        assert_eq!(
+            FeedCache::in_dir(&cache_dir, &feed_dir).unwrap().hash(),
+            first.hash()
+        );
+        // a different size, since modification times can be too coarse to tell quick writes apart
+        fs::write(feed_dir.join("stops.txt"), "stop_id\ns2\ns3\n").unwrap();
+        assert_ne!(
+            FeedCache::in_dir(&cache_dir, &feed_dir).unwrap().hash(),
+            first.hash()
+        );
+
+        assert_eq!(
+            FeedCache::in_dir(&cache_dir, &zip_path).unwrap().hash(),
+            FeedCache::in_dir(&cache_dir, &copy_path).unwrap().hash()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_the_most_recently_used_caches_are_kept() {
+        let dir = temp_dir("prune");
+        let start = SystemTime::now() - Duration::from_secs(1_000);
+        for i in 0..MAX_CACHED_FEEDS + 2 {
+            let path = dir.join(format!("{}.bin", i));
+            File::create(&path).unwrap();
+            // the stamps are written oldest first, so the stamps rather than the caches' own modification times must be what decides
+            write_used(&path, start + Duration::from_secs(100 - i as u64)).unwrap();
+            fs::write(dir.join(format!("{}.source", i)), i.to_string()).unwrap();
+        }
+        prune(&dir).unwrap();
+        for i in 0..MAX_CACHED_FEEDS + 2 {
+            let kept = i < MAX_CACHED_FEEDS;
+            assert_eq!(dir.join(format!("{}.bin", i)).exists(), kept);
+            assert_eq!(dir.join(format!("{}.used", i)).exists(), kept);
+            assert_eq!(dir.join(format!("{}.source", i)).exists(), kept);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
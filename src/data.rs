@@ -1,19 +1,72 @@
-use druid::im::Vector;
+use druid::im::{self, OrdMap, Vector};
 use druid::{Data, Lens, Point};
 use gtfs_structures::{
-    Agency, Availability, BikesAllowedType, ContinuousPickupDropOff, LocationType,
-    PickupDropOffType, RawGtfs, RawStopTime, RawTrip, Route, RouteType, Shape, Stop, TimepointType,
+    Agency, Availability, BikesAllowedType, Calendar, ContinuousPickupDropOff, LocationType,
+    PickupDropOffType, RawStopTime, RawTrip, Route, RouteType, Shape, Stop, TimepointType,
 };
 use rgb::RGB8;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 use std::ops::Range;
-use std::rc::Rc;
+use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::analysis::{AnalysisReport, Census};
+use crate::export::GEOJSON_LAYERS;
+use crate::geometry::{cumulative_distances, project_onto_line};
+use crate::import::Import;
+use crate::layers::GeoJsonLayer;
+use crate::library::LibraryFeed;
+use crate::loading::Loading;
+use crate::osm::RoadNetwork;
+
 mod newtypes;
 pub use newtypes::*;
+mod ranges;
+pub use ranges::*;
+mod store;
+pub use store::*;
+
+/// stops further than this many metres from their trip's shape are flagged when calculating shape_dist_traveled
+pub const MAX_STOP_SHAPE_DISTANCE: f64 = 100.;
+
+/// fields which can be set for all the stops or trips selected on the map
+pub const SELECTION_FIELDS: [&str; 11] = [
+    "name",
+    "code",
+    "zone_id",
+    "platform_code",
+    "wheelchair_boarding",
+    "trip_headsign",
+    "trip_short_name",
+    "block_id",
+    "service_id",
+    "wheelchair_accessible",
+    "bikes_allowed",
+];
+
+/// the name of a value in a radio_vec, so enum fields can be edited as text
+fn radio_name<T: PartialEq>(radio_vec: Vec<(String, T)>, value: &T) -> String {
+    radio_vec
+        .into_iter()
+        .find(|(_name, radio_value)| radio_value == value)
+        .map(|(name, _radio_value)| name)
+        .unwrap_or_default()
+}
+fn radio_value<T>(radio_vec: Vec<(String, T)>, name: Option<String>) -> Result<T, String> {
+    let name = name.unwrap_or_default();
+    let names = radio_vec
+        .iter()
+        .map(|(radio_name, _value)| radio_name.clone())
+        .collect::<Vec<_>>()
+        .join(", ");
+    radio_vec
+        .into_iter()
+        .find(|(radio_name, _value)| radio_name == &name)
+        .map(|(_name, value)| value)
+        .ok_or_else(|| format!("{} is not one of: {}", name, names))
+}
 
 pub trait ListItem {
     fn id(&self) -> String;
@@ -33,6 +86,7 @@ pub struct MyStop {
     pub live: bool,
     pub selected: bool,
     pub scroll_to_me: usize,
+    pub edited: bool,
 
     pub id: String,
     pub code: Option<String>,
@@ -55,7 +109,8 @@ pub struct MyStop {
 
     #[data(ignore)]
     #[lens(ignore)]
-    pub stop: Option<Rc<Stop>>,
+    #[serde(skip)]
+    pub stop: Option<RawIndex<Stop>>,
     // stop_time: RawStopTime,
     #[serde(skip)]
     pub latlong: Point,
@@ -66,6 +121,7 @@ impl MyStop {
             live: true,
             selected: false,
             scroll_to_me: 0,
+            edited: true,
             id: Uuid::new_v4().to_string(),
             code: None,
             name: "new stop".to_string(),
@@ -82,11 +138,39 @@ impl MyStop {
             platform_code: None,
             transfers: 0,
             pathways: 0,
-            // pub stop: Option<Rc<Stop>>,
+            // pub stop: Option<Arc<Stop>>,
             stop: None,
             latlong,
         }
     }
+    /// the value of one of SELECTION_FIELDS as text, or None if stops don't have the field
+    pub fn field_value(&self, field: &str) -> Option<Option<String>> {
+        match field {
+            "name" => Some(Some(self.name.clone())),
+            "code" => Some(self.code.clone()),
+            "zone_id" => Some(self.zone_id.clone()),
+            "platform_code" => Some(self.platform_code.clone()),
+            "wheelchair_boarding" => Some(Some(radio_name(
+                MyAvailability::radio_vec(),
+                &self.wheelchair_boarding,
+            ))),
+            _ => None,
+        }
+    }
+    pub fn set_field_value(&mut self, field: &str, value: Option<String>) -> Result<(), String> {
+        match field {
+            "name" => self.name = value.unwrap_or_default(),
+            "code" => self.code = value,
+            "zone_id" => self.zone_id = value,
+            "platform_code" => self.platform_code = value,
+            "wheelchair_boarding" => {
+                self.wheelchair_boarding = radio_value(MyAvailability::radio_vec(), value)?
+            }
+            _ => return Err(format!("stops don't have a {} field", field)),
+        }
+        self.edited = true;
+        Ok(())
+    }
 }
 impl ListItem for MyStop {
     fn id(&self) -> String {
@@ -132,10 +216,12 @@ pub struct MyStopTime {
 
     #[data(ignore)]
     #[lens(ignore)]
-    pub stop_time: Option<Rc<RawStopTime>>,
+    #[serde(skip)]
+    pub stop_time: Option<RawIndex<RawStopTime>>,
     #[data(ignore)]
     #[lens(ignore)]
-    pub stop: Option<Rc<Stop>>,
+    #[serde(skip)]
+    pub stop: Option<RawIndex<Stop>>,
     // stop_time: RawStopTime,
     pub stop_name: String,
     // (lon, lat)
@@ -164,8 +250,8 @@ impl MyStopTime {
             shape_dist_traveled: None,
             timepoint: MyTimepointType(TimepointType::Approximate),
 
-            //  stop_time: Option<Rc<RawStopTime>>,
-            //  stop: Option<Rc<Stop>>,
+            //  stop_time: Option<Arc<RawStopTime>>,
+            //  stop: Option<Arc<Stop>>,
             stop_time: None,
             stop: None,
             stop_name: "stop name".to_string(),
@@ -192,6 +278,36 @@ impl ListItem for MyStopTime {
     }
 }
 
+/// A point of a shape which has been created or edited in the app
+#[derive(Clone, Data, Debug, PartialEq, Lens, Serialize, Deserialize)]
+pub struct MyShapePoint {
+    pub longitude: f64,
+    pub latitude: f64,
+    pub sequence: usize,
+    /// metres from the first point of the shape
+    pub dist_traveled: Option<f32>,
+}
+
+/// A stop_time whose stop is too far from the trip's shape
+#[derive(Clone, Data, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ShapeDistWarning {
+    pub trip_id: String,
+    pub stop_sequence: u16,
+    pub stop_id: String,
+    /// metres from the stop to the shape
+    pub distance: f64,
+}
+
+/// A generated shape waiting to be accepted or discarded
+#[derive(Clone, Data, Debug)]
+pub struct ShapePreview {
+    pub trip_id: String,
+    /// (long, lat) coords
+    pub coords: Vector<Point>,
+    /// number of legs between stops which couldn't be routed along the network
+    pub failed_legs: usize,
+}
+
 #[derive(Clone, Data, Debug, Lens, Serialize, Deserialize)]
 pub struct MyTrip {
     pub live: bool,
@@ -213,7 +329,8 @@ pub struct MyTrip {
     pub bikes_allowed: MyBikesAllowedType,
     #[data(ignore)]
     #[lens(ignore)]
-    pub trip: Option<Rc<RawTrip>>,
+    #[serde(skip)]
+    pub trip: Option<RawIndex<RawTrip>>,
     // #[data(ignore)]
     // trip: RawTrip,
     pub name: String,
@@ -247,6 +364,41 @@ impl MyTrip {
             n_stops: 0,
         }
     }
+    /// the value of one of SELECTION_FIELDS as text, or None if trips don't have the field
+    pub fn field_value(&self, field: &str) -> Option<Option<String>> {
+        match field {
+            "trip_headsign" => Some(self.trip_headsign.clone()),
+            "trip_short_name" => Some(self.trip_short_name.clone()),
+            "block_id" => Some(self.block_id.clone()),
+            "service_id" => Some(Some(self.service_id.clone())),
+            "wheelchair_accessible" => Some(Some(radio_name(
+                MyAvailability::radio_vec(),
+                &self.wheelchair_accessible,
+            ))),
+            "bikes_allowed" => Some(Some(radio_name(
+                MyBikesAllowedType::radio_vec(),
+                &self.bikes_allowed,
+            ))),
+            _ => None,
+        }
+    }
+    pub fn set_field_value(&mut self, field: &str, value: Option<String>) -> Result<(), String> {
+        match field {
+            "trip_headsign" => self.trip_headsign = value,
+            "trip_short_name" => self.trip_short_name = value,
+            "block_id" => self.block_id = value,
+            "service_id" => self.service_id = value.unwrap_or_default(),
+            "wheelchair_accessible" => {
+                self.wheelchair_accessible = radio_value(MyAvailability::radio_vec(), value)?
+            }
+            "bikes_allowed" => {
+                self.bikes_allowed = radio_value(MyBikesAllowedType::radio_vec(), value)?
+            }
+            _ => return Err(format!("trips don't have a {} field", field)),
+        }
+        self.edited = true;
+        Ok(())
+    }
 }
 impl ListItem for MyTrip {
     fn id(&self) -> String {
@@ -326,7 +478,8 @@ pub struct MyRoute {
 
     #[lens(ignore)]
     #[data(ignore)]
-    pub route: Option<Rc<Route>>,
+    #[serde(skip)]
+    pub route: Option<RawIndex<Route>>,
     // pub trips: Vector<MyTrip>,
     pub n_trips: usize,
 }
@@ -370,13 +523,13 @@ impl ListItem for MyRoute {
     fn item_type(&self) -> String {
         "route".to_string()
     }
-    fn data_info(&self) -> String {
-        format!(
-            "{} -> {}",
-            self.route.as_ref().unwrap().short_name.clone(),
-            self.short_name
-        )
-    }
+    // fn data_info(&self) -> String {
+    //     format!(
+    //         "{} -> {}",
+    //         self.route.as_ref().unwrap().short_name.clone(),
+    //         self.short_name
+    //     )
+    // }
     fn selected(&self) -> bool {
         self.selected
     }
@@ -402,7 +555,8 @@ pub struct MyAgency {
 
     #[lens(ignore)]
     #[data(ignore)]
-    pub agency: Option<Rc<Agency>>,
+    #[serde(skip)]
+    pub agency: Option<RawIndex<Agency>>,
     pub n_stops: usize,
 }
 impl ListItem for MyAgency {
@@ -430,18 +584,68 @@ pub enum EditType {
     Update,
     Create,
 }
+/// The state of an item before and after an action, so that the action can be undone
+#[derive(Clone, Data, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ActionChange {
+    None,
+    /// (long, lat) coords of the stop
+    StopPosition {
+        stop_id: String,
+        before: (f64, f64),
+        after: (f64, f64),
+    },
+    /// None means the shape had not been edited yet, so was still the shape from the feed, or didn't exist
+    ShapePoints {
+        shape_id: String,
+        before: Option<Vector<MyShapePoint>>,
+        after: Vector<MyShapePoint>,
+    },
+    TripShape {
+        trip_id: String,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    /// one of SELECTION_FIELDS, as text
+    Field {
+        item_type: String,
+        item_id: String,
+        field: String,
+        before: Option<String>,
+        after: Option<String>,
+        /// whether the item had been edited before this change, so undoing it can restore that
+        #[serde(default)]
+        before_edited: bool,
+    },
+    /// in metres along the trip's shape
+    StopTimeShapeDist {
+        trip_id: String,
+        stop_sequence: u16,
+        before: Option<f32>,
+        after: Option<f32>,
+    },
+    /// live is false for deleted items
+    Live {
+        item_type: String,
+        item_id: String,
+        before: bool,
+        after: bool,
+    },
+    /// changes made together by a bulk action, which are undone together
+    Group(Vector<ActionChange>),
+}
 #[derive(Clone, Data, Lens, Serialize, Deserialize)]
 pub struct Action {
     pub id: usize,
     pub edit_type: EditType,
     pub item_type: String,
     pub item_id: String,
+    pub change: ActionChange,
     // todo this of course means that the edit list won't get updated when eg a field name changes
     // #[data(ignore)]
 
     // not sure how to derive Serialize for trait object
     // #[lens(ignore)]
-    // pub item_data: Option<Rc<dyn ListItem>>,
+    // pub item_data: Option<Arc<dyn ListItem>>,
 }
 impl Debug for Action {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -450,11 +654,13 @@ impl Debug for Action {
             .field("edit_type", &self.edit_type)
             .field("item_type", &self.item_type)
             .field("item_id", &self.item_id)
+            .field("change", &self.change)
             .finish()
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// The feed as it was loaded, which is never edited. This is the only copy of the raw records, the items in AppData refer back to theirs with a RawIndex (though they copy the values of the fields they edit)
+#[derive(Default, Serialize, Deserialize)]
 pub struct MyGtfs {
     pub agencies: Vec<Agency>,
     pub routes: Vec<Route>,
@@ -462,6 +668,24 @@ pub struct MyGtfs {
     pub stop_times: Vec<RawStopTime>,
     pub stops: Vec<Stop>,
     pub shapes: Option<Vec<Shape>>,
+    /// only counted by --info, it isn't linked to the trips, shown, edited or written out
+    pub calendar: Option<Vec<Calendar>>,
+}
+
+/// Something which replaces the open feed, so has to wait until any unsaved changes have been saved or discarded
+#[derive(Clone, Data, Debug, PartialEq)]
+pub enum PendingAction {
+    Close,
+    /// path to a GTFS zip or directory
+    OpenFeed(String),
+    /// path to a GTFS zip or directory, which is copied into the library then opened
+    ImportFeed(String),
+    OpenProject(String),
+    /// id of a LibraryFeed, opens the library's copy
+    OpenLibraryFeed(String),
+    /// id of a LibraryFeed, copies its source into the library again then opens it
+    ReimportLibraryFeed(String),
+    Quit,
 }
 
 #[derive(Clone, Data, Lens, Serialize, Deserialize)]
@@ -502,12 +726,45 @@ impl ZoomLevel {
     pub fn to_f64(&self) -> f64 {
         self.to_usize() as f64
     }
+    /// the next zoom level in, or the same level if already fully zoomed in
+    pub fn zoomed_in(&self) -> ZoomLevel {
+        match self {
+            ZoomLevel::One => ZoomLevel::Two,
+            ZoomLevel::Two => ZoomLevel::Five,
+            ZoomLevel::Five => ZoomLevel::Ten,
+            ZoomLevel::Ten => ZoomLevel::Twenty,
+            ZoomLevel::Twenty | ZoomLevel::Fifty => ZoomLevel::Fifty,
+        }
+    }
     /// canvas_size / (zoom_f64 * 300.)
     pub fn path_width(&self, canvas_size: f64) -> f64 {
         canvas_size / (self.to_f64() * 300.)
     }
 }
 
+/// what trip paths on the map are coloured by
+#[derive(Debug, Copy, Clone, Data, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MapColorMode {
+    Route,
+    RouteType,
+    Agency,
+    Headway,
+    Wheelchair,
+    EditStatus,
+}
+impl MapColorMode {
+    pub fn radio_group_vec() -> Vec<(String, MapColorMode)> {
+        vec![
+            ("route".to_string(), MapColorMode::Route),
+            ("route type".to_string(), MapColorMode::RouteType),
+            ("agency".to_string(), MapColorMode::Agency),
+            ("headway".to_string(), MapColorMode::Headway),
+            ("wheelchair".to_string(), MapColorMode::Wheelchair),
+            ("edit status".to_string(), MapColorMode::EditStatus),
+        ]
+    }
+}
+
 // #[derive(Clone, Data, Lens)]
 #[derive(Clone, Data, Lens, Serialize, Deserialize)]
 pub struct AppData {
@@ -519,12 +776,12 @@ pub struct AppData {
     pub show_actions: bool,
     #[data(ignore)]
     #[lens(ignore)]
-    pub gtfs: Rc<MyGtfs>,
+    pub gtfs: Arc<MyGtfs>,
 
     pub selected_agency_id: Option<Option<String>>,
     pub selected_route_id: Option<String>,
     // (index, id)
-    pub selected_trip_id: Option<(usize, String)>,
+    pub selected_trip_id: Option<String>,
     pub selected_stop_time_id: Option<(String, u16)>,
     pub hovered_stop_time_id: Option<(String, u16)>,
     pub selected_stop_id: Option<String>,
@@ -536,12 +793,23 @@ pub struct AppData {
     // #[data(ignore)]
     // #[lens(ignore)]
     // pub selected_trip_path: Option<usize>,
+    // items are never removed from agencies, routes, trips, and stops (deleted items are kept with live: false), and new ones are added at the end, so the indexes below never change, see push_route() etc
+    /// (start, end) indexes into stop_times of each trip's stop_times, which are kept together in stop_sequence order. Kept up to date by insert_stop_time()
+    #[data(ignore)]
+    #[lens(ignore)]
+    pub stop_time_range_from_trip_id: StopTimeRanges,
+    #[data(ignore)]
+    #[lens(ignore)]
+    pub agency_index_from_id: im::HashMap<Option<String>, usize>,
+    #[data(ignore)]
+    #[lens(ignore)]
+    pub route_index_from_id: im::HashMap<String, usize>,
     #[data(ignore)]
     #[lens(ignore)]
-    pub stop_time_range_from_trip_id: HashMap<String, (usize, usize)>,
+    pub trip_index_from_id: im::HashMap<String, usize>,
     #[data(ignore)]
     #[lens(ignore)]
-    pub stop_index_from_id: HashMap<String, usize>,
+    pub stop_index_from_id: im::HashMap<String, usize>,
     #[data(ignore)]
     #[lens(ignore)]
     pub shapes_range_from_shape_id: Option<HashMap<String, Range<usize>>>,
@@ -551,12 +819,100 @@ pub struct AppData {
     pub trips: Vector<MyTrip>,
     pub stop_times: Vector<MyStopTime>,
     pub stops: Vector<MyStop>,
+    /// shapes which have been created or edited, these take precedence over the shapes in gtfs
+    pub edited_shapes: OrdMap<String, Vector<MyShapePoint>>,
+    #[serde(skip)]
+    pub shape_preview: Option<ShapePreview>,
+    /// stops found to be far from their shape the last time shape_dist_traveled was calculated
+    pub shape_dist_warnings: Vector<ShapeDistWarning>,
+    #[data(ignore)]
+    #[lens(ignore)]
+    #[serde(skip)]
+    pub road_network: Option<Arc<RoadNetwork>>,
+    /// path of the OSM extract being read in the background
+    #[serde(skip)]
+    pub osm_loading: Option<String>,
     pub expanded: bool,
     pub actions: Vector<Action>,
+    /// the id of the next action, which only ever goes up so ids stay unique after actions are undone or deleted
+    pub next_action_id: usize,
     pub edits: Vector<Edit>,
 
     pub map_zoom_level: ZoomLevel,
+    /// the centre of the map view in [0, 1] space, kept in sync by MapWidget so it can be saved in projects
+    #[data(ignore)]
+    pub map_focal_point: (f64, f64),
     pub map_stop_selection_mode: bool,
+    /// stops can be dragged to new positions on the map
+    pub map_stop_move_mode: bool,
+    /// when a dragged stop is dropped, snap it to the nearest trip shape
+    pub map_snap_to_shape: bool,
+    /// the vertices of the selected trip's shape can be dragged, inserted and deleted
+    pub map_shape_edit_mode: bool,
+    /// an existing shape_id to give the selected trip with ASSIGN_SHAPE
+    pub assign_shape_id: String,
+    /// draw stop names when zoomed in
+    pub map_show_labels: bool,
+    pub map_color_mode: MapColorMode,
+    /// GeoJSON files drawn underneath the network, for comparing it with other geography
+    #[serde(skip)]
+    pub map_layers: Vector<GeoJsonLayer>,
+    /// walk radius in metres around stops for the coverage analysis
+    pub analysis_radius: usize,
+    /// census properties holding the counts, matched case insensitively
+    pub analysis_population_field: String,
+    pub analysis_jobs_field: String,
+    #[serde(skip)]
+    pub census: Option<Arc<Census>>,
+    #[serde(skip)]
+    pub analysis_report: Option<Arc<AnalysisReport>>,
+    /// stops and trips selected on the map with shift click or box/lasso selection, for bulk actions
+    pub map_selected_stop_ids: Vector<String>,
+    pub map_selected_trip_ids: Vector<String>,
+    /// one of SELECTION_FIELDS
+    pub selection_field: String,
+    pub selection_value: String,
+    /// width in pixels of exported map images
+    pub map_export_resolution: usize,
+    /// export the whole network rather than the current view
+    pub map_export_whole_network: bool,
+    /// include stops and their names in exported maps
+    pub map_export_labels: bool,
+    /// one of GEOJSON_LAYERS
+    pub geojson_export_layer: String,
+    /// export only the selected stops and trips rather than the whole network: those selected on the map, the stop or trip selected in the lists, and the trips of the selected route
+    pub export_selection: bool,
+    /// a file being imported, shown with its field mapping until it is applied or cancelled
+    #[serde(skip)]
+    pub import: Option<Import>,
+    /// the GTFS zip or directory the feed was loaded from
+    #[serde(skip)]
+    pub source_path: Option<String>,
+    /// FeedCache::hash of the source, so a project can tell if its feed has changed. None if it couldn't be hashed
+    #[serde(skip)]
+    pub source_hash: Option<String>,
+    /// the project the edits were last saved to or opened from
+    #[serde(skip)]
+    pub project_path: Option<String>,
+    /// Project::edits_fingerprint when the edits were last saved or opened
+    #[data(ignore)]
+    #[serde(skip)]
+    pub saved_edits: u64,
+    /// waiting for the user to decide what to do with unsaved changes
+    #[serde(skip)]
+    pub pending_action: Option<PendingAction>,
+    /// feeds and projects opened most recently first
+    #[serde(skip)]
+    pub recent_files: Vector<String>,
+    /// every feed which has been opened, including those whose data has been removed
+    #[serde(skip)]
+    pub library: Vector<LibraryFeed>,
+    /// set while the feed is being read in the background, the loading screen is shown instead of the network
+    #[serde(skip)]
+    pub loading: Option<Loading>,
+    /// shown above everything else until it is dismissed, for things the user asked for which couldn't be done
+    #[serde(skip)]
+    pub error_message: Option<String>,
 }
 impl ListItem for AppData {
     fn id(&self) -> String {
@@ -577,14 +933,92 @@ impl ListItem for AppData {
 }
 // vector of trips (selected, vector of stop coords)
 impl AppData {
+    /// logs the error and shows it to the user
+    pub fn show_error(&mut self, message: &str, error: impl Display) {
+        myprint!(message, ": ", error);
+        self.error_message = Some(format!("{}: {}", message, error));
+    }
+    pub fn push_action(
+        &mut self,
+        edit_type: EditType,
+        item_type: &str,
+        item_id: String,
+        change: ActionChange,
+    ) {
+        self.actions.push_back(Action {
+            id: self.next_action_id,
+            edit_type,
+            item_type: item_type.to_string(),
+            item_id,
+            change,
+        });
+        self.next_action_id += 1;
+    }
+    pub fn agency(&self, agency_id: &Option<String>) -> Option<&MyAgency> {
+        self.agencies
+            .get(*self.agency_index_from_id.get(agency_id)?)
+    }
+    pub fn route(&self, route_id: &str) -> Option<&MyRoute> {
+        self.routes.get(*self.route_index_from_id.get(route_id)?)
+    }
+    pub fn trip(&self, trip_id: &str) -> Option<&MyTrip> {
+        self.trips.get(*self.trip_index_from_id.get(trip_id)?)
+    }
+    pub fn trip_mut(&mut self, trip_id: &str) -> Option<&mut MyTrip> {
+        let trip_index = *self.trip_index_from_id.get(trip_id)?;
+        self.trips.get_mut(trip_index)
+    }
+    pub fn stop(&self, stop_id: &str) -> Option<&MyStop> {
+        self.stops.get(*self.stop_index_from_id.get(stop_id)?)
+    }
+    /// index into stop_times, searching only the trip's stop_times
+    pub fn stop_time_index(&self, trip_id: &str, stop_sequence: u16) -> Option<usize> {
+        let (start_index, end_index) = self.stop_time_range_from_trip_id.get(trip_id)?;
+        (start_index..end_index).find(|i| self.stop_times[*i].stop_sequence == stop_sequence)
+    }
+
+    /// new routes go at the end of the list, so the indexes of the others don't change
+    pub fn push_route(&mut self, route: MyRoute) {
+        self.route_index_from_id
+            .insert(route.id.clone(), self.routes.len());
+        self.routes.push_back(route);
+    }
+    /// new trips go at the end of the list, so the indexes of the others don't change
+    pub fn push_trip(&mut self, trip: MyTrip) {
+        self.trip_index_from_id
+            .insert(trip.id.clone(), self.trips.len());
+        self.trips.push_back(trip);
+    }
+    /// agency ids can be edited directly in the agency's fields, unlike the other ids, so the whole index is recreated when one changes
+    pub fn rebuild_agency_index(&mut self) {
+        self.agency_index_from_id = self
+            .agencies
+            .iter()
+            .enumerate()
+            .map(|(i, agency)| (agency.id.clone(), i))
+            .collect();
+    }
+    pub fn push_stop(&mut self, stop: MyStop) {
+        self.stop_index_from_id
+            .insert(stop.id.clone(), self.stops.len());
+        self.stops.push_back(stop);
+    }
+    /// inserts a stop_time at the given index into stop_times, which must be within or at either end of its trip's stop_times, or at the end of stop_times if it is the trip's first
+    pub fn insert_stop_time(&mut self, index: usize, stop_time: MyStopTime) {
+        debug_assert!(
+            match self.stop_time_range_from_trip_id.get(&stop_time.trip_id) {
+                Some((start_index, end_index)) => (start_index..=end_index).contains(&index),
+                None => index == self.stop_times.len(),
+            }
+        );
+        self.stop_time_range_from_trip_id.grow(&stop_time.trip_id);
+        self.stop_times.insert(index, stop_time);
+    }
+
     pub fn trip_coords_from_stop_coords(&self, trip_id: String) -> Vec<Point> {
         dbg!("make trip coords");
-        let trip = self.trips.iter().find(|trip| trip.id == trip_id).unwrap();
-        let (start_index, end_index) = self
-            .stop_time_range_from_trip_id
-            .get(&trip.id)
-            .unwrap()
-            .clone();
+        let trip = self.trip(&trip_id).unwrap();
+        let (start_index, end_index) = self.stop_time_range_from_trip_id.get(&trip.id).unwrap();
         let mut points = Vec::new();
         for i in start_index..end_index {
             let stop_time = self.stop_times.get(i).unwrap();
@@ -598,16 +1032,518 @@ impl AppData {
         points
     }
 
+    /// ids of the trips which visit the given stop
+    pub fn trip_ids_for_stop(&self, stop_id: &str) -> Vec<String> {
+        let mut trip_ids = self
+            .stop_times
+            .iter()
+            .filter(|stop_time| stop_time.stop_id == stop_id)
+            .map(|stop_time| stop_time.trip_id.clone())
+            .collect::<Vec<_>>();
+        // stop_times are grouped by trip so any duplicates will be adjacent
+        trip_ids.dedup();
+        trip_ids
+    }
+
+    /// whether the trip's path is drawn from it's stop coords rather than a shape, in which case moving one of it's stops changes the path
+    pub fn trip_path_from_stop_coords(&self, trip: &MyTrip) -> bool {
+        match &trip.shape_id {
+            Some(shape_id) => !self.has_shape(shape_id),
+            None => true,
+        }
+    }
+
+    pub fn has_shape(&self, shape_id: &str) -> bool {
+        self.edited_shapes.contains_key(shape_id)
+            || self
+                .shapes_range_from_shape_id
+                .as_ref()
+                .map_or(false, |ranges| ranges.contains_key(shape_id))
+    }
+
+    /// the points of a shape ordered by sequence, using the edited version if there is one
+    pub fn shape_points(&self, shape_id: &str) -> Vec<MyShapePoint> {
+        if let Some(points) = self.edited_shapes.get(shape_id) {
+            return points.iter().cloned().collect();
+        }
+        match (&self.gtfs.shapes, &self.shapes_range_from_shape_id) {
+            (Some(shapes), Some(ranges)) => match ranges.get(shape_id) {
+                Some(range) => {
+                    let mut points = shapes[range.start..range.end]
+                        .iter()
+                        .map(|shape| MyShapePoint {
+                            longitude: shape.longitude,
+                            latitude: shape.latitude,
+                            sequence: shape.sequence,
+                            dist_traveled: shape.dist_traveled,
+                        })
+                        .collect::<Vec<_>>();
+                    points.sort_by(|point1, point2| point1.sequence.cmp(&point2.sequence));
+                    points
+                }
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn shape_coords(&self, shape_id: &str) -> Vec<Point> {
+        self.shape_points(shape_id)
+            .iter()
+            .map(|point| Point::new(point.longitude, point.latitude))
+            .collect::<Vec<_>>()
+    }
+
+    /// replaces the points of a shape with the given (long, lat) coords, recalculating shape_dist_traveled. Returns the previous edited version of the shape, if any.
+    pub fn set_shape_points(
+        &mut self,
+        shape_id: &str,
+        coords: &[Point],
+    ) -> Option<Vector<MyShapePoint>> {
+        let points = coords
+            .iter()
+            .zip(cumulative_distances(coords))
+            .enumerate()
+            .map(|(i, (coord, dist_traveled))| MyShapePoint {
+                longitude: coord.x,
+                latitude: coord.y,
+                sequence: i + 1,
+                dist_traveled: Some(dist_traveled as f32),
+            })
+            .collect::<Vector<_>>();
+        self.edited_shapes.insert(shape_id.to_string(), points)
+    }
+
+    /// puts back a previous edited version of a shape, or None to go back to the feed's version
+    pub fn restore_shape_points(&mut self, shape_id: &str, points: Option<Vector<MyShapePoint>>) {
+        match points {
+            Some(points) => {
+                self.edited_shapes.insert(shape_id.to_string(), points);
+            }
+            None => {
+                self.edited_shapes.remove(shape_id);
+            }
+        }
+    }
+
+    /// sets the shape_id of a trip, returning the previous shape_id
+    pub fn set_trip_shape_id(
+        &mut self,
+        trip_id: &str,
+        shape_id: Option<String>,
+    ) -> Option<Option<String>> {
+        let trip = self.trip_mut(trip_id)?;
+        let previous = trip.shape_id.clone();
+        trip.shape_id = shape_id;
+        trip.edited = true;
+        Some(previous)
+    }
+
+    /// the (long, lat) coords of the stops a trip visits, in order
+    pub fn trip_stop_coords(&self, trip: &MyTrip) -> Vec<Point> {
+        match self.stop_time_range_from_trip_id.get(&trip.id) {
+            Some((start_index, end_index)) => (start_index..end_index)
+                .map(|i| {
+                    let stop_time = self.stop_times.get(i).unwrap();
+                    self.stops
+                        .get(*self.stop_index_from_id.get(&stop_time.stop_id).unwrap())
+                        .unwrap()
+                        .latlong
+                })
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        }
+    }
+
+    /// ids of the trips on the same route as the given trip which visit the same stops in the same order and don't have a shape, including the given trip
+    pub fn trips_with_same_pattern(&self, trip_id: &str) -> Vec<String> {
+        let trip = self.trip(trip_id).unwrap();
+        let stop_ids = |trip_id: &str| match self.stop_time_range_from_trip_id.get(trip_id) {
+            Some((start_index, end_index)) => (start_index..end_index)
+                .map(|i| self.stop_times.get(i).unwrap().stop_id.clone())
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+        let pattern = stop_ids(trip_id);
+        self.trips
+            .iter()
+            .filter(|other_trip| {
+                other_trip.id == trip_id
+                    || (other_trip.route_id == trip.route_id
+                        && self.trip_path_from_stop_coords(other_trip)
+                        && stop_ids(&other_trip.id) == pattern)
+            })
+            .map(|other_trip| other_trip.id.clone())
+            .collect::<Vec<_>>()
+    }
+
+    /// recalculates shape_dist_traveled in metres for the points of every shape used by a trip, then for each trip's stop_times by projecting the stops onto the shape in order. Returns the stop_times whose stops are far from the shape, and the changes made so they can be undone.
+    pub fn compute_shape_dist_traveled(
+        &mut self,
+    ) -> (Vector<ShapeDistWarning>, Vector<ActionChange>) {
+        myprint!("compute shape_dist_traveled");
+        // (coords, cumulative distances) for each shape
+        let mut shape_lines = HashMap::new();
+        for trip in self.trips.iter() {
+            if let Some(shape_id) = &trip.shape_id {
+                if !shape_lines.contains_key(shape_id) && self.has_shape(shape_id) {
+                    let coords = self.shape_coords(shape_id);
+                    let distances = cumulative_distances(&coords);
+                    shape_lines.insert(shape_id.clone(), (coords, distances));
+                }
+            }
+        }
+        // every shape and stop_time which is changed, so it can all be undone together
+        let mut changes = Vector::new();
+        // feeds use various units for shape_dist_traveled so rewrite any which aren't already in metres
+        for (shape_id, (coords, distances)) in &shape_lines {
+            let in_metres = self
+                .shape_points(shape_id)
+                .iter()
+                .zip(distances.iter())
+                .all(|(point, distance)| point.dist_traveled == Some(*distance as f32));
+            if !in_metres {
+                let before = self.set_shape_points(shape_id, coords);
+                changes.push_back(ActionChange::ShapePoints {
+                    shape_id: shape_id.clone(),
+                    before,
+                    after: self.edited_shapes.get(shape_id).unwrap().clone(),
+                });
+            }
+        }
+
+        let mut warnings = Vector::new();
+        let trips = self.trips.clone();
+        for trip in trips.iter() {
+            let (coords, distances) = match trip
+                .shape_id
+                .as_ref()
+                .and_then(|shape_id| shape_lines.get(shape_id))
+            {
+                Some(shape_line) => shape_line,
+                None => continue,
+            };
+            let (start_index, end_index) = match self.stop_time_range_from_trip_id.get(&trip.id) {
+                Some(range) => range,
+                None => continue,
+            };
+            let mut from_segment = 0;
+            for i in start_index..end_index {
+                let stop_id = self.stop_times.get(i).unwrap().stop_id.clone();
+                let stop_index = *self.stop_index_from_id.get(&stop_id).unwrap();
+                let latlong = self.stops.get(stop_index).unwrap().latlong;
+                if let Some(projection) = project_onto_line(
+                    coords,
+                    distances,
+                    latlong,
+                    from_segment,
+                    MAX_STOP_SHAPE_DISTANCE,
+                ) {
+                    from_segment = projection.segment;
+                    let stop_time = self.stop_times.get_mut(i).unwrap();
+                    let shape_dist_traveled = Some(projection.distance_along as f32);
+                    if stop_time.shape_dist_traveled != shape_dist_traveled {
+                        changes.push_back(ActionChange::StopTimeShapeDist {
+                            trip_id: trip.id.clone(),
+                            stop_sequence: stop_time.stop_sequence,
+                            before: stop_time.shape_dist_traveled,
+                            after: shape_dist_traveled,
+                        });
+                        stop_time.shape_dist_traveled = shape_dist_traveled;
+                        stop_time.edited = true;
+                    }
+                    if projection.offset > MAX_STOP_SHAPE_DISTANCE {
+                        warnings.push_back(ShapeDistWarning {
+                            trip_id: trip.id.clone(),
+                            stop_sequence: stop_time.stop_sequence,
+                            stop_id,
+                            distance: projection.offset,
+                        });
+                    }
+                }
+            }
+        }
+        myprint!("finished compute shape_dist_traveled, warnings: ", warnings.len());
+        (warnings, changes)
+    }
+
+    pub fn set_stop_time_shape_dist(
+        &mut self,
+        trip_id: &str,
+        stop_sequence: u16,
+        shape_dist_traveled: Option<f32>,
+    ) {
+        if let Some(stop_time_index) = self.stop_time_index(trip_id, stop_sequence) {
+            let stop_time = &mut self.stop_times[stop_time_index];
+            stop_time.shape_dist_traveled = shape_dist_traveled;
+            stop_time.edited = true;
+        }
+    }
+
+    /// the trip's shape if it has one, else it's stop coords
+    pub fn trip_coords(&self, trip: &MyTrip) -> Vec<Point> {
+        match &trip.shape_id {
+            Some(shape_id) if self.has_shape(shape_id) => self.shape_coords(shape_id),
+            _ => self.trip_stop_coords(trip),
+        }
+    }
+
+    /// updates the position of a stop, returning it's previous (long, lat) position
+    pub fn set_stop_position(&mut self, stop_id: &str, latlong: Point) -> Option<Point> {
+        let stop_index = *self.stop_index_from_id.get(stop_id)?;
+        let stop = self.stops.get_mut(stop_index)?;
+        let previous = stop.latlong;
+        stop.longitude = Some(latlong.x);
+        stop.latitude = Some(latlong.y);
+        stop.latlong = latlong;
+        stop.edited = true;
+        Some(previous)
+    }
+
+    /// (item type, id) of the stops and then trips selected on the map
+    pub fn selection_items(&self) -> Vec<(String, String)> {
+        self.map_selected_stop_ids
+            .iter()
+            .map(|stop_id| ("stop".to_string(), stop_id.clone()))
+            .chain(
+                self.map_selected_trip_ids
+                    .iter()
+                    .map(|trip_id| ("trip".to_string(), trip_id.clone())),
+            )
+            .collect()
+    }
+
+    fn item_field_value(
+        &self,
+        item_type: &str,
+        item_id: &str,
+        field: &str,
+    ) -> Option<Option<String>> {
+        match item_type {
+            "stop" => self
+                .stop_index_from_id
+                .get(item_id)
+                .and_then(|stop_index| self.stops.get(*stop_index))
+                .and_then(|stop| stop.field_value(field)),
+            "trip" => self.trip(item_id).and_then(|trip| trip.field_value(field)),
+            _ => None,
+        }
+    }
+
+    /// whether a stop or trip has been edited, false if there is no such item
+    pub fn item_edited(&self, item_type: &str, item_id: &str) -> bool {
+        match item_type {
+            "stop" => self
+                .stop_index_from_id
+                .get(item_id)
+                .and_then(|stop_index| self.stops.get(*stop_index))
+                .map_or(false, |stop| stop.edited),
+            "trip" => self.trip(item_id).map_or(false, |trip| trip.edited),
+            _ => false,
+        }
+    }
+
+    pub fn set_item_edited(&mut self, item_type: &str, item_id: &str, edited: bool) {
+        match item_type {
+            "stop" => {
+                if let Some(stop_index) = self.stop_index_from_id.get(item_id).copied() {
+                    self.stops[stop_index].edited = edited;
+                }
+            }
+            "trip" => {
+                if let Some(trip) = self.trip_mut(item_id) {
+                    trip.edited = edited;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// sets one of SELECTION_FIELDS on a stop or trip, returning the previous value
+    pub fn set_item_field(
+        &mut self,
+        item_type: &str,
+        item_id: &str,
+        field: &str,
+        value: Option<String>,
+    ) -> Result<Option<String>, String> {
+        let before = self
+            .item_field_value(item_type, item_id, field)
+            .ok_or_else(|| format!("{} {} has no {} field", item_type, item_id, field))?;
+        match item_type {
+            "stop" => {
+                let stop_index = *self.stop_index_from_id.get(item_id).unwrap();
+                self.stops[stop_index].set_field_value(field, value)?;
+            }
+            _ => {
+                let trip = self.trip_mut(item_id).unwrap();
+                trip.set_field_value(field, value)?;
+            }
+        }
+        Ok(before)
+    }
+
+    /// sets live on a stop or trip, returning the previous value
+    pub fn set_item_live(&mut self, item_type: &str, item_id: &str, live: bool) -> Option<bool> {
+        match item_type {
+            "stop" => {
+                let stop_index = *self.stop_index_from_id.get(item_id)?;
+                let stop = self.stops.get_mut(stop_index)?;
+                stop.edited = true;
+                Some(std::mem::replace(&mut stop.live, live))
+            }
+            "trip" => {
+                let trip = self.trip_mut(item_id)?;
+                trip.edited = true;
+                Some(std::mem::replace(&mut trip.live, live))
+            }
+            _ => None,
+        }
+    }
+
+    /// sets a field on every selected item which has that field. An empty value clears optional fields
+    pub fn set_selection_field(
+        &mut self,
+        field: &str,
+        value: &str,
+    ) -> Result<Vector<ActionChange>, String> {
+        let value = if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        };
+        let mut changes = Vector::new();
+        for (item_type, item_id) in self.selection_items() {
+            // so a stop field can be set when both stops and trips are selected
+            if self.item_field_value(&item_type, &item_id, field).is_none() {
+                continue;
+            }
+            let before_edited = self.item_edited(&item_type, &item_id);
+            let before = self.set_item_field(&item_type, &item_id, field, value.clone())?;
+            changes.push_back(ActionChange::Field {
+                item_type,
+                item_id,
+                field: field.to_string(),
+                before,
+                after: value.clone(),
+                before_edited,
+            });
+        }
+        if changes.is_empty() {
+            return Err(format!("none of the selected items have a {} field", field));
+        }
+        Ok(changes)
+    }
+
+    pub fn delete_selection(&mut self) -> Vector<ActionChange> {
+        let mut changes = Vector::new();
+        for (item_type, item_id) in self.selection_items() {
+            if let Some(true) = self.set_item_live(&item_type, &item_id, false) {
+                changes.push_back(ActionChange::Live {
+                    item_type,
+                    item_id,
+                    before: true,
+                    after: false,
+                });
+            }
+        }
+        changes
+    }
+
+    /// the selected stops and trips, and the stop_times of the selected trips, as json
+    pub fn selection_subset_json(&self) -> serde_json::Result<String> {
+        #[derive(Serialize)]
+        struct Subset<'a> {
+            stops: Vec<&'a MyStop>,
+            trips: Vec<&'a MyTrip>,
+            stop_times: Vec<&'a MyStopTime>,
+        }
+        let trips = self
+            .trips
+            .iter()
+            .filter(|trip| self.map_selected_trip_ids.contains(&trip.id))
+            .collect::<Vec<_>>();
+        let stop_times = trips
+            .iter()
+            .filter_map(|trip| self.stop_time_range_from_trip_id.get(&trip.id))
+            .flat_map(|(start, end)| start..end)
+            .map(|i| &self.stop_times[i])
+            .collect();
+        let subset = Subset {
+            stops: self
+                .map_selected_stop_ids
+                .iter()
+                .filter_map(|stop_id| self.stop_index_from_id.get(stop_id))
+                .map(|stop_index| &self.stops[*stop_index])
+                .collect(),
+            trips,
+            stop_times,
+        };
+        serde_json::to_string_pretty(&subset)
+    }
+
+    /// undo the change recorded in an action
+    pub fn revert_change(&mut self, change: &ActionChange) {
+        match change {
+            ActionChange::StopPosition {
+                stop_id, before, ..
+            } => {
+                self.set_stop_position(stop_id, Point::new(before.0, before.1));
+            }
+            ActionChange::ShapePoints {
+                shape_id, before, ..
+            } => {
+                self.restore_shape_points(shape_id, before.clone());
+            }
+            ActionChange::TripShape {
+                trip_id, before, ..
+            } => {
+                self.set_trip_shape_id(trip_id, before.clone());
+            }
+            ActionChange::Field {
+                item_type,
+                item_id,
+                field,
+                before,
+                before_edited,
+                ..
+            } => {
+                let _ = self.set_item_field(item_type, item_id, field, before.clone());
+                self.set_item_edited(item_type, item_id, *before_edited);
+            }
+            ActionChange::StopTimeShapeDist {
+                trip_id,
+                stop_sequence,
+                before,
+                ..
+            } => {
+                self.set_stop_time_shape_dist(trip_id, *stop_sequence, *before);
+            }
+            ActionChange::Live {
+                item_type,
+                item_id,
+                before,
+                ..
+            } => {
+                self.set_item_live(item_type, item_id, *before);
+            }
+            ActionChange::Group(changes) => {
+                for change in changes.iter().rev() {
+                    self.revert_change(change);
+                }
+            }
+            // nothing recorded to revert
+            ActionChange::None => {}
+        }
+    }
+
     pub fn trips_coords_from_stop_coords(&self) -> Vec<Vec<Point>> {
         dbg!("make trips coords");
         self.trips
             .iter()
             .map(|trip| {
-                let (start_index, end_index) = self
-                    .stop_time_range_from_trip_id
-                    .get(&trip.id)
-                    .unwrap()
-                    .clone();
+                let (start_index, end_index) =
+                    self.stop_time_range_from_trip_id.get(&trip.id).unwrap();
                 let stop_time_coords = self.gtfs.stop_times[start_index..end_index]
                     .iter()
                     .map(|stop_time| {
@@ -626,33 +1562,11 @@ impl AppData {
     // TODO don't need to construct MyStopTime here
     pub fn trips_paths_from_shapes_or_stop_coords(&self) -> Vec<Vec<Point>> {
         dbg!("make trip coords");
-        if let Some(shapes) = &self.gtfs.shapes {
+        if self.gtfs.shapes.is_some() || !self.edited_shapes.is_empty() {
+            // trips without a shape, eg new trips, fall back to their stop coords
             self.trips
                 .iter()
-                .map(|trip| {
-                    if let Some(shape_id) = &trip.shape_id {
-                        let range = self
-                            .shapes_range_from_shape_id
-                            .as_ref()
-                            .unwrap()
-                            .get(shape_id)
-                            .unwrap();
-                        let mut shapes = shapes[range.start..range.end]
-                            .iter()
-                            .map(|shape| {
-                                (shape.sequence, Point::new(shape.longitude, shape.latitude))
-                            })
-                            .collect::<Vec<_>>();
-                        shapes.sort_by(|shape1, shape2| shape1.0.cmp(&shape2.0));
-                        shapes
-                            .iter()
-                            .map(|(_sequence, coords)| *coords)
-                            .collect::<Vec<_>>()
-                    } else {
-                        // TODO handle new trips which don't have shapes
-                        Vec::new()
-                    }
-                })
+                .map(|trip| self.trip_coords(trip))
                 .collect::<Vec<_>>()
         } else {
             self.trips_coords_from_stop_coords()
@@ -660,15 +1574,31 @@ impl AppData {
     }
 }
 
-pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
+/// The lookups into the tables of a MyGtfs which AppData uses, built by index_feed after it has sorted the tables. They are cached along with the feed, so reopening it doesn't have to sort and index it again
+#[derive(Default, Serialize, Deserialize)]
+pub struct FeedIndex {
+    pub stop_time_range_from_trip_id: StopTimeRanges,
+    pub agency_index_from_id: im::HashMap<Option<String>, usize>,
+    pub route_index_from_id: im::HashMap<String, usize>,
+    pub trip_index_from_id: im::HashMap<String, usize>,
+    pub stop_index_from_id: im::HashMap<String, usize>,
+    pub shapes_range_from_shape_id: Option<HashMap<String, Range<usize>>>,
+    /// in the order of MyGtfs::agencies
+    pub n_routes_of_agencies: Vec<usize>,
+    /// in the order of MyGtfs::routes
+    pub n_trips_of_routes: Vec<usize>,
+}
+
+/// sorts the tables of the feed, which the items made by make_indexed_data must be in the same order as, and builds the lookups into them
+pub fn index_feed(gtfs: &mut MyGtfs) -> FeedIndex {
     // NOTE: must pay attention to when Vector<x> and gtfs.x are being sorted and ensure they are the same
-    myprint!("start make_initial_data");
-    let agencies = gtfs.agencies.as_mut().unwrap();
-    let routes = gtfs.routes.as_mut().unwrap();
-    let trips = gtfs.trips.as_mut().unwrap();
-    let stop_times = gtfs.stop_times.as_mut().unwrap();
-    let stops = gtfs.stops.as_mut().unwrap();
-    let shapes = gtfs.shapes.as_ref().map(|shapes| shapes.as_ref().unwrap());
+    myprint!("start index_feed");
+    let agencies = &mut gtfs.agencies;
+    let routes = &mut gtfs.routes;
+    let trips = &mut gtfs.trips;
+    let stop_times = &mut gtfs.stop_times;
+    let stops = &mut gtfs.stops;
+    let shapes = gtfs.shapes.as_ref();
 
     myprint!("do stop_times stuff");
     // creates stop_time_range_from_trip_id which is a hashmap where each key is a trip_id pointing to the index range of it's stop times in the sorted stop_times below
@@ -676,40 +1606,34 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
     trips.sort_by(|x1, x2| x1.id.cmp(&x2.id));
     stop_times.sort_by(|stop1, stop2| stop1.stop_sequence.cmp(&stop2.stop_sequence));
     stop_times.sort_by(|x1, x2| x1.trip_id.cmp(&x2.trip_id));
-    let mut stop_time_range_from_trip_id = HashMap::new();
-    let mut trip_start_index = 0;
-    let mut trip_end_index = 0;
-    let mut current_trip = stop_times[0].trip_id.clone();
-    let stop_times2 = stop_times.clone();
-    for stop_time in stop_times2 {
-        // when we arrive at a new section of trip_id's insert the index range into to map, update the current trip, and reset the range start index
-        if current_trip != stop_time.trip_id {
-            stop_time_range_from_trip_id
-                .insert(current_trip.clone(), (trip_start_index, trip_end_index));
-            current_trip = stop_time.trip_id.clone();
-            trip_start_index = trip_end_index;
-        }
-        trip_end_index += 1;
-    }
-    // insert final trip id
-    stop_time_range_from_trip_id.insert(current_trip.clone(), (trip_start_index, trip_end_index));
-
-    // hash map for getting a stop by stop_id
-    let mut stop_map = HashMap::new();
-    let stops2 = stops.clone();
-    stops2.iter().for_each(|stop| {
-        stop_map.insert(stop.id.clone(), stop.clone());
-    });
+    let mut stop_time_range_from_trip_id = StopTimeRanges::default();
+    // the feed is empty while the window is waiting for it to load
+    if let Some(first_stop_time) = stop_times.get(0) {
+        let mut trip_start_index = 0;
+        let mut trip_end_index = 0;
+        let mut current_trip = first_stop_time.trip_id.clone();
+        for stop_time in stop_times.iter() {
+            // when we arrive at a new section of trip_id's insert the index range into to map, update the current trip, and reset the range start index
+            if current_trip != stop_time.trip_id {
+                stop_time_range_from_trip_id
+                    .push(current_trip.clone(), trip_end_index - trip_start_index);
+                current_trip = stop_time.trip_id.clone();
+                trip_start_index = trip_end_index;
+            }
+            trip_end_index += 1;
+        }
+        // insert final trip id
+        stop_time_range_from_trip_id.push(current_trip.clone(), trip_end_index - trip_start_index);
+    }
 
     agencies.sort_by(|x1, x2| x1.name.cmp(&x2.name));
 
-    // TODO should proabably just store stops in an im hashmap not vector, since below is will break if a stop is added/removed from stops vector.
     // hash map for getting a stop by stop_id
-    let mut stop_index_from_id = HashMap::new();
-    // let stops2 = self.gtfs.stops.clone();
-    stops.iter().enumerate().for_each(|(i, stop)| {
-        stop_index_from_id.insert(stop.id.clone(), i);
-    });
+    let stop_index_from_id = stops
+        .iter()
+        .enumerate()
+        .map(|(i, stop)| (stop.id.clone(), i))
+        .collect::<im::HashMap<_, _>>();
 
     let shapes_range_from_shape_id = if let Some(shapes) = shapes {
         let mut shapes_range_from_shape_id = HashMap::new();
@@ -735,21 +1659,84 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
 
     routes.sort_by(|route1, route2| route1.short_name.cmp(&route2.short_name));
 
+    let agency_index_from_id = agencies
+        .iter()
+        .enumerate()
+        .map(|(i, agency)| (agency.id.clone(), i))
+        .collect::<im::HashMap<_, _>>();
+    let route_index_from_id = routes
+        .iter()
+        .enumerate()
+        .map(|(i, route)| (route.id.clone(), i))
+        .collect::<im::HashMap<_, _>>();
+    let trip_index_from_id = trips
+        .iter()
+        .enumerate()
+        .map(|(i, trip)| (trip.id.clone(), i))
+        .collect::<im::HashMap<_, _>>();
+
+    // counted here rather than for each agency and route as the items are made, which would go through every route for each agency and every trip for each route
+    let mut n_routes_from_agency_id = HashMap::new();
+    for route in routes.iter() {
+        *n_routes_from_agency_id.entry(&route.agency_id).or_insert(0) += 1;
+    }
+    let mut n_trips_from_route_id = HashMap::new();
+    for trip in trips.iter() {
+        *n_trips_from_route_id.entry(&trip.route_id).or_insert(0) += 1;
+    }
+    let n_routes_of_agencies = agencies
+        .iter()
+        .map(|agency| *n_routes_from_agency_id.get(&agency.id).unwrap_or(&0))
+        .collect();
+    let n_trips_of_routes = routes
+        .iter()
+        .map(|route| *n_trips_from_route_id.get(&route.id).unwrap_or(&0))
+        .collect();
+
+    FeedIndex {
+        stop_time_range_from_trip_id,
+        agency_index_from_id,
+        route_index_from_id,
+        trip_index_from_id,
+        stop_index_from_id,
+        shapes_range_from_shape_id,
+        n_routes_of_agencies,
+        n_trips_of_routes,
+    }
+}
+
+pub fn make_initial_data(mut gtfs: MyGtfs) -> AppData {
+    let index = index_feed(&mut gtfs);
+    make_indexed_data(gtfs, index)
+}
+
+/// makes the items shown in the app from a feed which index_feed has already sorted, along with the lookups it built, which is all that's left to do when the feed comes from the cache
+pub fn make_indexed_data(gtfs: MyGtfs, index: FeedIndex) -> AppData {
+    let FeedIndex {
+        stop_time_range_from_trip_id,
+        agency_index_from_id,
+        route_index_from_id,
+        trip_index_from_id,
+        stop_index_from_id,
+        shapes_range_from_shape_id,
+        n_routes_of_agencies,
+        n_trips_of_routes,
+    } = index;
+
     myprint!("create my_gtfs");
-    // NOTE must make my_gtfs after doing sorting otherwise indexes/mappings/lookups will not be correct
-    let my_gtfs = MyGtfs {
-        agencies: agencies.clone(),
-        routes: routes.clone(),
-        trips: trips.clone(),
-        stop_times: stop_times.clone(),
-        stops: stops.clone(),
-        shapes: shapes.cloned(),
-    };
+    // the records are moved rather than cloned, so the feed is only held in memory once, with the items below indexing into it
+    let my_gtfs = Arc::new(gtfs);
+    let agencies = &my_gtfs.agencies;
+    let routes = &my_gtfs.routes;
+    let trips = &my_gtfs.trips;
+    let stop_times = &my_gtfs.stop_times;
+    let stops = &my_gtfs.stops;
 
     myprint!("make agencies");
     let agencies = agencies
         .iter()
-        .map(|agency| MyAgency {
+        .enumerate()
+        .map(|(i, agency)| MyAgency {
             show_deleted: true,
             live: true,
             visible: true,
@@ -766,20 +1753,19 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
             fare_url: agency.fare_url.clone(),
             email: agency.email.clone(),
 
-            agency: Some(Rc::new(agency.clone())),
+            agency: Some(RawIndex::new(i)),
             // routes: Vector::new(),
-            n_stops: routes
-                .iter()
-                .filter(|route| route.agency_id == agency.id)
-                .count(),
+            n_stops: n_routes_of_agencies[i],
         })
         .collect::<Vector<_>>();
 
     myprint!("make stop_times");
     let stop_times = stop_times
         .iter()
-        .map(|stop_time| {
-            let stop = stop_map.get(&stop_time.stop_id).unwrap();
+        .enumerate()
+        .map(|(i, stop_time)| {
+            let stop_index = *stop_index_from_id.get(&stop_time.stop_id).unwrap();
+            let stop = &stops[stop_index];
             MyStopTime {
                 live: true,
                 selected: false,
@@ -802,15 +1788,7 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
                 shape_dist_traveled: stop_time.shape_dist_traveled.clone(),
                 timepoint: MyTimepointType(stop_time.timepoint.clone()),
 
-                // stop_time: Some(Rc::new(stop_time.clone())),
-                // stop: Some(Rc::new(
-                //     stops
-                //         .iter()
-                //         .find(|stop| stop.id == stop_time.stop_id)
-                //         .unwrap()
-                //         .clone(),
-                // )),
-                stop_time: Some(Rc::new(stop_time.clone())),
+                stop_time: Some(RawIndex::new(i)),
                 stop: None,
                 // stop_time: stop_time.clone(),
                 stop_name: stop.name.clone(),
@@ -822,8 +1800,8 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
     myprint!("make trips");
     let trips = trips
         .iter()
-        .map(|trip| {
-            // adding the RawTrip to MyTrip is the tipping point which kills performance. Maybe AppData should just be storing a u32 index of the items position in the original RawGtfs data
+        .enumerate()
+        .map(|(i, trip)| {
             MyTrip {
                 live: true,
                 visible: true,
@@ -843,7 +1821,7 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
                 wheelchair_accessible: MyAvailability(trip.wheelchair_accessible),
                 bikes_allowed: MyBikesAllowedType(trip.bikes_allowed),
 
-                trip: Some(Rc::new(trip.clone())),
+                trip: Some(RawIndex::new(i)),
                 name: trip.id.clone(),
 
                 // stops: Vector::new(),
@@ -862,7 +1840,8 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
     myprint!("make routes");
     let routes = routes
         .iter()
-        .map(|route| MyRoute {
+        .enumerate()
+        .map(|(i, route)| MyRoute {
             new_item: false,
             live: true,
             visible: true,
@@ -883,22 +1862,21 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
             continuous_pickup: MyContinuousPickupDropOff(route.continuous_pickup.clone()),
             continuous_drop_off: MyContinuousPickupDropOff(route.continuous_drop_off.clone()),
 
-            route: Some(Rc::new(route.clone())),
+            route: Some(RawIndex::new(i)),
             // trips: Vector::new(),
-            n_trips: trips
-                .iter()
-                .filter(|trip| trip.route_id == route.id)
-                .count(),
+            n_trips: n_trips_of_routes[i],
         })
         .collect::<Vector<_>>();
 
     myprint!("make stops");
     let stops = stops
         .iter()
-        .map(|stop| MyStop {
+        .enumerate()
+        .map(|(i, stop)| MyStop {
             live: true,
             selected: false,
             scroll_to_me: 0,
+            edited: false,
 
             id: stop.id.clone(),
             code: stop.code.clone(),
@@ -917,9 +1895,7 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
             transfers: stop.transfers.len(),
             pathways: stop.pathways.len(),
 
-            // this adds 4 seconds
-            // stop: Some(Rc::new(stop.clone())),
-            stop: None,
+            stop: Some(RawIndex::new(i)),
             latlong: Point::new(stop.longitude.unwrap(), stop.latitude.unwrap()),
         })
         .collect::<Vector<_>>();
@@ -931,7 +1907,7 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
         show_deleted: true,
         show_edits: false,
         show_actions: false,
-        gtfs: Rc::new(my_gtfs),
+        gtfs: my_gtfs.clone(),
 
         selected_agency_id: None,
         selected_route_id: None,
@@ -945,6 +1921,9 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
         hovered_trip_paths: Vector::new(),
         // selected_trip_path: None,
         stop_time_range_from_trip_id,
+        agency_index_from_id,
+        route_index_from_id,
+        trip_index_from_id,
         stop_index_from_id,
         shapes_range_from_shape_id,
 
@@ -953,12 +1932,107 @@ pub fn make_initial_data(gtfs: &mut RawGtfs) -> AppData {
         trips,
         stop_times,
         stops,
+        edited_shapes: OrdMap::new(),
+        shape_preview: None,
+        shape_dist_warnings: Vector::new(),
+        road_network: None,
+        osm_loading: None,
         actions: Vector::new(),
+        next_action_id: 0,
         edits: Vector::new(),
         map_zoom_level: ZoomLevel::One,
         // map_zoom_level: ZoomLevel::Two,
+        map_focal_point: (0.5, 0.5),
         map_stop_selection_mode: false,
+        map_stop_move_mode: false,
+        map_snap_to_shape: false,
+        map_shape_edit_mode: false,
+        assign_shape_id: "".to_string(),
+        map_show_labels: true,
+        map_color_mode: MapColorMode::Route,
+        map_layers: Vector::new(),
+        analysis_radius: 400,
+        analysis_population_field: "population".to_string(),
+        analysis_jobs_field: "jobs".to_string(),
+        census: None,
+        analysis_report: None,
+        map_selected_stop_ids: Vector::new(),
+        map_selected_trip_ids: Vector::new(),
+        selection_field: SELECTION_FIELDS[0].to_string(),
+        selection_value: "".to_string(),
+        map_export_resolution: 4000,
+        map_export_whole_network: false,
+        map_export_labels: false,
+        geojson_export_layer: GEOJSON_LAYERS[0].to_string(),
+        export_selection: false,
+        import: None,
+        source_path: None,
+        source_hash: None,
+        project_path: None,
+        saved_edits: 0,
+        pending_action: None,
+        recent_files: Vector::new(),
+        library: Vector::new(),
+        loading: None,
+        error_message: None,
     };
     myprint!("finish make_initial_data");
     app_data
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_with_stop() -> (AppData, String) {
+        let mut data = make_initial_data(MyGtfs::default());
+        let mut stop = MyStop::new(Point::new(0., 0.));
+        stop.edited = false;
+        let stop_id = stop.id.clone();
+        data.push_stop(stop);
+        (data, stop_id)
+    }
+
+    fn stop<'a>(data: &'a AppData, stop_id: &str) -> &'a MyStop {
+        &data.stops[*data.stop_index_from_id.get(stop_id).unwrap()]
+    }
+
+    #[test]
+    fn action_ids_are_not_reused() {
+        let (mut data, _) = data_with_stop();
+        for item_id in ["a", "b"] {
+            data.push_action(
+                EditType::Update,
+                "stop",
+                item_id.to_string(),
+                ActionChange::None,
+            );
+        }
+        data.actions.remove(0);
+        data.push_action(
+            EditType::Update,
+            "stop",
+            "c".to_string(),
+            ActionChange::None,
+        );
+        let ids = data
+            .actions
+            .iter()
+            .map(|action| action.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn reverting_a_field_restores_edited() {
+        let (mut data, stop_id) = data_with_stop();
+        data.map_selected_stop_ids.push_back(stop_id.clone());
+        let changes = data.set_selection_field("name", "renamed").unwrap();
+        assert_eq!(stop(&data, &stop_id).name, "renamed");
+        assert!(stop(&data, &stop_id).edited);
+
+        data.revert_change(&ActionChange::Group(changes));
+        assert_eq!(stop(&data, &stop_id).name, "new stop");
+        assert!(!stop(&data, &stop_id).edited);
+    }
+}
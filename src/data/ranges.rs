@@ -0,0 +1,117 @@
+use druid::im::{self, Vector};
+use serde::{Deserialize, Serialize};
+
+/// The (start, end) indexes into AppData::stop_times of each trip's stop_times. Each trip's stop_times are kept together in stop_sequence order, with the trips' blocks in a fixed order, so a block starts at the total size of the blocks before it.
+/// The sizes are kept in a Fenwick tree, which gives those totals, and takes a stop_time being added to a block, in logarithmic time, where storing the ranges themselves would mean shifting the range of every later trip
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct StopTimeRanges {
+    /// position of each trip's block
+    block_from_trip_id: im::HashMap<String, usize>,
+    /// the trip of each block, in order
+    trip_ids: Vector<String>,
+    /// node i (counting from 1) holds the total size of the blocks from i - lowest_bit(i) up to but not including i
+    tree: Vector<usize>,
+}
+impl StopTimeRanges {
+    pub fn get(&self, trip_id: &str) -> Option<(usize, usize)> {
+        let block = *self.block_from_trip_id.get(trip_id)?;
+        Some((self.size_before(block), self.size_before(block + 1)))
+    }
+
+    pub fn contains_trip(&self, trip_id: &str) -> bool {
+        self.block_from_trip_id.contains_key(trip_id)
+    }
+
+    /// each trip which has stop_times, with its range, in the order of their blocks
+    pub fn iter(&self) -> impl Iterator<Item = (&String, (usize, usize))> + '_ {
+        self.trip_ids
+            .iter()
+            .enumerate()
+            .map(move |(block, trip_id)| {
+                (
+                    trip_id,
+                    (self.size_before(block), self.size_before(block + 1)),
+                )
+            })
+    }
+
+    /// adds a block of size stop_times for a trip which doesn't have one yet, after all the others
+    pub fn push(&mut self, trip_id: String, size: usize) {
+        let node = self.tree.len() + 1;
+        // the new node covers some of the blocks already in the tree as well as its own
+        let covered = self.size_before(node - 1) - self.size_before(node - lowest_bit(node));
+        self.block_from_trip_id
+            .insert(trip_id.clone(), self.trip_ids.len());
+        self.trip_ids.push_back(trip_id);
+        self.tree.push_back(size + covered);
+    }
+
+    /// the trip's block gains a stop_time, which starts a new block if the trip has none
+    pub fn grow(&mut self, trip_id: &str) {
+        match self.block_from_trip_id.get(trip_id).copied() {
+            Some(block) => {
+                let mut node = block + 1;
+                while node <= self.tree.len() {
+                    self.tree[node - 1] += 1;
+                    node += lowest_bit(node);
+                }
+            }
+            None => self.push(trip_id.to_string(), 1),
+        }
+    }
+
+    /// total size of the first n blocks
+    fn size_before(&self, n: usize) -> usize {
+        let mut size = 0;
+        let mut node = n;
+        while node > 0 {
+            size += self.tree[node - 1];
+            node -= lowest_bit(node);
+        }
+        size
+    }
+}
+
+fn lowest_bit(i: usize) -> usize {
+    i & i.wrapping_neg()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_follow_block_sizes() {
+        let sizes = [3, 0, 5, 1, 2, 7, 4, 1, 1];
+        let mut ranges = StopTimeRanges::default();
+        for (i, size) in sizes.iter().enumerate() {
+            ranges.push(i.to_string(), *size);
+        }
+        let mut start = 0;
+        for (i, size) in sizes.iter().enumerate() {
+            assert_eq!(ranges.get(&i.to_string()), Some((start, start + size)));
+            start += size;
+        }
+        assert_eq!(ranges.get("missing"), None);
+    }
+
+    #[test]
+    fn growing_a_block_shifts_the_later_ones() {
+        let mut ranges = StopTimeRanges::default();
+        ranges.push("a".to_string(), 2);
+        ranges.push("b".to_string(), 3);
+        ranges.push("c".to_string(), 1);
+        ranges.grow("a");
+        ranges.grow("new");
+        assert_eq!(
+            ranges.iter().collect::<Vec<_>>(),
+            vec![
+                (&"a".to_string(), (0, 3)),
+                (&"b".to_string(), (3, 6)),
+                (&"c".to_string(), (6, 7)),
+                (&"new".to_string(), (7, 8)),
+            ]
+        );
+        assert!(ranges.contains_trip("new"));
+    }
+}
@@ -0,0 +1,68 @@
+use gtfs_structures::{Agency, RawStopTime, RawTrip, Route, Stop};
+use std::fmt::{self, Debug};
+use std::marker::PhantomData;
+
+use super::MyGtfs;
+
+/// A record type held in one of the tables of MyGtfs
+pub trait GtfsRecord: Sized {
+    fn table(gtfs: &MyGtfs) -> &[Self];
+}
+impl GtfsRecord for Agency {
+    fn table(gtfs: &MyGtfs) -> &[Agency] {
+        &gtfs.agencies
+    }
+}
+impl GtfsRecord for Route {
+    fn table(gtfs: &MyGtfs) -> &[Route] {
+        &gtfs.routes
+    }
+}
+impl GtfsRecord for RawTrip {
+    fn table(gtfs: &MyGtfs) -> &[RawTrip] {
+        &gtfs.trips
+    }
+}
+impl GtfsRecord for RawStopTime {
+    fn table(gtfs: &MyGtfs) -> &[RawStopTime] {
+        &gtfs.stop_times
+    }
+}
+impl GtfsRecord for Stop {
+    fn table(gtfs: &MyGtfs) -> &[Stop] {
+        &gtfs.stops
+    }
+}
+
+/// The original (unedited) version of an item, stored as its index into the single copy of the feed in MyGtfs rather than as a clone of the record. It is only a u32, so it costs no more than the pointer it replaces and needs no reference counting, but the record has to be looked up with `get` and the feed it was made from. The My* structs still hold their own copies of the editable fields, which act as the edited version over the top of this
+pub struct RawIndex<T: GtfsRecord> {
+    index: u32,
+    record: PhantomData<T>,
+}
+impl<T: GtfsRecord> RawIndex<T> {
+    pub fn new(index: usize) -> RawIndex<T> {
+        RawIndex {
+            index: index as u32,
+            record: PhantomData,
+        }
+    }
+    /// position of the record in its MyGtfs table
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+    pub fn get<'a>(&self, gtfs: &'a MyGtfs) -> &'a T {
+        &T::table(gtfs)[self.index as usize]
+    }
+}
+// implemented by hand since deriving would require T: Clone/Copy, which the index doesn't need
+impl<T: GtfsRecord> Clone for RawIndex<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T: GtfsRecord> Copy for RawIndex<T> {}
+impl<T: GtfsRecord> Debug for RawIndex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RawIndex({})", self.index)
+    }
+}
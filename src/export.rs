@@ -0,0 +1,412 @@
+use druid::Point;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::data::{AppData, MyRGB8, MyRoute, MyStop, MyTrip};
+use crate::project::VIEW_FIELDS;
+
+/// the GeoJSON layers which can be exported: stops as points, each trip's path, each distinct path of a route, and each route's paths together
+pub const GEOJSON_LAYERS: [&str; 4] = ["stops", "trips", "patterns", "routes"];
+
+/// A path followed by one or more trips of the same route
+pub struct ExportPath<'a> {
+    /// the first trip found following the path
+    pub trip: &'a MyTrip,
+    pub route: Option<&'a MyRoute>,
+    /// (long, lat)
+    pub coords: Vec<Point>,
+    pub n_trips: usize,
+}
+
+/// the stop selected in the list, or on the map
+fn is_selected_stop(data: &AppData, stop: &MyStop) -> bool {
+    data.selected_stop_id.as_ref() == Some(&stop.id)
+        || data.map_selected_stop_ids.contains(&stop.id)
+}
+
+/// the trip selected in the list, the trips of the selected route, or the trips selected on the map
+fn is_selected_trip(data: &AppData, trip: &MyTrip) -> bool {
+    data.selected_trip_id.as_ref() == Some(&trip.id)
+        || data.selected_route_id.as_ref() == Some(&trip.route_id)
+        || data.map_selected_trip_ids.contains(&trip.id)
+}
+
+/// the stops which haven't been deleted, or only those selected
+pub fn export_stops(data: &AppData, selection_only: bool) -> Vec<&MyStop> {
+    data.stops
+        .iter()
+        .filter(|stop| stop.live)
+        .filter(|stop| !selection_only || is_selected_stop(data, stop))
+        .collect()
+}
+
+/// the path of each trip which hasn't been deleted, or only the trips selected. Trips follow their shape if they have one, otherwise their stops
+pub fn export_trips(data: &AppData, selection_only: bool) -> Vec<ExportPath> {
+    data.trips
+        .iter()
+        .filter(|trip| trip.live)
+        .filter(|trip| !selection_only || is_selected_trip(data, trip))
+        .map(|trip| ExportPath {
+            trip,
+            route: data.route(&trip.route_id),
+            coords: data.trip_coords(trip),
+            n_trips: 1,
+        })
+        .filter(|path| path.coords.len() > 1)
+        .collect()
+}
+
+/// export_trips with the trips of each route which share a shape, or visit the same stops without a shape, merged into one path
+pub fn export_patterns(data: &AppData, selection_only: bool) -> Vec<ExportPath> {
+    let mut patterns: Vec<ExportPath> = Vec::new();
+    let mut pattern_index_from_key = HashMap::new();
+    for path in export_trips(data, selection_only) {
+        let key = match &path.trip.shape_id {
+            Some(shape_id) if data.has_shape(shape_id) => (
+                path.trip.route_id.clone(),
+                Some(shape_id.clone()),
+                Vec::new(),
+            ),
+            _ => {
+                let stop_ids = match data.stop_time_range_from_trip_id.get(&path.trip.id) {
+                    Some((start_index, end_index)) => (start_index..end_index)
+                        .map(|i| data.stop_times[i].stop_id.clone())
+                        .collect(),
+                    None => Vec::new(),
+                };
+                (path.trip.route_id.clone(), None, stop_ids)
+            }
+        };
+        match pattern_index_from_key.get(&key) {
+            Some(pattern_index) => patterns[*pattern_index].n_trips += 1,
+            None => {
+                pattern_index_from_key.insert(key, patterns.len());
+                patterns.push(path);
+            }
+        }
+    }
+    patterns
+}
+
+/// a GeoJSON FeatureCollection of one of GEOJSON_LAYERS
+pub fn geojson_export(
+    data: &AppData,
+    layer: &str,
+    selection_only: bool,
+) -> Result<Value, Box<dyn Error>> {
+    let features = match layer {
+        "stops" => export_stops(data, selection_only)
+            .into_iter()
+            .map(stop_feature)
+            .collect::<Result<Vec<_>, _>>()?,
+        "trips" => export_trips(data, selection_only)
+            .iter()
+            .map(|path| path_feature(path, false))
+            .collect(),
+        "patterns" => export_patterns(data, selection_only)
+            .iter()
+            .map(|path| path_feature(path, true))
+            .collect(),
+        "routes" => route_features(&export_patterns(data, selection_only)),
+        _ => {
+            return Err(format!(
+                "unknown GeoJSON layer {}, expected one of {}",
+                layer,
+                GEOJSON_LAYERS.join(", ")
+            )
+            .into())
+        }
+    };
+    Ok(json!({
+        "type": "FeatureCollection",
+        "features": features,
+    }))
+}
+
+/// a point with all the stop's fields as properties. Stops without a position have no geometry
+fn stop_feature(stop: &MyStop) -> Result<Value, serde_json::Error> {
+    let mut properties = match serde_json::to_value(stop)? {
+        Value::Object(properties) => properties,
+        _ => Map::new(),
+    };
+    // deleted stops aren't exported, so live is always true
+    for field in VIEW_FIELDS.iter().chain(["live"].iter()) {
+        properties.remove(*field);
+    }
+    let geometry = match (stop.longitude, stop.latitude) {
+        (Some(longitude), Some(latitude)) => json!({
+            "type": "Point",
+            "coordinates": [longitude, latitude],
+        }),
+        _ => Value::Null,
+    };
+    Ok(json!({
+        "type": "Feature",
+        "geometry": geometry,
+        "properties": properties,
+    }))
+}
+
+/// a LineString with the trip's and route's details. Patterns have the number of trips following them instead of the trip id
+fn path_feature(path: &ExportPath, pattern: bool) -> Value {
+    let mut properties = route_properties(&path.trip.route_id, path.route);
+    if pattern {
+        properties.insert("n_trips".to_string(), json!(path.n_trips));
+    } else {
+        properties.insert("trip_id".to_string(), json!(path.trip.id));
+    }
+    properties.insert("shape_id".to_string(), json!(path.trip.shape_id));
+    properties.insert("trip_headsign".to_string(), json!(path.trip.trip_headsign));
+    properties.insert(
+        "direction_id".to_string(),
+        serde_json::to_value(&path.trip.direction_id).unwrap_or(Value::Null),
+    );
+    json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "LineString",
+            "coordinates": coordinates(&path.coords),
+        },
+        "properties": properties,
+    })
+}
+
+/// a MultiLineString for each route, made of its patterns
+fn route_features(patterns: &[ExportPath]) -> Vec<Value> {
+    // keep the order the routes are first seen in
+    let mut route_ids = Vec::new();
+    let mut paths_from_route_id: HashMap<&str, Vec<&ExportPath>> = HashMap::new();
+    for path in patterns {
+        let route_id = path.trip.route_id.as_str();
+        if !paths_from_route_id.contains_key(route_id) {
+            route_ids.push(route_id);
+        }
+        paths_from_route_id.entry(route_id).or_default().push(path);
+    }
+    route_ids
+        .into_iter()
+        .map(|route_id| {
+            let paths = &paths_from_route_id[route_id];
+            let mut properties = route_properties(route_id, paths[0].route);
+            properties.insert(
+                "n_trips".to_string(),
+                json!(paths.iter().map(|path| path.n_trips).sum::<usize>()),
+            );
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "MultiLineString",
+                    "coordinates": paths
+                        .iter()
+                        .map(|path| coordinates(&path.coords))
+                        .collect::<Vec<_>>(),
+                },
+                "properties": properties,
+            })
+        })
+        .collect()
+}
+
+fn route_properties(route_id: &str, route: Option<&MyRoute>) -> Map<String, Value> {
+    let mut properties = Map::new();
+    properties.insert("route_id".to_string(), json!(route_id));
+    if let Some(route) = route {
+        properties.insert("agency_id".to_string(), json!(route.agency_id));
+        properties.insert("route_short_name".to_string(), json!(route.short_name));
+        properties.insert("route_long_name".to_string(), json!(route.long_name));
+        properties.insert(
+            "route_type".to_string(),
+            serde_json::to_value(&route.route_type).unwrap_or(Value::Null),
+        );
+        properties.insert("route_color".to_string(), json!(hex_color(route.color)));
+        properties.insert(
+            "route_text_color".to_string(),
+            json!(hex_color(route.text_color)),
+        );
+    }
+    properties
+}
+
+/// #rrggbb
+pub fn hex_color(color: MyRGB8) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.0.r, color.0.g, color.0.b)
+}
+
+fn coordinates(coords: &[Point]) -> Vec<[f64; 2]> {
+    coords.iter().map(|point| [point.x, point.y]).collect()
+}
+
+/// a KML document with a folder for each agency holding a folder for each route, whose patterns are drawn in the route's colour, followed by a folder of stops
+pub fn kml_export(data: &AppData, selection_only: bool) -> String {
+    let patterns = export_patterns(data, selection_only);
+
+    // keep the order the agencies and routes are first seen in
+    let mut agencies: Vec<(String, Vec<(&str, Vec<&ExportPath>)>)> = Vec::new();
+    for path in &patterns {
+        let agency_name = path
+            .route
+            .and_then(|route| data.agency(&route.agency_id))
+            .map_or("unknown agency".to_string(), |agency| agency.name.clone());
+        let agency_index = match agencies.iter().position(|(name, _)| *name == agency_name) {
+            Some(agency_index) => agency_index,
+            None => {
+                agencies.push((agency_name, Vec::new()));
+                agencies.len() - 1
+            }
+        };
+        let routes = &mut agencies[agency_index].1;
+        let route_id = path.trip.route_id.as_str();
+        match routes.iter_mut().find(|(id, _)| *id == route_id) {
+            Some((_, paths)) => paths.push(path),
+            None => routes.push((route_id, vec![path])),
+        }
+    }
+
+    let mut kml = String::new();
+    kml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    kml.push_str("<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n");
+    kml.push_str("<name>gtfs_manager export</name>\n");
+    for (_, routes) in &agencies {
+        for (route_id, paths) in routes {
+            if let Some(route) = paths[0].route {
+                kml.push_str(&format!(
+                    "<Style id=\"route-{}\"><LineStyle><color>{}</color><width>3</width></LineStyle></Style>\n",
+                    escape_xml(route_id),
+                    kml_color(route.color)
+                ));
+            }
+        }
+    }
+    for (agency_name, routes) in &agencies {
+        kml.push_str(&format!(
+            "<Folder>\n<name>{}</name>\n",
+            escape_xml(agency_name)
+        ));
+        for (route_id, paths) in routes {
+            kml.push_str(&format!(
+                "<Folder>\n<name>{}</name>\n",
+                escape_xml(&route_name(route_id, paths[0].route))
+            ));
+            for path in paths {
+                kml.push_str("<Placemark>\n");
+                kml.push_str(&format!("<name>{}</name>\n", escape_xml(&path_name(path))));
+                if path.route.is_some() {
+                    kml.push_str(&format!(
+                        "<styleUrl>#route-{}</styleUrl>\n",
+                        escape_xml(route_id)
+                    ));
+                }
+                kml.push_str("<LineString><tessellate>1</tessellate><coordinates>");
+                let coords = path
+                    .coords
+                    .iter()
+                    .map(|point| format!("{},{}", point.x, point.y))
+                    .collect::<Vec<_>>();
+                kml.push_str(&coords.join(" "));
+                kml.push_str("</coordinates></LineString>\n</Placemark>\n");
+            }
+            kml.push_str("</Folder>\n");
+        }
+        kml.push_str("</Folder>\n");
+    }
+    kml.push_str("<Folder>\n<name>stops</name>\n");
+    for stop in export_stops(data, selection_only) {
+        if let (Some(longitude), Some(latitude)) = (stop.longitude, stop.latitude) {
+            kml.push_str("<Placemark>\n");
+            kml.push_str(&format!("<name>{}</name>\n", escape_xml(&stop.name)));
+            kml.push_str(&format!(
+                "<description>{}</description>\n",
+                escape_xml(&stop_description(stop))
+            ));
+            kml.push_str(&format!(
+                "<Point><coordinates>{},{}</coordinates></Point>\n</Placemark>\n",
+                longitude, latitude
+            ));
+        }
+    }
+    kml.push_str("</Folder>\n</Document>\n</kml>\n");
+    kml
+}
+
+/// a GPX file with a waypoint for each stop and a track for each pattern. GPX has no styling so route colours are left out
+pub fn gpx_export(data: &AppData, selection_only: bool) -> String {
+    let mut gpx = String::new();
+    gpx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    gpx.push_str(
+        "<gpx version=\"1.1\" creator=\"gtfs_manager\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+    );
+    // waypoints have to come before tracks
+    for stop in export_stops(data, selection_only) {
+        if let (Some(longitude), Some(latitude)) = (stop.longitude, stop.latitude) {
+            gpx.push_str(&format!(
+                "<wpt lat=\"{}\" lon=\"{}\"><name>{}</name><desc>{}</desc></wpt>\n",
+                latitude,
+                longitude,
+                escape_xml(&stop.name),
+                escape_xml(&stop_description(stop))
+            ));
+        }
+    }
+    for path in export_patterns(data, selection_only) {
+        gpx.push_str("<trk>\n");
+        gpx.push_str(&format!(
+            "<name>{}</name>\n",
+            escape_xml(&format!(
+                "{} {}",
+                route_name(&path.trip.route_id, path.route),
+                path_name(&path)
+            ))
+        ));
+        gpx.push_str("<trkseg>\n");
+        for point in &path.coords {
+            gpx.push_str(&format!(
+                "<trkpt lat=\"{}\" lon=\"{}\"/>\n",
+                point.y, point.x
+            ));
+        }
+        gpx.push_str("</trkseg>\n</trk>\n");
+    }
+    gpx.push_str("</gpx>\n");
+    gpx
+}
+
+fn route_name(route_id: &str, route: Option<&MyRoute>) -> String {
+    match route {
+        Some(route) if !route.short_name.is_empty() && !route.long_name.is_empty() => {
+            format!("{} {}", route.short_name, route.long_name)
+        }
+        Some(route) if !route.short_name.is_empty() => route.short_name.clone(),
+        Some(route) if !route.long_name.is_empty() => route.long_name.clone(),
+        _ => route_id.to_string(),
+    }
+}
+
+/// the headsign, or the shape or trip id if there isn't one, and how many trips follow the path
+fn path_name(path: &ExportPath) -> String {
+    let name = match (&path.trip.trip_headsign, &path.trip.shape_id) {
+        (Some(headsign), _) if !headsign.is_empty() => headsign.clone(),
+        (_, Some(shape_id)) => shape_id.clone(),
+        _ => path.trip.id.clone(),
+    };
+    format!("{} ({} trips)", name, path.n_trips)
+}
+
+fn stop_description(stop: &MyStop) -> String {
+    match &stop.code {
+        Some(code) => format!("stop_id {}, stop_code {}", stop.id, code),
+        None => format!("stop_id {}", stop.id),
+    }
+}
+
+/// KML colours are aabbggrr
+fn kml_color(color: MyRGB8) -> String {
+    format!("ff{:02x}{:02x}{:02x}", color.0.b, color.0.g, color.0.r)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
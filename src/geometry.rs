@@ -0,0 +1,139 @@
+use druid::kurbo::{Line, ParamCurveNearest};
+use druid::Point;
+
+/// mean radius of the earth in metres
+const EARTH_RADIUS: f64 = 6_371_000.;
+
+/// great circle distance in metres between two (long, lat) coords
+pub fn haversine_distance(a: Point, b: Point) -> f64 {
+    let lat_a = a.y.to_radians();
+    let lat_b = b.y.to_radians();
+    let delta_lat = (b.y - a.y).to_radians();
+    let delta_long = (b.x - a.x).to_radians();
+    let h = (delta_lat / 2.).sin().powi(2)
+        + lat_a.cos() * lat_b.cos() * (delta_long / 2.).sin().powi(2);
+    2. * EARTH_RADIUS * h.sqrt().asin()
+}
+
+/// distance in metres from the first coord to each coord along a line of (long, lat) coords
+pub fn cumulative_distances(coords: &[Point]) -> Vec<f64> {
+    let mut total = 0.;
+    let mut distances = Vec::with_capacity(coords.len());
+    for (i, coord) in coords.iter().enumerate() {
+        if i > 0 {
+            total += haversine_distance(coords[i - 1], *coord);
+        }
+        distances.push(total);
+    }
+    distances
+}
+
+/// converts a (long, lat) coord to approximate metres east and north of origin. Only accurate near origin, but this is enough for projecting stops onto nearby shapes
+pub fn to_local_metres(coord: Point, origin: Point) -> Point {
+    let metres_per_degree = EARTH_RADIUS.to_radians();
+    Point::new(
+        (coord.x - origin.x) * metres_per_degree * origin.y.to_radians().cos(),
+        (coord.y - origin.y) * metres_per_degree,
+    )
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct LineProjection {
+    /// index of the segment the coord was projected onto
+    pub segment: usize,
+    /// metres along the line from it's first coord
+    pub distance_along: f64,
+    /// metres from the coord to the line
+    pub offset: f64,
+}
+
+/// projects a (long, lat) coord onto a line of (long, lat) coords, only considering segments from from_segment onwards so that consecutive stops progress along the line. `distances` are the cumulative distances of the line's coords.
+/// The first part of the line within max_offset is preferred over a closer part further along, so that stops on lines which double back on themselves aren't matched to the wrong direction.
+pub fn project_onto_line(
+    coords: &[Point],
+    distances: &[f64],
+    coord: Point,
+    from_segment: usize,
+    max_offset: f64,
+) -> Option<LineProjection> {
+    let mut best: Option<LineProjection> = None;
+    for i in from_segment..coords.len().saturating_sub(1) {
+        let line = Line::new(
+            to_local_metres(coords[i], coord),
+            to_local_metres(coords[i + 1], coord),
+        );
+        let nearest = line.nearest(Point::ORIGIN, 1.);
+        let projection = LineProjection {
+            segment: i,
+            distance_along: distances[i] + (distances[i + 1] - distances[i]) * nearest.t,
+            offset: nearest.distance_sq.sqrt(),
+        };
+        match best {
+            Some(best_projection) if projection.offset >= best_projection.offset => {
+                if best_projection.offset < max_offset {
+                    break;
+                }
+            }
+            _ => best = Some(projection),
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(coords: &[(f64, f64)]) -> (Vec<Point>, Vec<f64>) {
+        let coords = coords
+            .iter()
+            .map(|(long, lat)| Point::new(*long, *lat))
+            .collect::<Vec<_>>();
+        let distances = cumulative_distances(&coords);
+        (coords, distances)
+    }
+
+    #[test]
+    fn projects_onto_nearest_point_of_segment() {
+        let (coords, distances) = line(&[(0., 0.), (0.01, 0.), (0.02, 0.)]);
+        let projection =
+            project_onto_line(&coords, &distances, Point::new(0.005, 0.0001), 0, 50.).unwrap();
+        assert_eq!(projection.segment, 0);
+        assert!((projection.distance_along - distances[1] / 2.).abs() < 0.01);
+        // 0.0001 degrees of latitude is about 11m
+        assert!((projection.offset - 11.12).abs() < 0.1);
+    }
+
+    #[test]
+    fn only_considers_segments_from_from_segment() {
+        let (coords, distances) = line(&[(0., 0.), (0.01, 0.), (0.02, 0.)]);
+        let projection =
+            project_onto_line(&coords, &distances, Point::new(0.005, 0.), 1, 50.).unwrap();
+        assert_eq!(projection.segment, 1);
+        assert!((projection.distance_along - distances[1]).abs() < 0.01);
+        assert!(projection.offset > 500.);
+    }
+
+    #[test]
+    fn prefers_first_part_of_line_within_max_offset() {
+        // out along the equator then back 20m to the north
+        let (coords, distances) = line(&[(0., 0.), (0.005, 0.), (0.01, 0.), (0., 0.0002)]);
+        // on the way back, but within max_offset of the way out
+        let stop = Point::new(0.0025, 0.00015);
+        let projection = project_onto_line(&coords, &distances, stop, 0, 50.).unwrap();
+        assert_eq!(projection.segment, 0);
+        assert!(projection.distance_along < distances[1]);
+
+        // with nothing within max_offset the closest part is used
+        let projection = project_onto_line(&coords, &distances, stop, 0, 1.).unwrap();
+        assert_eq!(projection.segment, 2);
+        assert!(projection.distance_along > distances[2]);
+    }
+
+    #[test]
+    fn lines_without_segments_have_no_projection() {
+        let (coords, distances) = line(&[(0., 0.)]);
+        assert!(project_onto_line(&coords, &distances, Point::ORIGIN, 0, 50.).is_none());
+        assert!(project_onto_line(&[], &[], Point::ORIGIN, 0, 50.).is_none());
+    }
+}
@@ -0,0 +1,383 @@
+use druid::im::Vector;
+use druid::{Data, Lens, Point};
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::data::{ActionChange, AppData, MyStop};
+use crate::layers::{features_from_file, LayerFeature, LayerGeometry};
+
+/// stop fields which can be imported. Position comes from the geometry of GeoJSON points, or latitude and longitude columns of CSVs
+pub const STOP_IMPORT_FIELDS: [&str; 8] = [
+    "id",
+    "code",
+    "name",
+    "zone_id",
+    "platform_code",
+    "wheelchair_boarding",
+    "latitude",
+    "longitude",
+];
+/// shape fields which can be imported. The shape is assigned to the trip if trip_id is mapped
+pub const SHAPE_IMPORT_FIELDS: [&str; 2] = ["shape_id", "trip_id"];
+/// stop fields which are updated on existing stops, since they are the fields which can be undone
+const STOP_UPDATE_FIELDS: [&str; 5] = [
+    "code",
+    "name",
+    "zone_id",
+    "platform_code",
+    "wheelchair_boarding",
+];
+/// other column names which are recognised as each field, compared case insensitively
+const FIELD_ALIASES: [(&str, &[&str]); 6] = [
+    ("id", &["stop_id"]),
+    ("code", &["stop_code"]),
+    ("name", &["stop_name"]),
+    ("latitude", &["lat", "stop_lat", "y"]),
+    ("longitude", &["lon", "lng", "long", "stop_lon", "x"]),
+    ("platform_code", &["platform"]),
+];
+
+#[derive(Clone, Copy, Data, Debug, PartialEq)]
+pub enum ImportKind {
+    Stops,
+    Shapes,
+}
+impl Default for ImportKind {
+    fn default() -> Self {
+        ImportKind::Stops
+    }
+}
+
+/// Which column of the imported file is used for a field
+#[derive(Clone, Data, Default, Lens)]
+pub struct FieldMapping {
+    pub field: String,
+    /// empty if the field isn't imported
+    pub column: String,
+    /// all the file's columns, so each mapping can offer them
+    pub columns: Arc<Vec<String>>,
+}
+
+/// A CSV or GeoJSON file which has been read, waiting for its columns to be mapped to fields before its features are added to the feed
+#[derive(Clone, Data, Default, Lens)]
+pub struct Import {
+    /// the file name
+    pub name: String,
+    pub kind: ImportKind,
+    pub mappings: Vector<FieldMapping>,
+    /// an existing stop whose "id" or "code" matches is updated, rather than a new stop created. Empty to always create stops
+    pub match_field: String,
+    pub features: Arc<Vec<LayerFeature>>,
+    /// set if the features couldn't be imported with the current mapping
+    pub error: Option<String>,
+}
+impl Import {
+    /// reads a CSV, or a GeoJSON file, guessing which column is which field from their names
+    pub fn from_file(path: &Path, kind: ImportKind) -> Result<Import, Box<dyn Error>> {
+        let is_csv = path
+            .extension()
+            .map(|extension| extension.eq_ignore_ascii_case("csv"))
+            .unwrap_or(false);
+        let features = match (kind, is_csv) {
+            (ImportKind::Stops, true) => csv_features(path)?,
+            (ImportKind::Shapes, true) => {
+                return Err("shapes can only be imported from GeoJSON LineStrings".into())
+            }
+            (_, false) => features_from_file(path)?,
+        };
+        if features.is_empty() {
+            return Err(format!("{} has no features", path.display()).into());
+        }
+        let columns = Arc::new(
+            features
+                .iter()
+                .flat_map(|feature| feature.properties.iter().map(|(key, _value)| key.clone()))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>(),
+        );
+        let fields = match kind {
+            ImportKind::Stops => &STOP_IMPORT_FIELDS[..],
+            ImportKind::Shapes => &SHAPE_IMPORT_FIELDS[..],
+        };
+        let mappings = fields
+            .iter()
+            .map(|field| FieldMapping {
+                field: field.to_string(),
+                column: guess_column(field, &columns).unwrap_or_default(),
+                columns: columns.clone(),
+            })
+            .collect::<Vector<_>>();
+        let match_field = match kind {
+            ImportKind::Stops => "id".to_string(),
+            ImportKind::Shapes => "".to_string(),
+        };
+        Ok(Import {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "import".to_string()),
+            kind,
+            mappings,
+            match_field,
+            features: Arc::new(features),
+            error: None,
+        })
+    }
+
+    /// the feature's value of the column mapped to field, None if the field isn't mapped or the value is empty
+    fn value(&self, feature: &LayerFeature, field: &str) -> Option<String> {
+        let column = &self
+            .mappings
+            .iter()
+            .find(|mapping| mapping.field == field)?
+            .column;
+        feature
+            .properties
+            .iter()
+            .find(|(key, _value)| key == column)
+            .map(|(_key, value)| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn is_mapped(&self, field: &str) -> bool {
+        self.mappings
+            .iter()
+            .any(|mapping| mapping.field == field && !mapping.column.is_empty())
+    }
+
+    /// adds the features to the feed, returning the changes made so they can be undone together. Nothing is changed if any feature can't be imported
+    pub fn apply(&self, data: &mut AppData) -> Result<Vector<ActionChange>, String> {
+        let mut changes = Vector::new();
+        let result = match self.kind {
+            ImportKind::Stops => self.apply_stops(data, &mut changes),
+            ImportKind::Shapes => self.apply_shapes(data, &mut changes),
+        };
+        match result {
+            Ok(()) => Ok(changes),
+            Err(error) => {
+                data.revert_change(&ActionChange::Group(changes));
+                Err(error)
+            }
+        }
+    }
+
+    /// empty values are skipped rather than clearing the field
+    fn apply_stops(
+        &self,
+        data: &mut AppData,
+        changes: &mut Vector<ActionChange>,
+    ) -> Result<(), String> {
+        if !self.match_field.is_empty() && !self.is_mapped(&self.match_field) {
+            return Err(format!(
+                "stops are matched by {}, but no column is mapped to it",
+                self.match_field
+            ));
+        }
+        let stop_id_from_code = data
+            .stops
+            .iter()
+            .filter(|stop| stop.live)
+            .filter_map(|stop| stop.code.clone().map(|code| (code, stop.id.clone())))
+            .collect::<HashMap<_, _>>();
+        for (i, feature) in self.features.iter().enumerate() {
+            let row_error = |error: String| format!("feature {}: {}", i + 1, error);
+            let position = self.position(feature).map_err(row_error)?;
+            let existing_id = match self.match_field.as_str() {
+                "id" => self
+                    .value(feature, "id")
+                    .filter(|id| data.stop(id).map_or(false, |stop| stop.live)),
+                "code" => self
+                    .value(feature, "code")
+                    .and_then(|code| stop_id_from_code.get(&code).cloned()),
+                _ => None,
+            };
+            match existing_id {
+                Some(stop_id) => {
+                    for field in STOP_UPDATE_FIELDS {
+                        let value = self.value(feature, field);
+                        if value.is_none() {
+                            continue;
+                        }
+                        let before_edited = data.item_edited("stop", &stop_id);
+                        let before = data
+                            .set_item_field("stop", &stop_id, field, value.clone())
+                            .map_err(row_error)?;
+                        if before != value {
+                            changes.push_back(ActionChange::Field {
+                                item_type: "stop".to_string(),
+                                item_id: stop_id.clone(),
+                                field: field.to_string(),
+                                before,
+                                after: value,
+                                before_edited,
+                            });
+                        } else {
+                            // nothing changed, so the stop is only as edited as it was
+                            data.set_item_edited("stop", &stop_id, before_edited);
+                        }
+                    }
+                    if let Some(position) = position {
+                        let before = data.set_stop_position(&stop_id, position).unwrap();
+                        if before != position {
+                            changes.push_back(ActionChange::StopPosition {
+                                stop_id: stop_id.clone(),
+                                before: (before.x, before.y),
+                                after: (position.x, position.y),
+                            });
+                        }
+                    }
+                }
+                None => {
+                    let position = position
+                        .ok_or_else(|| row_error("new stops need a position".to_string()))?;
+                    let mut stop = MyStop::new(position);
+                    // keep the imported id unless a stop already has it
+                    if let Some(id) = self.value(feature, "id") {
+                        if data.stop(&id).is_none() {
+                            stop.id = id;
+                        }
+                    }
+                    for field in STOP_UPDATE_FIELDS {
+                        if let Some(value) = self.value(feature, field) {
+                            stop.set_field_value(field, Some(value))
+                                .map_err(row_error)?;
+                        }
+                    }
+                    // a new stop is recorded as going from deleted to live, so undoing it deletes it again
+                    changes.push_back(ActionChange::Live {
+                        item_type: "stop".to_string(),
+                        item_id: stop.id.clone(),
+                        before: false,
+                        after: true,
+                    });
+                    data.push_stop(stop);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// the (long, lat) of a GeoJSON point, or of the latitude and longitude columns. None if the feature has neither
+    fn position(&self, feature: &LayerFeature) -> Result<Option<Point>, String> {
+        let point = feature
+            .geometries
+            .iter()
+            .find_map(|geometry| match geometry {
+                LayerGeometry::Point(point) => Some(*point),
+                _ => None,
+            });
+        if point.is_some() {
+            return Ok(point);
+        }
+        let coord = |field: &str| {
+            self.value(feature, field)
+                .map(|value| {
+                    value
+                        .parse::<f64>()
+                        .map_err(|_| format!("{} {} is not a number", field, value))
+                })
+                .transpose()
+        };
+        match (coord("longitude")?, coord("latitude")?) {
+            (Some(longitude), Some(latitude)) => Ok(Some(Point::new(longitude, latitude))),
+            _ => Ok(None),
+        }
+    }
+
+    fn apply_shapes(
+        &self,
+        data: &mut AppData,
+        changes: &mut Vector<ActionChange>,
+    ) -> Result<(), String> {
+        for feature in self.features.iter() {
+            let lines = feature
+                .geometries
+                .iter()
+                .filter_map(|geometry| match geometry {
+                    LayerGeometry::LineString(coords) if coords.len() > 1 => Some(coords),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let shape_id = self.value(feature, "shape_id");
+            for (i, coords) in lines.iter().enumerate() {
+                let shape_id = match &shape_id {
+                    // each line of a MultiLineString becomes its own shape
+                    Some(shape_id) if lines.len() > 1 => format!("{}-{}", shape_id, i + 1),
+                    Some(shape_id) => shape_id.clone(),
+                    None => Uuid::new_v4().to_string(),
+                };
+                let before = data.set_shape_points(&shape_id, coords);
+                let after = data.edited_shapes.get(&shape_id).unwrap().clone();
+                changes.push_back(ActionChange::ShapePoints {
+                    shape_id: shape_id.clone(),
+                    before,
+                    after,
+                });
+                // only the first line can be the trip's shape
+                if i > 0 {
+                    continue;
+                }
+                if let Some(trip_id) = self.value(feature, "trip_id") {
+                    match data.set_trip_shape_id(&trip_id, Some(shape_id.clone())) {
+                        Some(before) => changes.push_back(ActionChange::TripShape {
+                            trip_id,
+                            before,
+                            after: Some(shape_id),
+                        }),
+                        None => {
+                            myprint!("imported shape's trip doesn't exist: ", trip_id);
+                        }
+                    }
+                }
+            }
+        }
+        if changes.is_empty() {
+            return Err("none of the features are LineStrings".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// the column named the same as the field, or one of its aliases
+fn guess_column(field: &str, columns: &[String]) -> Option<String> {
+    let aliases = FIELD_ALIASES
+        .iter()
+        .find(|(alias_field, _aliases)| *alias_field == field)
+        .map_or(&[][..], |(_field, aliases)| *aliases);
+    std::iter::once(field)
+        .chain(aliases.iter().copied())
+        .find_map(|name| {
+            columns
+                .iter()
+                .find(|column| column.eq_ignore_ascii_case(name))
+                .cloned()
+        })
+}
+
+/// each row as a feature without geometry, since which columns hold the position is only known once they are mapped
+fn csv_features(path: &Path) -> Result<Vec<LayerFeature>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let mut features = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        features.push(LayerFeature {
+            geometries: Vec::new(),
+            properties: headers
+                .iter()
+                // a byte order mark would otherwise stop the first column matching its field
+                .map(|header| header.trim_start_matches('\u{feff}'))
+                .zip(record.iter())
+                .map(|(header, value)| (header.to_string(), value.to_string()))
+                .collect(),
+        });
+    }
+    Ok(features)
+}
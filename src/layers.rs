@@ -0,0 +1,209 @@
+use druid::{Color, Data, Lens, Point};
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// colours given to layers in the order they are loaded, and cycled through by the layer colour button
+pub const LAYER_COLORS: [(u8, u8, u8); 6] = [
+    (230, 85, 13),
+    (49, 130, 189),
+    (117, 107, 177),
+    (49, 163, 84),
+    (214, 39, 40),
+    (99, 99, 99),
+];
+
+/// A single geometry of a GeoJSON feature, in (long, lat) coords. Multi geometries and geometry collections are flattened into several of these
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerGeometry {
+    Point(Point),
+    LineString(Vec<Point>),
+    /// the exterior ring followed by any holes
+    Polygon(Vec<Vec<Point>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerFeature {
+    pub geometries: Vec<LayerGeometry>,
+    /// (key, value as text) sorted by key
+    pub properties: Vec<(String, String)>,
+}
+
+/// A read only GeoJSON file drawn underneath the network on the map
+#[derive(Clone, Data, Lens)]
+pub struct GeoJsonLayer {
+    pub id: String,
+    /// the file name
+    pub name: String,
+    pub visible: bool,
+    /// index into LAYER_COLORS
+    pub color_index: usize,
+    /// line width as a multiple of the width of the network's paths
+    pub stroke_width: f64,
+    /// 0 for polygon outlines only
+    pub fill_opacity: f64,
+    pub features: Arc<Vec<LayerFeature>>,
+}
+impl GeoJsonLayer {
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        color_index: usize,
+    ) -> Result<GeoJsonLayer, Box<dyn Error>> {
+        let path = path.as_ref();
+        let features = features_from_file(path)?;
+        Ok(GeoJsonLayer {
+            id: Uuid::new_v4().to_string(),
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| "layer".to_string()),
+            visible: true,
+            color_index: color_index % LAYER_COLORS.len(),
+            stroke_width: 1.,
+            fill_opacity: 0.2,
+            features: Arc::new(features),
+        })
+    }
+
+    pub fn color(&self) -> Color {
+        let (r, g, b) = LAYER_COLORS[self.color_index % LAYER_COLORS.len()];
+        Color::rgb8(r, g, b)
+    }
+}
+
+/// the features of a GeoJSON FeatureCollection, Feature, or geometry file
+pub fn features_from_file(path: impl AsRef<Path>) -> Result<Vec<LayerFeature>, Box<dyn Error>> {
+    let value: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let mut features = Vec::new();
+    parse_object(&value, &mut features)?;
+    Ok(features)
+}
+
+/// adds the features in a FeatureCollection, Feature, or bare geometry to features
+fn parse_object(value: &Value, features: &mut Vec<LayerFeature>) -> Result<(), Box<dyn Error>> {
+    match value.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => {
+            let collection = value
+                .get("features")
+                .and_then(Value::as_array)
+                .ok_or("FeatureCollection has no features array")?;
+            for feature in collection {
+                parse_object(feature, features)?;
+            }
+        }
+        Some("Feature") => {
+            let mut geometries = Vec::new();
+            // unlocated features have a null geometry
+            if let Some(geometry) = value.get("geometry").filter(|geometry| !geometry.is_null()) {
+                parse_geometry(geometry, &mut geometries)?;
+            }
+            let properties = value
+                .get("properties")
+                .and_then(Value::as_object)
+                .map(|properties| {
+                    properties
+                        .iter()
+                        .map(|(key, value)| (key.clone(), property_text(value)))
+                        .collect()
+                })
+                .unwrap_or_default();
+            features.push(LayerFeature {
+                geometries,
+                properties,
+            });
+        }
+        Some(_) => {
+            let mut geometries = Vec::new();
+            parse_geometry(value, &mut geometries)?;
+            features.push(LayerFeature {
+                geometries,
+                properties: Vec::new(),
+            });
+        }
+        None => return Err("not a GeoJSON object".into()),
+    }
+    Ok(())
+}
+
+fn parse_geometry(
+    value: &Value,
+    geometries: &mut Vec<LayerGeometry>,
+) -> Result<(), Box<dyn Error>> {
+    let geometry_type = value
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or("geometry has no type")?;
+    if geometry_type == "GeometryCollection" {
+        let collection = value
+            .get("geometries")
+            .and_then(Value::as_array)
+            .ok_or("GeometryCollection has no geometries array")?;
+        for geometry in collection {
+            parse_geometry(geometry, geometries)?;
+        }
+        return Ok(());
+    }
+
+    let coordinates = value
+        .get("coordinates")
+        .ok_or_else(|| format!("{} has no coordinates", geometry_type))?;
+    match geometry_type {
+        "Point" => geometries.push(LayerGeometry::Point(position(coordinates)?)),
+        "MultiPoint" => {
+            for point in array(coordinates)? {
+                geometries.push(LayerGeometry::Point(position(point)?));
+            }
+        }
+        "LineString" => geometries.push(LayerGeometry::LineString(positions(coordinates)?)),
+        "MultiLineString" => {
+            for line in array(coordinates)? {
+                geometries.push(LayerGeometry::LineString(positions(line)?));
+            }
+        }
+        "Polygon" => geometries.push(LayerGeometry::Polygon(rings(coordinates)?)),
+        "MultiPolygon" => {
+            for polygon in array(coordinates)? {
+                geometries.push(LayerGeometry::Polygon(rings(polygon)?));
+            }
+        }
+        _ => return Err(format!("unknown geometry type: {}", geometry_type).into()),
+    }
+    Ok(())
+}
+
+fn array(value: &Value) -> Result<&Vec<Value>, Box<dyn Error>> {
+    value
+        .as_array()
+        .ok_or_else(|| format!("expected an array of coordinates, found: {}", value).into())
+}
+
+/// a [long, lat] position, ignoring any altitude
+fn position(value: &Value) -> Result<Point, Box<dyn Error>> {
+    let position = array(value)?;
+    match (
+        position.get(0).and_then(Value::as_f64),
+        position.get(1).and_then(Value::as_f64),
+    ) {
+        (Some(long), Some(lat)) => Ok(Point::new(long, lat)),
+        _ => Err(format!("invalid position: {}", value).into()),
+    }
+}
+
+fn positions(value: &Value) -> Result<Vec<Point>, Box<dyn Error>> {
+    array(value)?.iter().map(position).collect()
+}
+
+fn rings(value: &Value) -> Result<Vec<Vec<Point>>, Box<dyn Error>> {
+    array(value)?.iter().map(positions).collect()
+}
+
+fn property_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => "".to_string(),
+        _ => value.to_string(),
+    }
+}
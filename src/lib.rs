@@ -1,13 +1,14 @@
 // ignore unused warnings while prototyping
 // #![allow(unused)]
 
+/// logs to stderr, so it doesn't mix with what the command line modes print to stdout
 macro_rules! myprint {
     ($($args: expr),*) => {
-        print!("{} [{}:{}] ", chrono::Utc::now().time(), file!(), line!());
+        eprint!("{} [{}:{}] ", chrono::Utc::now().time(), file!(), line!());
         $(
-            print!("{}", $args);
+            eprint!("{}", $args);
         )*
-        println!("");
+        eprintln!("");
     }
 }
 // pub(crate) use myprint;
@@ -21,6 +22,47 @@ pub use map::MapWidget;
 mod data;
 pub use data::*;
 
+mod geometry;
+pub use geometry::*;
+
+mod osm;
+pub use osm::*;
+
+mod layers;
+pub use layers::*;
+
+mod analysis;
+pub use analysis::*;
+
+mod export;
+pub use export::*;
+
+mod import;
+pub use import::*;
+
+mod cache;
+pub use cache::*;
+
+mod project;
+pub use project::*;
+
+mod library;
+pub use library::*;
+
+mod source;
+pub use source::*;
+
+mod xml_feed;
+
+mod loading;
+pub use loading::*;
+
+mod recent;
+pub use recent::*;
+
+mod menu;
+pub use menu::*;
+
 mod views;
 pub use views::*;
 
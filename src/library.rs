@@ -0,0 +1,272 @@
+use chrono::Utc;
+use druid::im::Vector;
+use druid::{Data, Lens};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::cache::FeedCache;
+use crate::data::AppData;
+use crate::source::{feed_directory, is_feed_file, is_stdin, FeedSource};
+
+/// A feed which has been imported into the library. The library keeps its own copy of the feed, which can be removed to free up space while keeping the record, so the feed is still listed and can be imported again from its source
+#[derive(Clone, Data, Lens, Serialize, Deserialize)]
+pub struct LibraryFeed {
+    pub id: String,
+    pub name: String,
+    /// names of the feed's agencies
+    pub agency: String,
+    /// the path or URL the feed was imported from
+    pub source: String,
+    /// the library's copy of the feed, None if the data has been removed, leaving a ghost record
+    pub data_path: Option<String>,
+    /// FeedCache::hash of the source when it was copied, so reimporting an unchanged feed doesn't copy it again
+    #[serde(default)]
+    pub source_hash: Option<String>,
+    /// feed_start_date and feed_end_date from feed_info.txt, as YYYYMMDD
+    pub start_date: Option<String>,
+    pub end_date: Option<String>,
+    /// in bytes
+    pub size: u64,
+    pub imported: String,
+    pub last_opened: String,
+}
+impl LibraryFeed {
+    /// copies the feed at source into the library, updating the existing record if it is being reimported. The copy is skipped if the library already has the same version of the feed. local is where the feed was read from, which is a download if source is a URL, otherwise source itself
+    pub fn import(
+        source: &Path,
+        local: &Path,
+        existing: Option<LibraryFeed>,
+        data: &AppData,
+    ) -> Result<LibraryFeed, Box<dyn Error>> {
+        let source_hash = FeedCache::for_source(local)?.map(|cache| cache.hash().to_string());
+        if let Some(existing) = &existing {
+            let has_data = existing
+                .data_path
+                .as_ref()
+                .map_or(false, |data_path| Path::new(data_path).exists());
+            if has_data && source_hash.is_some() && existing.source_hash == source_hash {
+                let mut feed = existing.clone();
+                feed.touch();
+                return Ok(feed);
+            }
+        }
+
+        let feeds_dir = library_dir().ok_or("no directory to keep the library in")?;
+        fs::create_dir_all(&feeds_dir)?;
+        let id = existing
+            .as_ref()
+            .map_or_else(|| Uuid::new_v4().to_string(), |feed| feed.id.clone());
+
+        let (data_path, size) = if local.is_dir() {
+            let data_path = feeds_dir.join(&id);
+            fs::create_dir_all(&data_path)?;
+            let mut size = 0;
+            for entry in fs::read_dir(feed_directory(local))? {
+                let path = entry?.path();
+                if is_feed_file(&path) {
+                    size += fs::copy(&path, data_path.join(path.file_name().unwrap()))?;
+                }
+            }
+            (data_path, size)
+        } else {
+            // keep the extension so TransXChange and NeTEx files are still read as XML
+            let extension = local.extension().map_or_else(
+                || "zip".to_string(),
+                |extension| extension.to_string_lossy().to_lowercase(),
+            );
+            let data_path = feeds_dir.join(format!("{}.{}", id, extension));
+            let size = fs::copy(local, &data_path)?;
+            (data_path, size)
+        };
+        let (start_date, end_date) = match read_feed_dates(&data_path) {
+            Ok(dates) => dates,
+            Err(error) => {
+                myprint!("failed to read feed_info.txt: ", error);
+                (None, None)
+            }
+        };
+
+        let now = now();
+        Ok(LibraryFeed {
+            id,
+            name: existing.as_ref().map_or_else(
+                || {
+                    source.file_stem().map_or("feed".to_string(), |name| {
+                        name.to_string_lossy().to_string()
+                    })
+                },
+                |feed| feed.name.clone(),
+            ),
+            agency: data
+                .agencies
+                .iter()
+                .map(|agency| agency.name.clone())
+                .collect::<Vec<_>>()
+                .join(", "),
+            source: fs::canonicalize(source)
+                .unwrap_or_else(|_| source.to_path_buf())
+                .to_string_lossy()
+                .to_string(),
+            data_path: Some(data_path.to_string_lossy().to_string()),
+            source_hash,
+            start_date,
+            end_date,
+            size,
+            imported: existing.map_or_else(|| now.clone(), |feed| feed.imported),
+            last_opened: now,
+        })
+    }
+
+    /// deletes the library's copy of the feed and its cache, keeping the record
+    pub fn remove_data(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(data_path) = self.data_path.take() {
+            let data_path = Path::new(&data_path);
+            if let Ok(Some(cache)) = FeedCache::for_source(data_path) {
+                cache.remove()?;
+            }
+            if data_path.is_dir() {
+                fs::remove_dir_all(data_path)?;
+            } else {
+                fs::remove_file(data_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn touch(&mut self) {
+        self.last_opened = now();
+    }
+}
+
+/// What the loading thread should do with the library once the feed has loaded
+pub enum LibraryUpdate {
+    None,
+    /// the feed was opened from the library, so only needs its last opened time updating
+    Opened(LibraryFeed),
+    /// copy the feed into the library, replacing the existing record's data if there is one
+    Import(Option<LibraryFeed>),
+}
+impl LibraryUpdate {
+    /// a feed opened from the library's copy or from the source of a library feed updates that feed's record. Other feeds are only added to the library when asked to, see for_import()
+    pub fn for_feed(library: &Vector<LibraryFeed>, path: &Path) -> LibraryUpdate {
+        match Self::for_project(library, path) {
+            LibraryUpdate::None => match find_by_source(library, path) {
+                Some(feed) => LibraryUpdate::Opened(feed),
+                None => LibraryUpdate::None,
+            },
+            update => update,
+        }
+    }
+
+    /// adds the feed at path to the library, or updates the copy if it is already in it. The library's own copies, and stdin which has nowhere to be reimported from, aren't imported
+    pub fn for_import(library: &Vector<LibraryFeed>, path: &Path) -> LibraryUpdate {
+        match Self::for_project(library, path) {
+            LibraryUpdate::None if is_stdin(path) => LibraryUpdate::None,
+            LibraryUpdate::None => LibraryUpdate::Import(find_by_source(library, path)),
+            update => update,
+        }
+    }
+
+    /// projects only update the record of a library feed they were saved from, they never import their source
+    pub fn for_project(library: &Vector<LibraryFeed>, path: &Path) -> LibraryUpdate {
+        match library
+            .iter()
+            .find(|feed| feed.data_path.as_ref().map(Path::new) == Some(path))
+        {
+            Some(feed) => LibraryUpdate::Opened(feed.clone()),
+            None => LibraryUpdate::None,
+        }
+    }
+}
+
+fn find_by_source(library: &Vector<LibraryFeed>, path: &Path) -> Option<LibraryFeed> {
+    let source = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    library
+        .iter()
+        .find(|feed| Path::new(&feed.source) == source)
+        .cloned()
+}
+
+fn now() -> String {
+    Utc::now().format("%Y-%m-%d %H:%M").to_string()
+}
+
+/// (feed_start_date, feed_end_date), both optional as is feed_info.txt itself
+fn read_feed_dates(path: &Path) -> Result<(Option<String>, Option<String>), Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct FeedInfo {
+        #[serde(default)]
+        feed_start_date: Option<String>,
+        #[serde(default)]
+        feed_end_date: Option<String>,
+    }
+    let mut source = FeedSource::open(path)?;
+    let file = match source.file("feed_info.txt")? {
+        Some(file) => file,
+        None => return Ok((None, None)),
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::Fields)
+        .from_reader(file);
+    match reader.deserialize::<FeedInfo>().next() {
+        Some(feed_info) => {
+            let feed_info = feed_info?;
+            Ok((feed_info.feed_start_date, feed_info.feed_end_date))
+        }
+        None => Ok((None, None)),
+    }
+}
+
+/// the library's feeds, most recently opened first. Empty if there is no library yet or it can't be read
+pub fn read_library() -> Vector<LibraryFeed> {
+    let path = match data_dir() {
+        Some(dir) => dir.join("library.json"),
+        None => return Vector::new(),
+    };
+    File::open(path)
+        .ok()
+        .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+        .unwrap_or_default()
+}
+
+/// replaces the record with the same id, or adds it, then saves the library
+pub fn update_library(library: &mut Vector<LibraryFeed>, feed: LibraryFeed) {
+    library.retain(|existing| existing.id != feed.id);
+    library.push_front(feed);
+    write_library(library);
+}
+
+pub fn write_library(library: &Vector<LibraryFeed>) {
+    let result = data_dir()
+        .ok_or_else(|| "no directory to keep the library in".into())
+        .and_then(|dir| -> Result<(), Box<dyn Error>> {
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("library.json"), serde_json::to_string(library)?)?;
+            Ok(())
+        });
+    if let Err(error) = result {
+        myprint!("failed to save library: ", error);
+    }
+}
+
+fn library_dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("feeds"))
+}
+
+/// $XDG_DATA_HOME/gtfs_manager, falling back to ~/.local/share/gtfs_manager, or %APPDATA%\gtfs_manager on windows
+fn data_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+    };
+    base.map(|base| base.join("gtfs_manager"))
+}
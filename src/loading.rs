@@ -0,0 +1,397 @@
+use druid::{Data, ExtEventSink, Lens, Selector, SingleUse, Target};
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::{env, iter, process};
+
+use crate::cache::FeedCache;
+use crate::data::{index_feed, make_indexed_data, AppData, MyGtfs};
+use crate::library::{LibraryFeed, LibraryUpdate};
+use crate::osm::RoadNetwork;
+use crate::project::Project;
+use crate::source::{is_stdin, is_url, FeedSource, FeedSourceError};
+
+/// agency, stops, routes, trips, stop_times, calendar and shapes
+const FEED_FILE_COUNT: usize = 7;
+/// how often to report progress and check for cancellation while reading a large file
+const ROWS_PER_UPDATE: usize = 100_000;
+/// how much of a download is written at a time, between checks for cancellation
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// sent by the loading thread when it starts a new step or gets further through a large file
+pub const LOADING_PROGRESS: Selector<LoadingProgress> = Selector::new("loading.progress");
+/// sent by the loading thread once it is done, with the loaded data or the reason it stopped
+pub const LOADING_FINISHED: Selector<SingleUse<LoadingFinished>> =
+    Selector::new("loading.finished");
+/// sent by the thread reading an OSM extract once it is done
+pub const OSM_LOADED: Selector<SingleUse<Result<RoadNetwork, String>>> =
+    Selector::new("osm.loaded");
+
+/// gives each load an id, so the progress and result of a load which has since been replaced can be ignored
+static NEXT_LOAD_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// State of the loading screen shown while a feed is read in the background
+#[derive(Clone, Data, Lens)]
+pub struct Loading {
+    pub id: usize,
+    pub path: String,
+    /// description of what is currently being done
+    pub step: String,
+    pub steps_done: usize,
+    pub steps_total: usize,
+    /// set if loading failed or was cancelled
+    pub error: Option<String>,
+    #[data(ignore)]
+    #[lens(ignore)]
+    cancel: Arc<AtomicBool>,
+}
+impl Loading {
+    pub fn new(path: String) -> Loading {
+        Loading {
+            id: NEXT_LOAD_ID.fetch_add(1, Ordering::Relaxed),
+            path,
+            step: "starting".to_string(),
+            steps_done: 0,
+            // each file then building the network
+            steps_total: FEED_FILE_COUNT + 1,
+            error: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+    pub fn fraction(&self) -> f64 {
+        self.steps_done as f64 / self.steps_total as f64
+    }
+    /// ask the loading thread to stop, it checks between files and every ROWS_PER_UPDATE rows
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+    pub fn apply(&mut self, progress: &LoadingProgress) {
+        self.steps_done = progress.steps_done;
+        self.step = progress.step.clone();
+    }
+}
+
+#[derive(Clone)]
+pub struct LoadingProgress {
+    /// the id of the Loading this is progress of
+    pub load_id: usize,
+    pub steps_done: usize,
+    pub step: String,
+}
+
+/// The AppData made on the loading thread, and the feed's library record if it was updated
+pub struct LoadedFeed(pub AppData, pub Option<LibraryFeed>);
+
+pub struct LoadingFinished {
+    /// the id of the Loading this is the result of
+    pub load_id: usize,
+    pub result: Result<LoadedFeed, String>,
+}
+
+/// Reads the feed at path, downloading it first if it is a URL, on a new thread so the window can open immediately, then applies the project's edits if there is one, and updates or imports its library record. Progress is sent with LOADING_PROGRESS and the result with LOADING_FINISHED, both tagged with the loading's id. Cancelling the loading stops the thread at its next check
+pub fn load_feed_in_background(
+    path: PathBuf,
+    project: Option<Project>,
+    library: LibraryUpdate,
+    sink: ExtEventSink,
+    loading: &Loading,
+) {
+    let load_id = loading.id;
+    let cancel = loading.cancel.clone();
+    thread::spawn(move || {
+        let mut loader = FeedLoader {
+            sink: Some(&sink),
+            load_id,
+            cancel: &cancel,
+            steps_done: 0,
+        };
+        let result = loader
+            .download(&path)
+            .and_then(|download| {
+                // the URL is still what the feed is recorded as coming from, only the feed itself is read from the download
+                let local_path = download
+                    .as_ref()
+                    .map_or(path.as_path(), |download| download.0.as_path());
+                let mut app_data = loader.load(local_path)?;
+                app_data.source_path = Some(path.to_string_lossy().to_string());
+                if let Some(project) = project {
+                    loader.progress("applying project edits".to_string());
+                    app_data.project_path = project
+                        .path
+                        .as_ref()
+                        .map(|path| path.to_string_lossy().to_string());
+                    project.apply(&mut app_data)?;
+                }
+                // so that only changes made after opening count as unsaved
+                app_data.saved_edits = Project::new(&app_data, path.clone()).edits_fingerprint();
+                let library_feed = match library {
+                    LibraryUpdate::None => None,
+                    LibraryUpdate::Opened(mut feed) => {
+                        feed.touch();
+                        Some(feed)
+                    }
+                    LibraryUpdate::Import(existing) => {
+                        loader.progress("copying feed to library".to_string());
+                        match LibraryFeed::import(&path, local_path, existing, &app_data) {
+                            Ok(feed) => Some(feed),
+                            // the feed is still usable, it just won't be listed
+                            Err(error) => {
+                                app_data.show_error("failed to import feed to library", error);
+                                None
+                            }
+                        }
+                    }
+                };
+                Ok(LoadedFeed(app_data, library_feed))
+            })
+            .map_err(|error| error.to_string());
+        if let Err(error) = &result {
+            myprint!("failed to load feed: ", error);
+        }
+        let finished = LoadingFinished { load_id, result };
+        if let Err(error) =
+            sink.submit_command(LOADING_FINISHED, SingleUse::new(finished), Target::Auto)
+        {
+            myprint!("failed to send loaded feed: ", error);
+        }
+    });
+}
+
+/// Reads the OSM extract at path on a new thread, since large extracts take a while to turn into a network. The result is sent with OSM_LOADED
+pub fn load_osm_in_background(path: PathBuf, sink: ExtEventSink) {
+    thread::spawn(move || {
+        let result = RoadNetwork::from_pbf(&path).map_err(|error| error.to_string());
+        if let Err(error) = sink.submit_command(OSM_LOADED, SingleUse::new(result), Target::Auto) {
+            myprint!("failed to send OSM network: ", error);
+        }
+    });
+}
+
+/// Reads the feed at path, downloading it first if it is a URL, on the current thread, for use from the command line without opening a window
+pub fn load_feed(path: &Path) -> Result<AppData, Box<dyn Error>> {
+    let cancel = AtomicBool::new(false);
+    let mut loader = FeedLoader {
+        sink: None,
+        load_id: 0,
+        cancel: &cancel,
+        steps_done: 0,
+    };
+    let download = loader.download(path)?;
+    let mut app_data = loader.load(
+        download
+            .as_ref()
+            .map_or(path, |download| download.0.as_path()),
+    )?;
+    app_data.source_path = Some(path.to_string_lossy().to_string());
+    Ok(app_data)
+}
+
+/// A feed downloaded to a temporary file, which is deleted once it has been read
+struct Download(PathBuf);
+impl Drop for Download {
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_file(&self.0) {
+            myprint!("failed to remove download: ", error);
+        }
+    }
+}
+
+struct FeedLoader<'a> {
+    /// None when loading without a window
+    sink: Option<&'a ExtEventSink>,
+    load_id: usize,
+    cancel: &'a AtomicBool,
+    steps_done: usize,
+}
+impl<'a> FeedLoader<'a> {
+    /// downloads the feed if path is a URL, since zips have their directory at the end so can't be read as they arrive. None if path is already local
+    fn download(&mut self, path: &Path) -> Result<Option<Download>, Box<dyn Error>> {
+        if !is_url(path) {
+            return Ok(None);
+        }
+        let url = path.to_string_lossy();
+        self.progress(format!("downloading {}", url));
+        let mut response = reqwest::blocking::get(&*url)?.error_for_status()?;
+        // keep the extension so TransXChange and NeTEx files are still read as XML
+        let url_path = url
+            .split(|c: char| c == '?' || c == '#')
+            .next()
+            .unwrap_or_default();
+        let extension = match Path::new(url_path).extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("xml") => "xml",
+            _ => "zip",
+        };
+        // made before anything is written, so the file is removed however the download ends
+        let download = Download(env::temp_dir().join(format!(
+            "gtfs_manager_download_{}_{}.{}",
+            process::id(),
+            self.load_id,
+            extension
+        )));
+        let mut file = File::create(&download.0)?;
+        let mut buffer = vec![0; DOWNLOAD_CHUNK_SIZE];
+        loop {
+            if self.cancelled() {
+                return Err("loading cancelled".into());
+            }
+            let read = response.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+        }
+        Ok(Some(download))
+    }
+
+    fn load(&mut self, path: &Path) -> Result<AppData, Box<dyn Error>> {
+        self.progress("checking cache".to_string());
+        let cache = if is_stdin(path) {
+            // stdin can only be read once, so can't be hashed before it is read
+            None
+        } else {
+            match FeedCache::for_source(path) {
+                Ok(cache) => cache,
+                Err(error) => {
+                    myprint!("failed to hash feed for cache: ", error);
+                    None
+                }
+            }
+        };
+        let cached = match cache.as_ref().map(FeedCache::read).transpose() {
+            Ok(cached) => cached.flatten(),
+            // a cache we can't read is the same as no cache, it will be overwritten below
+            Err(error) => {
+                myprint!("failed to read cache: ", error);
+                None
+            }
+        };
+        let source_hash = cache.as_ref().map(|cache| cache.hash().to_string());
+        if let Some((gtfs, index)) = cached {
+            self.steps_done = FEED_FILE_COUNT;
+            self.progress("building network from cache".to_string());
+            let mut app_data = make_indexed_data(gtfs, index);
+            app_data.source_hash = source_hash;
+            return Ok(app_data);
+        }
+
+        let mut source = FeedSource::open(path)?;
+        let mut gtfs = MyGtfs {
+            agencies: self.read_table(&mut source, "agency.txt")?,
+            stops: self.read_table(&mut source, "stops.txt")?,
+            routes: self.read_table(&mut source, "routes.txt")?,
+            trips: self.read_table(&mut source, "trips.txt")?,
+            stop_times: self.read_table(&mut source, "stop_times.txt")?,
+            shapes: self.read_optional_table(&mut source, "shapes.txt")?,
+            calendar: self.read_optional_table(&mut source, "calendar.txt")?,
+        };
+        self.progress("indexing".to_string());
+        let index = index_feed(&mut gtfs);
+        if let Some(cache) = cache {
+            self.progress("writing cache".to_string());
+            // the tables have been sorted by index_feed, so are cached sorted
+            if let Err(error) = cache.write(&gtfs, &index) {
+                myprint!("failed to write cache: ", error);
+            }
+        }
+        self.progress("building network".to_string());
+        let mut app_data = make_indexed_data(gtfs, index);
+        app_data.source_hash = source_hash;
+        // only shown when the feed is first read, reopening it from the cache doesn't convert it again
+        if let Some(warning) = source.warning() {
+            app_data.show_error("some of the feed couldn't be converted", warning);
+        }
+        Ok(app_data)
+    }
+
+    fn progress(&self, step: String) {
+        // the window may have already been closed, in which case there is nobody to tell
+        if let Some(sink) = self.sink {
+            let _ = sink.submit_command(
+                LOADING_PROGRESS,
+                LoadingProgress {
+                    load_id: self.load_id,
+                    steps_done: self.steps_done,
+                    step,
+                },
+                Target::Auto,
+            );
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    fn read_table<T: DeserializeOwned>(
+        &mut self,
+        source: &mut FeedSource,
+        name: &str,
+    ) -> Result<Vec<T>, Box<dyn Error>> {
+        self.read_optional_table(source, name)?.ok_or_else(|| {
+            FeedSourceError::MissingFile {
+                feed: source.name(),
+                name: name.to_string(),
+            }
+            .into()
+        })
+    }
+
+    fn read_optional_table<T: DeserializeOwned>(
+        &mut self,
+        source: &mut FeedSource,
+        name: &str,
+    ) -> Result<Option<Vec<T>>, Box<dyn Error>> {
+        if self.cancelled() {
+            return Err("loading cancelled".into());
+        }
+        self.progress(format!("reading {}", name));
+        let table = match source.file(name)? {
+            Some(reader) => Some(self.read_records(reader, name)?),
+            None => None,
+        };
+        self.steps_done += 1;
+        Ok(table)
+    }
+
+    fn read_records<T: DeserializeOwned>(
+        &self,
+        reader: impl Read,
+        name: &str,
+    ) -> Result<Vec<T>, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::Fields)
+            .from_reader(reader);
+        // some feeds start with a byte order mark, which would otherwise end up in the first column's name
+        let headers = reader.headers()?;
+        if let Some(first) = headers
+            .get(0)
+            .and_then(|first| first.strip_prefix('\u{feff}'))
+        {
+            let headers = iter::once(first)
+                .chain(headers.iter().skip(1))
+                .collect::<csv::StringRecord>();
+            reader.set_headers(headers);
+        }
+
+        let mut records = Vec::new();
+        for record in reader.deserialize() {
+            records.push(record.map_err(|error| format!("{}: {}", name, error))?);
+            if records.len() % ROWS_PER_UPDATE == 0 {
+                if self.cancelled() {
+                    return Err("loading cancelled".into());
+                }
+                self.progress(format!("reading {}: {} rows", name, records.len()));
+            }
+        }
+        Ok(records)
+    }
+}
@@ -3,104 +3,180 @@
 // On Windows platform, don't show a console when opening the app.
 #![windows_subsystem = "windows"]
 
-use chrono::Utc;
 use clap::Parser;
 use druid::{AppLauncher, Color, WindowDesc};
-use gtfs_structures::RawGtfs;
 use std::error::Error;
+use std::ffi::OsStr;
 use std::fmt::Debug;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
 
 use gtfs_manager::{
-    main_widget, make_initial_data, AppData, Delegate, VARIABLE_STOP_TIME_BORDER_COLOR,
+    geojson_export, gpx_export, kml_export, load_feed, load_feed_in_background,
+    load_osm_in_background, main_widget, make_initial_data, make_menu, read_library,
+    read_recent_files, Delegate, LibraryUpdate, Loading, MyGtfs, Project, GEOJSON_LAYERS,
+    PROJECT_EXTENSION, VARIABLE_STOP_TIME_BORDER_COLOR,
 };
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct CliArgs {
-    /// Optional path to a GTFS zip. If missing demo data will be loaded
+    /// Optional path or URL of a GTFS zip, path to a GTFS directory, a TransXChange or NeTEx XML file or zip, or a .gtfsproj project. If missing the start screen is shown
     pub path: Option<String>,
+    /// Optional path to an OSM PBF extract, used to generate shapes by routing along roads and rails
+    #[clap(long)]
+    pub osm: Option<String>,
+    /// Print a summary of the feed and exit without opening a window. Pass - as the path to read a zipped feed from stdin
+    #[clap(long)]
+    pub info: bool,
+    /// Write one of the layers stops, trips, patterns or routes of the feed as GeoJSON and exit without opening a window
+    #[clap(long, value_name = "LAYER", possible_values = GEOJSON_LAYERS, requires = "output")]
+    pub export_geojson: Option<String>,
+    /// Write the feed's routes, coloured and in folders by agency, and its stops as KML and exit without opening a window
+    #[clap(long, requires = "output", conflicts_with_all = &["export-geojson", "export-gpx"])]
+    pub export_kml: bool,
+    /// Write the feed's routes as tracks and its stops as waypoints in a GPX file and exit without opening a window
+    #[clap(long, requires = "output", conflicts_with_all = &["export-geojson", "export-kml"])]
+    pub export_gpx: bool,
+    /// Where to write exports
+    #[clap(long, short)]
+    pub output: Option<String>,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // before parsing, so --help and argument errors are shown too
+    #[cfg(windows)]
+    attach_parent_console();
     let args = CliArgs::parse();
-
-    // experimenting with storing a demo dataset as bincode but couldn't get the data to deserialize to bincode using gtfs_structures' custom serde implementations
-    let path = Path::new("sao-paulo-sptrans.bincode");
-    // let path = Path::new("sao-paulo-sptrans.json");
-    let initial_data = if path.exists() {
-        println!("{:?} json: read from disk", Utc::now());
-        // let file = fs::File::open(path)?;
-
-        println!("{:?} json: deserialize", Utc::now());
-        let input = File::open(path)?;
-        let buffered = BufReader::new(input);
-        let initial_data: AppData = bincode::deserialize_from(buffered)?;
-        // let initial_data: AppData = serde_json::from_reader(file)?;
-        initial_data
-    } else {
-        println!("reading raw gtfs");
-        let mut gtfs = if let Some(path) = &args.path {
-            RawGtfs::new(path)?
-        } else {
-            panic!("sfad")
-        };
-        gtfs.print_stats();
-
-        println!("making initial data");
-        let initial_data = make_initial_data(&mut gtfs);
-
-        // // bincode
-        // let bincode_path = "sao-paulo-sptrans.bincode";
-        // {
-        //     println!("{:?} bincode: serialize", Utc::now());
-        //     let se_bincode_vec: Vec<u8> = bincode::serialize(&initial_data).unwrap();
-
-        //     println!("{:?} bincode: write to disk", Utc::now());
-        //     fs::write(bincode_path, se_bincode_vec).expect("write failed");
-        // }
-
-        // println!("{:?} bincode: read from disk", Utc::now());
-        // let read_bincode_string = fs::read(bincode_path).unwrap();
-
-        // println!("{:?} bincode: deserialize", Utc::now());
-        // let initial_data: AppData = bincode::deserialize(&read_bincode_string[..]).unwrap();
-
-        // json
-        {
-            // println!("{:?} json: serialize", Utc::now());
-            // // let serialized_data = serde_json::to_string(&initial_data)?;
-            // let serialized_data: Vec<u8> = bincode::serialize(&initial_data)?;
-
-            // println!("{:?} json: write to disk", Utc::now());
-            // fs::write(path, serialized_data)?;
+    if args.info || args.export_geojson.is_some() || args.export_kml || args.export_gpx {
+        if let Err(error) = run_headless(&args) {
+            eprintln!("error: {}", error);
+            process::exit(1);
         }
-        initial_data
-    };
-
-    // println!("{:?} bincode: deserialize directly", Utc::now());
-    // // println!("{:?} bincode: deserialize directly", start.elapsed());
-    // let input = File::open(bincode_path).unwrap();
-    // let buffered = BufReader::new(input);
-    // let de_bincode: AppData = bincode::deserialize_from(buffered).unwrap();
+        return Ok(());
+    }
+
+    // the feed is read in the background so the window can show its progress, until then the app has an empty feed. Feeds which have been opened before are read from the cache. Without a path the start screen is shown
+    let mut initial_data = make_initial_data(MyGtfs::default());
+    initial_data.recent_files = read_recent_files();
+    initial_data.library = read_library();
+    let mut feed_to_load = None;
+    if let Some(path) = &args.path {
+        // a project is opened by loading its feed then applying its edits
+        let (source, project, library) =
+            if Path::new(path).extension() == Some(OsStr::new(PROJECT_EXTENSION)) {
+                let project = Project::from_file(Path::new(path))?;
+                let library = LibraryUpdate::for_project(&initial_data.library, &project.source);
+                (project.source.clone(), Some(project), library)
+            } else {
+                let library = LibraryUpdate::for_feed(&initial_data.library, Path::new(path));
+                (PathBuf::from(path), None, library)
+            };
+        let loading = Loading::new(source.to_string_lossy().to_string());
+        feed_to_load = Some((source, project, library, loading.clone()));
+        initial_data.loading = Some(loading);
+    }
+
+    // the OSM network is read in the background too, and is kept when the feed finishes loading
+    if let Some(osm_path) = &args.osm {
+        initial_data.osm_loading = Some(osm_path.clone());
+    }
 
     println!("making main window");
     let main_window = WindowDesc::new(main_widget())
+        .menu(make_menu)
         .title("Select")
         .window_size((1400., 1000.));
 
     println!("launching app");
-    AppLauncher::with_window(main_window)
+    let launcher = AppLauncher::with_window(main_window)
         .configure_env(|env, _state| {
             env.set(
                 VARIABLE_STOP_TIME_BORDER_COLOR,
                 Color::rgb(54. / 255., 58. / 255., 74. / 255.),
             );
         })
-        .delegate(Delegate {})
-        // .log_to_console()
-        .launch(initial_data)?;
+        .delegate(Delegate {});
+    // .log_to_console()
+    if let Some((source, project, library, loading)) = feed_to_load {
+        load_feed_in_background(
+            source,
+            project,
+            library,
+            launcher.get_external_handle(),
+            &loading,
+        );
+    }
+    if let Some(osm_path) = &args.osm {
+        load_osm_in_background(PathBuf::from(osm_path), launcher.get_external_handle());
+    }
+    launcher.launch(initial_data)?;
+    Ok(())
+}
+
+/// The app is built for the Windows GUI subsystem so opening it doesn't also open a console, but that leaves it without one to print to when run from a terminal. Attaching to the terminal's console gives it one. This fails when the app wasn't started from a console, when there is nowhere to print anyway
+#[cfg(windows)]
+fn attach_parent_console() {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+/// does what the arguments ask for without opening a window
+fn run_headless(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let path = args
+        .path
+        .as_ref()
+        .ok_or("a feed or project path is needed")?;
+    let data = if Path::new(path).extension() == Some(OsStr::new(PROJECT_EXTENSION)) {
+        let project = Project::from_file(Path::new(path))?;
+        let mut data = load_feed(&project.source)?;
+        project.apply(&mut data)?;
+        data
+    } else {
+        load_feed(Path::new(path))?
+    };
+    if args.info {
+        println!("agencies: {}", data.agencies.len());
+        println!("routes: {}", data.routes.len());
+        println!("trips: {}", data.trips.len());
+        println!("stop times: {}", data.stop_times.len());
+        println!("stops: {}", data.stops.len());
+        println!(
+            "services: {}",
+            data.gtfs
+                .calendar
+                .as_ref()
+                .map_or(0, |calendar| calendar.len())
+        );
+        println!(
+            "shapes: {}",
+            data.shapes_range_from_shape_id
+                .as_ref()
+                .map_or(0, |shapes| shapes.len())
+        );
+    }
+    if let Some(layer) = &args.export_geojson {
+        let geojson = serde_json::to_string(&geojson_export(&data, layer, false)?)?;
+        fs::write(output_path(args)?, geojson)?;
+    }
+    if args.export_kml {
+        fs::write(output_path(args)?, kml_export(&data, false))?;
+    }
+    if args.export_gpx {
+        fs::write(output_path(args)?, gpx_export(&data, false))?;
+    }
     Ok(())
 }
+
+fn output_path(args: &CliArgs) -> Result<&str, Box<dyn Error>> {
+    args.output
+        .as_deref()
+        .ok_or_else(|| "--output is needed".into())
+}
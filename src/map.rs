@@ -1,18 +1,27 @@
 use druid::im::Vector;
-use druid::kurbo::{BezPath, Circle, ParamCurveNearest, Shape};
-use druid::piet::{Device, ImageFormat, InterpolationMode, PietImage};
+use druid::kurbo::{BezPath, Circle, Line, ParamCurve, ParamCurveNearest, PathEl, Shape};
+use druid::piet::{
+    Device, ImageFormat, InterpolationMode, PietImage, Text, TextLayout, TextLayoutBuilder,
+};
 use druid::widget::prelude::*;
 use druid::{
-    Affine, BoxConstraints, Color, Cursor, Data, Env, Event, LayoutCtx, Point, Rect, RenderContext,
-    Size, Vec2, Widget,
+    Affine, BoxConstraints, Color, Cursor, Data, Env, Event, FontFamily, KbKey, LayoutCtx, Point,
+    Rect, RenderContext, Selector, Size, Vec2, Widget,
 };
 use rgb::RGB;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
 use crate::app_delegate::*;
 use crate::data::*;
+use crate::geometry::haversine_distance;
+use crate::layers::LayerGeometry;
+
+mod color_mode;
+use color_mode::TripColors;
 
 // bitmaps larger than 10,000 x 10,000 will crash. This no longer seems to be a problem, was possibly because of the way we were drawing to it or something rather than an inherent problem with bitmaps of that size. 20,000 does add about 2GB to the memory use of the app though, so not a perfect solution. This is possibly why we will want immediate mode to kick in at some point?
 // why is different sizes a problem?
@@ -30,6 +39,45 @@ const SMALL_CIRCLE_MULT: f64 = 1.;
 const LARGE_CIRCLE_WHITE_BACKGROUND_MULT: f64 = 4.;
 const LARGE_CICLE_BLACK_BACKGROUND_MULT: f64 = 3.5;
 const LARGE_CIRCLE_MULT: f64 = 3.;
+// how close (in path widths) a dropped stop needs to be to a shape to snap to it
+const SNAP_DISTANCE_MULT: f64 = 5.;
+
+// stops are grouped into clusters below this zoom, which is also where labels start being drawn
+const CLUSTER_MAX_ZOOM: usize = 5;
+// width (in path widths) of the grid cells stops are clustered by
+const CLUSTER_CELL_MULT: f64 = 8.;
+const CLUSTER_COLOR: Color = Color::rgb8(40, 60, 110);
+
+// fitting the map to the selection won't zoom in further than this
+const FIT_MAX_ZOOM: usize = 10;
+// how much larger than the selection the visible area is when fitting
+const FIT_MARGIN: f64 = 1.2;
+const CAMERA_ANIMATION_NANOS: u64 = 400_000_000;
+
+// focus can only be requested while handling an event, so the map sends this to itself once added
+const FOCUS_MAP: Selector = Selector::new("focus.map");
+
+// stop labels are only drawn at this zoom or greater
+const LABEL_MIN_ZOOM: usize = 5;
+const MAX_LABELS: usize = 200;
+const LABEL_FONT_SIZE: f64 = 11.;
+const TOOLTIP_FONT_SIZE: f64 = 12.;
+// max number of routes listed in a tooltip
+const TOOLTIP_MAX_ROUTES: usize = 5;
+// max number of GeoJSON feature properties listed in a tooltip
+const TOOLTIP_MAX_PROPERTIES: usize = 10;
+
+// exported maps
+const EXPORT_FONT_SIZE_PROPORTION: f64 = 1. / 150.;
+
+// scale bar, coordinate readout, north arrow, and reset view button
+const OVERLAY_MARGIN: f64 = 10.;
+const OVERLAY_FONT_SIZE: f64 = 11.;
+const SCALE_BAR_MAX_WIDTH: f64 = 120.;
+// wide enough for the longest lat/long
+const READOUT_SIZE: Size = Size::new(140., 16.);
+const RESET_VIEW_SIZE: f64 = 28.;
+const LEGEND_SWATCH_SIZE: f64 = 12.;
 
 /// For storing a point normalised to [0, 1]. However will not panic if values fall outside [0, 1].
 #[derive(Default)]
@@ -62,6 +110,45 @@ impl NormalPoint {
             y: self.y + vector.y / size.height,
         }
     }
+    /// the focal point which puts the given REFERENCE_SIZE point in the centre of a canvas of the given size
+    fn centering(point: Point, size: Size) -> NormalPoint {
+        let scale = size.max_side() / REFERENCE_SIZE as f64;
+        NormalPoint {
+            x: point.x * scale / size.width,
+            y: point.y * scale / size.height,
+        }
+    }
+    /// the REFERENCE_SIZE point in the centre of a canvas of the given size, ie the inverse of NormalPoint::centering()
+    fn centered_point(&self, size: Size) -> Point {
+        let scale = size.max_side() / REFERENCE_SIZE as f64;
+        Point::new(self.x * size.width / scale, self.y * size.height / scale)
+    }
+}
+
+/// The map moving to show a new area, see MapWidget::fit_bounds()
+struct CameraAnimation {
+    /// centre of the map before and after the animation, in REFERENCE_SIZE coords
+    from: Point,
+    to: Point,
+    zoom_level: ZoomLevel,
+    /// nanoseconds
+    elapsed: u64,
+}
+
+/// the most zoomed in level (up to FIT_MAX_ZOOM) at which bounds (in REFERENCE_SIZE coords) fits on a canvas of the given size
+fn fitting_zoom_level(bounds: Rect, canvas_size: Size) -> ZoomLevel {
+    ZoomLevel::radio_group_vec()
+        .into_iter()
+        .map(|(_name, zoom_level)| zoom_level)
+        .filter(|zoom_level| {
+            let visible_size = canvas_size
+                * (REFERENCE_SIZE as f64 / (canvas_size.max_side() * zoom_level.to_f64()));
+            zoom_level.to_usize() <= FIT_MAX_ZOOM
+                && bounds.width() * FIT_MARGIN <= visible_size.width
+                && bounds.height() * FIT_MARGIN <= visible_size.height
+        })
+        .last()
+        .unwrap_or(ZoomLevel::One)
 }
 
 /// returns a bounding box rect for all latlong points. Note (x0,y0) is the bottom left of the rect.
@@ -81,14 +168,121 @@ fn min_max_trips_coords(trips: &Vec<Vec<Point>>) -> Rect {
     Rect::new(longmin, latmin, longmax, latmax)
 }
 
+/// A stop which is being dragged to a new position in stop move mode
+struct DraggedStop {
+    stop_id: String,
+    stop_index: usize,
+    /// position in REFERENCE_SIZE coords before the drag started
+    start_point: Point,
+    /// indexes into all_trip_paths_combined of the trips whose paths are drawn through the stop, so need to be redrawn as it moves
+    trip_indexes: Vec<usize>,
+    /// (stop id, stop index, start point) of the other selected stops, when dragging a selected stop, which move along with it
+    other_stops: Vec<(String, usize, Point)>,
+}
+
+/// An area being drawn on the map to select the stops and trips inside it, in canvas coords
+enum SelectionArea {
+    /// (start, end) of a shift drag
+    Box(Point, Point),
+    /// the mouse positions of an alt drag
+    Lasso(Vec<Point>),
+}
+impl SelectionArea {
+    fn canvas_points(&self) -> Vec<Point> {
+        match self {
+            SelectionArea::Box(start, end) => vec![
+                *start,
+                Point::new(end.x, start.y),
+                *end,
+                Point::new(start.x, end.y),
+            ],
+            SelectionArea::Lasso(points) => points.clone(),
+        }
+    }
+}
+
+/// Stops grouped for drawing at one zoom level
+#[derive(Default)]
+struct StopClusters {
+    /// (centre in REFERENCE_SIZE coords, indexes into stop_circles) of groups of 2 or more stops
+    clusters: Vec<(Point, Vec<usize>)>,
+    /// index into clusters of each stop, or None if the stop is drawn on its own
+    cluster_of_stop: Vec<Option<usize>>,
+}
+impl StopClusters {
+    /// groups stops in the same cell of a grid sized to the zoom level, so that zoomed out maps show a count rather than a blob of overlapping circles
+    fn new(stop_circles: &[Point], zoom_level: ZoomLevel) -> StopClusters {
+        let mut stop_clusters = StopClusters {
+            clusters: Vec::new(),
+            cluster_of_stop: vec![None; stop_circles.len()],
+        };
+        if zoom_level.to_usize() >= CLUSTER_MAX_ZOOM {
+            return stop_clusters;
+        }
+        let cell_size = zoom_level.path_width(REFERENCE_SIZE as f64) * CLUSTER_CELL_MULT;
+        let mut cells: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+        for (i, point) in stop_circles.iter().enumerate() {
+            cells
+                .entry((
+                    (point.x / cell_size).floor() as i64,
+                    (point.y / cell_size).floor() as i64,
+                ))
+                .or_insert_with(Vec::new)
+                .push(i);
+        }
+        for stop_indexes in cells.into_values() {
+            if stop_indexes.len() < 2 {
+                continue;
+            }
+            let sum = stop_indexes
+                .iter()
+                .fold(Vec2::ZERO, |sum, i| sum + stop_circles[*i].to_vec2());
+            let centre = (sum / stop_indexes.len() as f64).to_point();
+            for i in &stop_indexes {
+                stop_clusters.cluster_of_stop[*i] = Some(stop_clusters.clusters.len());
+            }
+            stop_clusters.clusters.push((centre, stop_indexes));
+        }
+        stop_clusters
+    }
+}
+
+/// radius in REFERENCE_SIZE coords of a cluster marker, growing slowly with the number of stops
+fn cluster_radius(path_width: f64, n_stops: usize) -> f64 {
+    path_width * (2.5 + 1.5 * (n_stops as f64).log10())
+}
+
+/// A GeoJSON layer feature's geometries in REFERENCE_SIZE coords
+#[derive(Default)]
+struct FeaturePaths {
+    points: Vec<Point>,
+    lines: Vec<BezPath>,
+    /// the exterior ring and holes of each polygon as a single path, to be filled with the even-odd rule
+    polygons: Vec<BezPath>,
+}
+
+/// adds the id if it is not already in ids, else removes it
+fn toggle_id(ids: &mut Vector<String>, id: String) {
+    match ids.index_of(&id) {
+        Some(index) => {
+            ids.remove(index);
+        }
+        None => ids.push_back(id),
+    }
+}
+
 pub struct MyImage(PietImage);
 unsafe impl Send for MyImage {}
 
 #[derive(Default)]
 pub struct MapWidget {
     mouse_position: Option<Point>,
+    /// where the mouse was when the hovered item last changed, so the tooltip doesn't need redrawing on every mouse move
+    tooltip_position: Option<Point>,
     /// (trip_id, color, text_color, path)
     all_trip_paths_combined: Vec<(String, Color, Color, BezPath)>,
+    /// index into all_trip_paths_combined of each trip's path. Paths are only ever replaced or appended for new trips, not removed, so existing indexes stay valid
+    trip_path_index_from_id: HashMap<String, usize>,
     all_trip_paths_from_shapes: Vec<(String, Color, Color, BezPath)>,
     all_trip_paths_bitmap_grouped: Vec<(Rect, Vec<usize>)>,
     last_updated_trip_index: Option<usize>,
@@ -104,9 +298,28 @@ pub struct MapWidget {
     drag_last_pos: Option<Point>,
     // focal_point should be a lat long coord which is then converted as required, in order to preserve focus between zoom levels. but then we have to dertmine what the ORIGIN coord is. better to just have focal point as a point in [0,1] space.
     focal_point: NormalPoint,
+    /// the (long, lat) bounding box the REFERENCE_SIZE coords were made from, needed to convert them back to latlong
+    long_lat_rect: Rect,
+    dragged_stop: Option<DraggedStop>,
+    /// (shape_id, points in REFERENCE_SIZE coords) of the selected trip's shape while in shape edit mode
+    edited_shape: Option<(String, Vec<Point>)>,
+    /// index into edited_shape of the vertex being dragged
+    dragged_vertex: Option<usize>,
+    /// colours the paths are currently drawn with, for data.map_color_mode
+    trip_colors: Option<TripColors>,
+    selection_area: Option<SelectionArea>,
+    /// the features of each of data.map_layers
+    layer_paths: Vec<Vec<FeaturePaths>>,
+    /// (layer index, feature index)
+    hovered_layer_feature: Option<(usize, usize)>,
 
     /// (paths, stops)
     cached_image_map: HashMap<ZoomLevel, (Arc<Mutex<MyImage>>, Arc<Mutex<MyImage>>)>,
+    /// how stops are grouped in each stops bitmap
+    stop_clusters: HashMap<ZoomLevel, StopClusters>,
+    /// index into stop_clusters for the current zoom level
+    hovered_cluster: Option<usize>,
+    camera_animation: Option<CameraAnimation>,
     recreate_bitmap: bool,
 }
 impl MapWidget {
@@ -195,6 +408,425 @@ impl MapWidget {
         }
     }
 
+    /// the trips which visit the stop and have their path drawn from stop coords, as indexes into all_trip_paths_combined
+    fn trips_drawn_through_stop(&self, data: &AppData, stop_id: &str) -> Vec<usize> {
+        data.trip_ids_for_stop(stop_id)
+            .iter()
+            .filter(|trip_id| {
+                data.trip(trip_id)
+                    .map(|trip| data.trip_path_from_stop_coords(trip))
+                    .unwrap_or(false)
+            })
+            .filter_map(|trip_id| self.trip_path_index_from_id.get(trip_id).copied())
+            .collect::<Vec<_>>()
+    }
+    fn trip_path_from_stop_circles(&self, data: &AppData, trip_id: &str) -> BezPath {
+        let stop_times_range = data.stop_time_range_from_trip_id.get(trip_id).unwrap();
+        bez_path_from_coords_iter((stop_times_range.0..stop_times_range.1).map(|i| {
+            let stop_time = data.stop_times.get(i).unwrap();
+            let stop_index = *data.stop_index_from_id.get(&stop_time.stop_id).unwrap();
+            self.stop_circles[stop_index]
+        }))
+    }
+    /// recreate the paths of the given trips from the current stop_circles
+    fn update_paths_from_stop_circles(&mut self, data: &AppData, trip_indexes: &[usize]) {
+        for trip_index in trip_indexes {
+            let trip_id = self.all_trip_paths_combined[*trip_index].0.clone();
+            let new_path = self.trip_path_from_stop_circles(data, &trip_id);
+            self.all_trip_paths_combined[*trip_index].3 = new_path.clone();
+            self.update_single_path_in_grouped_rects(*trip_index, new_path);
+        }
+    }
+    fn start_stop_drag(&mut self, data: &AppData, stop_id: String) {
+        myprint!("start dragging stop: {}", stop_id);
+        let stop_index = *data.stop_index_from_id.get(&stop_id).unwrap();
+        let mut trip_indexes = self.trips_drawn_through_stop(data, &stop_id);
+        // dragging one of the selected stops drags all of them
+        let mut other_stops = Vec::new();
+        if data.map_selected_stop_ids.contains(&stop_id) {
+            for other_stop_id in &data.map_selected_stop_ids {
+                if other_stop_id == &stop_id {
+                    continue;
+                }
+                let other_stop_index = *data.stop_index_from_id.get(other_stop_id).unwrap();
+                other_stops.push((
+                    other_stop_id.clone(),
+                    other_stop_index,
+                    self.stop_circles[other_stop_index],
+                ));
+                for trip_index in self.trips_drawn_through_stop(data, other_stop_id) {
+                    if !trip_indexes.contains(&trip_index) {
+                        trip_indexes.push(trip_index);
+                    }
+                }
+            }
+        }
+        self.dragged_stop = Some(DraggedStop {
+            stop_id,
+            stop_index,
+            start_point: self.stop_circles[stop_index],
+            trip_indexes,
+            other_stops,
+        });
+    }
+    fn move_dragged_stop(&mut self, data: &AppData, point: Point) {
+        let (stop_index, start_point, trip_indexes, other_stops) = match &self.dragged_stop {
+            Some(dragged_stop) => (
+                dragged_stop.stop_index,
+                dragged_stop.start_point,
+                dragged_stop.trip_indexes.clone(),
+                dragged_stop.other_stops.clone(),
+            ),
+            None => return,
+        };
+        self.stop_circles[stop_index] = point;
+        let offset = point - start_point;
+        for (_stop_id, other_stop_index, other_start_point) in other_stops {
+            self.stop_circles[other_stop_index] = other_start_point + offset;
+        }
+        self.update_paths_from_stop_circles(data, &trip_indexes);
+    }
+
+    /// adds the stops, and trips whose paths are entirely, inside the selection area to the selection
+    fn select_in_area(
+        &self,
+        data: &mut AppData,
+        canvas_size: Size,
+        selection_area: &SelectionArea,
+    ) {
+        let points = selection_area
+            .canvas_points()
+            .iter()
+            .map(|point| self.reference_point_from_mouse(data, canvas_size, *point))
+            .collect::<Vec<_>>();
+        if points.len() < 3 {
+            return;
+        }
+        let mut polygon = bez_path_from_coords_iter(points.into_iter());
+        polygon.close_path();
+        let bounding_box = polygon.bounding_box();
+        let inside = |point: Point| bounding_box.contains(point) && polygon.winding(point) != 0;
+
+        let stop_ids = data
+            .stops
+            .iter()
+            .zip(self.stop_circles.iter())
+            .filter(|(stop, stop_circle)| stop.live && inside(**stop_circle))
+            .map(|(stop, _stop_circle)| stop.id.clone())
+            .collect::<Vec<_>>();
+        let trip_ids = self
+            .all_trip_paths_combined
+            .iter()
+            .filter(|(_trip_id, _color, _text_color, path)| {
+                path.segments().count() > 0
+                    && path
+                        .segments()
+                        .all(|seg| inside(seg.start()) && inside(seg.end()))
+            })
+            .map(|(trip_id, _color, _text_color, _path)| trip_id.clone())
+            .collect::<Vec<_>>();
+        for stop_id in stop_ids {
+            if !data.map_selected_stop_ids.contains(&stop_id) {
+                data.map_selected_stop_ids.push_back(stop_id);
+            }
+        }
+        for trip_id in trip_ids {
+            if !data.map_selected_trip_ids.contains(&trip_id) {
+                data.map_selected_trip_ids.push_back(trip_id);
+            }
+        }
+    }
+    /// finds the closest point on a path drawn from a shape, if there is one within snapping distance
+    fn nearest_shape_point(&self, data: &AppData, point: Point) -> Option<Point> {
+        let snap_distance =
+            data.map_zoom_level.path_width(REFERENCE_SIZE as f64) * SNAP_DISTANCE_MULT;
+        let snap_distance2 = snap_distance * snap_distance;
+        let mut nearest: Option<(f64, Point)> = None;
+        for (rect, path_indexes) in &self.all_trip_paths_bitmap_grouped {
+            if !rect.contains(point) {
+                continue;
+            }
+            for index in path_indexes {
+                let (trip_id, _color, _text_color, path) =
+                    self.all_trip_paths_combined.get(*index).unwrap();
+                // don't snap to paths which are themselves drawn from stop coords
+                let trip = data.trip(trip_id).unwrap();
+                if data.trip_path_from_stop_coords(trip) {
+                    continue;
+                }
+                for seg in path.segments() {
+                    let seg_nearest = seg.nearest(point, 1.);
+                    if seg_nearest.distance_sq < snap_distance2
+                        && nearest
+                            .map(|(distance_sq, _point)| seg_nearest.distance_sq < distance_sq)
+                            .unwrap_or(true)
+                    {
+                        nearest = Some((seg_nearest.distance_sq, seg.eval(seg_nearest.t)));
+                    }
+                }
+            }
+        }
+        nearest.map(|(_distance_sq, point)| point)
+    }
+
+    /// recolour all the trip paths, eg when data.map_color_mode changes
+    fn apply_trip_colors(&mut self, trip_colors: TripColors) {
+        for (trip_id, color, _text_color, _path) in self
+            .all_trip_paths_combined
+            .iter_mut()
+            .chain(self.all_trip_paths_from_shapes.iter_mut())
+            .chain(self.filtered_trip_paths.iter_mut())
+        {
+            *color = trip_colors.get(trip_id);
+        }
+        self.trip_colors = Some(trip_colors);
+    }
+
+    /// index into all_trip_paths_combined of the trip's path. Trips added since the map was made (eg by ADD_TRIP) get an empty path appended, which the caller then replaces
+    fn trip_path_index(&mut self, data: &AppData, trip_id: &str) -> usize {
+        if let Some(trip_index) = self.trip_path_index_from_id.get(trip_id) {
+            return *trip_index;
+        }
+        let (color, text_color) = data
+            .trip(trip_id)
+            .and_then(|trip| data.route(&trip.route_id))
+            .map_or((Color::BLACK, Color::WHITE), |route| {
+                let RGB { r, g, b } = route.color.0;
+                let color = Color::rgb8(r, g, b);
+                let RGB { r, g, b } = route.text_color.0;
+                (color, Color::rgb8(r, g, b))
+            });
+        let trip_index = self.all_trip_paths_combined.len();
+        self.all_trip_paths_combined
+            .push((trip_id.to_string(), color, text_color, BezPath::new()));
+        self.trip_path_index_from_id
+            .insert(trip_id.to_string(), trip_index);
+        trip_index
+    }
+
+    /// recreate the paths of the given trips from their shape or stop coords
+    fn rebuild_trip_paths(&mut self, data: &AppData, trip_ids: &[String]) {
+        for trip_id in trip_ids {
+            if let Some(trip) = data.trip(trip_id) {
+                let trip_index = self.trip_path_index(data, trip_id);
+                let new_path = bez_path_from_coords_iter(data.trip_coords(trip).iter().map(
+                    |coord| {
+                        MapWidget::latlong_to_canvas(
+                            *coord,
+                            self.long_lat_rect,
+                            REFERENCE_SIZE as f64,
+                        )
+                    },
+                ));
+                self.all_trip_paths_combined[trip_index].3 = new_path.clone();
+                self.update_single_path_in_grouped_rects(trip_index, new_path);
+            }
+        }
+    }
+
+    /// load the selected trip's shape for editing, if in shape edit mode
+    fn load_edited_shape(&mut self, data: &AppData) {
+        self.dragged_vertex = None;
+        self.edited_shape = if data.map_shape_edit_mode {
+            data.selected_trip_id
+                .as_ref()
+                .and_then(|trip_id| data.trip(trip_id))
+                .and_then(|trip| trip.shape_id.clone())
+                .filter(|shape_id| data.has_shape(shape_id))
+                .map(|shape_id| {
+                    let points = data
+                        .shape_coords(&shape_id)
+                        .iter()
+                        .map(|coord| {
+                            MapWidget::latlong_to_canvas(
+                                *coord,
+                                self.long_lat_rect,
+                                REFERENCE_SIZE as f64,
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    (shape_id, points)
+                })
+        } else {
+            None
+        };
+    }
+    fn edited_shape_latlongs(&self) -> Option<(String, Vec<Point>)> {
+        self.edited_shape.as_ref().map(|(shape_id, points)| {
+            let latlongs = points
+                .iter()
+                .map(|point| {
+                    MapWidget::canvas_to_latlong(*point, self.long_lat_rect, REFERENCE_SIZE as f64)
+                })
+                .collect::<Vec<_>>();
+            (shape_id.clone(), latlongs)
+        })
+    }
+    fn find_hovered_vertex(&self, data: &AppData, point: Point) -> Option<usize> {
+        let path_width = data.map_zoom_level.path_width(REFERENCE_SIZE as f64);
+        let l_circle = path_width * PATH_HIGHLIGHTED * LARGE_CIRCLE_MULT;
+        self.edited_shape.as_ref().and_then(|(_shape_id, points)| {
+            points
+                .iter()
+                .position(|vertex| Circle::new(*vertex, l_circle).contains(point))
+        })
+    }
+    /// the index of the edited shape's segment under point, and the nearest point on it
+    fn find_hovered_shape_segment(&self, data: &AppData, point: Point) -> Option<(usize, Point)> {
+        let path_width = data.map_zoom_level.path_width(REFERENCE_SIZE as f64) * PATH_HIGHLIGHTED;
+        let path_width2 = path_width * path_width;
+        self.edited_shape.as_ref().and_then(|(_shape_id, points)| {
+            points.windows(2).enumerate().find_map(|(i, vertices)| {
+                let line = Line::new(vertices[0], vertices[1]);
+                let nearest = line.nearest(point, 1.);
+                if nearest.distance_sq < path_width2 {
+                    Some((i, line.eval(nearest.t)))
+                } else {
+                    None
+                }
+            })
+        })
+    }
+
+    /// converting a mouse Point (in canvas coords) to a Point in REFERENCE_SIZE coords
+    fn reference_point_from_mouse(
+        &self,
+        data: &AppData,
+        canvas_size: Size,
+        mouse_position: Point,
+    ) -> Point {
+        let fp = self
+            .focal_point
+            .to_point_within_size(Size::new(1., 1.))
+            .to_vec2();
+        let b = fp
+            - Size::new(
+                1. / (data.map_zoom_level.to_f64() * 2.),
+                1. / (data.map_zoom_level.to_f64() * 2.),
+            )
+            .to_vec2();
+        let a = mouse_position.to_vec2() / (canvas_size.max_side() * data.map_zoom_level.to_f64());
+        ((b + a) * REFERENCE_SIZE as f64).to_point()
+    }
+
+    /// the inverse of reference_point_from_mouse, converting a Point in REFERENCE_SIZE coords to the canvas coords it is drawn at
+    fn reference_point_to_canvas(&self, data: &AppData, canvas_size: Size, point: Point) -> Point {
+        let zoom = data.map_zoom_level.to_f64();
+        let fp = self
+            .focal_point
+            .to_point_within_size(Size::new(1., 1.))
+            .to_vec2();
+        let normalised = point.to_vec2() / REFERENCE_SIZE as f64 - fp
+            + Size::new(1. / (zoom * 2.), 1. / (zoom * 2.)).to_vec2();
+        (normalised * canvas_size.max_side() * zoom).to_point()
+    }
+
+    /// index into all_trip_paths_combined of the selected trip's path
+    fn selected_trip_path_index(&self, data: &AppData) -> Option<usize> {
+        data.selected_trip_id
+            .as_ref()
+            .and_then(|trip_id| self.trip_path_index_from_id.get(trip_id))
+            .copied()
+    }
+
+    /// bounding box in REFERENCE_SIZE coords of the most specific of the selected stop, stop_time, trip, route, or agency, or of all trips if whole_feed
+    fn selection_bounds(&self, data: &AppData, whole_feed: bool) -> Option<Rect> {
+        if !whole_feed {
+            let stop_id = data.selected_stop_id.clone().or_else(|| {
+                let (trip_id, stop_sequence) = data.selected_stop_time_id.as_ref()?;
+                let stop_time_index = data.stop_time_index(trip_id, *stop_sequence)?;
+                Some(data.stop_times[stop_time_index].stop_id.clone())
+            });
+            if let Some(stop_id) = stop_id {
+                let point = *self
+                    .stop_circles
+                    .get(*data.stop_index_from_id.get(&stop_id)?)?;
+                return Some(Rect::from_points(point, point));
+            }
+        }
+
+        let trip_ids = if whole_feed {
+            None
+        } else if let Some(trip_id) = &data.selected_trip_id {
+            Some(vec![trip_id.clone()].into_iter().collect::<HashSet<_>>())
+        } else if let Some(route_id) = &data.selected_route_id {
+            Some(
+                data.trips
+                    .iter()
+                    .filter(|trip| &trip.route_id == route_id)
+                    .map(|trip| trip.id.clone())
+                    .collect::<HashSet<_>>(),
+            )
+        } else if let Some(agency_id) = &data.selected_agency_id {
+            let route_ids = data
+                .routes
+                .iter()
+                .filter(|route| &route.agency_id == agency_id)
+                .map(|route| route.id.clone())
+                .collect::<HashSet<_>>();
+            Some(
+                data.trips
+                    .iter()
+                    .filter(|trip| route_ids.contains(&trip.route_id))
+                    .map(|trip| trip.id.clone())
+                    .collect::<HashSet<_>>(),
+            )
+        } else {
+            // nothing is selected
+            return None;
+        };
+
+        let trips_points = self
+            .all_trip_paths_combined
+            .iter()
+            .filter(|(id, _color, _text_color, _path)| {
+                trip_ids
+                    .as_ref()
+                    .map_or(true, |trip_ids| trip_ids.contains(id))
+            })
+            .map(|(_id, _color, _text_color, path)| {
+                path.elements()
+                    .iter()
+                    .filter_map(|element| match element {
+                        PathEl::MoveTo(point) | PathEl::LineTo(point) => Some(*point),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .filter(|points| !points.is_empty())
+            .collect::<Vec<_>>();
+        if trips_points.is_empty() {
+            None
+        } else {
+            Some(min_max_trips_coords(&trips_points))
+        }
+    }
+
+    /// starts animating the map to centre on bounds (in REFERENCE_SIZE coords), zoomed in as far as it will fit. The caller needs to request an anim frame
+    fn fit_bounds(&mut self, bounds: Rect, canvas_size: Size) {
+        self.camera_animation = Some(CameraAnimation {
+            from: self.focal_point.centered_point(canvas_size),
+            to: bounds.center(),
+            zoom_level: fitting_zoom_level(bounds, canvas_size),
+            elapsed: 0,
+        });
+    }
+
+    /// converting a mouse Point (in canvas coords) to a (long, lat) coord
+    fn latlong_from_mouse(
+        &self,
+        data: &AppData,
+        long_lat_rect: Rect,
+        canvas_size: Size,
+        mouse_position: Point,
+    ) -> Point {
+        MapWidget::canvas_to_latlong(
+            self.reference_point_from_mouse(data, canvas_size, mouse_position),
+            long_lat_rect,
+            REFERENCE_SIZE as f64,
+        )
+    }
+
     fn latlong_to_canvas(latlong: Point, latlong_rect: Rect, canvas_max_dimension: f64) -> Point {
         let latlong_origin_vec = latlong - latlong_rect.origin();
         // NOTE since (x0,y0) is the bottom left of latlong_rect, we need to flip the xaxis to match the top left origin of canvas
@@ -207,6 +839,16 @@ impl MapWidget {
         (flipped_latlong_origin_vec * canvas_max_dimension / latlong_rect.size().max_side())
             .to_point()
     }
+    /// the inverse of latlong_to_canvas
+    fn canvas_to_latlong(point: Point, latlong_rect: Rect, canvas_max_dimension: f64) -> Point {
+        let flipped_latlong_origin_vec =
+            point.to_vec2() * latlong_rect.size().max_side() / canvas_max_dimension;
+        let latlong_origin_vec = Vec2::new(
+            flipped_latlong_origin_vec.x,
+            latlong_rect.height() - flipped_latlong_origin_vec.y,
+        );
+        latlong_rect.origin() + latlong_origin_vec
+    }
 
     fn draw_paths_onto_paint_ctx(&self, data: &AppData, ctx: &mut PaintCtx) {
         let _ = ctx.save();
@@ -415,9 +1057,9 @@ impl MapWidget {
             ctx.stroke(path, color, path_width);
         }
 
-        if let Some((index, _id)) = &data.selected_trip_id {
+        if let Some(index) = self.selected_trip_path_index(data) {
             let (trip_id, color, _text_color, path) =
-                self.all_trip_paths_combined.get(*index).unwrap();
+                self.all_trip_paths_combined.get(index).unwrap();
             // dbg!(path);
             ctx.stroke(path, &Color::WHITE, path_wb);
             ctx.stroke(path, &Color::BLACK, path_bb);
@@ -477,6 +1119,570 @@ impl MapWidget {
         let _ = ctx.restore();
     }
 
+    /// draw the paths of the trips going through the dragged stop over the top of the (now outdated) bitmap
+    fn draw_dragged_stop_paths(&self, data: &AppData, ctx: &mut PaintCtx) {
+        let dragged_stop = match &self.dragged_stop {
+            Some(dragged_stop) => dragged_stop,
+            None => return,
+        };
+        let _ = ctx.save();
+
+        let transformed_focal_point = self
+            .focal_point
+            .to_point_within_size(ctx.size() * data.map_zoom_level.to_f64())
+            .to_vec2()
+            * -1.;
+        ctx.transform(Affine::translate(transformed_focal_point));
+        let center_adjust = ctx.size() * 0.5;
+        ctx.transform(Affine::translate(center_adjust.to_vec2()));
+        ctx.transform(Affine::scale(data.map_zoom_level.to_f64()));
+        let ctx_max_side = ctx.size().max_side();
+        ctx.transform(Affine::scale(ctx_max_side / REFERENCE_SIZE as f64));
+
+        let path_width = data.map_zoom_level.path_width(ctx.size().max_side()) * PATH_HIGHLIGHTED;
+        let path_bb = path_width * PATH_BLACK_BACKGROUND_MULT;
+        for index in &dragged_stop.trip_indexes {
+            let (_trip_id, color, _text_color, path) =
+                self.all_trip_paths_combined.get(*index).unwrap();
+            ctx.stroke(path, &Color::BLACK, path_bb);
+            ctx.stroke(path, color, path_width);
+        }
+        let _ = ctx.restore();
+    }
+
+    fn create_layer_paths(&mut self, data: &AppData) {
+        let long_lat_rect = self.long_lat_rect;
+        let latlong_to_bitmap = |coord: &Point| {
+            MapWidget::latlong_to_canvas(*coord, long_lat_rect, REFERENCE_SIZE as f64)
+        };
+        self.layer_paths = data
+            .map_layers
+            .iter()
+            .map(|layer| {
+                layer
+                    .features
+                    .iter()
+                    .map(|feature| {
+                        let mut paths = FeaturePaths::default();
+                        for geometry in &feature.geometries {
+                            match geometry {
+                                LayerGeometry::Point(point) => {
+                                    paths.points.push(latlong_to_bitmap(point))
+                                }
+                                LayerGeometry::LineString(line) => paths.lines.push(
+                                    bez_path_from_coords_iter(line.iter().map(latlong_to_bitmap)),
+                                ),
+                                LayerGeometry::Polygon(rings) => {
+                                    let mut polygon = BezPath::new();
+                                    for ring in rings {
+                                        for (i, point) in
+                                            ring.iter().map(latlong_to_bitmap).enumerate()
+                                        {
+                                            if i == 0 {
+                                                polygon.move_to(point);
+                                            } else {
+                                                polygon.line_to(point);
+                                            }
+                                        }
+                                        polygon.close_path();
+                                    }
+                                    paths.polygons.push(polygon);
+                                }
+                            }
+                        }
+                        paths
+                    })
+                    .collect()
+            })
+            .collect();
+        self.hovered_layer_feature = None;
+    }
+
+    /// (layer index, feature index) of the topmost visible layer feature at a point in REFERENCE_SIZE coords
+    fn find_hovered_layer_feature(&self, data: &AppData, point: Point) -> Option<(usize, usize)> {
+        let path_width = data.map_zoom_level.path_width(REFERENCE_SIZE as f64);
+        for (layer_index, (layer, features)) in data
+            .map_layers
+            .iter()
+            .zip(self.layer_paths.iter())
+            .enumerate()
+            .rev()
+        {
+            if !layer.visible {
+                continue;
+            }
+            let tolerance = path_width * layer.stroke_width.max(1.) * LARGE_CIRCLE_MULT;
+            for (feature_index, paths) in features.iter().enumerate().rev() {
+                let hovered = paths
+                    .points
+                    .iter()
+                    .any(|feature_point| feature_point.distance(point) < tolerance)
+                    || paths.lines.iter().any(|line| {
+                        line.segments()
+                            .any(|seg| seg.nearest(point, 1.).distance_sq < tolerance * tolerance)
+                    })
+                    || paths.polygons.iter().any(|polygon| {
+                        polygon.bounding_box().contains(point) && polygon.winding(point) % 2 != 0
+                    });
+                if hovered {
+                    return Some((layer_index, feature_index));
+                }
+            }
+        }
+        None
+    }
+
+    /// GeoJSON layers, drawn underneath the network
+    fn draw_layers(&self, data: &AppData, ctx: &mut PaintCtx) {
+        if !data.map_layers.iter().any(|layer| layer.visible) {
+            return;
+        }
+        let _ = ctx.save();
+
+        let transformed_focal_point = self
+            .focal_point
+            .to_point_within_size(ctx.size() * data.map_zoom_level.to_f64())
+            .to_vec2()
+            * -1.;
+        ctx.transform(Affine::translate(transformed_focal_point));
+        let center_adjust = ctx.size() * 0.5;
+        ctx.transform(Affine::translate(center_adjust.to_vec2()));
+        ctx.transform(Affine::scale(data.map_zoom_level.to_f64()));
+        let ctx_max_side = ctx.size().max_side();
+        ctx.transform(Affine::scale(ctx_max_side / REFERENCE_SIZE as f64));
+
+        let path_width = data.map_zoom_level.path_width(ctx.size().max_side());
+        for (layer_index, (layer, features)) in data
+            .map_layers
+            .iter()
+            .zip(self.layer_paths.iter())
+            .enumerate()
+        {
+            if !layer.visible {
+                continue;
+            }
+            let color = layer.color();
+            let fill_color = color.clone().with_alpha(layer.fill_opacity);
+            for (feature_index, paths) in features.iter().enumerate() {
+                let mut width = path_width * layer.stroke_width;
+                if self.hovered_layer_feature == Some((layer_index, feature_index)) {
+                    width *= PATH_HIGHLIGHTED * 2.;
+                }
+                for polygon in &paths.polygons {
+                    if layer.fill_opacity > 0. {
+                        ctx.fill_even_odd(polygon, &fill_color);
+                    }
+                    ctx.stroke(polygon, &color, width);
+                }
+                for line in &paths.lines {
+                    ctx.stroke(line, &color, width);
+                }
+                for point in &paths.points {
+                    ctx.fill(Circle::new(*point, width * LARGE_CIRCLE_MULT), &color);
+                }
+            }
+        }
+        let _ = ctx.restore();
+    }
+
+    /// highlights the selected stops and trips, and the selection area being drawn
+    fn draw_selection(&self, data: &AppData, ctx: &mut PaintCtx) {
+        let selection_color = Color::rgba8(0, 120, 255, 160);
+        if !data.map_selected_stop_ids.is_empty() || !data.map_selected_trip_ids.is_empty() {
+            let _ = ctx.save();
+
+            let transformed_focal_point = self
+                .focal_point
+                .to_point_within_size(ctx.size() * data.map_zoom_level.to_f64())
+                .to_vec2()
+                * -1.;
+            ctx.transform(Affine::translate(transformed_focal_point));
+            let center_adjust = ctx.size() * 0.5;
+            ctx.transform(Affine::translate(center_adjust.to_vec2()));
+            ctx.transform(Affine::scale(data.map_zoom_level.to_f64()));
+            let ctx_max_side = ctx.size().max_side();
+            ctx.transform(Affine::scale(ctx_max_side / REFERENCE_SIZE as f64));
+
+            let path_width = data.map_zoom_level.path_width(ctx.size().max_side());
+            for (trip_id, color, _text_color, path) in &self.all_trip_paths_combined {
+                if data.map_selected_trip_ids.contains(trip_id) {
+                    ctx.stroke(
+                        path,
+                        &selection_color,
+                        path_width * PATH_WHITE_BACKGROUND_MULT,
+                    );
+                    ctx.stroke(path, color, path_width);
+                }
+            }
+            for stop_id in &data.map_selected_stop_ids {
+                if let Some(stop_index) = data.stop_index_from_id.get(stop_id) {
+                    let point = self.stop_circles[*stop_index];
+                    ctx.fill(
+                        Circle::new(point, path_width * LARGE_CIRCLE_WHITE_BACKGROUND_MULT),
+                        &selection_color,
+                    );
+                    ctx.fill(
+                        Circle::new(point, path_width * LARGE_CIRCLE_MULT),
+                        &Color::WHITE,
+                    );
+                }
+            }
+            let _ = ctx.restore();
+        }
+
+        if let Some(selection_area) = &self.selection_area {
+            let mut area = bez_path_from_coords_iter(selection_area.canvas_points().into_iter());
+            area.close_path();
+            ctx.fill(&area, &Color::rgba8(0, 120, 255, 40));
+            ctx.stroke(&area, &selection_color, 1.);
+        }
+    }
+
+    fn draw_edited_shape(&self, data: &AppData, ctx: &mut PaintCtx) {
+        let points = match &self.edited_shape {
+            Some((_shape_id, points)) => points,
+            None => return,
+        };
+        let _ = ctx.save();
+
+        let transformed_focal_point = self
+            .focal_point
+            .to_point_within_size(ctx.size() * data.map_zoom_level.to_f64())
+            .to_vec2()
+            * -1.;
+        ctx.transform(Affine::translate(transformed_focal_point));
+        let center_adjust = ctx.size() * 0.5;
+        ctx.transform(Affine::translate(center_adjust.to_vec2()));
+        ctx.transform(Affine::scale(data.map_zoom_level.to_f64()));
+        let ctx_max_side = ctx.size().max_side();
+        ctx.transform(Affine::scale(ctx_max_side / REFERENCE_SIZE as f64));
+
+        let path_width = data.map_zoom_level.path_width(ctx.size().max_side()) * PATH_HIGHLIGHTED;
+        let s_circle_bb = path_width * SMALL_CIRCLE_BLACK_BACKGROUND_MULT;
+        let s_circle = path_width * SMALL_CIRCLE_MULT;
+        let l_circle_bb = path_width * LARGE_CICLE_BLACK_BACKGROUND_MULT;
+        let l_circle = path_width * LARGE_CIRCLE_MULT;
+
+        let path = bez_path_from_coords_iter(points.iter().cloned());
+        ctx.stroke(path, &Color::BLACK, path_width * 0.5);
+        for (i, point) in points.iter().enumerate() {
+            if self.dragged_vertex == Some(i) {
+                ctx.fill(Circle::new(*point, l_circle_bb), &Color::BLACK);
+                ctx.fill(Circle::new(*point, l_circle), &Color::WHITE);
+            } else {
+                ctx.fill(Circle::new(*point, s_circle_bb), &Color::BLACK);
+                ctx.fill(Circle::new(*point, s_circle), &Color::YELLOW);
+            }
+        }
+        let _ = ctx.restore();
+    }
+
+    /// draw a generated shape which hasn't been accepted yet
+    fn draw_shape_preview(&self, data: &AppData, ctx: &mut PaintCtx) {
+        let shape_preview = match &data.shape_preview {
+            Some(shape_preview) => shape_preview,
+            None => return,
+        };
+        let _ = ctx.save();
+
+        let transformed_focal_point = self
+            .focal_point
+            .to_point_within_size(ctx.size() * data.map_zoom_level.to_f64())
+            .to_vec2()
+            * -1.;
+        ctx.transform(Affine::translate(transformed_focal_point));
+        let center_adjust = ctx.size() * 0.5;
+        ctx.transform(Affine::translate(center_adjust.to_vec2()));
+        ctx.transform(Affine::scale(data.map_zoom_level.to_f64()));
+        let ctx_max_side = ctx.size().max_side();
+        ctx.transform(Affine::scale(ctx_max_side / REFERENCE_SIZE as f64));
+
+        let path_width = data.map_zoom_level.path_width(ctx.size().max_side()) * PATH_HIGHLIGHTED;
+        let path_bb = path_width * PATH_BLACK_BACKGROUND_MULT;
+        let path = bez_path_from_coords_iter(shape_preview.coords.iter().map(|coord| {
+            MapWidget::latlong_to_canvas(*coord, self.long_lat_rect, REFERENCE_SIZE as f64)
+        }));
+        ctx.stroke(&path, &Color::BLACK, path_bb);
+        ctx.stroke(&path, &Color::FUCHSIA, path_width);
+        let _ = ctx.restore();
+    }
+
+    /// mark stops which are too far from their trip's shape
+    fn draw_shape_dist_warnings(&self, data: &AppData, ctx: &mut PaintCtx) {
+        if data.shape_dist_warnings.is_empty() {
+            return;
+        }
+        let _ = ctx.save();
+
+        let transformed_focal_point = self
+            .focal_point
+            .to_point_within_size(ctx.size() * data.map_zoom_level.to_f64())
+            .to_vec2()
+            * -1.;
+        ctx.transform(Affine::translate(transformed_focal_point));
+        let center_adjust = ctx.size() * 0.5;
+        ctx.transform(Affine::translate(center_adjust.to_vec2()));
+        ctx.transform(Affine::scale(data.map_zoom_level.to_f64()));
+        let ctx_max_side = ctx.size().max_side();
+        ctx.transform(Affine::scale(ctx_max_side / REFERENCE_SIZE as f64));
+
+        let path_width = data.map_zoom_level.path_width(ctx.size().max_side()) * PATH_HIGHLIGHTED;
+        let l_circle_bb = path_width * LARGE_CICLE_BLACK_BACKGROUND_MULT;
+        let l_circle = path_width * LARGE_CIRCLE_MULT;
+        for warning in data.shape_dist_warnings.iter() {
+            if let Some(stop_index) = data.stop_index_from_id.get(&warning.stop_id) {
+                let point = self.stop_circles[*stop_index];
+                ctx.fill(Circle::new(point, l_circle_bb), &Color::BLACK);
+                ctx.fill(Circle::new(point, l_circle), &Color::RED);
+            }
+        }
+        let _ = ctx.restore();
+    }
+
+    /// draw stop names next to the stops, skipping any which would overlap an already drawn label. The selected trip's stops are labelled first.
+    fn draw_stop_labels(&self, data: &AppData, ctx: &mut PaintCtx) {
+        if !data.map_show_labels || data.map_zoom_level.to_usize() < LABEL_MIN_ZOOM {
+            return;
+        }
+        let canvas_rect = ctx.size().to_rect();
+        let minimap_rect = canvas_rect.scale_from_origin(MINIMAP_PROPORTION);
+
+        let mut stop_indexes = Vec::new();
+        if let Some(trip_id) = &data.selected_trip_id {
+            if let Some(stop_times_range) = data.stop_time_range_from_trip_id.get(trip_id) {
+                for i in stop_times_range.0..stop_times_range.1 {
+                    let stop_time = data.stop_times.get(i).unwrap();
+                    stop_indexes.push(*data.stop_index_from_id.get(&stop_time.stop_id).unwrap());
+                }
+            }
+        }
+        stop_indexes.extend(0..data.stops.len());
+
+        let mut label_rects: Vec<Rect> = Vec::new();
+        for stop_index in stop_indexes {
+            if label_rects.len() >= MAX_LABELS {
+                break;
+            }
+            let point =
+                self.reference_point_to_canvas(data, ctx.size(), self.stop_circles[stop_index]);
+            if !canvas_rect.contains(point)
+                || minimap_rect.contains(point)
+                || label_rects.iter().any(|rect| rect.contains(point))
+            {
+                continue;
+            }
+            let stop = data.stops.get(stop_index).unwrap();
+            let layout = ctx
+                .text()
+                .new_text_layout(stop.name.clone())
+                .font(FontFamily::SYSTEM_UI, LABEL_FONT_SIZE)
+                .text_color(Color::BLACK)
+                .build()
+                .unwrap();
+            let label_rect = Rect::from_origin_size(
+                point + Vec2::new(6., -layout.size().height / 2.),
+                layout.size(),
+            )
+            .inflate(2., 1.);
+            if label_rects
+                .iter()
+                .chain(std::iter::once(&minimap_rect))
+                .any(|rect| rect.intersect(label_rect).area() > 0.)
+            {
+                continue;
+            }
+            ctx.fill(label_rect.to_rounded_rect(2.), &Color::rgba(1., 1., 1., 0.8));
+            ctx.draw_text(&layout, label_rect.origin() + Vec2::new(2., 1.));
+            label_rects.push(label_rect);
+        }
+    }
+
+    /// (text, background color, text color) for each line of the tooltip for whatever is under the mouse
+    fn tooltip_lines(&self, data: &AppData) -> Vec<(String, Color, Color)> {
+        let stop_text = |stop: &MyStop| match &stop.code {
+            Some(code) => format!("{} ({})", stop.name, code),
+            None => stop.name.clone(),
+        };
+        let hovered_stop = if let Some(stop_id) = &self.hovered_stop_id {
+            data.stop_index_from_id
+                .get(stop_id)
+                .and_then(|stop_index| data.stops.get(*stop_index))
+        } else if let Some((trip_id, stop_sequence)) = &data.hovered_stop_time_id {
+            data.stop_time_range_from_trip_id
+                .get(trip_id)
+                .and_then(|stop_times_range| {
+                    (stop_times_range.0..stop_times_range.1)
+                        .map(|i| data.stop_times.get(i).unwrap())
+                        .find(|stop_time| &stop_time.stop_sequence == stop_sequence)
+                })
+                .and_then(|stop_time| data.stop_index_from_id.get(&stop_time.stop_id))
+                .and_then(|stop_index| data.stops.get(*stop_index))
+        } else {
+            None
+        };
+        if let Some(stop) = hovered_stop {
+            return vec![(stop_text(stop), Color::WHITE, Color::BLACK)];
+        }
+        if let Some((_centre, stop_indexes)) = self.hovered_cluster_stops(data) {
+            let mut lines = vec![(
+                format!("{} stops, click to zoom in", stop_indexes.len()),
+                CLUSTER_COLOR,
+                Color::WHITE,
+            )];
+            lines.extend(
+                stop_indexes
+                    .iter()
+                    .take(TOOLTIP_MAX_ROUTES)
+                    .filter_map(|stop_index| data.stops.get(*stop_index))
+                    .map(|stop| (stop_text(stop), Color::WHITE, Color::BLACK)),
+            );
+            if stop_indexes.len() > TOOLTIP_MAX_ROUTES {
+                lines.push((
+                    format!("and {} more", stop_indexes.len() - TOOLTIP_MAX_ROUTES),
+                    Color::WHITE,
+                    Color::BLACK,
+                ));
+            }
+            return lines;
+        }
+
+        let mut route_ids: Vec<&String> = Vec::new();
+        for index in &data.hovered_trip_paths {
+            let (trip_id, _color, _text_color, _path) =
+                self.all_trip_paths_combined.get(*index).unwrap();
+            if let Some(trip) = data.trip(trip_id) {
+                if !route_ids.contains(&&trip.route_id) {
+                    route_ids.push(&trip.route_id);
+                }
+            }
+        }
+        let n_routes = route_ids.len();
+        let mut lines = route_ids
+            .iter()
+            .take(TOOLTIP_MAX_ROUTES)
+            .filter_map(|route_id| data.route(route_id))
+            .map(|route| {
+                let RGB { r, g, b } = route.color.0;
+                let color = Color::rgb8(r, g, b);
+                let RGB { r, g, b } = route.text_color.0;
+                let text_color = Color::rgb8(r, g, b);
+                (
+                    format!("{} {}", route.short_name, route.long_name),
+                    color,
+                    text_color,
+                )
+            })
+            .collect::<Vec<_>>();
+        if n_routes > TOOLTIP_MAX_ROUTES {
+            lines.push((
+                format!("and {} more", n_routes - TOOLTIP_MAX_ROUTES),
+                Color::WHITE,
+                Color::BLACK,
+            ));
+        }
+        if n_routes == 0 {
+            return self.layer_tooltip_lines(data);
+        }
+        lines
+    }
+
+    /// the layer name and properties of the hovered GeoJSON layer feature
+    fn layer_tooltip_lines(&self, data: &AppData) -> Vec<(String, Color, Color)> {
+        let (layer_index, feature_index) = match self.hovered_layer_feature {
+            Some(hovered_layer_feature) => hovered_layer_feature,
+            None => return Vec::new(),
+        };
+        let layer = match data.map_layers.get(layer_index) {
+            Some(layer) => layer,
+            None => return Vec::new(),
+        };
+        let feature = match layer.features.get(feature_index) {
+            Some(feature) => feature,
+            None => return Vec::new(),
+        };
+        let mut lines = vec![(layer.name.clone(), layer.color(), Color::WHITE)];
+        lines.extend(
+            feature
+                .properties
+                .iter()
+                .take(TOOLTIP_MAX_PROPERTIES)
+                .map(|(key, value)| (format!("{}: {}", key, value), Color::WHITE, Color::BLACK)),
+        );
+        if feature.properties.len() > TOOLTIP_MAX_PROPERTIES {
+            lines.push((
+                format!(
+                    "and {} more",
+                    feature.properties.len() - TOOLTIP_MAX_PROPERTIES
+                ),
+                Color::WHITE,
+                Color::BLACK,
+            ));
+        }
+        lines
+    }
+
+    fn draw_tooltip(&self, data: &AppData, ctx: &mut PaintCtx) {
+        let mouse_position = match self.tooltip_position {
+            Some(mouse_position) => mouse_position,
+            None => return,
+        };
+        if self.dragged_stop.is_some() || self.dragged_vertex.is_some() {
+            return;
+        }
+        let lines = self.tooltip_lines(data);
+        if lines.is_empty() {
+            return;
+        }
+        let layouts = lines
+            .into_iter()
+            .map(|(text, color, text_color)| {
+                let layout = ctx
+                    .text()
+                    .new_text_layout(text)
+                    .font(FontFamily::SYSTEM_UI, TOOLTIP_FONT_SIZE)
+                    .text_color(text_color)
+                    .build()
+                    .unwrap();
+                (layout, color)
+            })
+            .collect::<Vec<_>>();
+        let padding = Vec2::new(4., 2.);
+        let width = layouts
+            .iter()
+            .map(|(layout, _color)| layout.size().width)
+            .fold(0., f64::max)
+            + padding.x * 2.;
+        let line_height = layouts
+            .iter()
+            .map(|(layout, _color)| layout.size().height)
+            .fold(0., f64::max)
+            + padding.y * 2.;
+        let height = line_height * layouts.len() as f64;
+
+        // keep the tooltip inside the canvas
+        let size = ctx.size();
+        let mut origin = mouse_position + Vec2::new(12., 12.);
+        if origin.x + width > size.width {
+            origin.x = (mouse_position.x - 12. - width).max(0.);
+        }
+        if origin.y + height > size.height {
+            origin.y = (mouse_position.y - 12. - height).max(0.);
+        }
+
+        ctx.fill(
+            Rect::from_origin_size(origin, (width, height)).inflate(1., 1.),
+            &Color::BLACK,
+        );
+        for (i, (layout, color)) in layouts.iter().enumerate() {
+            let line_origin = origin + Vec2::new(0., line_height * i as f64);
+            ctx.fill(
+                Rect::from_origin_size(line_origin, (width, line_height)),
+                color,
+            );
+            ctx.draw_text(layout, line_origin + padding);
+        }
+    }
+
     fn draw_highlighted_stop(&self, data: &AppData, ctx: &mut PaintCtx) {
         myprint!("draw_stop_highlights");
         let _ = ctx.save();
@@ -516,6 +1722,17 @@ impl MapWidget {
             ctx.fill(Circle::new(*point, l_circle_bb), &Color::BLACK);
             ctx.fill(Circle::new(*point, l_circle), &Color::WHITE);
         }
+        if let Some((centre, stop_indexes)) = self.hovered_cluster_stops(data) {
+            let radius = cluster_radius(
+                data.map_zoom_level.path_width(ctx.size().max_side()),
+                stop_indexes.len(),
+            );
+            ctx.stroke(
+                Circle::new(*centre, radius + path_width),
+                &Color::BLACK,
+                path_width,
+            );
+        }
         let _ = ctx.restore();
     }
 
@@ -537,38 +1754,456 @@ impl MapWidget {
         let ctx_max_side = ctx.size().max_side();
         ctx.transform(Affine::scale(ctx_max_side / REFERENCE_SIZE as f64));
 
-        let path_width = data.map_zoom_level.path_width(ctx.size().max_side()) * PATH_HIGHLIGHTED;
+        let path_width = data.map_zoom_level.path_width(ctx.size().max_side()) * PATH_HIGHLIGHTED;
+
+        let s_circle_bb = path_width * SMALL_CIRCLE_BLACK_BACKGROUND_MULT;
+        let s_circle = path_width * SMALL_CIRCLE_MULT;
+
+        let _l_circle_wb = path_width * LARGE_CIRCLE_WHITE_BACKGROUND_MULT;
+        let l_circle_bb = path_width * LARGE_CICLE_BLACK_BACKGROUND_MULT;
+        let l_circle = path_width * LARGE_CIRCLE_MULT;
+
+        // paint all stops first, then paint hovered stop over the top. This ensures it is not covered by any unhovered stop and avoids needing to compare stop ids every loop iteration (actually we still need to compare stop ids to find the Point, unless eg we save the index of the Point)
+        for (point, _stop) in self.stop_circles.iter().zip(data.stops.iter()) {
+            ctx.fill(Circle::new(*point, s_circle_bb), &Color::BLACK);
+            ctx.fill(Circle::new(*point, s_circle), &Color::WHITE);
+        }
+        if let Some(stop_id) = &self.hovered_stop_id {
+            let point = self
+                .stop_circles
+                .iter()
+                .zip(data.stops.iter())
+                .find_map(|(point, stop)| {
+                    if &stop.id == stop_id {
+                        Some(point)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap();
+            ctx.fill(Circle::new(*point, l_circle_bb), &Color::BLACK);
+            ctx.fill(Circle::new(*point, l_circle), &Color::WHITE);
+        }
+        let _ = ctx.restore();
+    }
+    /// metres per canvas pixel at the center of the view
+    fn metres_per_pixel(&self, data: &AppData, canvas_size: Size) -> f64 {
+        let center = canvas_size.to_rect().center();
+        let a = self.latlong_from_mouse(data, self.long_lat_rect, canvas_size, center);
+        let b = self.latlong_from_mouse(
+            data,
+            self.long_lat_rect,
+            canvas_size,
+            center + Vec2::new(100., 0.),
+        );
+        haversine_distance(a, b) / 100.
+    }
+
+    /// the largest 1, 2, or 5 x 10^n metres which is no more than max_metres
+    fn scale_bar_metres(max_metres: f64) -> f64 {
+        let magnitude = 10f64.powf(max_metres.log10().floor());
+        [5., 2., 1.]
+            .iter()
+            .map(|multiple| multiple * magnitude)
+            .find(|metres| *metres <= max_metres)
+            .unwrap_or(magnitude)
+    }
+
+    fn readout_rect(canvas_size: Size) -> Rect {
+        Rect::from_origin_size(
+            (
+                canvas_size.width - OVERLAY_MARGIN - READOUT_SIZE.width,
+                canvas_size.height - OVERLAY_MARGIN - READOUT_SIZE.height,
+            ),
+            READOUT_SIZE,
+        )
+    }
+
+    fn reset_view_rect(canvas_size: Size) -> Rect {
+        Rect::from_origin_size(
+            (
+                canvas_size.width - OVERLAY_MARGIN - RESET_VIEW_SIZE,
+                OVERLAY_MARGIN,
+            ),
+            (RESET_VIEW_SIZE, RESET_VIEW_SIZE),
+        )
+    }
+
+    /// draw text on a translucent white background, with origin being the top left, or if right_align the top right, of the background
+    fn draw_overlay_text(ctx: &mut PaintCtx, text: String, origin: Point, right_align: bool) {
+        let layout = ctx
+            .text()
+            .new_text_layout(text)
+            .font(FontFamily::SYSTEM_UI, OVERLAY_FONT_SIZE)
+            .text_color(Color::BLACK)
+            .build()
+            .unwrap();
+        let padding = Vec2::new(3., 1.);
+        let size = layout.size() + (padding * 2.).to_size();
+        let origin = if right_align {
+            origin - Vec2::new(size.width, 0.)
+        } else {
+            origin
+        };
+        ctx.fill(
+            Rect::from_origin_size(origin, size).to_rounded_rect(2.),
+            &Color::rgba(1., 1., 1., 0.8),
+        );
+        ctx.draw_text(&layout, origin + padding);
+    }
+
+    /// lat/long under the mouse, on an opaque background so it can be repainted on it's own as the mouse moves
+    fn draw_readout(&self, data: &AppData, ctx: &mut PaintCtx) {
+        if let Some(mouse_position) = self.mouse_position {
+            let size = ctx.size();
+            let latlong = self.latlong_from_mouse(data, self.long_lat_rect, size, mouse_position);
+            let layout = ctx
+                .text()
+                .new_text_layout(format!("{:.5}, {:.5}", latlong.y, latlong.x))
+                .font(FontFamily::SYSTEM_UI, OVERLAY_FONT_SIZE)
+                .text_color(Color::BLACK)
+                .build()
+                .unwrap();
+            let rect = MapWidget::readout_rect(size);
+            ctx.fill(rect, &Color::WHITE);
+            ctx.draw_text(
+                &layout,
+                Point::new(rect.x1 - 3. - layout.size().width, rect.y0 + 1.),
+            );
+        }
+    }
+
+    /// scale bar in the bottom left, lat/long of the mouse in the bottom right, and north arrow and reset view button in the top right
+    fn draw_overlays(&self, data: &AppData, ctx: &mut PaintCtx) {
+        let size = ctx.size();
+
+        // scale bar
+        let metres_per_pixel = self.metres_per_pixel(data, size);
+        if metres_per_pixel > 0. && metres_per_pixel.is_finite() {
+            let metres = MapWidget::scale_bar_metres(SCALE_BAR_MAX_WIDTH * metres_per_pixel);
+            let width = metres / metres_per_pixel;
+            let y = size.height - OVERLAY_MARGIN;
+            let x0 = OVERLAY_MARGIN;
+            let x1 = OVERLAY_MARGIN + width;
+            let mut bar = BezPath::new();
+            bar.move_to((x0, y - 5.));
+            bar.line_to((x0, y));
+            bar.line_to((x1, y));
+            bar.line_to((x1, y - 5.));
+            ctx.stroke(&bar, &Color::WHITE, 4.);
+            ctx.stroke(&bar, &Color::BLACK, 2.);
+            let text = if metres >= 1000. {
+                format!("{} km", metres / 1000.)
+            } else {
+                format!("{} m", metres)
+            };
+            MapWidget::draw_overlay_text(ctx, text, Point::new(x0, y - 25.), false);
+        }
+
+        self.draw_readout(data, ctx);
+
+        // reset view button
+        let reset_rect = MapWidget::reset_view_rect(size);
+        ctx.fill(reset_rect.to_rounded_rect(3.), &Color::WHITE);
+        ctx.stroke(reset_rect.to_rounded_rect(3.), &Color::BLACK, 1.);
+        let center = reset_rect.center();
+        ctx.stroke(
+            Circle::new(center, RESET_VIEW_SIZE / 4.),
+            &Color::BLACK,
+            1.5,
+        );
+        ctx.fill(Circle::new(center, 2.), &Color::BLACK);
+        for direction in [Vec2::new(1., 0.), Vec2::new(0., 1.)] {
+            let reach = direction * RESET_VIEW_SIZE * 0.4;
+            ctx.stroke(Line::new(center - reach, center + reach), &Color::BLACK, 1.);
+        }
+
+        // north arrow. The map is drawn with north always at the top
+        let arrow_center = Point::new(
+            reset_rect.center().x,
+            reset_rect.y1 + OVERLAY_MARGIN + RESET_VIEW_SIZE / 2.,
+        );
+        let mut arrow = BezPath::new();
+        arrow.move_to(arrow_center + Vec2::new(0., -12.));
+        arrow.line_to(arrow_center + Vec2::new(7., 8.));
+        arrow.line_to(arrow_center + Vec2::new(0., 4.));
+        arrow.line_to(arrow_center + Vec2::new(-7., 8.));
+        arrow.close_path();
+        ctx.fill(&arrow, &Color::BLACK);
+        ctx.stroke(&arrow, &Color::WHITE, 1.);
+        let layout = ctx
+            .text()
+            .new_text_layout("N")
+            .font(FontFamily::SYSTEM_UI, OVERLAY_FONT_SIZE)
+            .text_color(Color::BLACK)
+            .build()
+            .unwrap();
+        ctx.draw_text(
+            &layout,
+            arrow_center + Vec2::new(-layout.size().width / 2., 9.),
+        );
+    }
+
+    /// size in pixels of an exported map. The current view keeps the aspect ratio of the canvas, and the whole network is cropped to the extent of the data
+    fn export_size(&self, data: &AppData, canvas_size: Size) -> Size {
+        let resolution = data.map_export_resolution as f64;
+        let size = if data.map_export_whole_network {
+            let long_lat_size = self.long_lat_rect.size();
+            long_lat_size * resolution / long_lat_size.max_side()
+        } else {
+            Size::new(
+                resolution,
+                resolution * canvas_size.height / canvas_size.width,
+            )
+        };
+        Size::new(size.width.round().max(1.), size.height.round().max(1.))
+    }
+
+    /// transform from REFERENCE_SIZE coords to the exported map, which is the same as the transform used to draw to the canvas, but for the export size
+    fn export_transform(&self, data: &AppData, size: Size) -> Affine {
+        if data.map_export_whole_network {
+            Affine::scale(size.max_side() / REFERENCE_SIZE as f64)
+        } else {
+            let zoom = data.map_zoom_level.to_f64();
+            let transformed_focal_point =
+                self.focal_point.to_point_within_size(size * zoom).to_vec2() * -1.;
+            Affine::translate(transformed_focal_point)
+                * Affine::translate((size * 0.5).to_vec2())
+                * Affine::scale(zoom)
+                * Affine::scale(size.max_side() / REFERENCE_SIZE as f64)
+        }
+    }
+
+    /// path width in pixels of the exported map
+    fn export_path_width(&self, data: &AppData, size: Size) -> f64 {
+        let zoom_level = if data.map_export_whole_network {
+            ZoomLevel::One
+        } else {
+            data.map_zoom_level
+        };
+        zoom_level.path_width(REFERENCE_SIZE as f64)
+            * self.export_transform(data, size).as_coeffs()[0]
+    }
 
-        let s_circle_bb = path_width * SMALL_CIRCLE_BLACK_BACKGROUND_MULT;
-        let s_circle = path_width * SMALL_CIRCLE_MULT;
+    /// (position, name) of the stops to label in an exported map, skipping stops outside the map or whose label would overlap an earlier label. Label sizes are estimated from the number of characters, since the svg export has no text layout.
+    fn export_labels(&self, data: &AppData, size: Size) -> Vec<(Point, String)> {
+        if !data.map_export_labels {
+            return Vec::new();
+        }
+        let transform = self.export_transform(data, size);
+        let font_size = size.max_side() * EXPORT_FONT_SIZE_PROPORTION;
+        let mut label_rects: Vec<Rect> = Vec::new();
+        let mut labels = Vec::new();
+        for (stop_circle, stop) in self.stop_circles.iter().zip(data.stops.iter()) {
+            let point = transform * *stop_circle;
+            if !size.to_rect().contains(point) {
+                continue;
+            }
+            let label_rect = Rect::from_origin_size(
+                point + Vec2::new(font_size * 0.5, -font_size * 0.6),
+                (
+                    stop.name.chars().count() as f64 * font_size * 0.6,
+                    font_size * 1.2,
+                ),
+            );
+            if label_rects
+                .iter()
+                .any(|rect| rect.intersect(label_rect).area() > 0.)
+            {
+                labels.push((point, String::new()));
+                continue;
+            }
+            label_rects.push(label_rect);
+            labels.push((point, stop.name.clone()));
+        }
+        labels
+    }
 
-        let _l_circle_wb = path_width * LARGE_CIRCLE_WHITE_BACKGROUND_MULT;
-        let l_circle_bb = path_width * LARGE_CICLE_BLACK_BACKGROUND_MULT;
-        let l_circle = path_width * LARGE_CIRCLE_MULT;
+    fn draw_export(&self, data: &AppData, ctx: &mut impl RenderContext, size: Size) {
+        ctx.fill(size.to_rect(), &Color::WHITE);
+        let path_width = self.export_path_width(data, size);
 
-        // paint all stops first, then paint hovered stop over the top. This ensures it is not covered by any unhovered stop and avoids needing to compare stop ids every loop iteration (actually we still need to compare stop ids to find the Point, unless eg we save the index of the Point)
-        for (point, _stop) in self.stop_circles.iter().zip(data.stops.iter()) {
-            ctx.fill(Circle::new(*point, s_circle_bb), &Color::BLACK);
-            ctx.fill(Circle::new(*point, s_circle), &Color::WHITE);
+        let _ = ctx.save();
+        let transform = self.export_transform(data, size);
+        ctx.transform(transform);
+        for (_trip_id, color, _text_color, path) in &self.all_trip_paths_combined {
+            ctx.stroke(path, color, path_width / transform.as_coeffs()[0]);
         }
-        if let Some(stop_id) = &self.hovered_stop_id {
-            let point = self
-                .stop_circles
-                .iter()
-                .zip(data.stops.iter())
-                .find_map(|(point, stop)| {
-                    if &stop.id == stop_id {
-                        Some(point)
-                    } else {
-                        None
-                    }
-                })
+        let _ = ctx.restore();
+
+        // labels are drawn without the transform so the text isn't scaled
+        let font_size = size.max_side() * EXPORT_FONT_SIZE_PROPORTION;
+        for (point, name) in self.export_labels(data, size) {
+            ctx.fill(Circle::new(point, path_width), &Color::BLACK);
+            ctx.fill(Circle::new(point, path_width * 0.6), &Color::WHITE);
+            if name.is_empty() {
+                continue;
+            }
+            let layout = ctx
+                .text()
+                .new_text_layout(name)
+                .font(FontFamily::SYSTEM_UI, font_size)
+                .text_color(Color::BLACK)
+                .build()
                 .unwrap();
-            ctx.fill(Circle::new(*point, l_circle_bb), &Color::BLACK);
-            ctx.fill(Circle::new(*point, l_circle), &Color::WHITE);
+            ctx.draw_text(
+                &layout,
+                point + Vec2::new(font_size * 0.5, -layout.size().height / 2.),
+            );
+        }
+    }
+
+    /// render the current view, or the whole network, to a png using the same piet bitmap pipeline as make_bitmap
+    fn export_png(
+        &self,
+        data: &AppData,
+        canvas_size: Size,
+        path: impl AsRef<Path>,
+    ) -> Result<(), Box<dyn Error>> {
+        let size = self.export_size(data, canvas_size);
+        let mut device = Device::new()?;
+        let mut target = device.bitmap_target(size.width as usize, size.height as usize, 1.)?;
+        {
+            let mut piet_context = target.render_context();
+            self.draw_export(data, &mut piet_context, size);
+            piet_context.finish()?;
+        }
+        target.save_to_file(path)?;
+        Ok(())
+    }
+
+    /// write the current view, or the whole network, as svg paths in their route colours
+    fn export_svg(
+        &self,
+        data: &AppData,
+        canvas_size: Size,
+        path: impl AsRef<Path>,
+    ) -> Result<(), Box<dyn Error>> {
+        let escape = |text: &str| {
+            text.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        };
+        let hex = |color: &Color| {
+            let (r, g, b, _a) = color.as_rgba8();
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        };
+
+        let size = self.export_size(data, canvas_size);
+        let transform = self.export_transform(data, size);
+        let path_width = self.export_path_width(data, size);
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+            size.width, size.height
+        );
+        svg.push_str(&format!(
+            "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>\n",
+            size.width, size.height
+        ));
+        svg.push_str(&format!(
+            "<g fill=\"none\" stroke-width=\"{}\" stroke-linecap=\"round\" stroke-linejoin=\"round\">\n",
+            path_width
+        ));
+        for (trip_id, color, _text_color, trip_path) in &self.all_trip_paths_combined {
+            let mut trip_path = trip_path.clone();
+            trip_path.apply_affine(transform);
+            // skip paths which are entirely outside the view
+            if trip_path.bounding_box().intersect(size.to_rect()).area() == 0. {
+                continue;
+            }
+            svg.push_str(&format!(
+                "<path id=\"trip-{}\" stroke=\"{}\" d=\"{}\"/>\n",
+                escape(trip_id),
+                hex(color),
+                trip_path.to_svg()
+            ));
+        }
+        svg.push_str("</g>\n");
+
+        let labels = self.export_labels(data, size);
+        if !labels.is_empty() {
+            let font_size = size.max_side() * EXPORT_FONT_SIZE_PROPORTION;
+            svg.push_str(&format!(
+                "<g font-family=\"sans-serif\" font-size=\"{}\" dominant-baseline=\"middle\">\n",
+                font_size
+            ));
+            for (point, name) in labels {
+                svg.push_str(&format!(
+                    "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"white\" stroke=\"black\" stroke-width=\"{}\"/>\n",
+                    point.x,
+                    point.y,
+                    path_width * 0.8,
+                    path_width * 0.4
+                ));
+                if !name.is_empty() {
+                    svg.push_str(&format!(
+                        "<text x=\"{}\" y=\"{}\">{}</text>\n",
+                        point.x + font_size * 0.5,
+                        point.y,
+                        escape(&name)
+                    ));
+                }
+            }
+            svg.push_str("</g>\n");
+        }
+        svg.push_str("</svg>\n");
+        std::fs::write(path, svg)?;
+        Ok(())
+    }
+
+    /// legend for data.map_color_mode, above the scale bar
+    fn draw_legend(&self, ctx: &mut PaintCtx) {
+        let legend = match &self.trip_colors {
+            Some(trip_colors) if !trip_colors.legend.is_empty() => &trip_colors.legend,
+            _ => return,
+        };
+        let layouts = legend
+            .iter()
+            .map(|(label, color)| {
+                let layout = ctx
+                    .text()
+                    .new_text_layout(label.clone())
+                    .font(FontFamily::SYSTEM_UI, OVERLAY_FONT_SIZE)
+                    .text_color(Color::BLACK)
+                    .build()
+                    .unwrap();
+                (layout, color)
+            })
+            .collect::<Vec<_>>();
+        let line_height = layouts
+            .iter()
+            .map(|(layout, _color)| layout.size().height)
+            .fold(LEGEND_SWATCH_SIZE, f64::max)
+            + 2.;
+        let width = layouts
+            .iter()
+            .map(|(layout, _color)| layout.size().width)
+            .fold(0., f64::max)
+            + LEGEND_SWATCH_SIZE
+            + 12.;
+        let height = line_height * layouts.len() as f64 + 4.;
+        // leave room for the scale bar below
+        let origin = Point::new(
+            OVERLAY_MARGIN,
+            ctx.size().height - OVERLAY_MARGIN - 40. - height,
+        );
+        ctx.fill(
+            Rect::from_origin_size(origin, (width, height)).to_rounded_rect(3.),
+            &Color::rgba(1., 1., 1., 0.8),
+        );
+        for (i, (layout, color)) in layouts.iter().enumerate() {
+            let line_origin = origin + Vec2::new(4., 2. + line_height * i as f64);
+            let swatch = Rect::from_origin_size(
+                line_origin + Vec2::new(0., (line_height - LEGEND_SWATCH_SIZE) / 2.),
+                (LEGEND_SWATCH_SIZE, LEGEND_SWATCH_SIZE),
+            );
+            ctx.fill(swatch, *color);
+            ctx.stroke(swatch, &Color::BLACK, 0.5);
+            ctx.draw_text(layout, line_origin + Vec2::new(LEGEND_SWATCH_SIZE + 4., 0.));
         }
-        let _ = ctx.restore();
     }
+
     fn draw_minimap(&self, data: &AppData, ctx: &mut PaintCtx) {
         ctx.with_save(|ctx: &mut PaintCtx| {
             let rect = ctx.size().to_rect();
@@ -754,17 +2389,50 @@ impl MapWidget {
 
         let path_width = data.map_zoom_level.path_width(REFERENCE_SIZE as f64);
         let s_circle_bb = path_width * PATH_HIGHLIGHTED * SMALL_CIRCLE_BLACK_BACKGROUND_MULT;
-        for (stop_circle_point, stop) in self.stop_circles.iter().zip(data.stops.iter()) {
+        let stop_clusters = self.stop_clusters.get(&data.map_zoom_level);
+        for (i, (stop_circle_point, stop)) in
+            self.stop_circles.iter().zip(data.stops.iter()).enumerate()
+        {
             // let stop_time = data.stop_times.get(i).unwrap();
             // let stop_index = *data.stop_index_from_id.get(&stop_time.stop_id).unwrap();
             // let stop_circle_point = self.stop_circles[stop_index];
 
-            if Circle::new(*stop_circle_point, s_circle_bb).contains(translated_mouse_position) {
+            // stops drawn as part of a cluster can't be hovered individually
+            let clustered = stop_clusters
+                .and_then(|stop_clusters| stop_clusters.cluster_of_stop.get(i))
+                .map(|cluster| cluster.is_some())
+                .unwrap_or(false);
+            if !clustered
+                && Circle::new(*stop_circle_point, s_circle_bb).contains(translated_mouse_position)
+            {
                 return Some(stop.id.clone());
             }
         }
         None
     }
+    /// (centre, stop indexes) of the hovered cluster
+    fn hovered_cluster_stops(&self, data: &AppData) -> Option<&(Point, Vec<usize>)> {
+        self.stop_clusters
+            .get(&data.map_zoom_level)?
+            .clusters
+            .get(self.hovered_cluster?)
+    }
+    fn find_hovered_cluster(
+        &self,
+        data: &AppData,
+        ctx: &EventCtx,
+        mouse_position: Point,
+    ) -> Option<usize> {
+        let stop_clusters = self.stop_clusters.get(&data.map_zoom_level)?;
+        let point = self.reference_point_from_mouse(data, ctx.size(), mouse_position);
+        let path_width = data.map_zoom_level.path_width(REFERENCE_SIZE as f64);
+        stop_clusters
+            .clusters
+            .iter()
+            .position(|(centre, stop_indexes)| {
+                centre.distance(point) < cluster_radius(path_width, stop_indexes.len())
+            })
+    }
 }
 
 fn bez_path_from_coords_iter<I, P>(coords_iter: I) -> BezPath
@@ -786,6 +2454,71 @@ where
 impl Widget<AppData> for MapWidget {
     fn event(&mut self, ctx: &mut druid::EventCtx, event: &Event, data: &mut AppData, _env: &Env) {
         match event {
+            Event::Command(cmd) => {
+                if cmd.is(FOCUS_MAP) {
+                    // so keyboard shortcuts work without first clicking the map
+                    ctx.request_focus();
+                    ctx.set_handled();
+                } else if let Some(file_info) = cmd.get(EXPORT_MAP_PNG) {
+                    myprint!("handle EXPORT_MAP_PNG command");
+                    if let Err(error) = self.export_png(data, ctx.size(), file_info.path()) {
+                        data.show_error("failed to export PNG", error);
+                    }
+                    ctx.set_handled();
+                } else if let Some(file_info) = cmd.get(EXPORT_MAP_SVG) {
+                    myprint!("handle EXPORT_MAP_SVG command");
+                    if let Err(error) = self.export_svg(data, ctx.size(), file_info.path()) {
+                        data.show_error("failed to export SVG", error);
+                    }
+                    ctx.set_handled();
+                } else if let Some(whole_feed) = cmd.get(FIT_MAP) {
+                    myprint!("handle FIT_MAP command");
+                    // with nothing selected, fitting the selection fits the whole feed
+                    if let Some(bounds) = self
+                        .selection_bounds(data, *whole_feed)
+                        .or_else(|| self.selection_bounds(data, true))
+                    {
+                        self.fit_bounds(bounds, ctx.size());
+                        ctx.request_anim_frame();
+                    }
+                    ctx.set_handled();
+                }
+            }
+            Event::KeyDown(key_event) => {
+                // f fits the selection and shift f or home the whole feed
+                let whole_feed = match &key_event.key {
+                    KbKey::Character(character) if character == "f" => Some(false),
+                    KbKey::Character(character) if character == "F" => Some(true),
+                    KbKey::Home => Some(true),
+                    _ => None,
+                };
+                if let Some(whole_feed) = whole_feed {
+                    ctx.submit_command(FIT_MAP.with(whole_feed));
+                    ctx.set_handled();
+                }
+            }
+            Event::AnimFrame(interval) => {
+                if let Some(animation) = &mut self.camera_animation {
+                    animation.elapsed += interval;
+                    let t = (animation.elapsed as f64 / CAMERA_ANIMATION_NANOS as f64).min(1.);
+                    // zoom out before moving so the destination is in view on the way, but only zoom in once there
+                    if t >= 1. || animation.zoom_level.to_usize() < data.map_zoom_level.to_usize() {
+                        data.map_zoom_level = animation.zoom_level;
+                    }
+                    // smoothstep
+                    let eased = t * t * (3. - 2. * t);
+                    self.focal_point = NormalPoint::centering(
+                        animation.from.lerp(animation.to, eased),
+                        ctx.size(),
+                    );
+                    if t >= 1. {
+                        self.camera_animation = None;
+                    } else {
+                        ctx.request_anim_frame();
+                    }
+                    ctx.request_paint();
+                }
+            }
             // Event::Wheel(mouse_event) => {
             //     let mut change = mouse_event.wheel_delta.y;
             //     let multiplier = 2000. / self.speed;
@@ -800,15 +2533,89 @@ impl Widget<AppData> for MapWidget {
             // }
             Event::MouseDown(mouse_event) => {
                 ctx.override_cursor(&Cursor::Pointer);
+                // so the map gets key presses
+                ctx.request_focus();
+                self.camera_animation = None;
                 self.down_click_pos = Some(mouse_event.pos);
-                self.drag_last_pos = Some(mouse_event.pos);
+                // in stop move mode, pressing on a stop drags the stop rather than panning the map
+                let hovered_stop_id = if data.map_stop_move_mode {
+                    self.find_hovered_stop(data, ctx, mouse_event.pos)
+                } else {
+                    None
+                };
+                // similarly in shape edit mode for the vertices of the shape
+                let hovered_vertex = if data.map_shape_edit_mode {
+                    let point = self.reference_point_from_mouse(data, ctx.size(), mouse_event.pos);
+                    self.find_hovered_vertex(data, point)
+                } else {
+                    None
+                };
+                // shift drag draws a box and alt drag a lasso to select stops and trips. Shift click toggles the selection of a single stop or trip
+                if mouse_event.mods.shift() || mouse_event.mods.alt() {
+                    self.selection_area = Some(if mouse_event.mods.alt() {
+                        SelectionArea::Lasso(vec![mouse_event.pos])
+                    } else {
+                        SelectionArea::Box(mouse_event.pos, mouse_event.pos)
+                    });
+                } else if let Some(stop_id) = hovered_stop_id {
+                    self.hovered_stop_id = Some(stop_id.clone());
+                    self.start_stop_drag(data, stop_id);
+                } else if let Some(vertex_index) = hovered_vertex {
+                    // right click deletes a vertex, as long as that leaves a line
+                    if mouse_event.button.is_right() {
+                        if let Some((shape_id, mut latlongs)) = self.edited_shape_latlongs() {
+                            if latlongs.len() > 2 {
+                                latlongs.remove(vertex_index);
+                                ctx.submit_command(UPDATE_SHAPE.with((shape_id, latlongs)));
+                            }
+                        }
+                        self.down_click_pos = None;
+                    } else {
+                        self.dragged_vertex = Some(vertex_index);
+                        ctx.request_paint();
+                    }
+                } else {
+                    self.drag_last_pos = Some(mouse_event.pos);
+                }
             }
             Event::MouseMove(mouse_event) => {
                 // TODO is this the right place to do this?
-                data.hovered_stop_time_id = None;
+                let hovered_stop_time_before = data.hovered_stop_time_id.take();
+
+                // drawing a selection area
+                if let Some(selection_area) = &mut self.selection_area {
+                    if mouse_event.buttons.has_left() {
+                        match selection_area {
+                            SelectionArea::Box(_start, end) => *end = mouse_event.pos,
+                            SelectionArea::Lasso(points) => points.push(mouse_event.pos),
+                        }
+                        ctx.request_paint();
+                    }
+
+                // moving a stop
+                } else if self.dragged_stop.is_some() {
+                    self.mouse_position = Some(mouse_event.pos);
+                    if mouse_event.buttons.has_left() {
+                        let point =
+                            self.reference_point_from_mouse(data, ctx.size(), mouse_event.pos);
+                        self.move_dragged_stop(data, point);
+                        ctx.request_paint();
+                    }
+
+                // moving a shape vertex
+                } else if let Some(vertex_index) = self.dragged_vertex {
+                    self.mouse_position = Some(mouse_event.pos);
+                    if mouse_event.buttons.has_left() {
+                        let point =
+                            self.reference_point_from_mouse(data, ctx.size(), mouse_event.pos);
+                        if let Some((_shape_id, points)) = &mut self.edited_shape {
+                            points[vertex_index] = point;
+                        }
+                        ctx.request_paint();
+                    }
 
                 // panning
-                if let Some(drag_start) = self.drag_last_pos {
+                } else if let Some(drag_start) = self.drag_last_pos {
                     // println!("mouse move: drag");
                     if mouse_event.buttons.has_left() {
                         let drag_vector = mouse_event.pos.to_vec2() - drag_start.to_vec2();
@@ -829,13 +2636,20 @@ impl Widget<AppData> for MapWidget {
                 } else {
                     // println!("mouse move: check for highlight");
                     self.mouse_position = Some(mouse_event.pos);
+                    let hovered_before = (
+                        data.hovered_trip_paths.clone(),
+                        hovered_stop_time_before,
+                        self.hovered_stop_id.clone(),
+                        self.hovered_cluster,
+                        self.hovered_layer_feature,
+                    );
 
                     // if in normal mode check for path and stop_time hovers
-                    if !data.map_stop_selection_mode {
+                    if !data.map_stop_selection_mode && !data.map_stop_move_mode {
                         // if hovering a stop on a selected path, highlight/englarge it
-                        if let Some((index, _id)) = &data.selected_trip_id {
+                        if let Some(index) = self.selected_trip_path_index(data) {
                             let (trip_id, _color, _text_color, path) =
-                                self.all_trip_paths_combined.get(*index).unwrap();
+                                self.all_trip_paths_combined.get(index).unwrap();
                             // println!("mouse move: check for hover: stop");
                             // TODO below is still going too slow and will cause a backup after lots of mouse move events - seems to be fixed now
 
@@ -877,27 +2691,171 @@ impl Widget<AppData> for MapWidget {
                     } else {
                         // should arguably
                         self.hovered_stop_id = self.find_hovered_stop(data, ctx, mouse_event.pos);
+                        self.hovered_cluster = if self.hovered_stop_id.is_none() {
+                            self.find_hovered_cluster(data, ctx, mouse_event.pos)
+                        } else {
+                            None
+                        };
+                    }
+
+                    // GeoJSON layer features are only hovered when nothing in the network is
+                    self.hovered_layer_feature = if data.hovered_trip_paths.is_empty()
+                        && data.hovered_stop_time_id.is_none()
+                        && self.hovered_stop_id.is_none()
+                    {
+                        let point =
+                            self.reference_point_from_mouse(data, ctx.size(), mouse_event.pos);
+                        self.find_hovered_layer_feature(data, point)
+                    } else {
+                        None
+                    };
+
+                    let hovered_after = (
+                        data.hovered_trip_paths.clone(),
+                        data.hovered_stop_time_id.clone(),
+                        self.hovered_stop_id.clone(),
+                        self.hovered_cluster,
+                        self.hovered_layer_feature,
+                    );
+                    if hovered_after != hovered_before {
+                        self.tooltip_position = Some(mouse_event.pos);
                         ctx.request_paint();
+                    } else {
+                        // only the coordinate readout needs to follow the mouse, see paint()
+                        ctx.request_paint_rect(MapWidget::readout_rect(ctx.size()));
                     }
 
                     self.highlighted_stop_circle = None;
                 }
             }
             Event::MouseUp(mouse_event) => {
+                // finish selecting
+                if let Some(selection_area) = self.selection_area.take() {
+                    if self.down_click_pos == Some(mouse_event.pos) {
+                        if let Some(stop_id) = self.find_hovered_stop(data, ctx, mouse_event.pos) {
+                            toggle_id(&mut data.map_selected_stop_ids, stop_id);
+                        } else if let Some(index) =
+                            self.find_hovered_paths(data, ctx, mouse_event.pos).get(0)
+                        {
+                            let trip_id = self.all_trip_paths_combined[*index].0.clone();
+                            toggle_id(&mut data.map_selected_trip_ids, trip_id);
+                        }
+                    } else {
+                        self.select_in_area(data, ctx.size(), &selection_area);
+                    }
+                    self.down_click_pos = None;
+                    ctx.request_paint();
+
+                // drop a dragged stop
+                } else if self.dragged_stop.is_some() {
+                    if self.down_click_pos != Some(mouse_event.pos) {
+                        let mut point =
+                            self.reference_point_from_mouse(data, ctx.size(), mouse_event.pos);
+                        if data.map_snap_to_shape {
+                            if let Some(shape_point) = self.nearest_shape_point(data, point) {
+                                point = shape_point;
+                            }
+                        }
+                        self.move_dragged_stop(data, point);
+                        let dragged_stop = self.dragged_stop.take().unwrap();
+                        let latlong = MapWidget::canvas_to_latlong(
+                            point,
+                            self.long_lat_rect,
+                            REFERENCE_SIZE as f64,
+                        );
+                        // paths and bitmaps will be recreated in update() once the stop has been updated
+                        if dragged_stop.other_stops.is_empty() {
+                            ctx.submit_command(MOVE_STOP.with((dragged_stop.stop_id, latlong)));
+                        } else {
+                            let mut stop_latlongs = vec![(dragged_stop.stop_id, latlong)];
+                            for (stop_id, stop_index, _start_point) in dragged_stop.other_stops {
+                                stop_latlongs.push((
+                                    stop_id,
+                                    MapWidget::canvas_to_latlong(
+                                        self.stop_circles[stop_index],
+                                        self.long_lat_rect,
+                                        REFERENCE_SIZE as f64,
+                                    ),
+                                ));
+                            }
+                            ctx.submit_command(MOVE_STOPS.with(stop_latlongs));
+                        }
+                    } else {
+                        // just a click, so put everything back where it was
+                        let start_point = self.dragged_stop.as_ref().unwrap().start_point;
+                        self.move_dragged_stop(data, start_point);
+                        self.dragged_stop = None;
+                    }
+                    ctx.override_cursor(&Cursor::Arrow);
+                    self.down_click_pos = None;
+                    ctx.request_paint();
+
+                // drop a dragged shape vertex
+                } else if let Some(vertex_index) = self.dragged_vertex.take() {
+                    if self.down_click_pos != Some(mouse_event.pos) {
+                        let point =
+                            self.reference_point_from_mouse(data, ctx.size(), mouse_event.pos);
+                        if let Some((_shape_id, points)) = &mut self.edited_shape {
+                            points[vertex_index] = point;
+                        }
+                        if let Some(shape_id_latlongs) = self.edited_shape_latlongs() {
+                            ctx.submit_command(UPDATE_SHAPE.with(shape_id_latlongs));
+                        }
+                    } else {
+                        self.load_edited_shape(data);
+                    }
+                    ctx.override_cursor(&Cursor::Arrow);
+                    self.down_click_pos = None;
+                    ctx.request_paint();
+
+                // clicking on the edited shape inserts a new vertex
+                } else if let Some((segment_index, point)) = self
+                    .down_click_pos
+                    .filter(|click_down_pos| click_down_pos == &mouse_event.pos)
+                    .and_then(|_| {
+                        let point =
+                            self.reference_point_from_mouse(data, ctx.size(), mouse_event.pos);
+                        self.find_hovered_shape_segment(data, point)
+                    })
+                {
+                    if let Some((_shape_id, points)) = &mut self.edited_shape {
+                        points.insert(segment_index + 1, point);
+                    }
+                    if let Some(shape_id_latlongs) = self.edited_shape_latlongs() {
+                        ctx.submit_command(UPDATE_SHAPE.with(shape_id_latlongs));
+                    }
+                    self.down_click_pos = None;
+                    ctx.request_paint();
+
                 // only handle up click if the down click was on the map
-                if let Some(click_down_pos) = self.down_click_pos {
+                } else if let Some(click_down_pos) = self.down_click_pos {
                     if mouse_event.pos == click_down_pos {
                         myprint!("mouse_up: same pos");
                         // if mouse inside minimap
                         let minimap_rect =
                             ctx.size().to_rect().scale_from_origin(MINIMAP_PROPORTION);
-                        if minimap_rect.contains(mouse_event.pos) {
+                        if MapWidget::reset_view_rect(ctx.size()).contains(mouse_event.pos) {
+                            self.focal_point = NormalPoint::CENTER;
+                            data.map_zoom_level = ZoomLevel::One;
+                            self.down_click_pos = None;
+                            ctx.request_paint();
+                        } else if minimap_rect.contains(mouse_event.pos) {
                             self.focal_point = NormalPoint::from_canvas_point(
                                 mouse_event.pos,
                                 ctx.size() * MINIMAP_PROPORTION,
                             );
                             self.down_click_pos = None;
                             ctx.request_paint();
+                        } else if let Some(centre) = self
+                            .hovered_cluster_stops(data)
+                            .map(|(centre, _stop_indexes)| *centre)
+                        {
+                            // clicking a cluster zooms in on it
+                            self.focal_point = NormalPoint::centering(centre, ctx.size());
+                            data.map_zoom_level = data.map_zoom_level.zoomed_in();
+                            self.hovered_cluster = None;
+                            self.down_click_pos = None;
+                            ctx.request_paint();
                         } else {
                             // select a trip, stop_time, or stop
                             if !data.map_stop_selection_mode {
@@ -908,30 +2866,19 @@ impl Widget<AppData> for MapWidget {
                                 if let Some(index) = data.hovered_trip_paths.get(0) {
                                     let (id, _color, _text_color, _path) =
                                         self.all_trip_paths_combined.get(*index).unwrap();
-                                    let route_id = data
-                                        .trips
-                                        .iter()
-                                        .find(|trip| &trip.id == id)
-                                        .unwrap()
-                                        .route_id
-                                        .clone();
-                                    let agency_id = data
-                                        .routes
-                                        .iter()
-                                        .find(|route| route.id == route_id)
-                                        .unwrap()
-                                        .agency_id
-                                        .clone();
+                                    let route_id = data.trip(id).unwrap().route_id.clone();
+                                    let agency_id =
+                                        data.route(&route_id).unwrap().agency_id.clone();
                                     data.selected_agency_id = Some(agency_id);
                                     data.selected_route_id = Some(route_id);
-                                    data.selected_trip_id = Some((*index, id.clone()));
+                                    data.selected_trip_id = Some(id.clone());
                                     data.selected_stop_time_id = None;
                                     // data.selected_trip_path = Some(*index);
 
                                     // check if hovering a stop on selected trip
-                                } else if let Some((index, _id)) = &data.selected_trip_id {
+                                } else if let Some(index) = self.selected_trip_path_index(data) {
                                     let (trip_id, _color, _text_color, path) =
-                                        self.all_trip_paths_combined.get(*index).unwrap();
+                                        self.all_trip_paths_combined.get(index).unwrap();
                                     if self.is_path_hovered(data, ctx, path, mouse_event.pos) {
                                         if let Some(hovered_stop_time_id) =
                                             &data.hovered_stop_time_id
@@ -966,37 +2913,11 @@ impl Widget<AppData> for MapWidget {
                                     let long_lat_rect =
                                         min_max_trips_coords(&trips_coords_from_shapes);
 
-                                    // normalise mouse position
-                                    let fp = self
-                                        .focal_point
-                                        .to_point_within_size(Size::new(1., 1.))
-                                        .to_vec2();
-                                    let b = fp
-                                        - Size::new(
-                                            1. / (data.map_zoom_level.to_f64() * 2.),
-                                            1. / (data.map_zoom_level.to_f64() * 2.),
-                                        )
-                                        .to_vec2();
-                                    let a = mouse_event.pos.to_vec2()
-                                        / (ctx.size().max_side() * data.map_zoom_level.to_f64());
-                                    let normalised_mouse_position = b + a;
-
-                                    // Note long_lat_rect_square represents a coord system with bottom left origin
-                                    // We need to account for the fact the long_lat_rect is not square, so normalised_mouse_position (1,1) would actually fall outside of long_lat_rect. The easiest way to do this is to make long_lat_rect square
-                                    let max_side = long_lat_rect.size().max_side();
-                                    let long_lat_rect_square = Rect::new(
-                                        long_lat_rect.x0,
-                                        long_lat_rect.y1 - max_side,
-                                        long_lat_rect.x0 + max_side,
-                                        long_lat_rect.y1,
-                                    );
-                                    let latlong = Point::new(
-                                        long_lat_rect_square.x0
-                                            + long_lat_rect_square.width()
-                                                * normalised_mouse_position.x,
-                                        long_lat_rect_square.y1
-                                            - long_lat_rect_square.height()
-                                                * normalised_mouse_position.y,
+                                    let latlong = self.latlong_from_mouse(
+                                        data,
+                                        long_lat_rect,
+                                        ctx.size(),
+                                        mouse_event.pos,
                                     );
 
                                     ctx.submit_command(NEW_STOP.with(latlong));
@@ -1032,6 +2953,11 @@ impl Widget<AppData> for MapWidget {
             }
             _ => {}
         }
+        // saved in projects, so kept in AppData, but only written when the view has actually moved
+        let focal_point = (self.focal_point.x, self.focal_point.y);
+        if data.map_focal_point != focal_point {
+            data.map_focal_point = focal_point;
+        }
     }
     fn update(
         &mut self,
@@ -1110,6 +3036,23 @@ impl Widget<AppData> for MapWidget {
         }
         // TODO should also check if agency is selected and highlight it's paths, but this will kill performance everytime we select SPTRANS in order to select a different route... also app should start with SPTRANS unselected
 
+        // fit the map to selections made elsewhere, eg in the list. Selections made by clicking the map happen while it is hot, and are already in view
+        myprint!("update: check: selection to fit");
+        if !ctx.is_hot()
+            && (!data.selected_agency_id.same(&old_data.selected_agency_id)
+                || !data.selected_route_id.same(&old_data.selected_route_id)
+                || !data.selected_trip_id.same(&old_data.selected_trip_id)
+                || !data
+                    .selected_stop_time_id
+                    .same(&old_data.selected_stop_time_id)
+                || !data.selected_stop_id.same(&old_data.selected_stop_id))
+        {
+            if let Some(bounds) = self.selection_bounds(data, false) {
+                self.fit_bounds(bounds, ctx.size());
+                ctx.request_anim_frame();
+            }
+        }
+
         myprint!("update: check: trips");
         if !data.trips.same(&old_data.trips) {
             myprint!("update: trips: paint");
@@ -1118,6 +3061,20 @@ impl Widget<AppData> for MapWidget {
             // self.redraw_base = true;
             ctx.request_paint();
         }
+        // trips added since the map was made need paths of their own before they can be drawn or redrawn
+        if data.trips.len() != old_data.trips.len() {
+            let new_trip_ids = data
+                .trips
+                .iter()
+                .filter(|trip| !self.trip_path_index_from_id.contains_key(&trip.id))
+                .map(|trip| trip.id.clone())
+                .collect::<Vec<_>>();
+            if !new_trip_ids.is_empty() {
+                myprint!("update: trips: make paths for new trips");
+                self.rebuild_trip_paths(data, &new_trip_ids);
+                self.recreate_bitmap = true;
+            }
+        }
 
         // check for stop_times which have been edited
         myprint!("update: check: data_stop_time.stop_id");
@@ -1140,18 +3097,10 @@ impl Widget<AppData> for MapWidget {
                         MapWidget::latlong_to_canvas(coord, long_lat_rect, REFERENCE_SIZE as f64)
                     };
 
-                    let (trip_index, trip) = data
-                        .trips
-                        .iter()
-                        .enumerate()
-                        .find(|(_index, trip)| trip.id == data_stop_time.trip_id)
-                        .unwrap();
+                    let trip = data.trip(&data_stop_time.trip_id).unwrap();
+                    let trip_index = self.trip_path_index(data, &trip.id);
 
-                    let route = data
-                        .routes
-                        .iter()
-                        .find(|route| route.id == trip.route_id)
-                        .unwrap();
+                    let route = data.route(&trip.route_id).unwrap();
                     let RGB { r, g, b } = route.color.0;
                     let color = Color::rgb8(r, g, b);
                     let RGB { r, g, b } = route.text_color.0;
@@ -1178,12 +3127,10 @@ impl Widget<AppData> for MapWidget {
             // first ensure we actually have an updated trip, rather than an entirely new trip
             if data.trips.len() == old_data.trips.len() {
                 // find which trip has been updated
-                let ((trip_index, trip), _) = data
+                let trip = data
                     .trips
                     .iter()
-                    .enumerate()
-                    .zip(old_data.trips.iter())
-                    .find(|((_trip_index, trip), _old_trip)| {
+                    .find(|trip| {
                         let data_range = data.stop_time_range_from_trip_id.get(&trip.id).unwrap();
                         let data_size = data_range.1 - data_range.0;
                         let old_data_range =
@@ -1192,6 +3139,7 @@ impl Widget<AppData> for MapWidget {
                         data_size != old_data_size
                     })
                     .unwrap();
+                let trip_index = self.trip_path_index(data, &trip.id);
                 myprint!("update: recreate trip path for trip with stop_time added or deleted");
 
                 // TODO making latlong_to_bitmap() here is expensive and could be avoided
@@ -1201,11 +3149,7 @@ impl Widget<AppData> for MapWidget {
                     MapWidget::latlong_to_canvas(coord, long_lat_rect, REFERENCE_SIZE as f64)
                 };
 
-                let route = data
-                    .routes
-                    .iter()
-                    .find(|route| route.id == trip.route_id)
-                    .unwrap();
+                let route = data.route(&trip.route_id).unwrap();
                 let RGB { r, g, b } = route.color.0;
                 let color = Color::rgb8(r, g, b);
                 let RGB { r, g, b } = route.text_color.0;
@@ -1226,9 +3170,107 @@ impl Widget<AppData> for MapWidget {
             }
         }
 
+        // check for stops which have been moved, either by dragging them or undoing a move
+        myprint!("update: check: stop positions");
+        if !data.stops.same(&old_data.stops) && data.stops.len() == old_data.stops.len() {
+            let moved_stop_ids = data
+                .stops
+                .iter()
+                .zip(old_data.stops.iter())
+                .filter(|(stop, old_stop)| stop.latlong != old_stop.latlong)
+                .map(|(stop, _old_stop)| stop.id.clone())
+                .collect::<Vec<_>>();
+            if !moved_stop_ids.is_empty() {
+                myprint!("update: stop positions: recreate paths");
+                for stop_id in &moved_stop_ids {
+                    let stop_index = *data.stop_index_from_id.get(stop_id).unwrap();
+                    self.stop_circles[stop_index] = MapWidget::latlong_to_canvas(
+                        data.stops[stop_index].latlong,
+                        self.long_lat_rect,
+                        REFERENCE_SIZE as f64,
+                    );
+                    let trip_indexes = self.trips_drawn_through_stop(data, stop_id);
+                    self.update_paths_from_stop_circles(data, &trip_indexes);
+                }
+                // more than one path might have changed and the old positions need removing, so redraw the bitmaps from scratch
+                self.cached_image_map.clear();
+                self.last_updated_trip_index = None;
+                self.recreate_bitmap = true;
+                ctx.request_paint();
+            }
+        }
+
+        // check for shapes which have been edited, created, or assigned to a different trip
+        myprint!("update: check: shapes");
+        let mut trip_ids_to_redraw = Vec::new();
+        if !data.edited_shapes.same(&old_data.edited_shapes) {
+            let changed_shape_ids = data
+                .edited_shapes
+                .keys()
+                .chain(old_data.edited_shapes.keys())
+                .filter(|shape_id| {
+                    match (
+                        data.edited_shapes.get(*shape_id),
+                        old_data.edited_shapes.get(*shape_id),
+                    ) {
+                        (Some(points), Some(old_points)) => !points.same(old_points),
+                        _ => true,
+                    }
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            trip_ids_to_redraw.extend(
+                data.trips
+                    .iter()
+                    .filter(|trip| {
+                        trip.shape_id
+                            .as_ref()
+                            .map_or(false, |shape_id| changed_shape_ids.contains(shape_id))
+                    })
+                    .map(|trip| trip.id.clone()),
+            );
+        }
+        if !data.trips.same(&old_data.trips) && data.trips.len() == old_data.trips.len() {
+            trip_ids_to_redraw.extend(
+                data.trips
+                    .iter()
+                    .zip(old_data.trips.iter())
+                    .filter(|(trip, old_trip)| trip.shape_id != old_trip.shape_id)
+                    .map(|(trip, _old_trip)| trip.id.clone()),
+            );
+        }
+        if !trip_ids_to_redraw.is_empty() {
+            myprint!("update: shapes: recreate paths");
+            self.rebuild_trip_paths(data, &trip_ids_to_redraw);
+            self.cached_image_map.clear();
+            self.last_updated_trip_index = None;
+            self.recreate_bitmap = true;
+            ctx.request_paint();
+        }
+        if !trip_ids_to_redraw.is_empty()
+            || !data.selected_trip_id.same(&old_data.selected_trip_id)
+            || !data
+                .map_shape_edit_mode
+                .same(&old_data.map_shape_edit_mode)
+        {
+            self.load_edited_shape(data);
+            ctx.request_paint();
+        }
+
+        if !data.shape_preview.same(&old_data.shape_preview)
+            || !data
+                .shape_dist_warnings
+                .same(&old_data.shape_dist_warnings)
+        {
+            myprint!("update: shape_preview or shape_dist_warnings: paint");
+            ctx.request_paint();
+        }
+
         myprint!("update: check: map_zoom_level");
         if !data.map_zoom_level.same(&&old_data.map_zoom_level) {
             myprint!("update: map_zoom_level: paint");
+            // clusters are different at each zoom level
+            self.hovered_cluster = None;
             ctx.request_paint();
         }
 
@@ -1240,6 +3282,57 @@ impl Widget<AppData> for MapWidget {
             myprint!("update: map_stop_selection_mode: paint");
             ctx.request_paint();
         }
+        if !data.map_show_labels.same(&old_data.map_show_labels)
+            || !data
+                .map_selected_stop_ids
+                .same(&old_data.map_selected_stop_ids)
+            || !data
+                .map_selected_trip_ids
+                .same(&old_data.map_selected_trip_ids)
+        {
+            ctx.request_paint();
+        }
+        if !data.map_stop_move_mode.same(&old_data.map_stop_move_mode) {
+            myprint!("update: map_stop_move_mode: paint");
+            self.hovered_stop_id = None;
+            self.hovered_cluster = None;
+            ctx.request_paint();
+        }
+
+        // layer visibility and style are read when painting, so only new or removed layers need converting
+        myprint!("update: check: map_layers");
+        if !data.map_layers.same(&old_data.map_layers) {
+            let features_changed = data.map_layers.len() != old_data.map_layers.len()
+                || data
+                    .map_layers
+                    .iter()
+                    .zip(old_data.map_layers.iter())
+                    .any(|(layer, old_layer)| !layer.features.same(&old_layer.features));
+            if features_changed {
+                self.create_layer_paths(data);
+            }
+            ctx.request_paint();
+        }
+
+        // paths recreated above are given their route colour, so in the other colour modes they need recolouring whenever trips change, not just when the mode does
+        myprint!("update: check: map_color_mode");
+        if !data.map_color_mode.same(&old_data.map_color_mode)
+            || (data.map_color_mode != MapColorMode::Route
+                && (!data.trips.same(&old_data.trips)
+                    || !data.stop_times.same(&old_data.stop_times)
+                    || !data.routes.same(&old_data.routes)))
+        {
+            let trip_colors = TripColors::new(data, data.map_color_mode);
+            let colors_changed = self.trip_colors.as_ref() != Some(&trip_colors);
+            self.apply_trip_colors(trip_colors);
+            if colors_changed {
+                myprint!("update: map_color_mode: recreate bitmaps");
+                self.cached_image_map.clear();
+                self.last_updated_trip_index = None;
+                self.recreate_bitmap = true;
+                ctx.request_paint();
+            }
+        }
 
         // if !old_data.same(data) {
         //     println!("data has changed?!?!");
@@ -1272,6 +3365,13 @@ impl Widget<AppData> for MapWidget {
 
         // TODO encode gtfs coords and painting coords into two distinct types for clarity
 
+        // when only the coordinate readout has changed, don't redraw the map and labels underneath it
+        let region = ctx.region().bounding_box();
+        if MapWidget::readout_rect(ctx.size()).intersect(region) == region {
+            self.draw_readout(data, ctx);
+            return;
+        }
+
         myprint!("paint");
         let size = ctx.size();
         let rect = size.to_rect();
@@ -1377,6 +3477,7 @@ impl Widget<AppData> for MapWidget {
 
                     let stops_handle = thread::spawn(move || {
                         myprint!("start drawing stops image: {}", zoom_level.to_usize());
+                        let stop_clusters = StopClusters::new(&stop_circles, zoom_level);
 
                         // setup bitmap target
                         let mut device = Device::new().unwrap();
@@ -1396,9 +3497,32 @@ impl Widget<AppData> for MapWidget {
                             //     ctx.fill(Circle::new(*point, s_circle_bb), &Color::BLACK);
                             //     ctx.fill(Circle::new(*point, s_circle), &Color::WHITE);
                             // }
-                            for point in stop_circles {
-                                piet_context.fill(Circle::new(point, s_circle_bb), &Color::BLACK);
-                                piet_context.fill(Circle::new(point, s_circle), &Color::WHITE);
+                            for (point, cluster) in stop_circles
+                                .iter()
+                                .zip(stop_clusters.cluster_of_stop.iter())
+                            {
+                                if cluster.is_none() {
+                                    piet_context
+                                        .fill(Circle::new(*point, s_circle_bb), &Color::BLACK);
+                                    piet_context.fill(Circle::new(*point, s_circle), &Color::WHITE);
+                                }
+                            }
+                            for (centre, stop_indexes) in &stop_clusters.clusters {
+                                let radius = cluster_radius(path_width, stop_indexes.len());
+                                piet_context.fill(
+                                    Circle::new(*centre, radius + path_width * 0.5),
+                                    &Color::WHITE,
+                                );
+                                piet_context.fill(Circle::new(*centre, radius), &CLUSTER_COLOR);
+                                let layout = piet_context
+                                    .text()
+                                    .new_text_layout(stop_indexes.len().to_string())
+                                    .font(FontFamily::SYSTEM_UI, radius)
+                                    .text_color(Color::WHITE)
+                                    .build()
+                                    .unwrap();
+                                piet_context
+                                    .draw_text(&layout, *centre - layout.size().to_vec2() * 0.5);
                             }
 
                             let _ = piet_context.restore();
@@ -1407,7 +3531,7 @@ impl Widget<AppData> for MapWidget {
                         }
                         let image_buf = target.to_image_buf(ImageFormat::RgbaPremul).unwrap();
                         myprint!("finish drawing stops image: {}", zoom_level.to_usize());
-                        (image_buf, zoom_level)
+                        (image_buf, zoom_level, stop_clusters)
                     });
 
                     handles.push((paths_handle, stops_handle));
@@ -1427,7 +3551,8 @@ impl Widget<AppData> for MapWidget {
                     )
                     .unwrap();
 
-                let (image_buf, zoom_level) = stops_handle.join().unwrap();
+                let (image_buf, zoom_level, stop_clusters) = stops_handle.join().unwrap();
+                self.stop_clusters.insert(zoom_level, stop_clusters);
                 let stops_bitmap = ctx
                     .make_image(
                         bitmap_size,
@@ -1450,6 +3575,8 @@ impl Widget<AppData> for MapWidget {
             myprint!("paint: redraw base: make image finish");
         }
 
+        self.draw_layers(data, ctx);
+
         // TODO come up with proper heuristic based on the size of coords or density of paths to determine when to switch to immediate mode, so that it generalises to maps other than SP
         if self.cached_image_map.contains_key(&data.map_zoom_level) {
             myprint!("paint: draw bitmap");
@@ -1458,27 +3585,52 @@ impl Widget<AppData> for MapWidget {
             myprint!("paint: draw immediate mode");
             self.draw_paths_onto_paint_ctx(data, ctx);
         }
-        if !data.map_stop_selection_mode {
+        if !data.map_stop_selection_mode && !data.map_stop_move_mode {
             myprint!("paint: draw highlights");
             // TODO temporarily drawing highlights here too until we add another cache for non hover highlights
             self.draw_highlights(data, ctx);
         } else {
-            self.draw_stops_bitmap_onto_paint_context(data, ctx);
+            if self.cached_image_map.contains_key(&data.map_zoom_level) {
+                self.draw_stops_bitmap_onto_paint_context(data, ctx);
+            }
+            self.draw_dragged_stop_paths(data, ctx);
             self.draw_highlighted_stop(data, ctx);
         }
+        self.draw_edited_shape(data, ctx);
+        self.draw_shape_preview(data, ctx);
+        self.draw_shape_dist_warnings(data, ctx);
+        self.draw_selection(data, ctx);
+        myprint!("paint: draw labels");
+        self.draw_stop_labels(data, ctx);
         myprint!("paint: draw minimap");
         self.draw_minimap(data, ctx);
+        self.draw_overlays(data, ctx);
+        self.draw_legend(ctx);
+        self.draw_tooltip(data, ctx);
     }
 
     fn lifecycle(
         &mut self,
-        _ctx: &mut druid::LifeCycleCtx,
+        ctx: &mut druid::LifeCycleCtx,
         event: &LifeCycle,
         data: &AppData,
         _env: &Env,
     ) {
         match event {
+            // remove tooltip when the mouse leaves the map
+            LifeCycle::HotChanged(false) => {
+                self.mouse_position = None;
+                self.hovered_cluster = None;
+                ctx.request_paint();
+            }
             LifeCycle::WidgetAdded => {
+                ctx.register_for_focus();
+                ctx.submit_command(FOCUS_MAP.to(ctx.widget_id()));
+                // restore the view saved in a project
+                self.focal_point = NormalPoint {
+                    x: data.map_focal_point.0,
+                    y: data.map_focal_point.1,
+                };
                 // TODO this should obviously be decoupled from widget impl
                 let trips_coords_from_shapes = data.trips_paths_from_shapes_or_stop_coords();
                 // let trips_coords_from_stop_coords = data.trips_coords_from_stop_coords();
@@ -1486,6 +3638,7 @@ impl Widget<AppData> for MapWidget {
                 // find size of path data
                 // NOTE (x0,y0) is the bottom left of the rect since data is latlong coords
                 let long_lat_rect = min_max_trips_coords(&trips_coords_from_shapes);
+                self.long_lat_rect = long_lat_rect;
 
                 let latlong_to_bitmap = |coord: Point| {
                     MapWidget::latlong_to_canvas(coord, long_lat_rect, REFERENCE_SIZE as f64)
@@ -1500,11 +3653,7 @@ impl Widget<AppData> for MapWidget {
                     .zip(data.trips.iter())
                     .filter(|(_coords, trip)| trip.visible)
                     .map(|(coords, trip)| {
-                        let route = data
-                            .routes
-                            .iter()
-                            .find(|route| route.id == trip.route_id)
-                            .unwrap();
+                        let route = data.route(&trip.route_id).unwrap();
                         let RGB { r, g, b } = route.color.0;
                         let color = Color::rgb8(r, g, b);
                         let RGB { r, g, b } = route.text_color.0;
@@ -1521,6 +3670,15 @@ impl Widget<AppData> for MapWidget {
                     .collect::<Vec<_>>();
 
                 self.all_trip_paths_combined = self.all_trip_paths_from_shapes.clone();
+                self.trip_path_index_from_id = self
+                    .all_trip_paths_combined
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (trip_id, _color, _text_color, _path))| (trip_id.clone(), i))
+                    .collect();
+                if data.map_color_mode != MapColorMode::Route {
+                    self.apply_trip_colors(TripColors::new(data, data.map_color_mode));
+                }
                 myprint!("finished paint: redraw base: make paths");
                 myprint!("paint: redraw base: group paths");
                 self.all_trip_paths_bitmap_grouped = self.group_paths_into_rects();
@@ -1533,6 +3691,8 @@ impl Widget<AppData> for MapWidget {
                     .map(|stop| latlong_to_bitmap(stop.latlong))
                     .collect::<Vec<_>>();
                 myprint!("finished paint: redraw base: make circles");
+
+                self.create_layer_paths(data);
             }
             _ => {}
         }
@@ -0,0 +1,228 @@
+use druid::Color;
+use gtfs_structures::Availability;
+use rgb::RGB;
+use std::collections::HashMap;
+
+use crate::data::*;
+
+/// categorical colours for route types and agencies, repeated if there are more categories than colours
+const PALETTE: [Color; 10] = [
+    Color::rgb8(31, 119, 180),
+    Color::rgb8(255, 127, 14),
+    Color::rgb8(44, 160, 44),
+    Color::rgb8(214, 39, 40),
+    Color::rgb8(148, 103, 189),
+    Color::rgb8(140, 86, 75),
+    Color::rgb8(227, 119, 194),
+    Color::rgb8(127, 127, 127),
+    Color::rgb8(188, 189, 34),
+    Color::rgb8(23, 190, 207),
+];
+const NO_DATA: Color = Color::rgb8(180, 180, 180);
+
+/// (max headway in minutes, label, colour) from most to least frequent
+const HEADWAY_BANDS: [(u32, &str, Color); 5] = [
+    (10, "every 10 min or better", Color::rgb8(26, 150, 65)),
+    (20, "every 10-20 min", Color::rgb8(166, 217, 106)),
+    (30, "every 20-30 min", Color::rgb8(253, 174, 97)),
+    (60, "every 30-60 min", Color::rgb8(244, 109, 67)),
+    (u32::MAX, "less than hourly", Color::rgb8(215, 48, 39)),
+];
+
+/// colour of each trip's path for a MapColorMode, and the legend explaining them
+#[derive(PartialEq)]
+pub struct TripColors {
+    colors: HashMap<String, Color>,
+    /// (label, colour), empty for MapColorMode::Route since there are too many routes for a legend to be useful
+    pub legend: Vec<(String, Color)>,
+}
+impl TripColors {
+    pub fn new(data: &AppData, mode: MapColorMode) -> TripColors {
+        let mut trip_colors = TripColors {
+            colors: HashMap::new(),
+            legend: Vec::new(),
+        };
+        match mode {
+            MapColorMode::Route => {
+                let route_colors = data
+                    .routes
+                    .iter()
+                    .map(|route| {
+                        let RGB { r, g, b } = route.color.0;
+                        (&route.id, Color::rgb8(r, g, b))
+                    })
+                    .collect::<HashMap<_, _>>();
+                for trip in &data.trips {
+                    if let Some(color) = route_colors.get(&trip.route_id) {
+                        trip_colors.colors.insert(trip.id.clone(), color.clone());
+                    }
+                }
+            }
+            MapColorMode::RouteType => {
+                let route_types = data
+                    .routes
+                    .iter()
+                    .map(|route| (&route.id, route.route_type))
+                    .collect::<HashMap<_, _>>();
+                trip_colors.categorise(data, |trip| {
+                    route_types.get(&trip.route_id).map(|route_type| {
+                        MyRouteType::radio_vec()
+                            .into_iter()
+                            .find(|(_name, radio_route_type)| radio_route_type == route_type)
+                            .map(|(name, _route_type)| name)
+                            .unwrap_or_else(|| format!("{:?}", route_type.0))
+                    })
+                });
+            }
+            MapColorMode::Agency => {
+                let agency_names = data
+                    .agencies
+                    .iter()
+                    .map(|agency| (&agency.id, &agency.name))
+                    .collect::<HashMap<_, _>>();
+                let route_agency_names = data
+                    .routes
+                    .iter()
+                    .map(|route| {
+                        let name = agency_names
+                            .get(&route.agency_id)
+                            .map(|name| name.to_string())
+                            // a feed with a single agency doesn't need to give routes an agency_id
+                            .or_else(|| data.agencies.get(0).map(|agency| agency.name.clone()));
+                        (&route.id, name)
+                    })
+                    .collect::<HashMap<_, _>>();
+                trip_colors.categorise(data, |trip| {
+                    route_agency_names.get(&trip.route_id).cloned().flatten()
+                });
+            }
+            MapColorMode::Headway => {
+                let headways = trip_headways(data);
+                for trip in &data.trips {
+                    let color = headways
+                        .get(&trip.id)
+                        .and_then(|headway| {
+                            HEADWAY_BANDS
+                                .iter()
+                                .find(|(max_headway, _label, _color)| headway <= max_headway)
+                        })
+                        .map(|(_max_headway, _label, color)| color.clone())
+                        .unwrap_or(NO_DATA);
+                    trip_colors.colors.insert(trip.id.clone(), color);
+                }
+                trip_colors.legend = HEADWAY_BANDS
+                    .iter()
+                    .map(|(_max_headway, label, color)| (label.to_string(), color.clone()))
+                    .collect();
+                trip_colors
+                    .legend
+                    .push(("single trip or no times".to_string(), NO_DATA));
+            }
+            MapColorMode::Wheelchair => {
+                let accessible = Color::rgb8(26, 150, 65);
+                let not_accessible = Color::rgb8(215, 48, 39);
+                for trip in &data.trips {
+                    let color = match trip.wheelchair_accessible.0 {
+                        Availability::Available => accessible.clone(),
+                        Availability::NotAvailable => not_accessible.clone(),
+                        _ => NO_DATA,
+                    };
+                    trip_colors.colors.insert(trip.id.clone(), color);
+                }
+                trip_colors.legend = vec![
+                    ("accessible".to_string(), accessible),
+                    ("not accessible".to_string(), not_accessible),
+                    ("no information".to_string(), NO_DATA),
+                ];
+            }
+            MapColorMode::EditStatus => {
+                let new = Color::rgb8(26, 150, 65);
+                let edited = Color::rgb8(255, 127, 14);
+                let deleted = Color::rgb8(215, 48, 39);
+                for trip in &data.trips {
+                    let color = if !trip.live {
+                        deleted.clone()
+                    } else if trip.trip.is_none() {
+                        new.clone()
+                    } else if trip.edited {
+                        edited.clone()
+                    } else {
+                        NO_DATA
+                    };
+                    trip_colors.colors.insert(trip.id.clone(), color);
+                }
+                trip_colors.legend = vec![
+                    ("new".to_string(), new),
+                    ("edited".to_string(), edited),
+                    ("deleted".to_string(), deleted),
+                    ("unchanged".to_string(), NO_DATA),
+                ];
+            }
+        }
+        trip_colors
+    }
+
+    /// gives each distinct category a colour from PALETTE, in order of first appearance
+    fn categorise(&mut self, data: &AppData, category: impl Fn(&MyTrip) -> Option<String>) {
+        let mut category_colors: HashMap<String, Color> = HashMap::new();
+        for trip in &data.trips {
+            let color = match category(trip) {
+                Some(category) => {
+                    if !category_colors.contains_key(&category) {
+                        let color = PALETTE[category_colors.len() % PALETTE.len()].clone();
+                        self.legend.push((category.clone(), color.clone()));
+                        category_colors.insert(category.clone(), color);
+                    }
+                    category_colors.get(&category).unwrap().clone()
+                }
+                None => NO_DATA,
+            };
+            self.colors.insert(trip.id.clone(), color);
+        }
+    }
+
+    pub fn get(&self, trip_id: &str) -> Color {
+        self.colors.get(trip_id).cloned().unwrap_or(NO_DATA)
+    }
+}
+
+/// median minutes between the first departures of trips on the same route, direction, and service, for each trip id
+fn trip_headways(data: &AppData) -> HashMap<String, u32> {
+    let mut departures: HashMap<(String, String, String), Vec<(u32, String)>> = HashMap::new();
+    for trip in &data.trips {
+        let first_departure = data
+            .stop_time_range_from_trip_id
+            .get(&trip.id)
+            .filter(|(start, end)| start < end)
+            .and_then(|(start, _end)| data.stop_times.get(start))
+            .and_then(|stop_time| stop_time.departure_time.or(stop_time.arrival_time));
+        if let Some(first_departure) = first_departure {
+            departures
+                .entry((
+                    trip.route_id.clone(),
+                    format!("{:?}", trip.direction_id),
+                    trip.service_id.clone(),
+                ))
+                .or_insert_with(Vec::new)
+                .push((first_departure, trip.id.clone()));
+        }
+    }
+
+    let mut headways = HashMap::new();
+    for (_key, mut trip_departures) in departures {
+        if trip_departures.len() < 2 {
+            continue;
+        }
+        trip_departures.sort();
+        let mut gaps = trip_departures
+            .windows(2)
+            .map(|pair| pair[1].0 - pair[0].0)
+            .collect::<Vec<_>>();
+        gaps.sort_unstable();
+        let median_minutes = gaps[gaps.len() / 2] / 60;
+        for (_departure, trip_id) in trip_departures {
+            headways.insert(trip_id, median_minutes);
+        }
+    }
+    headways
+}
@@ -0,0 +1,114 @@
+use druid::commands::{SHOW_OPEN_PANEL, SHOW_SAVE_PANEL};
+use druid::{
+    menu, Env, FileDialogOptions, FileSpec, LocalizedString, Menu, MenuItem, SysMods, WindowId,
+};
+
+use crate::app_delegate::*;
+use crate::data::AppData;
+use crate::project::PROJECT_EXTENSION;
+
+pub fn open_feed_dialog() -> FileDialogOptions {
+    FileDialogOptions::new()
+        .allowed_types(vec![
+            FileSpec::new("GTFS zip", &["zip"]),
+            FileSpec::new("TransXChange or NeTEx", &["xml", "zip"]),
+        ])
+        .accept_command(OPEN_FEED)
+}
+
+/// for feeds which have already been unzipped
+pub fn open_feed_folder_dialog() -> FileDialogOptions {
+    FileDialogOptions::new()
+        .select_directories()
+        .accept_command(OPEN_FEED)
+}
+
+/// like open_feed_dialog, but copies the feed into the library
+pub fn import_feed_dialog() -> FileDialogOptions {
+    open_feed_dialog().accept_command(IMPORT_FEED)
+}
+
+pub fn import_feed_folder_dialog() -> FileDialogOptions {
+    open_feed_folder_dialog().accept_command(IMPORT_FEED)
+}
+
+pub fn open_project_dialog() -> FileDialogOptions {
+    FileDialogOptions::new()
+        .allowed_types(vec![FileSpec::new("GTFS project", &[PROJECT_EXTENSION])])
+        .accept_command(OPEN_PROJECT)
+}
+
+pub fn save_project_dialog() -> FileDialogOptions {
+    FileDialogOptions::new()
+        .allowed_types(vec![FileSpec::new("GTFS project", &[PROJECT_EXTENSION])])
+        .default_name(format!("edits.{}", PROJECT_EXTENSION))
+        .accept_command(SAVE_PROJECT)
+}
+
+/// the menu bar, rebuilt when the recent files change
+pub fn make_menu(_window: Option<WindowId>, data: &AppData, _env: &Env) -> Menu<AppData> {
+    #[cfg(target_os = "macos")]
+    let base = Menu::empty().entry(menu::sys::mac::application::default());
+    #[cfg(not(target_os = "macos"))]
+    let base = Menu::empty();
+    base.entry(file_menu(data))
+        .rebuild_on(|old: &AppData, data: &AppData, _| old.recent_files != data.recent_files)
+}
+
+fn file_menu(data: &AppData) -> Menu<AppData> {
+    let recent_files = data
+        .recent_files
+        .iter()
+        .fold(Menu::new("Open recent"), |menu, path| {
+            menu.entry(MenuItem::new(path.clone()).command(OPEN_RECENT.with(path.clone())))
+        });
+    let file = Menu::new(LocalizedString::new("common-menu-file-menu"))
+        .entry(
+            MenuItem::new("Open feed...")
+                .command(SHOW_OPEN_PANEL.with(open_feed_dialog()))
+                .hotkey(SysMods::Cmd, "o"),
+        )
+        .entry(
+            MenuItem::new("Open feed folder...")
+                .command(SHOW_OPEN_PANEL.with(open_feed_folder_dialog())),
+        )
+        .entry(
+            MenuItem::new("Open project...").command(SHOW_OPEN_PANEL.with(open_project_dialog())),
+        )
+        .entry(recent_files)
+        .separator()
+        .entry(
+            MenuItem::new("Add feed to library...")
+                .command(SHOW_OPEN_PANEL.with(import_feed_dialog())),
+        )
+        .entry(
+            MenuItem::new("Add feed folder to library...")
+                .command(SHOW_OPEN_PANEL.with(import_feed_folder_dialog())),
+        )
+        .separator()
+        .entry(
+            MenuItem::new("Save project")
+                .command(SAVE)
+                .hotkey(SysMods::Cmd, "s")
+                .enabled_if(|data: &AppData, _| data.source_path.is_some()),
+        )
+        .entry(
+            MenuItem::new("Save project as...")
+                .command(SHOW_SAVE_PANEL.with(save_project_dialog()))
+                .hotkey(SysMods::CmdShift, "S")
+                .enabled_if(|data: &AppData, _| data.source_path.is_some()),
+        )
+        .separator()
+        .entry(
+            MenuItem::new("Close")
+                .command(CLOSE_FEED)
+                .hotkey(SysMods::Cmd, "w")
+                .enabled_if(|data: &AppData, _| data.source_path.is_some()),
+        );
+    // quit is in the application menu on mac
+    if cfg!(target_os = "macos") {
+        file
+    } else {
+        file.separator().entry(menu::sys::win::file::exit())
+    }
+}
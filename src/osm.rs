@@ -0,0 +1,295 @@
+use druid::Point;
+use gtfs_structures::RouteType;
+use osmpbfreader::{OsmObj, OsmPbfReader, Tags};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+
+use crate::data::MyRouteType;
+use crate::geometry::haversine_distance;
+
+/// size in degrees of the cells used to look up the nearest node
+const GRID_CELL_SIZE: f64 = 0.005;
+/// don't snap stops to nodes further away than this (metres)
+const MAX_SNAP_DISTANCE: f64 = 500.;
+
+/// The kind of OSM ways a route type can travel along
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NetworkMode {
+    Road,
+    Rail,
+    Tram,
+    Ferry,
+}
+impl NetworkMode {
+    pub fn from_route_type(route_type: &MyRouteType) -> Option<NetworkMode> {
+        match route_type.0 {
+            RouteType::Bus | RouteType::Coach | RouteType::Taxi => Some(NetworkMode::Road),
+            RouteType::Rail | RouteType::Subway | RouteType::Funicular => Some(NetworkMode::Rail),
+            RouteType::Tramway => Some(NetworkMode::Tram),
+            RouteType::Ferry => Some(NetworkMode::Ferry),
+            _ => None,
+        }
+    }
+    fn bit(&self) -> u8 {
+        match self {
+            NetworkMode::Road => 1,
+            NetworkMode::Rail => 1 << 1,
+            NetworkMode::Tram => 1 << 2,
+            NetworkMode::Ferry => 1 << 3,
+        }
+    }
+    /// bitmask of the modes which can use a way with these tags
+    fn way_modes(tags: &Tags) -> u8 {
+        let mut modes = 0;
+        if tags.contains_key("highway")
+            && ![
+                "footway",
+                "path",
+                "cycleway",
+                "steps",
+                "pedestrian",
+                "bridleway",
+                "corridor",
+                "proposed",
+                "construction",
+            ]
+            .iter()
+            .any(|value| tags.contains("highway", value))
+        {
+            modes |= NetworkMode::Road.bit();
+        }
+        if [
+            "rail",
+            "light_rail",
+            "subway",
+            "narrow_gauge",
+            "monorail",
+            "funicular",
+        ]
+        .iter()
+        .any(|value| tags.contains("railway", value))
+        {
+            modes |= NetworkMode::Rail.bit();
+        }
+        if tags.contains("railway", "tram") {
+            modes |= NetworkMode::Tram.bit();
+        }
+        if tags.contains("route", "ferry") {
+            modes |= NetworkMode::Ferry.bit();
+        }
+        modes
+    }
+}
+
+/// A shape made by routing between a trip's stops
+#[derive(Debug, Clone)]
+pub struct RoutedShape {
+    /// (long, lat) coords
+    pub coords: Vec<Point>,
+    /// legs between stops for which no route was found, so were drawn as a straight line
+    pub failed_legs: usize,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+struct QueueItem {
+    /// distance travelled plus the estimate of the remaining distance
+    estimate: f64,
+    node: usize,
+}
+impl Eq for QueueItem {}
+impl Ord for QueueItem {
+    // reversed so that BinaryHeap pops the smallest estimate first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+impl PartialOrd for QueueItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Graph of the ways in an OSM extract which transit can travel along
+pub struct RoadNetwork {
+    /// (long, lat) of each node
+    nodes: Vec<Point>,
+    /// modes which can reach each node
+    node_modes: Vec<u8>,
+    /// for each node, (neighbour node index, distance in metres, modes)
+    edges: Vec<Vec<(usize, f64, u8)>>,
+    /// node indexes in each GRID_CELL_SIZE cell
+    grid: HashMap<(i64, i64), Vec<usize>>,
+}
+impl RoadNetwork {
+    pub fn from_pbf(path: impl AsRef<Path>) -> Result<RoadNetwork, Box<dyn Error>> {
+        myprint!("osm: read pbf");
+        let file = File::open(path)?;
+        let mut pbf = OsmPbfReader::new(file);
+        let objs = pbf.get_objs_and_deps(|obj| {
+            obj.is_way() && NetworkMode::way_modes(obj.tags()) != 0
+        })?;
+
+        myprint!("osm: make graph");
+        let mut network = RoadNetwork {
+            nodes: Vec::new(),
+            node_modes: Vec::new(),
+            edges: Vec::new(),
+            grid: HashMap::new(),
+        };
+        let mut node_index_from_id = HashMap::new();
+        for obj in objs.values() {
+            if let OsmObj::Node(node) = obj {
+                node_index_from_id.insert(node.id, network.nodes.len());
+                network.nodes.push(Point::new(node.lon(), node.lat()));
+                network.node_modes.push(0);
+                network.edges.push(Vec::new());
+            }
+        }
+        for obj in objs.values() {
+            if let OsmObj::Way(way) = obj {
+                let modes = NetworkMode::way_modes(&way.tags);
+                // trains and trams are assumed to be able to use track in both directions
+                let oneway = modes == NetworkMode::Road.bit()
+                    && (way.tags.contains("oneway", "yes")
+                        || way.tags.contains("junction", "roundabout"));
+                let node_indexes = way
+                    .nodes
+                    .iter()
+                    .filter_map(|node_id| node_index_from_id.get(node_id).cloned())
+                    .collect::<Vec<_>>();
+                for pair in node_indexes.windows(2) {
+                    let (a, b) = (pair[0], pair[1]);
+                    let distance = haversine_distance(network.nodes[a], network.nodes[b]);
+                    network.edges[a].push((b, distance, modes));
+                    if !oneway {
+                        network.edges[b].push((a, distance, modes));
+                    }
+                    network.node_modes[a] |= modes;
+                    network.node_modes[b] |= modes;
+                }
+            }
+        }
+        for (i, node) in network.nodes.iter().enumerate() {
+            if network.node_modes[i] != 0 {
+                network
+                    .grid
+                    .entry(RoadNetwork::grid_cell(*node))
+                    .or_insert_with(Vec::new)
+                    .push(i);
+            }
+        }
+        myprint!(
+            "osm: finished, nodes: ",
+            network.nodes.len(),
+            " cells: ",
+            network.grid.len()
+        );
+        Ok(network)
+    }
+
+    fn grid_cell(coord: Point) -> (i64, i64) {
+        (
+            (coord.x / GRID_CELL_SIZE).floor() as i64,
+            (coord.y / GRID_CELL_SIZE).floor() as i64,
+        )
+    }
+
+    /// the nearest node usable by the mode, searching the cell containing coord and the cells around it
+    fn nearest_node(&self, coord: Point, mode: NetworkMode) -> Option<usize> {
+        let (cell_x, cell_y) = RoadNetwork::grid_cell(coord);
+        let mut nearest: Option<(f64, usize)> = None;
+        for x in cell_x - 1..=cell_x + 1 {
+            for y in cell_y - 1..=cell_y + 1 {
+                if let Some(node_indexes) = self.grid.get(&(x, y)) {
+                    for node_index in node_indexes {
+                        if self.node_modes[*node_index] & mode.bit() == 0 {
+                            continue;
+                        }
+                        let distance = haversine_distance(coord, self.nodes[*node_index]);
+                        if nearest.map_or(true, |(nearest_distance, _)| distance < nearest_distance)
+                        {
+                            nearest = Some((distance, *node_index));
+                        }
+                    }
+                }
+            }
+        }
+        nearest
+            .filter(|(distance, _node_index)| *distance < MAX_SNAP_DISTANCE)
+            .map(|(_distance, node_index)| node_index)
+    }
+
+    /// A* search between two nodes, returning the node indexes along the way
+    fn shortest_path(&self, start: usize, end: usize, mode: NetworkMode) -> Option<Vec<usize>> {
+        let end_coord = self.nodes[end];
+        let mut distances = HashMap::new();
+        let mut came_from = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        distances.insert(start, 0.);
+        queue.push(QueueItem {
+            estimate: haversine_distance(self.nodes[start], end_coord),
+            node: start,
+        });
+        while let Some(QueueItem { node, .. }) = queue.pop() {
+            if node == end {
+                let mut path = vec![end];
+                let mut current = end;
+                while let Some(previous) = came_from.get(&current) {
+                    path.push(*previous);
+                    current = *previous;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let distance = distances[&node];
+            for (neighbour, edge_distance, modes) in &self.edges[node] {
+                if modes & mode.bit() == 0 {
+                    continue;
+                }
+                let neighbour_distance = distance + edge_distance;
+                if distances
+                    .get(neighbour)
+                    .map_or(true, |current_distance| neighbour_distance < *current_distance)
+                {
+                    distances.insert(*neighbour, neighbour_distance);
+                    came_from.insert(*neighbour, node);
+                    queue.push(QueueItem {
+                        estimate: neighbour_distance
+                            + haversine_distance(self.nodes[*neighbour], end_coord),
+                        node: *neighbour,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// routes along the network between each pair of consecutive stops (long, lat). Legs which can't be routed are drawn as a straight line.
+    pub fn route_through_stops(&self, stop_coords: &[Point], mode: NetworkMode) -> RoutedShape {
+        let mut coords = Vec::new();
+        let mut failed_legs = 0;
+        for (i, stop_coord) in stop_coords.iter().enumerate() {
+            coords.push(*stop_coord);
+            if let Some(next_stop_coord) = stop_coords.get(i + 1) {
+                let path = self
+                    .nearest_node(*stop_coord, mode)
+                    .zip(self.nearest_node(*next_stop_coord, mode))
+                    .and_then(|(start, end)| self.shortest_path(start, end, mode));
+                match path {
+                    Some(path) => coords.extend(path.iter().map(|node| self.nodes[*node])),
+                    None => failed_legs += 1,
+                }
+            }
+        }
+        RoutedShape {
+            coords,
+            failed_legs,
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use druid::im::{OrdMap, Vector};
+use druid::Point;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::data::*;
+
+/// bump when the format changes in a way older projects can't be read with
+const PROJECT_VERSION: u32 = 1;
+pub const PROJECT_EXTENSION: &str = "gtfsproj";
+
+/// Work in progress on a feed, saved as json separately from the feed itself, so a half finished editing session can be reopened and continued without exporting a new GTFS.
+/// Only the items which differ from the feed are stored, each with the index of its original record in MyGtfs (None for new items), which is stable since the feed's tables are always sorted the same way
+#[derive(Serialize, Deserialize)]
+pub struct Project {
+    version: u32,
+    /// the GTFS zip or directory the edits apply to
+    pub source: PathBuf,
+    /// FeedCache::hash of the source when the project was saved. The items are matched to the feed's records by index, so the edits can't be applied to a different version of the feed
+    #[serde(default)]
+    source_hash: Option<String>,
+    /// where the project was read from
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+
+    agencies: Vec<(Option<usize>, MyAgency)>,
+    routes: Vec<(Option<usize>, MyRoute)>,
+    trips: Vec<(Option<usize>, MyTrip)>,
+    stops: Vec<(Option<usize>, MyStop)>,
+    /// all the stop_times of each trip which has had any of its stop_times edited or added, in order
+    stop_times: Vec<(Option<usize>, MyStopTime)>,
+    edited_shapes: OrdMap<String, Vector<MyShapePoint>>,
+    actions: Vector<Action>,
+
+    selected_agency_id: Option<Option<String>>,
+    selected_route_id: Option<String>,
+    selected_trip_id: Option<String>,
+    selected_stop_time_id: Option<(String, u16)>,
+    selected_stop_id: Option<String>,
+    show_deleted: bool,
+    show_edits: bool,
+    show_actions: bool,
+    map_zoom_level: ZoomLevel,
+    map_focal_point: (f64, f64),
+}
+impl Project {
+    pub fn new(data: &AppData, source: PathBuf) -> Project {
+        let edited_trip_ids = data
+            .stop_times
+            .iter()
+            .filter(|stop_time| stop_time_edited(&data.gtfs, stop_time))
+            .map(|stop_time| stop_time.trip_id.clone())
+            .collect::<HashSet<_>>();
+        let mut stop_times = Vec::new();
+        for (trip_id, (start_index, end_index)) in data.stop_time_range_from_trip_id.iter() {
+            if edited_trip_ids.contains(trip_id) {
+                // indexing im::Vector is logarithmic, where skipping through its iterator is linear
+                stop_times.extend((start_index..end_index).map(|i| {
+                    let stop_time = &data.stop_times[i];
+                    (
+                        stop_time.stop_time.as_ref().map(|raw| raw.index()),
+                        stop_time.clone(),
+                    )
+                }));
+            }
+        }
+
+        Project {
+            version: PROJECT_VERSION,
+            source,
+            source_hash: data.source_hash.clone(),
+            path: None,
+            agencies: edited_items(
+                &data.agencies,
+                |agency| &agency.agency,
+                |agency| agency_edited(&data.gtfs, agency),
+            ),
+            routes: edited_items(
+                &data.routes,
+                |route| &route.route,
+                |route| route_edited(&data.gtfs, route),
+            ),
+            trips: edited_items(
+                &data.trips,
+                |trip| &trip.trip,
+                |trip| trip_edited(&data.gtfs, trip),
+            ),
+            stops: edited_items(
+                &data.stops,
+                |stop| &stop.stop,
+                |stop| stop_edited(&data.gtfs, stop),
+            ),
+            stop_times,
+            edited_shapes: data.edited_shapes.clone(),
+            actions: data.actions.clone(),
+
+            selected_agency_id: data.selected_agency_id.clone(),
+            selected_route_id: data.selected_route_id.clone(),
+            selected_trip_id: data.selected_trip_id.clone(),
+            selected_stop_time_id: data.selected_stop_time_id.clone(),
+            selected_stop_id: data.selected_stop_id.clone(),
+            show_deleted: data.show_deleted,
+            show_edits: data.show_edits,
+            show_actions: data.show_actions,
+            map_zoom_level: data.map_zoom_level,
+            map_focal_point: data.map_focal_point,
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Project, Box<dyn Error>> {
+        let mut project: Project = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        project.path = Some(path.to_path_buf());
+        if project.version != PROJECT_VERSION {
+            return Err(format!(
+                "project was saved with version {} of the format, but only version {} can be read",
+                project.version, PROJECT_VERSION
+            )
+            .into());
+        }
+        Ok(project)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    /// hash of the edited items, ignoring view state like which are selected or expanded, so it only changes when the feed is edited. Used to tell if there are unsaved changes
+    pub fn edits_fingerprint(&self) -> u64 {
+        let mut edits = serde_json::json!([
+            self.agencies,
+            self.routes,
+            self.trips,
+            self.stops,
+            self.stop_times,
+            self.edited_shapes,
+        ]);
+        remove_view_fields(&mut edits);
+        let mut hasher = DefaultHasher::new();
+        edits.to_string().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// replays the saved items over the freshly loaded feed and restores the view. Fails without changing anything if the feed isn't the one the project was saved with
+    pub fn apply(self, data: &mut AppData) -> Result<(), Box<dyn Error>> {
+        // feeds which couldn't be hashed, eg read from stdin, can't be checked
+        if let (Some(saved_hash), Some(source_hash)) = (&self.source_hash, &data.source_hash) {
+            if saved_hash != source_hash {
+                return Err(format!(
+                    "{} has changed since the project was saved, so its edits can't be applied",
+                    self.source.to_string_lossy()
+                )
+                .into());
+            }
+        }
+        for (raw_index, mut agency) in self.agencies {
+            match raw_index {
+                Some(i) if i < data.agencies.len() => {
+                    agency.agency = data.agencies[i].agency.clone();
+                    let old_id = std::mem::replace(&mut data.agencies[i], agency).id;
+                    data.agency_index_from_id.remove(&old_id);
+                    data.agency_index_from_id
+                        .insert(data.agencies[i].id.clone(), i);
+                }
+                _ => {
+                    data.agency_index_from_id
+                        .insert(agency.id.clone(), data.agencies.len());
+                    data.agencies.push_back(agency);
+                }
+            }
+        }
+        for (raw_index, mut route) in self.routes {
+            match raw_index {
+                Some(i) if i < data.routes.len() => {
+                    route.route = data.routes[i].route.clone();
+                    let old_id = std::mem::replace(&mut data.routes[i], route).id;
+                    data.route_index_from_id.remove(&old_id);
+                    data.route_index_from_id
+                        .insert(data.routes[i].id.clone(), i);
+                }
+                _ => data.push_route(route),
+            }
+        }
+        for (raw_index, mut stop) in self.stops {
+            stop.latlong = Point::new(
+                stop.longitude.unwrap_or_default(),
+                stop.latitude.unwrap_or_default(),
+            );
+            match raw_index {
+                Some(i) if i < data.stops.len() => {
+                    stop.stop = data.stops[i].stop.clone();
+                    let old_id = std::mem::replace(&mut data.stops[i], stop).id;
+                    data.stop_index_from_id.remove(&old_id);
+                    data.stop_index_from_id.insert(data.stops[i].id.clone(), i);
+                }
+                _ => data.push_stop(stop),
+            }
+        }
+        for (raw_index, mut trip) in self.trips {
+            match raw_index {
+                Some(i) if i < data.trips.len() => {
+                    trip.trip = data.trips[i].trip.clone();
+                    let old_id = std::mem::replace(&mut data.trips[i], trip).id;
+                    data.trip_index_from_id.remove(&old_id);
+                    data.trip_index_from_id.insert(data.trips[i].id.clone(), i);
+                }
+                _ => data.push_trip(trip),
+            }
+        }
+
+        // the saved stop_times of each trip overwrite its loaded ones in order, and any extra ones are inserted after them
+        let mut trip_position = None;
+        for (raw_index, mut stop_time) in self.stop_times {
+            stop_time.stop_time = raw_index
+                .filter(|i| *i < data.gtfs.stop_times.len())
+                .map(RawIndex::new);
+            if let Some(stop) = data.stop(&stop_time.stop_id) {
+                stop_time.latlong = stop.latlong;
+            }
+            let position = match trip_position {
+                Some((trip_id, position)) if trip_id == stop_time.trip_id => position + 1,
+                _ => data
+                    .stop_time_range_from_trip_id
+                    .get(&stop_time.trip_id)
+                    .map_or(data.stop_times.len(), |(start_index, _)| start_index),
+            };
+            let trip_end = data
+                .stop_time_range_from_trip_id
+                .get(&stop_time.trip_id)
+                .map(|(_, end_index)| end_index);
+            trip_position = Some((stop_time.trip_id.clone(), position));
+            if trip_end.map_or(false, |end_index| position < end_index) {
+                data.stop_times[position] = stop_time;
+            } else {
+                data.insert_stop_time(position, stop_time);
+            }
+        }
+
+        data.edited_shapes = self.edited_shapes;
+        data.next_action_id = self
+            .actions
+            .iter()
+            .map(|action| action.id + 1)
+            .max()
+            .unwrap_or(0);
+        data.actions = self.actions;
+
+        // only restore the selection if the items still exist, eg if the feed has been replaced with a newer version
+        if let Some(agency_id) = &self.selected_agency_id {
+            if data.agency(agency_id).is_some() {
+                data.selected_agency_id = self.selected_agency_id;
+            }
+        }
+        if let Some(route_id) = &self.selected_route_id {
+            if data.route(route_id).is_some() {
+                data.selected_route_id = self.selected_route_id;
+            }
+        }
+        if let Some(trip_id) = &self.selected_trip_id {
+            if data.trip(trip_id).is_some() {
+                data.selected_trip_id = self.selected_trip_id;
+            }
+        }
+        if let Some((trip_id, stop_sequence)) = &self.selected_stop_time_id {
+            if data.stop_time_index(trip_id, *stop_sequence).is_some() {
+                data.selected_stop_time_id = self.selected_stop_time_id;
+            }
+        }
+        if let Some(stop_id) = &self.selected_stop_id {
+            if data.stop(stop_id).is_some() {
+                data.selected_stop_id = self.selected_stop_id;
+            }
+        }
+        data.show_deleted = self.show_deleted;
+        data.show_edits = self.show_edits;
+        data.show_actions = self.show_actions;
+        data.map_zoom_level = self.map_zoom_level;
+        data.map_focal_point = self.map_focal_point;
+        Ok(())
+    }
+}
+
+/// fields of the My* items which are only used by the views
+pub(crate) const VIEW_FIELDS: [&str; 7] = [
+    "selected",
+    "expanded",
+    "visible",
+    "show_editing",
+    "show_deleted",
+    "hovered",
+    "scroll_to_me",
+];
+
+fn remove_view_fields(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for field in VIEW_FIELDS {
+                object.remove(field);
+            }
+            object.values_mut().for_each(remove_view_fields);
+        }
+        Value::Array(array) => array.iter_mut().for_each(remove_view_fields),
+        _ => {}
+    }
+}
+
+fn edited_items<T: Clone, R: GtfsRecord>(
+    items: &Vector<T>,
+    raw: impl Fn(&T) -> &Option<RawIndex<R>>,
+    edited: impl Fn(&T) -> bool,
+) -> Vec<(Option<usize>, T)> {
+    items
+        .iter()
+        .filter(|item| edited(item))
+        .map(|item| (raw(item).as_ref().map(|raw| raw.index()), item.clone()))
+        .collect()
+}
+
+fn agency_edited(gtfs: &MyGtfs, agency: &MyAgency) -> bool {
+    match &agency.agency {
+        Some(raw) => {
+            let raw = raw.get(gtfs);
+            !agency.live
+                || agency.id != raw.id
+                || agency.name != raw.name
+                || agency.url != raw.url
+                || agency.timezone != raw.timezone
+                || agency.lang != raw.lang
+                || agency.phone != raw.phone
+                || agency.fare_url != raw.fare_url
+                || agency.email != raw.email
+        }
+        None => true,
+    }
+}
+
+fn route_edited(gtfs: &MyGtfs, route: &MyRoute) -> bool {
+    match &route.route {
+        Some(raw) => {
+            let raw = raw.get(gtfs);
+            !route.live
+                || route.id != raw.id
+                || route.short_name != raw.short_name
+                || route.long_name != raw.long_name
+                || route.desc != raw.desc
+                || route.route_type.0 != raw.route_type
+                || route.url != raw.url
+                || route.agency_id != raw.agency_id
+                || route.order != raw.order
+                || route.color.0 != raw.color
+                || route.text_color.0 != raw.text_color
+                || route.continuous_pickup.0 != raw.continuous_pickup
+                || route.continuous_drop_off.0 != raw.continuous_drop_off
+        }
+        None => true,
+    }
+}
+
+fn trip_edited(gtfs: &MyGtfs, trip: &MyTrip) -> bool {
+    match &trip.trip {
+        Some(raw) => {
+            let raw = raw.get(gtfs);
+            trip.edited
+                || !trip.live
+                || trip.id != raw.id
+                || trip.service_id != raw.service_id
+                || trip.route_id != raw.route_id
+                || trip.shape_id != raw.shape_id
+                || trip.trip_headsign != raw.trip_headsign
+                || trip.trip_short_name != raw.trip_short_name
+                || trip.direction_id.map(|direction| direction.0) != raw.direction_id
+                || trip.block_id != raw.block_id
+                || trip.wheelchair_accessible.0 != raw.wheelchair_accessible
+                || trip.bikes_allowed.0 != raw.bikes_allowed
+        }
+        None => true,
+    }
+}
+
+fn stop_edited(gtfs: &MyGtfs, stop: &MyStop) -> bool {
+    match &stop.stop {
+        Some(raw) => {
+            let raw = raw.get(gtfs);
+            stop.edited
+                || !stop.live
+                || stop.id != raw.id
+                || stop.code != raw.code
+                || stop.name != raw.name
+                || stop.description != raw.description
+                || stop.location_type.0 != raw.location_type
+                || stop.parent_station != raw.parent_station
+                || stop.zone_id != raw.zone_id
+                || stop.url != raw.url
+                || stop.longitude != raw.longitude
+                || stop.latitude != raw.latitude
+                || stop.timezone != raw.timezone
+                || stop.wheelchair_boarding.0 != raw.wheelchair_boarding
+                || stop.level_id != raw.level_id
+                || stop.platform_code != raw.platform_code
+        }
+        None => true,
+    }
+}
+
+fn stop_time_edited(gtfs: &MyGtfs, stop_time: &MyStopTime) -> bool {
+    match &stop_time.stop_time {
+        Some(raw) => {
+            let raw = raw.get(gtfs);
+            stop_time.edited
+                || !stop_time.live
+                || stop_time.trip_id != raw.trip_id
+                || stop_time.arrival_time != raw.arrival_time
+                || stop_time.departure_time != raw.departure_time
+                || stop_time.stop_id != raw.stop_id
+                || stop_time.stop_sequence != raw.stop_sequence
+                || stop_time.stop_headsign != raw.stop_headsign
+                || stop_time.pickup_type.0 != raw.pickup_type
+                || stop_time.drop_off_type.0 != raw.drop_off_type
+                || stop_time.continuous_pickup.0 != raw.continuous_pickup
+                || stop_time.continuous_drop_off.0 != raw.continuous_drop_off
+                || stop_time.shape_dist_traveled != raw.shape_dist_traveled
+                || stop_time.timepoint.0 != raw.timepoint
+        }
+        None => true,
+    }
+}
@@ -0,0 +1,53 @@
+use druid::im::Vector;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// how many entries the Open Recent menu shows
+const MAX_RECENT_FILES: usize = 10;
+
+/// the feeds and projects opened most recently, first. Empty if none have been opened or the list can't be read
+pub fn read_recent_files() -> Vector<String> {
+    let path = match config_dir() {
+        Some(dir) => dir.join("recent.json"),
+        None => return Vector::new(),
+    };
+    File::open(path)
+        .ok()
+        .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+        .unwrap_or_default()
+}
+
+/// moves path to the front of the list and saves it
+pub fn remember_recent_file(recent_files: &mut Vector<String>, path: &str) {
+    recent_files.retain(|recent| recent != path);
+    recent_files.push_front(path.to_string());
+    recent_files.truncate(MAX_RECENT_FILES);
+    if let Err(error) = write_recent_files(recent_files) {
+        myprint!("failed to save recent files: ", error);
+    }
+}
+
+fn write_recent_files(recent_files: &Vector<String>) -> Result<(), Box<dyn Error>> {
+    let dir = config_dir().ok_or("no config directory")?;
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join("recent.json"),
+        serde_json::to_string(recent_files)?,
+    )?;
+    Ok(())
+}
+
+/// $XDG_CONFIG_HOME/gtfs_manager, falling back to ~/.config/gtfs_manager, or %APPDATA%\gtfs_manager on windows
+pub fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    base.map(|base| base.join("gtfs_manager"))
+}
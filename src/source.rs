@@ -0,0 +1,601 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use crate::xml_feed::convert_xml_feed;
+
+/// pass this as the path to read a zipped feed from stdin, eg `curl https://example.com/gtfs.zip | gtfs_manager - --info`
+pub const STDIN_PATH: &str = "-";
+
+/// the files which mark a directory, or a folder inside a zip, as holding a feed
+const FEED_MARKER_FILES: [&str; 2] = ["agency.txt", "stops.txt"];
+
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN_PATH)
+}
+
+/// feeds at http and https URLs are downloaded to a temporary file before they are opened
+pub fn is_url(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+#[derive(Debug)]
+pub enum FeedSourceError {
+    NotFound(PathBuf),
+    /// the path couldn't be looked at, eg for lack of permission
+    Io(PathBuf, io::Error),
+    /// the path is a file, but not one which can be read as a zip
+    NotAZip(PathBuf, ZipError),
+    Stdin(io::Error),
+    /// nothing was piped in
+    EmptyStdin,
+    /// stdin was read but isn't a zip
+    StdinNotAZip(ZipError),
+    /// the directory or zip, or any folder in it, doesn't have a file the feed needs
+    MissingFile {
+        feed: String,
+        name: String,
+    },
+    Read {
+        name: String,
+        error: io::Error,
+    },
+    ReadZip {
+        name: String,
+        error: ZipError,
+    },
+    /// a TransXChange or NeTEx document which couldn't be converted
+    Xml {
+        name: String,
+        error: String,
+    },
+}
+impl fmt::Display for FeedSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeedSourceError::NotFound(path) => write!(f, "{} does not exist", path.display()),
+            FeedSourceError::Io(path, error) => {
+                write!(f, "failed to open {}: {}", path.display(), error)
+            }
+            FeedSourceError::NotAZip(path, error) => write!(
+                f,
+                "{} is not a GTFS zip or directory: {}",
+                path.display(),
+                error
+            ),
+            FeedSourceError::Stdin(error) => write!(f, "failed to read stdin: {}", error),
+            FeedSourceError::EmptyStdin => write!(f, "nothing was piped to stdin, expected a GTFS zip"),
+            FeedSourceError::StdinNotAZip(error) => {
+                write!(f, "stdin is not a GTFS zip: {}", error)
+            }
+            FeedSourceError::MissingFile { feed, name } => write!(
+                f,
+                "{} has no {}, it may not be a GTFS feed or the file may be in an unexpected folder",
+                feed, name
+            ),
+            FeedSourceError::Read { name, error } => write!(f, "failed to read {}: {}", name, error),
+            FeedSourceError::ReadZip { name, error } => {
+                write!(f, "failed to read {} from zip: {}", name, error)
+            }
+            FeedSourceError::Xml { name, error } => {
+                write!(f, "failed to convert {}: {}", name, error)
+            }
+        }
+    }
+}
+impl Error for FeedSourceError {}
+
+pub(crate) trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Where the feed's files are read from: a directory of .txt files, a zip file, or a zip read whole from stdin. Feeds are often zipped with their files inside a folder, or unzipped into one, so the files are looked for in the shallowest folder that has them.
+/// An XML file, or a directory or zip of them without any GTFS files, is converted from TransXChange or NeTEx into the GTFS files up front
+pub(crate) enum FeedSource {
+    Directory(PathBuf),
+    Zip {
+        archive: ZipArchive<Box<dyn ReadSeek>>,
+        /// the folder inside the zip holding the feed's files, with a trailing / unless it is the top level
+        root: String,
+        /// what to call the zip in errors
+        name: String,
+    },
+    Converted {
+        files: HashMap<String, Vec<u8>>,
+        name: String,
+        /// what was dropped converting the feed, if anything
+        warning: Option<String>,
+    },
+}
+impl FeedSource {
+    pub(crate) fn open(path: &Path) -> Result<FeedSource, FeedSourceError> {
+        if is_stdin(path) {
+            return Self::open_stdin();
+        }
+        let metadata = fs::metadata(path).map_err(|error| match error.kind() {
+            io::ErrorKind::NotFound => FeedSourceError::NotFound(path.to_path_buf()),
+            _ => FeedSourceError::Io(path.to_path_buf(), error),
+        })?;
+        if metadata.is_dir() {
+            let dir = feed_directory(path);
+            if !has_feed(&dir) {
+                let documents = xml_files(&dir)?;
+                if !documents.is_empty() {
+                    return Self::converted(&documents, path.display().to_string());
+                }
+            }
+            return Ok(FeedSource::Directory(dir));
+        }
+        if is_xml(&path.to_string_lossy()) {
+            let text = fs::read_to_string(path).map_err(|error| FeedSourceError::Read {
+                name: path.display().to_string(),
+                error,
+            })?;
+            let name = path.display().to_string();
+            return Self::converted(&[(name.clone(), text)], name);
+        }
+        let file = File::open(path).map_err(|error| FeedSourceError::Read {
+            name: path.display().to_string(),
+            error,
+        })?;
+        let archive = ZipArchive::new(Box::new(file) as Box<dyn ReadSeek>)
+            .map_err(|error| FeedSourceError::NotAZip(path.to_path_buf(), error))?;
+        Self::zip(archive, path.display().to_string())
+    }
+
+    /// zips have their directory at the end, so stdin has to be read whole before anything can be found in it
+    fn open_stdin() -> Result<FeedSource, FeedSourceError> {
+        Self::read_stdin(io::stdin())
+    }
+
+    fn read_stdin(mut stdin: impl Read) -> Result<FeedSource, FeedSourceError> {
+        let mut bytes = Vec::new();
+        stdin
+            .read_to_end(&mut bytes)
+            .map_err(FeedSourceError::Stdin)?;
+        if bytes.is_empty() {
+            return Err(FeedSourceError::EmptyStdin);
+        }
+        let archive = ZipArchive::new(Box::new(Cursor::new(bytes)) as Box<dyn ReadSeek>)
+            .map_err(FeedSourceError::StdinNotAZip)?;
+        Self::zip(archive, "stdin".to_string())
+    }
+
+    fn zip(
+        mut archive: ZipArchive<Box<dyn ReadSeek>>,
+        name: String,
+    ) -> Result<FeedSource, FeedSourceError> {
+        let root = archive
+            .file_names()
+            .filter(|file_name| !is_junk(file_name))
+            .filter_map(|file_name| {
+                let (folder, file) = match file_name.rsplit_once('/') {
+                    Some((folder, file)) => (format!("{}/", folder), file),
+                    None => (String::new(), file_name),
+                };
+                FEED_MARKER_FILES.contains(&file).then(|| folder)
+            })
+            .min_by_key(|folder| folder.matches('/').count());
+        let root = match root {
+            Some(root) => root,
+            None => {
+                let xml_names = archive
+                    .file_names()
+                    .filter(|file_name| !is_junk(file_name) && is_xml(file_name))
+                    .map(|file_name| file_name.to_string())
+                    .collect::<Vec<_>>();
+                if !xml_names.is_empty() {
+                    let mut documents = Vec::new();
+                    for xml_name in xml_names {
+                        let mut text = String::new();
+                        archive
+                            .by_name(&xml_name)
+                            .map_err(|error| FeedSourceError::ReadZip {
+                                name: xml_name.clone(),
+                                error,
+                            })?
+                            .read_to_string(&mut text)
+                            .map_err(|error| FeedSourceError::Read {
+                                name: xml_name.clone(),
+                                error,
+                            })?;
+                        documents.push((xml_name, text));
+                    }
+                    return Self::converted(&documents, name);
+                }
+                // the files will be reported missing when they are read
+                String::new()
+            }
+        };
+        Ok(FeedSource::Zip {
+            archive,
+            root,
+            name,
+        })
+    }
+
+    fn converted(
+        documents: &[(String, String)],
+        name: String,
+    ) -> Result<FeedSource, FeedSourceError> {
+        let feed = convert_xml_feed(documents)?;
+        Ok(FeedSource::Converted {
+            files: feed.files,
+            name,
+            warning: feed.warning,
+        })
+    }
+
+    /// the named file, or None if the feed doesn't have it
+    pub(crate) fn file(
+        &mut self,
+        name: &str,
+    ) -> Result<Option<Box<dyn Read + '_>>, FeedSourceError> {
+        match self {
+            FeedSource::Directory(dir) => {
+                let path = dir.join(name);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                let file = File::open(path).map_err(|error| FeedSourceError::Read {
+                    name: name.to_string(),
+                    error,
+                })?;
+                Ok(Some(Box::new(file)))
+            }
+            FeedSource::Zip { archive, root, .. } => {
+                match archive.by_name(&format!("{}{}", root, name)) {
+                    Ok(file) => Ok(Some(Box::new(file))),
+                    Err(ZipError::FileNotFound) => Ok(None),
+                    Err(error) => Err(FeedSourceError::ReadZip {
+                        name: name.to_string(),
+                        error,
+                    }),
+                }
+            }
+            FeedSource::Converted { files, .. } => Ok(files
+                .get(name)
+                .map(|file| Box::new(file.as_slice()) as Box<dyn Read + '_>)),
+        }
+    }
+
+    /// what had to be dropped converting the feed from XML, for the user to be told
+    pub(crate) fn warning(&self) -> Option<&str> {
+        match self {
+            FeedSource::Converted { warning, .. } => warning.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// what to call the feed in errors
+    pub(crate) fn name(&self) -> String {
+        match self {
+            FeedSource::Directory(dir) => dir.display().to_string(),
+            FeedSource::Zip { name, .. } | FeedSource::Converted { name, .. } => name.clone(),
+        }
+    }
+}
+
+/// the directory itself if it has the feed's files, otherwise its only subdirectory which does, as is left by unzipping a feed which was zipped inside a folder
+pub(crate) fn feed_directory(dir: &Path) -> PathBuf {
+    if has_feed(dir) {
+        return dir.to_path_buf();
+    }
+    let subdirectories = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.is_dir() && !is_junk(&path.file_name().unwrap().to_string_lossy())
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    match subdirectories.as_slice() {
+        [subdirectory] if has_feed(subdirectory) => subdirectory.clone(),
+        // the files will be reported missing when they are read
+        _ => dir.to_path_buf(),
+    }
+}
+
+fn has_feed(dir: &Path) -> bool {
+    FEED_MARKER_FILES.iter().any(|name| dir.join(name).exists())
+}
+
+/// the GTFS .txt files and TransXChange or NeTEx .xml files which make up a feed in a directory
+pub(crate) fn is_feed_file(path: &Path) -> bool {
+    path.extension().map_or(false, |extension| {
+        extension == "txt" || extension.eq_ignore_ascii_case("xml")
+    })
+}
+
+fn is_xml(name: &str) -> bool {
+    name.to_lowercase().ends_with(".xml")
+}
+
+/// the (name, text) of each XML file in the directory, in order of name
+fn xml_files(dir: &Path) -> Result<Vec<(String, String)>, FeedSourceError> {
+    let read_error = |error| FeedSourceError::Read {
+        name: dir.display().to_string(),
+        error,
+    };
+    let mut paths = fs::read_dir(dir)
+        .map_err(read_error)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read_error)?;
+    paths.retain(|path| is_xml(&path.to_string_lossy()) && path.is_file());
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let name = path.display().to_string();
+            fs::read_to_string(&path)
+                .map(|text| (name.clone(), text))
+                .map_err(|error| FeedSourceError::Read { name, error })
+        })
+        .collect()
+}
+
+/// macOS adds a __MACOSX folder of ._ files to zips it creates, which have the same names as the real files but hold metadata
+fn is_junk(name: &str) -> bool {
+    name.starts_with("__MACOSX")
+        || Path::new(name).file_name().map_or(false, |file_name| {
+            file_name.to_string_lossy().starts_with("._")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    const AGENCY: &str =
+        "agency_id,agency_name,agency_url,agency_timezone\n1,Test,https://example.com,UTC\n";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gtfs_manager_source_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn zip_bytes(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn read_agency(source: &mut FeedSource) -> String {
+        let mut text = String::new();
+        source
+            .file("agency.txt")
+            .unwrap()
+            .expect("agency.txt should be found")
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn opens_directory() {
+        let dir = temp_dir("directory");
+        fs::write(dir.join("agency.txt"), AGENCY).unwrap();
+        let mut source = FeedSource::open(&dir).unwrap();
+        assert!(matches!(&source, FeedSource::Directory(path) if *path == dir));
+        assert_eq!(read_agency(&mut source), AGENCY);
+        assert!(source.file("shapes.txt").unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn opens_zip() {
+        let dir = temp_dir("zip");
+        let path = dir.join("feed.zip");
+        fs::write(&path, zip_bytes(&[("agency.txt", AGENCY)])).unwrap();
+        let mut source = FeedSource::open(&path).unwrap();
+        assert!(matches!(&source, FeedSource::Zip { root, .. } if root.is_empty()));
+        assert_eq!(source.name(), path.display().to_string());
+        assert_eq!(read_agency(&mut source), AGENCY);
+        assert!(source.file("shapes.txt").unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn opens_zip_with_nested_folder() {
+        let dir = temp_dir("nested_zip");
+        let path = dir.join("feed.zip");
+        let bytes = zip_bytes(&[
+            ("__MACOSX/feed/._agency.txt", "junk"),
+            ("feed/agency.txt", AGENCY),
+            ("feed/extra/stops.txt", "stop_id\n"),
+        ]);
+        fs::write(&path, bytes).unwrap();
+        let mut source = FeedSource::open(&path).unwrap();
+        assert!(matches!(&source, FeedSource::Zip { root, .. } if root == "feed/"));
+        assert_eq!(read_agency(&mut source), AGENCY);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn opens_zip_from_stdin() {
+        let bytes = zip_bytes(&[("agency.txt", AGENCY)]);
+        let mut source = FeedSource::read_stdin(bytes.as_slice()).unwrap();
+        assert_eq!(source.name(), "stdin");
+        assert_eq!(read_agency(&mut source), AGENCY);
+    }
+
+    #[test]
+    fn finds_feed_in_only_subdirectory() {
+        let dir = temp_dir("feed_directory");
+        assert_eq!(feed_directory(&dir), dir);
+        let nested = dir.join("feed");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(dir.join("__MACOSX")).unwrap();
+        fs::write(nested.join("stops.txt"), "stop_id\n").unwrap();
+        assert_eq!(feed_directory(&dir), nested);
+        assert!(
+            matches!(FeedSource::open(&dir).unwrap(), FeedSource::Directory(path) if path == nested)
+        );
+        // with two candidates it isn't clear which is the feed
+        let other = dir.join("other");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("agency.txt"), AGENCY).unwrap();
+        assert_eq!(feed_directory(&dir), dir);
+        // files at the top level win
+        fs::write(dir.join("agency.txt"), AGENCY).unwrap();
+        assert_eq!(feed_directory(&dir), dir);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recognises_junk() {
+        assert!(is_junk("__MACOSX/"));
+        assert!(is_junk("__MACOSX/feed/agency.txt"));
+        assert!(is_junk("._agency.txt"));
+        assert!(is_junk("feed/._agency.txt"));
+        assert!(!is_junk("agency.txt"));
+        assert!(!is_junk("feed/agency.txt"));
+        assert!(!is_junk("feed_._/stops.txt"));
+    }
+
+    #[test]
+    fn not_found() {
+        let path = std::env::temp_dir().join(format!(
+            "gtfs_manager_source_missing_{}",
+            std::process::id()
+        ));
+        let error = FeedSource::open(&path).err().unwrap();
+        assert!(matches!(&error, FeedSourceError::NotFound(missing) if *missing == path));
+        assert_eq!(
+            error.to_string(),
+            format!("{} does not exist", path.display())
+        );
+    }
+
+    #[test]
+    fn io_error() {
+        let dir = temp_dir("io_error");
+        let file = dir.join("agency.txt");
+        fs::write(&file, AGENCY).unwrap();
+        // a file can't have anything inside it, which isn't the same as the path not existing
+        let path = file.join("feed.zip");
+        let error = FeedSource::open(&path).err().unwrap();
+        assert!(matches!(&error, FeedSourceError::Io(failed, _) if *failed == path));
+        assert!(error.to_string().starts_with("failed to open"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn not_a_zip() {
+        let dir = temp_dir("not_a_zip");
+        let path = dir.join("feed.zip");
+        fs::write(&path, "not a zip").unwrap();
+        let error = FeedSource::open(&path).err().unwrap();
+        assert!(matches!(&error, FeedSourceError::NotAZip(file, _) if *file == path));
+        assert!(error.to_string().contains("is not a GTFS zip or directory"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    struct FailingReader;
+    impl Read for FailingReader {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::Other, "broken pipe"))
+        }
+    }
+
+    #[test]
+    fn urls() {
+        assert!(is_url(Path::new("https://example.com/gtfs.zip")));
+        assert!(is_url(Path::new("http://example.com/gtfs.zip")));
+        assert!(!is_url(Path::new("gtfs.zip")));
+        assert!(!is_url(Path::new("https")));
+        assert!(!is_url(Path::new(STDIN_PATH)));
+    }
+
+    #[test]
+    fn stdin_errors() {
+        let error = FeedSource::read_stdin(FailingReader).err().unwrap();
+        assert!(matches!(error, FeedSourceError::Stdin(_)));
+        assert_eq!(error.to_string(), "failed to read stdin: broken pipe");
+
+        let error = FeedSource::read_stdin(io::empty()).err().unwrap();
+        assert!(matches!(error, FeedSourceError::EmptyStdin));
+
+        let error = FeedSource::read_stdin(&b"not a zip"[..]).err().unwrap();
+        assert!(matches!(error, FeedSourceError::StdinNotAZip(_)));
+        assert!(error.to_string().starts_with("stdin is not a GTFS zip"));
+    }
+
+    #[test]
+    fn missing_file() {
+        let error = FeedSourceError::MissingFile {
+            feed: "feed.zip".to_string(),
+            name: "stops.txt".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "feed.zip has no stops.txt, it may not be a GTFS feed or the file may be in an unexpected folder"
+        );
+    }
+
+    #[test]
+    fn read_error() {
+        let dir = temp_dir("read_error");
+        let path = dir.join("feed.xml");
+        fs::write(&path, [0xff, 0xfe, 0xfd]).unwrap();
+        let error = FeedSource::open(&path).err().unwrap();
+        assert!(
+            matches!(&error, FeedSourceError::Read { name, .. } if *name == path.display().to_string())
+        );
+        assert!(error.to_string().starts_with("failed to read"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_zip_error() {
+        let mut bytes = zip_bytes(&[("agency.txt", AGENCY)]);
+        // mark the file in the central directory as using a compression method which doesn't exist
+        let central = bytes
+            .windows(4)
+            .position(|window| window == b"PK\x01\x02")
+            .unwrap();
+        bytes[central + 10] = 99;
+        bytes[central + 11] = 0;
+        let mut source = FeedSource::read_stdin(bytes.as_slice()).unwrap();
+        let error = source.file("agency.txt").err().unwrap();
+        assert!(matches!(&error, FeedSourceError::ReadZip { name, .. } if name == "agency.txt"));
+        assert!(error
+            .to_string()
+            .starts_with("failed to read agency.txt from zip"));
+    }
+
+    #[test]
+    fn xml_error() {
+        let dir = temp_dir("xml_error");
+        let path = dir.join("feed.xml");
+        fs::write(&path, "<Timetable/>").unwrap();
+        let error = FeedSource::open(&path).err().unwrap();
+        assert!(
+            matches!(&error, FeedSourceError::Xml { name, .. } if *name == path.display().to_string())
+        );
+        assert!(error
+            .to_string()
+            .ends_with("expected a TransXChange or NeTEx document, found Timetable"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,17 +1,26 @@
 use druid::im::Vector;
 use druid::widget::{
     Button, Checkbox, Container, Controller, CrossAxisAlignment, Either, Flex, Label, LineBreaking,
-    List, MainAxisAlignment, Painter, RadioGroup, Scroll, Stepper, TextBox, ViewSwitcher,
+    List, MainAxisAlignment, Painter, ProgressBar, RadioGroup, Scroll, Stepper, TextBox,
+    ViewSwitcher,
 };
+use druid::commands::{SHOW_OPEN_PANEL, SHOW_SAVE_PANEL};
 use druid::{
-    Color, Data, Env, Event, EventCtx, Lens, LifeCycle, PaintCtx, RenderContext, UpdateCtx, Widget,
-    WidgetExt,
+    lens, Color, Data, Env, Event, EventCtx, FileDialogOptions, FileSpec, Lens, LifeCycle,
+    PaintCtx, RenderContext, UpdateCtx, Widget, WidgetExt,
 };
 use rgb::RGB8;
+use std::iter;
 
 use crate::app_delegate::*;
 use crate::data::*;
+use crate::export::GEOJSON_LAYERS;
+use crate::import::{FieldMapping, Import, ImportKind};
+use crate::layers::{GeoJsonLayer, LAYER_COLORS};
+use crate::library::LibraryFeed;
+use crate::loading::Loading;
 use crate::map::MapWidget;
+use crate::menu::*;
 
 mod constants;
 mod dropdown;
@@ -45,6 +54,25 @@ impl<W: Widget<MyTrip>> Controller<MyTrip, W> for TextBoxOnChange {
     }
 }
 
+/// submits AGENCY_ID_CHANGED after the agency's id is edited
+struct AgencyIdController;
+impl<W: Widget<MyAgency>> Controller<MyAgency, W> for AgencyIdController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut MyAgency,
+        env: &Env,
+    ) {
+        let old_id = data.id.clone();
+        child.event(ctx, event, data, env);
+        if data.id != old_id {
+            ctx.submit_command(AGENCY_ID_CHANGED);
+        }
+    }
+}
+
 fn delete_item_button<T: Data + ListItem>() -> impl Widget<T> {
     Button::new("x").on_click(|ctx, data: &mut T, _| {
         ctx.submit_command(ITEM_DELETE.with((data.item_type(), data.id())));
@@ -154,19 +182,10 @@ where
     ))
 }
 
-fn field_row<T: Data>(
-    name: &str,
-    update: impl Widget<T> + 'static,
-    _updated_flag: impl Fn(&T, &Env) -> bool + 'static,
-) -> impl Widget<T> {
+fn field_row<T: Data>(name: &str, update: impl Widget<T> + 'static) -> impl Widget<T> {
     Flex::column()
         .with_child(Label::new(name).with_font(ANNOTATION).fix_width(300.))
         .with_child(update.fix_width(300.))
-        // .with_child(Either::new(
-        //     updated_flag,
-        //     Label::new("udpated").fix_width(100.),
-        //     Label::new("").fix_width(100.),
-        // ))
         .cross_axis_alignment(CrossAxisAlignment::Start)
 }
 
@@ -287,30 +306,15 @@ pub fn stop_ui() -> impl Widget<MyStop> {
         .with_child(field_row(
             "id",
             Label::new(|data: &MyStop, _: &_| format!("{:?}", data.id)),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.id != data.id,
-                None => true,
-            },
         ))
         .with_default_spacer()
-        .with_child(field_row(
-            "code",
-            option_string().lens(MyStop::code),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.code != data.code,
-                None => true,
-            },
-        ))
+        .with_child(field_row("code", option_string().lens(MyStop::code)))
         .with_default_spacer()
         .with_child(field_row(
             "name",
             TextBox::new()
                 .with_placeholder("stop name")
                 .lens(MyStop::name),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.name != data.name,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -318,10 +322,6 @@ pub fn stop_ui() -> impl Widget<MyStop> {
             TextBox::new()
                 .with_placeholder("stop description")
                 .lens(MyStop::description),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.description != data.description,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -341,64 +341,24 @@ pub fn stop_ui() -> impl Widget<MyStop> {
                 },
             )
             .align_left(),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.location_type != data.location_type.0,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
             "parent_station",
             option_string().lens(MyStop::parent_station),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.parent_station != data.parent_station,
-                None => true,
-            },
         ))
         .with_default_spacer()
-        .with_child(field_row(
-            "zone_id",
-            option_string().lens(MyStop::zone_id),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.zone_id != data.zone_id,
-                None => true,
-            },
-        ))
+        .with_child(field_row("zone_id", option_string().lens(MyStop::zone_id)))
         .with_default_spacer()
-        .with_child(field_row(
-            "url",
-            option_string().lens(MyStop::url),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.url != data.url,
-                None => true,
-            },
-        ))
+        .with_child(field_row("url", option_string().lens(MyStop::url)))
         .with_default_spacer()
-        .with_child(field_row(
-            "longitude",
-            option_num().lens(MyStop::longitude),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.longitude != data.longitude,
-                None => true,
-            },
-        ))
+        .with_child(field_row("longitude", option_num().lens(MyStop::longitude)))
         .with_default_spacer()
-        .with_child(field_row(
-            "latitude",
-            option_num().lens(MyStop::latitude),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.latitude != data.latitude,
-                None => true,
-            },
-        ))
+        .with_child(field_row("latitude", option_num().lens(MyStop::latitude)))
         .with_default_spacer()
         .with_child(field_row(
             "timezone",
             option_string().lens(MyStop::timezone),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.timezone != data.timezone,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -418,28 +378,16 @@ pub fn stop_ui() -> impl Widget<MyStop> {
                 },
             )
             .align_left(),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.wheelchair_boarding != data.wheelchair_boarding.0,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
             "level_id",
             option_string().lens(MyStop::level_id),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.level_id != data.level_id,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
             "platform_code",
             option_string().lens(MyStop::platform_code),
-            |data: &MyStop, _: &_| match &data.stop {
-                Some(stop) => stop.platform_code != data.platform_code,
-                None => true,
-            },
         ))
         .cross_axis_alignment(CrossAxisAlignment::Start);
 
@@ -609,28 +557,16 @@ pub fn stop_time_fields() -> impl Widget<MyStopTime> {
         .with_child(field_row(
             "trip_id",
             Label::new(|data: &MyStopTime, _: &_| format!("{:?}", data.trip_id)),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.trip_id != data.trip_id,
-                None => true,
-            },
         ))
         .with_spacer(FIELD_SPACER_SIZE)
         .with_child(field_row(
             "arrival_time",
             option_u32().lens(MyStopTime::arrival_time),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.arrival_time != data.arrival_time,
-                None => true,
-            },
         ))
         .with_spacer(FIELD_SPACER_SIZE)
         .with_child(field_row(
             "departure_time",
             option_u32().lens(MyStopTime::departure_time),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.departure_time != data.departure_time,
-                None => true,
-            },
         ))
         .with_spacer(FIELD_SPACER_SIZE)
         // .with_child(field_row(
@@ -652,10 +588,6 @@ pub fn stop_time_fields() -> impl Widget<MyStopTime> {
                             ctx.submit_command(SELECT_STOP_LIST.with(data.stop_id.clone()));
                         },
                     ),
-                    |data: &MyStopTime, _: &_| match &data.stop_time {
-                        Some(stop_time) => stop_time.stop_id != data.stop_id,
-                        None => true,
-                    },
                 ))
                 .with_child(Button::new("edit").on_click(
                     |ctx: &mut EventCtx, _data: &mut MyStopTime, _| {
@@ -670,19 +602,11 @@ pub fn stop_time_fields() -> impl Widget<MyStopTime> {
                 |data: &MyStopTime| data.stop_sequence as f64,
                 |data: &mut MyStopTime, inner: f64| data.stop_sequence = inner as u16,
             )),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.stop_sequence != data.stop_sequence,
-                None => true,
-            },
         ))
         .with_spacer(FIELD_SPACER_SIZE)
         .with_child(field_row(
             "stop_headsign",
             option_string().lens(MyStopTime::stop_headsign),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.stop_headsign != data.stop_headsign,
-                None => true,
-            },
         ))
         .with_spacer(FIELD_SPACER_SIZE)
         .with_child(field_row(
@@ -702,10 +626,6 @@ pub fn stop_time_fields() -> impl Widget<MyStopTime> {
                 },
             )
             .align_left(),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.pickup_type != data.pickup_type.0,
-                None => true,
-            },
         ))
         .with_spacer(FIELD_SPACER_SIZE)
         .with_child(field_row(
@@ -725,10 +645,6 @@ pub fn stop_time_fields() -> impl Widget<MyStopTime> {
                 },
             )
             .align_left(),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.drop_off_type != data.drop_off_type.0,
-                None => true,
-            },
         ))
         .with_spacer(FIELD_SPACER_SIZE)
         .with_child(field_row(
@@ -748,10 +664,6 @@ pub fn stop_time_fields() -> impl Widget<MyStopTime> {
                 },
             )
             .align_left(),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.continuous_pickup != data.continuous_pickup.0,
-                None => true,
-            },
         ))
         .with_spacer(FIELD_SPACER_SIZE)
         .with_child(field_row(
@@ -773,19 +685,11 @@ pub fn stop_time_fields() -> impl Widget<MyStopTime> {
                 },
             )
             .align_left(),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.continuous_drop_off != data.continuous_drop_off.0,
-                None => true,
-            },
         ))
         .with_spacer(FIELD_SPACER_SIZE)
         .with_child(field_row(
             "shape_dist_traveled",
             option_f32().lens(MyStopTime::shape_dist_traveled),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.shape_dist_traveled != data.shape_dist_traveled,
-                None => true,
-            },
         ))
         .with_spacer(FIELD_SPACER_SIZE)
         .with_child(field_row(
@@ -803,10 +707,6 @@ pub fn stop_time_fields() -> impl Widget<MyStopTime> {
                 },
             )
             .align_left(),
-            |data: &MyStopTime, _: &_| match &data.stop_time {
-                Some(stop_time) => stop_time.timepoint != data.timepoint.0,
-                None => true,
-            },
         ))
         .cross_axis_alignment(CrossAxisAlignment::Start);
 
@@ -994,55 +894,31 @@ pub fn trip_fields() -> impl Widget<MyTrip> {
         .with_child(field_row(
             "id",
             Label::new(|data: &MyTrip, _: &_| format!("{:?}", data.id)),
-            |data: &MyTrip, _: &_| match &data.trip {
-                Some(trip) => trip.id != data.id,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
             "service_id",
             Label::new(|data: &MyTrip, _: &_| format!("{:?}", data.service_id)),
-            |data: &MyTrip, _: &_| match &data.trip {
-                Some(trip) => trip.service_id != data.service_id,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
             "route_id",
             Label::new(|data: &MyTrip, _: &_| format!("{:?}", data.route_id)),
-            |data: &MyTrip, _: &_| match &data.trip {
-                Some(trip) => trip.route_id != data.route_id,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
             "shape_id",
-            option_string().lens(MyTrip::shape_id),
-            |data: &MyTrip, _: &_| match &data.trip {
-                Some(trip) => trip.shape_id != data.shape_id,
-                None => true,
-            },
+            Label::new(|data: &MyTrip, _: &_| format!("{:?}", data.shape_id)),
         ))
         .with_default_spacer()
         .with_child(field_row(
             "trip_headsign",
             option_string().lens(MyTrip::trip_headsign),
-            |data: &MyTrip, _: &_| match &data.trip {
-                Some(trip) => trip.trip_headsign != data.trip_headsign,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
             "trip_short_name",
             option_string().lens(MyTrip::trip_short_name),
-            |data: &MyTrip, _: &_| match &data.trip {
-                Some(trip) => trip.trip_short_name != data.trip_short_name,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1071,19 +947,11 @@ pub fn trip_fields() -> impl Widget<MyTrip> {
                 },
             )
             .align_left(),
-            |data: &MyTrip, _: &_| match &data.trip {
-                Some(trip) => trip.direction_id != data.direction_id.map(|x| x.0),
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
             "block_id",
             option_string().lens(MyTrip::block_id),
-            |data: &MyTrip, _: &_| match &data.trip {
-                Some(trip) => trip.block_id != data.block_id,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1103,10 +971,6 @@ pub fn trip_fields() -> impl Widget<MyTrip> {
                 },
             )
             .align_left(),
-            |data: &MyTrip, _: &_| match &data.trip {
-                Some(trip) => trip.wheelchair_accessible != data.wheelchair_accessible.0,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1124,10 +988,6 @@ pub fn trip_fields() -> impl Widget<MyTrip> {
                 },
             )
             .align_left(),
-            |data: &MyTrip, _: &_| match &data.trip {
-                Some(trip) => trip.bikes_allowed != data.bikes_allowed.0,
-                None => true,
-            },
         ))
         .cross_axis_alignment(CrossAxisAlignment::Start);
 
@@ -1229,10 +1089,6 @@ pub fn route_fields() -> impl Widget<MyRoute> {
         .with_child(field_row(
             "id",
             Label::new(|data: &MyRoute, _: &_| format!("{:?}", data.id)),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.id != data.id,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1240,10 +1096,6 @@ pub fn route_fields() -> impl Widget<MyRoute> {
             TextBox::new()
                 .with_placeholder("route short_name")
                 .lens(MyRoute::short_name),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.short_name != data.short_name,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1251,20 +1103,9 @@ pub fn route_fields() -> impl Widget<MyRoute> {
             TextBox::new()
                 .with_placeholder("route long_name")
                 .lens(MyRoute::long_name),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.long_name != data.long_name,
-                None => true,
-            },
         ))
         .with_default_spacer()
-        .with_child(field_row(
-            "desc",
-            option_string().lens(MyRoute::desc),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.desc != data.desc,
-                None => true,
-            },
-        ))
+        .with_child(field_row("desc", option_string().lens(MyRoute::desc)))
         .with_default_spacer()
         .with_child(field_row(
             "route_type",
@@ -1283,37 +1124,18 @@ pub fn route_fields() -> impl Widget<MyRoute> {
                 },
             )
             .align_left(),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.route_type != data.route_type.0,
-                None => true,
-            },
         ))
         .with_default_spacer()
-        .with_child(field_row(
-            "url",
-            option_string().lens(MyRoute::url),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.url != data.url,
-                None => true,
-            },
-        ))
+        .with_child(field_row("url", option_string().lens(MyRoute::url)))
         .with_default_spacer()
         .with_child(field_row(
             "agency_id",
             Label::new(|data: &MyRoute, _: &_| format!("{:?}", data.agency_id)),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.agency_id != data.agency_id,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
             "order",
             Label::new(|data: &MyRoute, _: &_| format!("{:?}", data.order)),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.order != data.order,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1324,10 +1146,6 @@ pub fn route_fields() -> impl Widget<MyRoute> {
                 ctx.fill(rect, &Color::rgb8(r, g, b));
             })
             .fix_size(50., 10.),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.color != data.color.0,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1338,10 +1156,6 @@ pub fn route_fields() -> impl Widget<MyRoute> {
                 ctx.fill(rect, &Color::rgb8(r, g, b));
             })
             .fix_size(50., 10.),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.text_color != data.text_color.0,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1361,10 +1175,6 @@ pub fn route_fields() -> impl Widget<MyRoute> {
                 },
             )
             .align_left(),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.continuous_pickup != data.continuous_pickup.0,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1384,10 +1194,6 @@ pub fn route_fields() -> impl Widget<MyRoute> {
                 },
             )
             .align_left(),
-            |data: &MyRoute, _: &_| match &data.route {
-                Some(route) => route.continuous_drop_off != data.continuous_drop_off.0,
-                None => true,
-            },
         ))
         .cross_axis_alignment(CrossAxisAlignment::Start);
 
@@ -1486,11 +1292,9 @@ pub fn agency_fields() -> impl Widget<MyAgency> {
     let fields = Flex::column()
         .with_child(field_row(
             "id",
-            option_string().lens(MyAgency::id),
-            |data: &MyAgency, _: &_| match &data.agency {
-                Some(agency) => agency.id != data.id,
-                None => true,
-            },
+            option_string()
+                .lens(MyAgency::id)
+                .controller(AgencyIdController),
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1498,10 +1302,6 @@ pub fn agency_fields() -> impl Widget<MyAgency> {
             TextBox::new()
                 .with_placeholder("agency name")
                 .lens(MyAgency::name),
-            |data: &MyAgency, _: &_| match &data.agency {
-                Some(agency) => agency.name != data.name,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1509,10 +1309,6 @@ pub fn agency_fields() -> impl Widget<MyAgency> {
             TextBox::new()
                 .with_placeholder("agency url")
                 .lens(MyAgency::url),
-            |data: &MyAgency, _: &_| match &data.agency {
-                Some(agency) => agency.url != data.url,
-                None => true,
-            },
         ))
         .with_default_spacer()
         .with_child(field_row(
@@ -1520,47 +1316,18 @@ pub fn agency_fields() -> impl Widget<MyAgency> {
             TextBox::new()
                 .with_placeholder("agency timezone")
                 .lens(MyAgency::timezone),
-            |data: &MyAgency, _: &_| match &data.agency {
-                Some(agency) => agency.timezone != data.timezone,
-                None => true,
-            },
         ))
         .with_default_spacer()
-        .with_child(field_row(
-            "lang",
-            option_string().lens(MyAgency::lang),
-            |data: &MyAgency, _: &_| match &data.agency {
-                Some(agency) => agency.lang != data.lang,
-                None => true,
-            },
-        ))
+        .with_child(field_row("lang", option_string().lens(MyAgency::lang)))
         .with_default_spacer()
-        .with_child(field_row(
-            "phone",
-            option_string().lens(MyAgency::phone),
-            |data: &MyAgency, _: &_| match &data.agency {
-                Some(agency) => agency.phone != data.phone,
-                None => true,
-            },
-        ))
+        .with_child(field_row("phone", option_string().lens(MyAgency::phone)))
         .with_default_spacer()
         .with_child(field_row(
             "fare_url",
             option_string().lens(MyAgency::fare_url),
-            |data: &MyAgency, _: &_| match &data.agency {
-                Some(agency) => agency.fare_url != data.fare_url,
-                None => true,
-            },
         ))
         .with_default_spacer()
-        .with_child(field_row(
-            "email",
-            option_string().lens(MyAgency::email),
-            |data: &MyAgency, _: &_| match &data.agency {
-                Some(agency) => agency.email != data.email,
-                None => true,
-            },
-        ))
+        .with_child(field_row("email", option_string().lens(MyAgency::email)))
         .cross_axis_alignment(CrossAxisAlignment::Start);
 
     // let children_header = Flex::row()
@@ -1732,15 +1499,13 @@ fn parent_trip_selected() -> impl Widget<AppData> {
         .with_child(
             FilteredList::new(
                 List::new(trip_ui_small).with_spacing(CHILD_LIST_SPACING),
-                |trip: &MyTrip, filtered: &Option<(usize, String)>| {
-                    filtered
-                        .as_ref()
-                        .map_or(false, |(_index, id)| &trip.id == id)
+                |trip: &MyTrip, filtered: &Option<String>| {
+                    filtered.as_ref().map_or(false, |id| &trip.id == id)
                 },
             )
             .lens(druid::lens::Map::new(
                 |data: &AppData| (data.trips.clone(), data.selected_trip_id.clone()),
-                |data: &mut AppData, inner: (Vector<MyTrip>, Option<(usize, String)>)| {
+                |data: &mut AppData, inner: (Vector<MyTrip>, Option<String>)| {
                     data.trips = inner.0;
                     data.selected_trip_id = inner.1;
                 },
@@ -1837,15 +1602,15 @@ fn list_of_stop_times() -> impl Widget<AppData> {
     Scroll::new(
         FilteredList::new(
             List::new(stop_time_ui_small).with_spacing(CHILD_LIST_SPACING),
-            |stop_time: &MyStopTime, filtered: &Option<(usize, String)>| {
+            |stop_time: &MyStopTime, filtered: &Option<String>| {
                 filtered
                     .as_ref()
-                    .map_or(false, |(_index, id)| &stop_time.trip_id == id)
+                    .map_or(false, |id| &stop_time.trip_id == id)
             },
         )
         .lens(druid::lens::Map::new(
             |data: &AppData| (data.stop_times.clone(), data.selected_trip_id.clone()),
-            |data: &mut AppData, inner: (Vector<MyStopTime>, Option<(usize, String)>)| {
+            |data: &mut AppData, inner: (Vector<MyStopTime>, Option<String>)| {
                 data.stop_times = inner.0;
                 data.selected_trip_id = inner.1;
             },
@@ -1869,7 +1634,17 @@ fn trip_selected_view() -> Box<dyn Widget<AppData>> {
                             "Trip",
                             "Stop",
                             Label::new(|data: &MyTrip, _env: &_| format!("{}", data.id)),
-                            Flex::row(),
+                            Flex::row()
+                                .with_child(Button::new("new shape").on_click(
+                                    |ctx: &mut EventCtx, data: &mut MyTrip, _: &_| {
+                                        ctx.submit_command(CREATE_SHAPE.with(data.id.clone()))
+                                    },
+                                ))
+                                .with_child(Button::new("snap shape").on_click(
+                                    |ctx: &mut EventCtx, data: &mut MyTrip, _: &_| {
+                                        ctx.submit_command(GENERATE_SHAPE.with(data.id.clone()))
+                                    },
+                                )),
                             MyTrip::show_editing,
                             trip_fields(),
                             |ctx: &mut EventCtx, data: &mut MyTrip, _: &_| {
@@ -1878,15 +1653,13 @@ fn trip_selected_view() -> Box<dyn Widget<AppData>> {
                         )
                     })
                     .with_spacing(CHILD_LIST_SPACING),
-                    |trip: &MyTrip, filtered: &Option<(usize, String)>| {
-                        filtered
-                            .as_ref()
-                            .map_or(false, |(_index, id)| &trip.id == id)
+                    |trip: &MyTrip, filtered: &Option<String>| {
+                        filtered.as_ref().map_or(false, |id| &trip.id == id)
                     },
                 )
                 .lens(druid::lens::Map::new(
                     |data: &AppData| (data.trips.clone(), data.selected_trip_id.clone()),
-                    |data: &mut AppData, inner: (Vector<MyTrip>, Option<(usize, String)>)| {
+                    |data: &mut AppData, inner: (Vector<MyTrip>, Option<String>)| {
                         data.trips = inner.0;
                         data.selected_trip_id = inner.1;
                     },
@@ -1962,7 +1735,495 @@ fn list_stop_selected_view() -> Box<dyn Widget<AppData>> {
     )
 }
 
+fn layer_ui() -> impl Widget<GeoJsonLayer> {
+    Flex::row()
+        .with_child(Checkbox::new("").lens(GeoJsonLayer::visible))
+        .with_child(Label::new(|data: &GeoJsonLayer, _: &_| data.name.clone()))
+        .with_default_spacer()
+        .with_child(
+            Painter::new(|ctx: &mut PaintCtx, data: &GeoJsonLayer, _: &Env| {
+                let rect = ctx.size().to_rect();
+                ctx.fill(rect, &data.color());
+            })
+            .fix_size(20., 10.)
+            .on_click(|_ctx, data: &mut GeoJsonLayer, _| {
+                data.color_index = (data.color_index + 1) % LAYER_COLORS.len()
+            }),
+        )
+        .with_default_spacer()
+        .with_child(Label::new(|data: &GeoJsonLayer, _: &_| {
+            format!("width {}", data.stroke_width)
+        }))
+        .with_child(
+            Stepper::new()
+                .with_range(0.5, 10.)
+                .with_step(0.5)
+                .lens(GeoJsonLayer::stroke_width),
+        )
+        .with_default_spacer()
+        .with_child(Label::new(|data: &GeoJsonLayer, _: &_| {
+            format!("fill {:.0}%", data.fill_opacity * 100.)
+        }))
+        .with_child(
+            Stepper::new()
+                .with_range(0., 1.)
+                .with_step(0.1)
+                .lens(GeoJsonLayer::fill_opacity),
+        )
+        .with_default_spacer()
+        .with_child(
+            Button::new("remove").on_click(|ctx, data: &mut GeoJsonLayer, _| {
+                ctx.submit_command(REMOVE_LAYER.with(data.id.clone()))
+            }),
+        )
+}
+
+/// the stop spacing and coverage report from the last analysis run
+fn analysis_report_view() -> impl Widget<AppData> {
+    let report = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .with_child(Label::new("Analysis").with_font(HEADING_2))
+                .with_default_spacer()
+                .with_child(
+                    Button::new("close")
+                        .on_click(|_ctx, data: &mut AppData, _| data.analysis_report = None),
+                ),
+        )
+        .with_default_spacer()
+        .with_child(
+            Scroll::new(
+                Label::new(|data: &AppData, _: &_| match &data.analysis_report {
+                    Some(report) => report.to_string(),
+                    None => "".to_string(),
+                })
+                .with_font(TABLE),
+            )
+            .fix_height(300.),
+        )
+        .padding(10.)
+        .border(Color::grey(0.6), 2.)
+        .rounded(CORNER_RADIUS);
+
+    Either::new(
+        |data: &AppData, _: &_| data.analysis_report.is_some(),
+        report,
+        Flex::column(),
+    )
+}
+
+/// save the edits and view as a project to continue later, or open one
+fn project_controls() -> impl Widget<AppData> {
+    Flex::row()
+        .with_child(
+            Button::new("open project").on_click(|ctx, _data: &mut AppData, _| {
+                ctx.submit_command(SHOW_OPEN_PANEL.with(open_project_dialog()))
+            }),
+        )
+        .with_default_spacer()
+        .with_child(
+            Button::new("save project")
+                .on_click(|ctx, _data: &mut AppData, _| ctx.submit_command(SAVE)),
+        )
+        .with_default_spacer()
+        .with_child(
+            Button::new("close")
+                .on_click(|ctx, _data: &mut AppData, _| ctx.submit_command(CLOSE_FEED)),
+        )
+}
+
+/// the fields of a file being imported and the columns mapped to them, shown until the import is applied or cancelled
+fn import_view() -> impl Widget<AppData> {
+    let field_mapping = || {
+        Flex::row()
+            .with_child(
+                Label::new(|mapping: &FieldMapping, _: &_| mapping.field.clone()).fix_width(150.),
+            )
+            .with_child(Dropdown::new(
+                Button::new(|mapping: &FieldMapping, _: &Env| {
+                    if mapping.column.is_empty() {
+                        "not imported".to_string()
+                    } else {
+                        mapping.column.clone()
+                    }
+                })
+                .on_click(|ctx: &mut EventCtx, _, _| ctx.submit_notification(DROPDOWN_SHOW)),
+                |mapping: &FieldMapping, _| {
+                    RadioGroup::column(
+                        iter::once(("not imported".to_string(), "".to_string()))
+                            .chain(
+                                mapping
+                                    .columns
+                                    .iter()
+                                    .map(|column| (column.clone(), column.clone())),
+                            )
+                            .collect::<Vec<_>>(),
+                    )
+                    .lens(FieldMapping::column)
+                },
+            ))
+    };
+    let match_field = Either::new(
+        |import: &Import, _: &_| import.kind == ImportKind::Stops,
+        Flex::row()
+            .with_child(Label::new("update existing stops with the same"))
+            .with_child(
+                RadioGroup::row(vec![
+                    ("id".to_string(), "id".to_string()),
+                    ("code".to_string(), "code".to_string()),
+                    ("none, always add".to_string(), "".to_string()),
+                ])
+                .lens(Import::match_field),
+            ),
+        Flex::row(),
+    );
+    let import = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Label::new(|import: &Import, _: &_| {
+                format!(
+                    "import {} features from {}",
+                    import.features.len(),
+                    import.name
+                )
+            })
+            .with_font(HEADING_2),
+        )
+        .with_default_spacer()
+        .with_child(List::new(field_mapping).lens(Import::mappings))
+        .with_default_spacer()
+        .with_child(match_field)
+        .with_child(
+            Label::new(|import: &Import, _: &_| import.error.clone().unwrap_or_default())
+                .with_text_color(Color::RED),
+        )
+        .lens(lens::Map::new(
+            |data: &AppData| data.import.clone().unwrap_or_default(),
+            |data: &mut AppData, import: Import| {
+                if data.import.is_some() {
+                    data.import = Some(import);
+                }
+            },
+        ));
+    Either::new(
+        |data: &AppData, _: &_| data.import.is_some(),
+        Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(import)
+            .with_default_spacer()
+            .with_child(
+                Flex::row()
+                    .with_child(
+                        Button::new("import").on_click(|ctx, _data: &mut AppData, _| {
+                            ctx.submit_command(APPLY_IMPORT)
+                        }),
+                    )
+                    .with_default_spacer()
+                    .with_child(
+                        Button::new("cancel")
+                            .on_click(|_ctx, data: &mut AppData, _| data.import = None),
+                    ),
+            )
+            .padding(10.)
+            .border(Color::rgb8(200, 120, 0), 1.),
+        Flex::row(),
+    )
+}
+
+/// shown when opening or closing a feed would lose edits
+fn unsaved_changes_prompt() -> impl Widget<AppData> {
+    Either::new(
+        |data: &AppData, _: &_| data.pending_action.is_some(),
+        Flex::row()
+            .with_child(Label::new("there are unsaved changes"))
+            .with_default_spacer()
+            .with_child(
+                Button::new("save project")
+                    .on_click(|ctx, _data: &mut AppData, _| ctx.submit_command(SAVE)),
+            )
+            .with_default_spacer()
+            .with_child(
+                Button::new("discard")
+                    .on_click(|ctx, _data: &mut AppData, _| ctx.submit_command(DISCARD_CHANGES)),
+            )
+            .with_default_spacer()
+            .with_child(
+                Button::new("cancel")
+                    .on_click(|_ctx, data: &mut AppData, _| data.pending_action = None),
+            )
+            .padding(10.)
+            .border(Color::rgb8(200, 120, 0), 1.),
+        Flex::row(),
+    )
+}
+
+/// shown when no feed is open
+fn start_view() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(Label::new("no feed open"))
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("open feed").on_click(|ctx, _data: &mut AppData, _| {
+                        ctx.submit_command(SHOW_OPEN_PANEL.with(open_feed_dialog()))
+                    }),
+                )
+                .with_default_spacer()
+                .with_child(Button::new("open feed folder").on_click(
+                    |ctx, _data: &mut AppData, _| {
+                        ctx.submit_command(SHOW_OPEN_PANEL.with(open_feed_folder_dialog()))
+                    },
+                ))
+                .with_default_spacer()
+                .with_child(
+                    Button::new("open project").on_click(|ctx, _data: &mut AppData, _| {
+                        ctx.submit_command(SHOW_OPEN_PANEL.with(open_project_dialog()))
+                    }),
+                ),
+        )
+        .with_default_spacer()
+        .with_child(Label::new("library"))
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("add feed").on_click(|ctx, _data: &mut AppData, _| {
+                        ctx.submit_command(SHOW_OPEN_PANEL.with(import_feed_dialog()))
+                    }),
+                )
+                .with_default_spacer()
+                .with_child(Button::new("add feed folder").on_click(
+                    |ctx, _data: &mut AppData, _| {
+                        ctx.submit_command(SHOW_OPEN_PANEL.with(import_feed_folder_dialog()))
+                    },
+                )),
+        )
+        .with_child(List::new(library_feed_view).lens(AppData::library))
+        .with_default_spacer()
+        .with_child(Label::new("recent"))
+        .with_child(
+            List::new(|| {
+                Button::new(|path: &String, _: &_| path.clone()).on_click(
+                    |ctx, path: &mut String, _| ctx.submit_command(OPEN_RECENT.with(path.clone())),
+                )
+            })
+            .lens(AppData::recent_files),
+        )
+        .center()
+}
+
+/// a feed's metadata, with open and remove data buttons, or reimport if its data has already been removed
+fn library_feed_view() -> impl Widget<LibraryFeed> {
+    let details = Label::new(|feed: &LibraryFeed, _: &_| {
+        let dates = match (&feed.start_date, &feed.end_date) {
+            (None, None) => "no feed dates".to_string(),
+            (start, end) => format!(
+                "{} to {}",
+                start.as_deref().unwrap_or("?"),
+                end.as_deref().unwrap_or("?")
+            ),
+        };
+        let size = if feed.data_path.is_some() {
+            format!("{:.1} MB", feed.size as f64 / 1_000_000.)
+        } else {
+            "data removed".to_string()
+        };
+        format!(
+            "{} | {} | {} | last opened {}",
+            feed.agency, dates, size, feed.last_opened
+        )
+    });
+    let buttons = Either::new(
+        |feed: &LibraryFeed, _| feed.data_path.is_some(),
+        Flex::row()
+            .with_child(
+                Button::new("open").on_click(|ctx, feed: &mut LibraryFeed, _| {
+                    ctx.submit_command(OPEN_LIBRARY_FEED.with(feed.id.clone()))
+                }),
+            )
+            .with_default_spacer()
+            .with_child(
+                Button::new("remove data").on_click(|ctx, feed: &mut LibraryFeed, _| {
+                    ctx.submit_command(REMOVE_LIBRARY_DATA.with(feed.id.clone()))
+                }),
+            ),
+        Button::new("reimport").on_click(|ctx, feed: &mut LibraryFeed, _| {
+            ctx.submit_command(REIMPORT_LIBRARY_FEED.with(feed.id.clone()))
+        }),
+    );
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new(|feed: &LibraryFeed, _: &_| feed.name.clone()).with_font(HEADING_2))
+        .with_child(details)
+        .with_child(Label::new(|feed: &LibraryFeed, _: &_| feed.source.clone()))
+        .with_child(buttons)
+        .padding(5.)
+}
+
+/// bulk actions on the stops and trips selected on the map with shift click or box/lasso selection
+fn selection_view() -> impl Widget<AppData> {
+    let field_radio = RadioGroup::column(
+        SELECTION_FIELDS
+            .iter()
+            .map(|field| (field.to_string(), field.to_string()))
+            .collect::<Vec<_>>(),
+    )
+    .lens(AppData::selection_field);
+
+    let selection = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Label::new(|data: &AppData, _: &_| {
+                format!(
+                    "{} stops and {} trips selected",
+                    data.map_selected_stop_ids.len(),
+                    data.map_selected_trip_ids.len()
+                )
+            })
+            .with_font(HEADING_2),
+        )
+        .with_default_spacer()
+        .with_child(
+            Flex::row()
+                .with_child(
+                    Button::new("delete").on_click(|ctx, _data: &mut AppData, _| {
+                        ctx.submit_command(DELETE_SELECTION)
+                    }),
+                )
+                .with_default_spacer()
+                .with_child(
+                    Button::new("export subset").on_click(|ctx, _data: &mut AppData, _| {
+                        let options = FileDialogOptions::new()
+                            .allowed_types(vec![FileSpec::new("JSON", &["json"])])
+                            .default_name("selection.json")
+                            .accept_command(EXPORT_SELECTION);
+                        ctx.submit_command(SHOW_SAVE_PANEL.with(options))
+                    }),
+                )
+                .with_default_spacer()
+                .with_child(Button::new("clear selection").on_click(
+                    |_ctx, data: &mut AppData, _| {
+                        data.map_selected_stop_ids = Vector::new();
+                        data.map_selected_trip_ids = Vector::new();
+                    },
+                )),
+        )
+        .with_default_spacer()
+        .with_child(Label::new("set field"))
+        .with_child(field_radio)
+        .with_child(
+            Flex::row()
+                .with_child(
+                    TextBox::new()
+                        .with_placeholder("value (empty to clear)")
+                        .lens(AppData::selection_value),
+                )
+                .with_default_spacer()
+                .with_child(Button::new("set").on_click(|ctx, _data: &mut AppData, _| {
+                    ctx.submit_command(SET_SELECTION_FIELD)
+                })),
+        )
+        .padding(10.)
+        .border(Color::grey(0.6), 2.)
+        .rounded(CORNER_RADIUS);
+
+    Either::new(
+        |data: &AppData, _: &_| {
+            !data.map_selected_stop_ids.is_empty() || !data.map_selected_trip_ids.is_empty()
+        },
+        selection,
+        Flex::column(),
+    )
+}
+
+/// shows the loading screen until the feed has been read, then the network. The network views are only built once it has loaded, since MapWidget draws its paths when it is added
 pub fn main_widget() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(error_message_view())
+        .with_flex_child(
+            ViewSwitcher::new(
+                |data: &AppData, _: &_| (data.loading.is_some(), data.source_path.is_some()),
+                |(loading, feed_open): &(bool, bool), _data: &AppData, _: &_| {
+                    if *loading {
+                        Box::new(loading_view())
+                    } else if *feed_open {
+                        Box::new(feed_view())
+                    } else {
+                        Box::new(start_view())
+                    }
+                },
+            ),
+            1.,
+        )
+}
+
+/// shows AppData.error_message until it is dismissed
+fn error_message_view() -> impl Widget<AppData> {
+    Either::new(
+        |data: &AppData, _: &_| data.error_message.is_some(),
+        Flex::row()
+            .with_child(
+                Label::new(|data: &AppData, _: &_| data.error_message.clone().unwrap_or_default())
+                    .with_line_break_mode(LineBreaking::WordWrap)
+                    .with_text_color(Color::RED)
+                    .fix_width(1000.),
+            )
+            .with_default_spacer()
+            .with_child(
+                Button::new("dismiss")
+                    .on_click(|_ctx, data: &mut AppData, _| data.error_message = None),
+            )
+            .padding(10.)
+            .border(Color::RED, 1.),
+        Flex::row(),
+    )
+}
+
+fn loading_view() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(Label::new(|data: &AppData, _: &_| {
+            format!(
+                "loading {}",
+                data.loading.as_ref().map_or("", |loading| &loading.path)
+            )
+        }))
+        .with_default_spacer()
+        .with_child(
+            ProgressBar::new()
+                .lens(lens::Map::new(
+                    |data: &AppData| data.loading.as_ref().map_or(0., Loading::fraction),
+                    |_: &mut AppData, _: f64| {},
+                ))
+                .fix_width(400.),
+        )
+        .with_default_spacer()
+        .with_child(Label::new(|data: &AppData, _: &_| match &data.loading {
+            Some(Loading {
+                error: Some(error), ..
+            }) => error.clone(),
+            Some(loading) => loading.step.clone(),
+            None => "".to_string(),
+        }))
+        .with_default_spacer()
+        .with_child(Either::new(
+            |data: &AppData, _: &_| {
+                data.loading
+                    .as_ref()
+                    .map_or(false, |loading| loading.error.is_some())
+            },
+            // back to the feed that was open before, or the start screen
+            Button::new("back").on_click(|_, data: &mut AppData, _| data.loading = None),
+            Button::new("cancel").on_click(|_, data: &mut AppData, _| {
+                if let Some(loading) = &data.loading {
+                    loading.cancel();
+                }
+            }),
+        ))
+        .center()
+}
+
+fn feed_view() -> impl Widget<AppData> {
     // todo what's the difference between Point::ZERO and Point::ORIGIN?
     println!("make main widget");
 
@@ -1998,7 +2259,7 @@ pub fn main_widget() -> impl Widget<AppData> {
         |selector: &(
             Option<Option<String>>,
             Option<String>,
-            Option<(usize, String)>,
+            Option<String>,
             Option<(String, u16)>,
             Option<String>,
         ),
@@ -2039,16 +2300,284 @@ pub fn main_widget() -> impl Widget<AppData> {
     )
     .fix_width(NARROW_LIST_WIDTH);
 
-    let zoom_level = RadioGroup::row(ZoomLevel::radio_group_vec()).lens(AppData::map_zoom_level);
+    let zoom_level = Flex::row()
+        .with_child(RadioGroup::row(ZoomLevel::radio_group_vec()).lens(AppData::map_zoom_level))
+        .with_default_spacer()
+        .with_child(
+            Button::new("fit selection (f)")
+                .on_click(|ctx, _data: &mut AppData, _| ctx.submit_command(FIT_MAP.with(false))),
+        )
+        .with_default_spacer()
+        .with_child(
+            Button::new("fit feed (shift f)")
+                .on_click(|ctx, _data: &mut AppData, _| ctx.submit_command(FIT_MAP.with(true))),
+        );
+    let color_mode = RadioGroup::row(MapColorMode::radio_group_vec()).lens(AppData::map_color_mode);
+    let map_edit_controls = Flex::row()
+        .with_child(Checkbox::new("labels").lens(AppData::map_show_labels))
+        .with_default_spacer()
+        .with_child(Checkbox::new("move stops").lens(AppData::map_stop_move_mode))
+        .with_default_spacer()
+        .with_child(Checkbox::new("snap to shapes").lens(AppData::map_snap_to_shape))
+        .with_default_spacer()
+        .with_child(Checkbox::new("edit shapes").lens(AppData::map_shape_edit_mode))
+        .with_default_spacer()
+        .with_child(
+            Button::new("undo")
+                .on_click(|ctx, _data: &mut AppData, _| ctx.submit_command(UNDO_ACTION)),
+        )
+        .with_default_spacer()
+        .with_child(Button::new("load OSM").on_click(|ctx, _data: &mut AppData, _| {
+            let options = FileDialogOptions::new()
+                .allowed_types(vec![FileSpec::new("OSM PBF", &["pbf"])])
+                .accept_command(LOAD_OSM);
+            ctx.submit_command(SHOW_OPEN_PANEL.with(options))
+        }))
+        .with_child(Label::new(|data: &AppData, _: &_| {
+            data.osm_loading
+                .as_ref()
+                .map_or_else(String::new, |path| format!("reading {}", path))
+        }))
+        .with_default_spacer()
+        .with_child(Either::new(
+            |data: &AppData, _: &_| data.shape_preview.is_some(),
+            Flex::row()
+                .with_child(Label::new(|data: &AppData, _: &_| {
+                    match &data.shape_preview {
+                        Some(shape_preview) if shape_preview.failed_legs > 0 => {
+                            format!("{} legs not routed", shape_preview.failed_legs)
+                        }
+                        _ => "".to_string(),
+                    }
+                }))
+                .with_child(Button::new("accept shape").on_click(
+                    |ctx, _data: &mut AppData, _| ctx.submit_command(ACCEPT_SHAPE_PREVIEW),
+                ))
+                .with_child(Button::new("discard shape").on_click(
+                    |ctx, _data: &mut AppData, _| ctx.submit_command(DISCARD_SHAPE_PREVIEW),
+                )),
+            Flex::row(),
+        ));
+    let map_tools = Flex::row()
+        .with_child(
+            Button::new("compute shape_dist_traveled")
+                .on_click(|ctx, _data: &mut AppData, _| ctx.submit_command(COMPUTE_SHAPE_DIST)),
+        )
+        .with_default_spacer()
+        .with_child(
+            TextBox::new()
+                .with_placeholder("shape_id")
+                .lens(AppData::assign_shape_id),
+        )
+        .with_child(
+            Button::new("assign shape to trip")
+                .on_click(|ctx, data: &mut AppData, _| {
+                    if let Some(trip_id) = &data.selected_trip_id {
+                        ctx.submit_command(
+                            ASSIGN_SHAPE.with((trip_id.clone(), data.assign_shape_id.clone())),
+                        )
+                    }
+                })
+                .disabled_if(|data: &AppData, _| {
+                    data.selected_trip_id.is_none() || data.assign_shape_id.is_empty()
+                }),
+        )
+        .with_default_spacer()
+        .with_child(
+            Label::new(|data: &AppData, _: &_| {
+                if data.shape_dist_warnings.is_empty() {
+                    "".to_string()
+                } else {
+                    format!(
+                        "{} stops more than {}m from their shape (shown in red)",
+                        data.shape_dist_warnings.len(),
+                        MAX_STOP_SHAPE_DISTANCE
+                    )
+                }
+            })
+            .with_text_color(Color::RED),
+        );
+    let map_export_controls = Flex::row()
+        .with_child(
+            RadioGroup::row(
+                [1000, 2000, 4000, 8000]
+                    .iter()
+                    .map(|resolution| (format!("{}px", resolution), *resolution))
+                    .collect::<Vec<_>>(),
+            )
+            .lens(AppData::map_export_resolution),
+        )
+        .with_default_spacer()
+        .with_child(Checkbox::new("whole network").lens(AppData::map_export_whole_network))
+        .with_default_spacer()
+        .with_child(Checkbox::new("stop labels").lens(AppData::map_export_labels))
+        .with_default_spacer()
+        .with_child(
+            Button::new("export PNG").on_click(|ctx, _data: &mut AppData, _| {
+                let options = FileDialogOptions::new()
+                    .allowed_types(vec![FileSpec::new("PNG image", &["png"])])
+                    .default_name("map.png")
+                    .accept_command(EXPORT_MAP_PNG);
+                ctx.submit_command(SHOW_SAVE_PANEL.with(options))
+            }),
+        )
+        .with_default_spacer()
+        .with_child(
+            Button::new("export SVG").on_click(|ctx, _data: &mut AppData, _| {
+                let options = FileDialogOptions::new()
+                    .allowed_types(vec![FileSpec::new("SVG image", &["svg"])])
+                    .default_name("map.svg")
+                    .accept_command(EXPORT_MAP_SVG);
+                ctx.submit_command(SHOW_SAVE_PANEL.with(options))
+            }),
+        );
+    let geojson_export_controls = Flex::row()
+        .with_child(
+            RadioGroup::row(
+                GEOJSON_LAYERS
+                    .iter()
+                    .map(|layer| (layer.to_string(), layer.to_string()))
+                    .collect::<Vec<_>>(),
+            )
+            .lens(AppData::geojson_export_layer),
+        )
+        .with_default_spacer()
+        .with_child(Checkbox::new("selection only").lens(AppData::export_selection))
+        .with_default_spacer()
+        .with_child(
+            Button::new("export GeoJSON").on_click(|ctx, data: &mut AppData, _| {
+                let options = FileDialogOptions::new()
+                    .allowed_types(vec![FileSpec::new("GeoJSON", &["geojson", "json"])])
+                    .default_name(format!("{}.geojson", data.geojson_export_layer))
+                    .accept_command(EXPORT_GEOJSON);
+                ctx.submit_command(SHOW_SAVE_PANEL.with(options))
+            }),
+        )
+        .with_default_spacer()
+        .with_child(
+            Button::new("export KML").on_click(|ctx, _data: &mut AppData, _| {
+                let options = FileDialogOptions::new()
+                    .allowed_types(vec![FileSpec::new("KML", &["kml"])])
+                    .default_name("routes.kml")
+                    .accept_command(EXPORT_KML);
+                ctx.submit_command(SHOW_SAVE_PANEL.with(options))
+            }),
+        )
+        .with_default_spacer()
+        .with_child(
+            Button::new("export GPX").on_click(|ctx, _data: &mut AppData, _| {
+                let options = FileDialogOptions::new()
+                    .allowed_types(vec![FileSpec::new("GPX", &["gpx"])])
+                    .default_name("routes.gpx")
+                    .accept_command(EXPORT_GPX);
+                ctx.submit_command(SHOW_SAVE_PANEL.with(options))
+            }),
+        );
+    let import_controls = Flex::row()
+        .with_child(
+            Button::new("import stops").on_click(|ctx, _data: &mut AppData, _| {
+                let options = FileDialogOptions::new()
+                    .allowed_types(vec![
+                        FileSpec::new("CSV", &["csv"]),
+                        FileSpec::new("GeoJSON", &["geojson", "json"]),
+                    ])
+                    .accept_command(IMPORT_STOPS);
+                ctx.submit_command(SHOW_OPEN_PANEL.with(options))
+            }),
+        )
+        .with_default_spacer()
+        .with_child(
+            Button::new("import shapes").on_click(|ctx, _data: &mut AppData, _| {
+                let options = FileDialogOptions::new()
+                    .allowed_types(vec![FileSpec::new("GeoJSON", &["geojson", "json"])])
+                    .accept_command(IMPORT_SHAPES);
+                ctx.submit_command(SHOW_OPEN_PANEL.with(options))
+            }),
+        );
+    let map_layer_controls = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Button::new("load GeoJSON layer").on_click(|ctx, _data: &mut AppData, _| {
+                let options = FileDialogOptions::new()
+                    .allowed_types(vec![FileSpec::new("GeoJSON", &["geojson", "json"])])
+                    .accept_command(LOAD_GEOJSON_LAYER);
+                ctx.submit_command(SHOW_OPEN_PANEL.with(options))
+            }),
+        )
+        .with_child(List::new(layer_ui).lens(AppData::map_layers));
+    let map_analysis_controls = Flex::row()
+        .with_child(Label::new("walk radius"))
+        .with_child(
+            RadioGroup::row(
+                [300, 400, 500, 800]
+                    .iter()
+                    .map(|radius| (format!("{}m", radius), *radius))
+                    .collect::<Vec<_>>(),
+            )
+            .lens(AppData::analysis_radius),
+        )
+        .with_default_spacer()
+        .with_child(
+            TextBox::new()
+                .with_placeholder("population field")
+                .lens(AppData::analysis_population_field),
+        )
+        .with_child(
+            TextBox::new()
+                .with_placeholder("jobs field")
+                .lens(AppData::analysis_jobs_field),
+        )
+        .with_default_spacer()
+        .with_child(
+            Button::new("load census").on_click(|ctx, _data: &mut AppData, _| {
+                let options = FileDialogOptions::new()
+                    .allowed_types(vec![
+                        FileSpec::new("GeoJSON", &["geojson", "json"]),
+                        FileSpec::new("CSV", &["csv"]),
+                    ])
+                    .accept_command(LOAD_CENSUS);
+                ctx.submit_command(SHOW_OPEN_PANEL.with(options))
+            }),
+        )
+        .with_child(Label::new(|data: &AppData, _: &_| match &data.census {
+            Some(census) => census.name.clone(),
+            None => "".to_string(),
+        }))
+        .with_default_spacer()
+        .with_child(
+            Button::new("run analysis")
+                .on_click(|ctx, _data: &mut AppData, _| ctx.submit_command(RUN_ANALYSIS)),
+        );
     // let map_widget = (MapWidget::new(1., 1., Point::ZERO).on_added(
     let map_widget = (MapWidget::new()).expand();
 
     Flex::row()
-        .with_flex_child(all_together_now, 1.)
+        .with_flex_child(
+            Flex::column()
+                .with_child(unsaved_changes_prompt())
+                .with_child(project_controls())
+                .with_default_spacer()
+                .with_child(selection_view())
+                .with_child(analysis_report_view())
+                .with_default_spacer()
+                .with_flex_child(all_together_now, 1.),
+            1.,
+        )
         .with_default_spacer()
         // .with_spacer(20.)
         .with_flex_child(
-            Flex::column().with_child(zoom_level).with_child(map_widget),
+            Flex::column()
+                .with_child(zoom_level)
+                .with_child(color_mode)
+                .with_child(map_edit_controls)
+                .with_child(map_tools)
+                .with_child(map_export_controls)
+                .with_child(geojson_export_controls)
+                .with_child(import_controls)
+                .with_child(import_view())
+                .with_child(map_layer_controls)
+                .with_child(map_analysis_controls)
+                .with_child(map_widget),
             1.,
         )
         .cross_axis_alignment(CrossAxisAlignment::Start)
@@ -33,6 +33,8 @@ pub const HEADING_2: FontDescriptor = FontDescriptor::new(FontFamily::SYSTEM_UI)
 pub const ANNOTATION: FontDescriptor = FontDescriptor::new(FontFamily::SYSTEM_UI)
     .with_weight(FontWeight::THIN)
     .with_size(10.0);
+/// for reports laid out in columns
+pub const TABLE: FontDescriptor = FontDescriptor::new(FontFamily::MONOSPACE).with_size(12.0);
 
 pub const VARIABLE_STOP_TIME_BORDER_COLOR: Key<Color> =
     Key::new("druid-help.stop-time.border-color");
@@ -0,0 +1,753 @@
+use chrono::{Duration, Local, NaiveDate};
+use roxmltree::{Document, Node};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+use crate::source::FeedSourceError;
+
+mod netex;
+mod transxchange;
+
+/// neither format has to say which time zone it is in, TransXChange is only used in the UK
+const TRANSXCHANGE_TIMEZONE: &str = "Europe/London";
+const DEFAULT_NETEX_TIMEZONE: &str = "Etc/UTC";
+/// used when a document doesn't say when its services end
+const DEFAULT_SERVICE_DAYS: i64 = 365;
+const DAY_NAMES: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+/// Converts TransXChange or NeTEx documents, given as (name, text), into the GTFS files agency.txt, routes.txt, trips.txt, stop_times.txt, stops.txt and calendar.txt, as CSV, so the feed is read the same way as a GTFS feed. Several documents are merged into one feed, as zips of TransXChange often have a document for each service
+pub(crate) fn convert_xml_feed(
+    documents: &[(String, String)],
+) -> Result<ConvertedFeed, FeedSourceError> {
+    let mut feed = FeedBuilder::default();
+    for (name, text) in documents {
+        myprint!("converting ", name);
+        let error = |error: String| FeedSourceError::Xml {
+            name: name.clone(),
+            error,
+        };
+        let document =
+            Document::parse(text).map_err(|parse_error| error(parse_error.to_string()))?;
+        let result = match document.root_element().tag_name().name() {
+            "TransXChange" => transxchange::convert(&document, &mut feed),
+            "PublicationDelivery" => netex::convert(&document, &mut feed),
+            other => Err(format!(
+                "expected a TransXChange or NeTEx document, found {}",
+                other
+            )),
+        };
+        result.map_err(error)?;
+    }
+    feed.into_files().map_err(|error| FeedSourceError::Xml {
+        name: "converted feed".to_string(),
+        error,
+    })
+}
+
+/// the GTFS files converted from TransXChange or NeTEx documents
+pub(crate) struct ConvertedFeed {
+    pub(crate) files: HashMap<String, Vec<u8>>,
+    /// what was dropped for not being able to be shown, if anything, so the user knows the feed is incomplete
+    pub(crate) warning: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AgencyRow {
+    agency_id: String,
+    agency_name: String,
+    agency_url: String,
+    agency_timezone: String,
+}
+
+#[derive(Serialize)]
+struct RouteRow {
+    route_id: String,
+    agency_id: String,
+    route_short_name: String,
+    route_long_name: String,
+    route_type: u16,
+}
+
+#[derive(Serialize)]
+struct TripRow {
+    route_id: String,
+    service_id: String,
+    trip_id: String,
+    trip_headsign: Option<String>,
+    direction_id: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct StopTimeRow {
+    trip_id: String,
+    arrival_time: String,
+    departure_time: String,
+    stop_id: String,
+    stop_sequence: usize,
+}
+
+#[derive(Serialize)]
+struct StopRow {
+    stop_id: String,
+    stop_code: Option<String>,
+    stop_name: String,
+    stop_lat: Option<f64>,
+    stop_lon: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct CalendarRow {
+    service_id: String,
+    monday: u8,
+    tuesday: u8,
+    wednesday: u8,
+    thursday: u8,
+    friday: u8,
+    saturday: u8,
+    sunday: u8,
+    start_date: String,
+    end_date: String,
+}
+
+/// A stop visited by a trip, with its times in seconds since the start of the service day
+struct StopVisit {
+    stop_id: String,
+    arrival: u32,
+    departure: u32,
+}
+
+/// The days a NeTEx DayType runs on, Monday first, between the dates of its operating periods and days
+#[derive(Default)]
+struct DayType {
+    days: [bool; 7],
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+}
+
+/// The GTFS tables as they are built up from the documents. Documents often repeat the same stops and operators, so records with an id which has already been added are ignored. Documents can also refer to operators and NeTEx day types which are only described in another document, so these are looked up once all the documents are read
+#[derive(Default)]
+struct FeedBuilder {
+    agencies: Vec<AgencyRow>,
+    routes: Vec<RouteRow>,
+    trips: Vec<TripRow>,
+    stop_times: Vec<StopTimeRow>,
+    stops: Vec<StopRow>,
+    calendar: Vec<CalendarRow>,
+    /// (table, id)
+    ids: HashSet<(&'static str, String)>,
+    stop_index_from_id: HashMap<String, usize>,
+    /// for the agency added for routes whose operator isn't in any of the documents
+    timezone: Option<String>,
+    day_types: HashMap<String, DayType>,
+    /// ids of the day types making up each service of NeTEx journeys
+    day_type_ids_from_service_id: HashMap<String, Vec<String>>,
+}
+impl FeedBuilder {
+    fn add_agency(&mut self, id: String, name: String, url: String, timezone: String) {
+        if self.ids.insert(("agency", id.clone())) {
+            self.agencies.push(AgencyRow {
+                agency_id: id,
+                agency_name: name,
+                agency_url: url,
+                agency_timezone: timezone,
+            });
+        }
+    }
+
+    fn add_route(
+        &mut self,
+        id: String,
+        agency_id: String,
+        short_name: String,
+        long_name: String,
+        route_type: u16,
+    ) {
+        if self.ids.insert(("route", id.clone())) {
+            self.routes.push(RouteRow {
+                route_id: id,
+                agency_id,
+                route_short_name: short_name,
+                route_long_name: long_name,
+                route_type,
+            });
+        }
+    }
+
+    /// stops without a position are kept in case another document has one, and dropped in into_files if none does
+    fn add_stop(
+        &mut self,
+        id: String,
+        code: Option<String>,
+        name: String,
+        position: Option<(f64, f64)>,
+    ) {
+        match self.stop_index_from_id.get(&id) {
+            Some(stop_index) => {
+                let stop = &mut self.stops[*stop_index];
+                if stop.stop_lat.is_none() {
+                    if let Some((longitude, latitude)) = position {
+                        stop.stop_lon = Some(longitude);
+                        stop.stop_lat = Some(latitude);
+                    }
+                }
+            }
+            None => {
+                self.stop_index_from_id.insert(id.clone(), self.stops.len());
+                self.stops.push(StopRow {
+                    stop_id: id,
+                    stop_code: code,
+                    stop_name: name,
+                    stop_lon: position.map(|(longitude, _)| longitude),
+                    stop_lat: position.map(|(_, latitude)| latitude),
+                });
+            }
+        }
+    }
+
+    /// a service which runs on the given days, Monday first, between the dates. The id is made from the days and dates, so services running at the same times are shared
+    fn add_service(
+        &mut self,
+        days: [bool; 7],
+        start_date: Option<NaiveDate>,
+        end_date: Option<NaiveDate>,
+    ) -> String {
+        let (start_date, end_date) = service_dates(start_date, end_date);
+        let id = format!(
+            "{}_{}_{}",
+            days.iter()
+                .map(|day| (*day as u8).to_string())
+                .collect::<String>(),
+            start_date,
+            end_date
+        );
+        self.add_calendar(id.clone(), days, start_date, end_date);
+        id
+    }
+
+    /// a service which runs on any of the NeTEx day types, which may not have been read yet
+    fn add_day_type_service(&mut self, day_type_ids: Vec<String>) -> String {
+        let id = day_type_ids.join("+");
+        self.day_type_ids_from_service_id
+            .insert(id.clone(), day_type_ids);
+        id
+    }
+
+    fn add_calendar(&mut self, id: String, days: [bool; 7], start_date: String, end_date: String) {
+        if self.ids.insert(("calendar", id.clone())) {
+            let day = |i: usize| days[i] as u8;
+            self.calendar.push(CalendarRow {
+                service_id: id,
+                monday: day(0),
+                tuesday: day(1),
+                wednesday: day(2),
+                thursday: day(3),
+                friday: day(4),
+                saturday: day(5),
+                sunday: day(6),
+                start_date,
+                end_date,
+            });
+        }
+    }
+
+    /// adds the trip and its stop times, with a suffix on its id if a trip from another document already has it
+    fn add_trip(
+        &mut self,
+        id: String,
+        route_id: String,
+        service_id: String,
+        headsign: Option<String>,
+        direction_id: Option<u8>,
+        visits: Vec<StopVisit>,
+    ) {
+        let mut trip_id = id.clone();
+        let mut n = 1;
+        while !self.ids.insert(("trip", trip_id.clone())) {
+            n += 1;
+            trip_id = format!("{}-{}", id, n);
+        }
+        for (i, visit) in visits.into_iter().enumerate() {
+            self.stop_times.push(StopTimeRow {
+                trip_id: trip_id.clone(),
+                arrival_time: format_time(visit.arrival),
+                departure_time: format_time(visit.departure),
+                stop_id: visit.stop_id,
+                stop_sequence: i + 1,
+            });
+        }
+        self.trips.push(TripRow {
+            route_id,
+            service_id,
+            trip_id,
+            trip_headsign: headsign,
+            direction_id,
+        });
+    }
+
+    /// Resolves the operators and day types, then drops what can't be shown. Stops have to have a position to be drawn, so stops which never got one are dropped, along with their stop times, and trips left visiting less than two stops. NeTEx site frames often hold every stop in a region, so stops no trip visits are dropped too. It is an error if no trips are left
+    fn into_files(mut self) -> Result<ConvertedFeed, String> {
+        let agency_ids = self
+            .agencies
+            .iter()
+            .map(|agency| agency.agency_id.clone())
+            .collect::<HashSet<_>>();
+        if self
+            .routes
+            .iter()
+            .any(|route| !agency_ids.contains(&route.agency_id))
+        {
+            if self.agencies.is_empty() {
+                let timezone = self
+                    .timezone
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_NETEX_TIMEZONE.to_string());
+                self.add_agency(
+                    "unknown".to_string(),
+                    "unknown operator".to_string(),
+                    String::new(),
+                    timezone,
+                );
+            }
+            let agency_id = self.agencies[0].agency_id.clone();
+            for route in &mut self.routes {
+                if !agency_ids.contains(&route.agency_id) {
+                    route.agency_id = agency_id.clone();
+                }
+            }
+        }
+
+        for (service_id, day_type_ids) in mem::take(&mut self.day_type_ids_from_service_id) {
+            let mut days = [false; 7];
+            let mut start_date = None;
+            let mut end_date = None;
+            for day_type_id in &day_type_ids {
+                match self.day_types.get(day_type_id) {
+                    Some(day_type) => {
+                        for (day, day_type_day) in days.iter_mut().zip(day_type.days) {
+                            *day |= day_type_day;
+                        }
+                        start_date = match (start_date, day_type.start_date) {
+                            (Some(start_date), Some(day_type_start_date)) => {
+                                Some(start_date.min(day_type_start_date))
+                            }
+                            (start_date, day_type_start_date) => start_date.or(day_type_start_date),
+                        };
+                        end_date = end_date.max(day_type.end_date);
+                    }
+                    None => {
+                        myprint!("day type not found: ", day_type_id);
+                    }
+                }
+            }
+            let (start_date, end_date) = service_dates(start_date, end_date);
+            self.add_calendar(service_id, days, start_date, end_date);
+        }
+
+        let n_stops = self.stops.len();
+        self.stops
+            .retain(|stop| stop.stop_lat.is_some() && stop.stop_lon.is_some());
+        let n_stops_without_position = n_stops - self.stops.len();
+        let stop_ids = self
+            .stops
+            .iter()
+            .map(|stop| stop.stop_id.as_str())
+            .collect::<HashSet<_>>();
+        self.stop_times
+            .retain(|stop_time| stop_ids.contains(stop_time.stop_id.as_str()));
+        let mut n_stop_times_from_trip_id: HashMap<&str, usize> = HashMap::new();
+        for stop_time in &self.stop_times {
+            *n_stop_times_from_trip_id
+                .entry(stop_time.trip_id.as_str())
+                .or_default() += 1;
+        }
+        let trip_ids = n_stop_times_from_trip_id
+            .into_iter()
+            .filter(|(_, n)| *n > 1)
+            .map(|(trip_id, _)| trip_id.to_string())
+            .collect::<HashSet<_>>();
+        let n_trips = self.trips.len();
+        self.trips.retain(|trip| trip_ids.contains(&trip.trip_id));
+        if self.trips.is_empty() {
+            return Err(if n_trips == 0 {
+                "the documents have no journeys".to_string()
+            } else {
+                format!(
+                    "none of the {} trips visit two stops with a position, {} of the {} stops have no position",
+                    n_trips, n_stops_without_position, n_stops
+                )
+            });
+        }
+        let mut dropped = Vec::new();
+        if n_stops_without_position > 0 {
+            dropped.push(format!(
+                "{} stops dropped for having no position",
+                n_stops_without_position
+            ));
+        }
+        if self.trips.len() < n_trips {
+            dropped.push(format!(
+                "{} trips dropped for visiting less than two stops with a position",
+                n_trips - self.trips.len()
+            ));
+        }
+        self.stop_times
+            .retain(|stop_time| trip_ids.contains(&stop_time.trip_id));
+        let visited_stop_ids = self
+            .stop_times
+            .iter()
+            .map(|stop_time| stop_time.stop_id.as_str())
+            .collect::<HashSet<_>>();
+        self.stops
+            .retain(|stop| visited_stop_ids.contains(stop.stop_id.as_str()));
+
+        let mut files = HashMap::new();
+        files.insert("agency.txt".to_string(), write_table(&self.agencies)?);
+        files.insert("routes.txt".to_string(), write_table(&self.routes)?);
+        files.insert("trips.txt".to_string(), write_table(&self.trips)?);
+        files.insert("stop_times.txt".to_string(), write_table(&self.stop_times)?);
+        files.insert("stops.txt".to_string(), write_table(&self.stops)?);
+        files.insert("calendar.txt".to_string(), write_table(&self.calendar)?);
+        Ok(ConvertedFeed {
+            files,
+            warning: (!dropped.is_empty()).then(|| dropped.join(", ")),
+        })
+    }
+}
+
+/// YYYYMMDD start and end dates, starting today if there is no start date and running for DEFAULT_SERVICE_DAYS if there is no end date
+fn service_dates(start_date: Option<NaiveDate>, end_date: Option<NaiveDate>) -> (String, String) {
+    let start_date = start_date.unwrap_or_else(|| Local::now().naive_local().date());
+    let end_date = end_date.unwrap_or_else(|| start_date + Duration::days(DEFAULT_SERVICE_DAYS));
+    (
+        start_date.format("%Y%m%d").to_string(),
+        end_date.format("%Y%m%d").to_string(),
+    )
+}
+
+fn write_table<T: Serialize>(records: &[T]) -> Result<Vec<u8>, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for record in records {
+        writer
+            .serialize(record)
+            .map_err(|error| error.to_string())?;
+    }
+    writer
+        .into_inner()
+        .map_err(|error| error.into_error().to_string())
+}
+
+/// the first child element with the given name, ignoring its namespace
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn descendants<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.descendants()
+        .filter(move |descendant| descendant.is_element() && descendant.tag_name().name() == name)
+}
+
+/// the element reached by following the names down from node
+fn path<'a, 'input>(node: Node<'a, 'input>, names: &[&str]) -> Option<Node<'a, 'input>> {
+    names.iter().try_fold(node, |node, name| child(node, name))
+}
+
+/// the trimmed text of the element reached by following the names down from node, None if it is empty
+fn path_text(node: Node, names: &[&str]) -> Option<String> {
+    path(node, names)?
+        .text()
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+/// (long, lat) from the Longitude and Latitude elements of node
+fn position(node: Node) -> Option<(f64, f64)> {
+    let longitude = path_text(node, &["Longitude"])?.parse().ok()?;
+    let latitude = path_text(node, &["Latitude"])?.parse().ok()?;
+    Some((longitude, latitude))
+}
+
+/// sets the days named by one of TransXChange's DaysOfWeek elements, such as MondayToFriday or NotSaturday, or a NeTEx DayOfWeekEnumeration, such as Weekdays
+fn set_days(days: &mut [bool; 7], name: &str) {
+    let range = match name {
+        "MondayToFriday" | "Weekdays" => 0..5,
+        "MondayToSaturday" => 0..6,
+        "MondayToSunday" | "Everyday" => 0..7,
+        "Weekend" => 5..7,
+        _ => {
+            if let Some(i) = DAY_NAMES.iter().position(|day| *day == name) {
+                days[i] = true;
+            } else if let Some(i) = name
+                .strip_prefix("Not")
+                .and_then(|name| DAY_NAMES.iter().position(|day| *day == name))
+            {
+                for (j, day) in days.iter_mut().enumerate() {
+                    *day |= j != i;
+                }
+            }
+            return;
+        }
+    };
+    for day in &mut days[range] {
+        *day = true;
+    }
+}
+
+/// the GTFS route_type for a TransXChange Mode or NeTEx TransportMode
+fn route_type(mode: Option<&str>) -> u16 {
+    match mode.map(|mode| mode.to_lowercase()).as_deref() {
+        Some("tram") => 0,
+        Some("metro") | Some("underground") => 1,
+        Some("rail") | Some("train") => 2,
+        Some("ferry") | Some("water") => 4,
+        Some("telecabin") | Some("cableway") => 6,
+        Some("funicular") => 7,
+        Some("trolleybus") => 11,
+        // bus, coach and anything not known
+        _ => 3,
+    }
+}
+
+/// seconds in an ISO 8601 duration such as PT1H30M, which both formats use for run and wait times
+fn parse_duration(text: &str) -> Option<u32> {
+    let text = text.trim().strip_prefix('P')?;
+    let mut seconds = 0.;
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in text.chars() {
+        match c {
+            'T' => in_time = true,
+            '0'..='9' | '.' => number.push(c),
+            _ => {
+                let value: f64 = number.parse().ok()?;
+                number.clear();
+                seconds += value
+                    * match (c, in_time) {
+                        ('D', false) => 86400.,
+                        ('H', true) => 3600.,
+                        ('M', true) => 60.,
+                        ('S', true) => 1.,
+                        _ => return None,
+                    };
+            }
+        }
+    }
+    Some(seconds.round() as u32)
+}
+
+/// seconds since midnight of a time such as 08:15 or 08:15:00
+fn parse_time(text: &str) -> Option<u32> {
+    let mut parts = text.trim().split(':');
+    let hours: u32 = parts.next()?.parse().ok()?;
+    let minutes: u32 = parts.next()?.parse().ok()?;
+    let seconds: u32 = match parts.next() {
+        // ignore any fraction of a second
+        Some(seconds) => seconds.get(..2)?.parse().ok()?,
+        None => 0,
+    };
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// the date of a date, or date and time, such as 2022-06-01 or 2022-06-01T00:00:00
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(text.trim().get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// HH:MM:SS, with hours past 24 for trips which run past midnight
+fn format_time(seconds: u32) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop_point(id: &str, position: Option<(f64, f64)>) -> String {
+        let place = position.map_or(String::new(), |(longitude, latitude)| {
+            format!(
+                "<Place><Location><Longitude>{}</Longitude><Latitude>{}</Latitude></Location></Place>",
+                longitude, latitude
+            )
+        });
+        format!(
+            "<StopPoint><AtcoCode>{}</AtcoCode>{}</StopPoint>",
+            id, place
+        )
+    }
+
+    fn timing_link(id: &str, from: &str, to: &str) -> String {
+        format!(
+            "<JourneyPatternTimingLink id=\"{}\"><From><StopPointRef>{}</StopPointRef></From><To><StopPointRef>{}</StopPointRef></To><RunTime>PT5M</RunTime></JourneyPatternTimingLink>",
+            id, from, to
+        )
+    }
+
+    /// a service with a journey from A to B to C and another from B to C, where C has no position unless given one
+    fn transxchange(c_position: Option<(f64, f64)>) -> String {
+        format!(
+            "<TransXChange>\
+                <StopPoints>{}{}{}</StopPoints>\
+                <JourneyPatternSections>\
+                    <JourneyPatternSection id=\"JPS1\">{}{}</JourneyPatternSection>\
+                    <JourneyPatternSection id=\"JPS2\">{}</JourneyPatternSection>\
+                </JourneyPatternSections>\
+                <Services><Service>\
+                    <ServiceCode>S1</ServiceCode>\
+                    <Lines><Line id=\"L1\"><LineName>1</LineName></Line></Lines>\
+                    <StandardService>\
+                        <JourneyPattern id=\"JP1\"><JourneyPatternSectionRefs>JPS1</JourneyPatternSectionRefs></JourneyPattern>\
+                        <JourneyPattern id=\"JP2\"><JourneyPatternSectionRefs>JPS2</JourneyPatternSectionRefs></JourneyPattern>\
+                    </StandardService>\
+                </Service></Services>\
+                <VehicleJourneys>\
+                    <VehicleJourney><VehicleJourneyCode>1</VehicleJourneyCode><ServiceRef>S1</ServiceRef><JourneyPatternRef>JP1</JourneyPatternRef><DepartureTime>08:00:00</DepartureTime></VehicleJourney>\
+                    <VehicleJourney><VehicleJourneyCode>2</VehicleJourneyCode><ServiceRef>S1</ServiceRef><JourneyPatternRef>JP2</JourneyPatternRef><DepartureTime>09:00:00</DepartureTime></VehicleJourney>\
+                </VehicleJourneys>\
+            </TransXChange>",
+            stop_point("A", Some((-1.0, 52.0))),
+            stop_point("B", Some((-1.1, 52.1))),
+            stop_point("C", c_position),
+            timing_link("L1", "A", "B"),
+            timing_link("L2", "B", "C"),
+            timing_link("L3", "B", "C"),
+        )
+    }
+
+    fn convert(text: String) -> Result<ConvertedFeed, FeedSourceError> {
+        convert_xml_feed(&[("test.xml".to_string(), text)])
+    }
+
+    fn lines(feed: &ConvertedFeed, name: &str) -> usize {
+        String::from_utf8_lossy(&feed.files[name]).lines().count()
+    }
+
+    #[test]
+    fn converts_transxchange() {
+        let feed = convert(transxchange(Some((-1.2, 52.2)))).unwrap();
+        assert_eq!(feed.warning, None);
+        // each table has a header
+        assert_eq!(lines(&feed, "stops.txt"), 4);
+        assert_eq!(lines(&feed, "trips.txt"), 3);
+        assert_eq!(lines(&feed, "stop_times.txt"), 6);
+        let stop_times = String::from_utf8_lossy(&feed.files["stop_times.txt"]).to_string();
+        assert!(stop_times.contains("S1:1,08:05:00,08:05:00,B,2"));
+    }
+
+    #[test]
+    fn warns_about_stops_without_a_position() {
+        let feed = convert(transxchange(None)).unwrap();
+        assert_eq!(
+            feed.warning.as_deref(),
+            Some("1 stops dropped for having no position, 1 trips dropped for visiting less than two stops with a position")
+        );
+        assert_eq!(lines(&feed, "stops.txt"), 3);
+        assert_eq!(lines(&feed, "trips.txt"), 2);
+    }
+
+    #[test]
+    fn refuses_feed_with_nothing_to_show() {
+        let text = transxchange(None)
+            .replace(&stop_point("B", Some((-1.1, 52.1))), &stop_point("B", None));
+        let error = convert(text).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "failed to convert converted feed: none of the 2 trips visit two stops with a position, 2 of the 3 stops have no position"
+        );
+        let error = convert("<TransXChange/>".to_string()).err().unwrap();
+        assert!(error
+            .to_string()
+            .ends_with("the documents have no journeys"));
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(5400));
+        assert_eq!(parse_duration(" PT45S "), Some(45));
+        assert_eq!(parse_duration("PT1.5M"), Some(90));
+        assert_eq!(parse_duration("P1DT2H"), Some(93600));
+        assert_eq!(parse_duration("PT0S"), Some(0));
+        assert_eq!(parse_duration("1H"), None);
+        // months aren't a fixed length
+        assert_eq!(parse_duration("P1M"), None);
+        assert_eq!(parse_duration("PT5X"), None);
+        assert_eq!(parse_duration("PTM"), None);
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_time("08:15"), Some(29700));
+        assert_eq!(parse_time(" 08:15:30 "), Some(29730));
+        assert_eq!(parse_time("08:15:30.500"), Some(29730));
+        assert_eq!(parse_time("25:00:00"), Some(90000));
+        assert_eq!(parse_time("08"), None);
+        assert_eq!(parse_time("08:xx"), None);
+        assert_eq!(parse_time("08:15:3"), None);
+    }
+
+    #[test]
+    fn formats_times() {
+        assert_eq!(format_time(0), "00:00:00");
+        assert_eq!(format_time(29730), "08:15:30");
+        assert_eq!(format_time(90061), "25:01:01");
+        assert_eq!(parse_time(&format_time(86399)), Some(86399));
+    }
+
+    #[test]
+    fn sets_days() {
+        let mut days = [false; 7];
+        set_days(&mut days, "MondayToFriday");
+        assert_eq!(days, [true, true, true, true, true, false, false]);
+        set_days(&mut days, "Sunday");
+        assert_eq!(days, [true, true, true, true, true, false, true]);
+
+        let mut days = [false; 7];
+        set_days(&mut days, "NotSaturday");
+        assert_eq!(days, [true, true, true, true, true, false, true]);
+
+        for (name, expected) in [
+            ("Weekdays", [true, true, true, true, true, false, false]),
+            (
+                "MondayToSaturday",
+                [true, true, true, true, true, true, false],
+            ),
+            ("MondayToSunday", [true; 7]),
+            ("Everyday", [true; 7]),
+            ("Weekend", [false, false, false, false, false, true, true]),
+            (
+                "Wednesday",
+                [false, false, true, false, false, false, false],
+            ),
+            // holidays aren't regular days
+            ("HolidaysOnly", [false; 7]),
+        ] {
+            let mut days = [false; 7];
+            set_days(&mut days, name);
+            assert_eq!(days, expected, "{}", name);
+        }
+    }
+}
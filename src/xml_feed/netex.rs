@@ -0,0 +1,321 @@
+use chrono::{Datelike, NaiveDate};
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+
+use super::{
+    child, children, descendants, parse_date, parse_time, path, path_text, position, route_type,
+    set_days, FeedBuilder, StopVisit, DEFAULT_NETEX_TIMEZONE,
+};
+
+/// A ServiceJourneyPattern, with the stop each of its points is at, in order
+struct JourneyPattern {
+    /// (StopPointInJourneyPattern id, stop id)
+    points: Vec<(String, String)>,
+    line_id: Option<String>,
+    headsign: Option<String>,
+    direction_id: Option<u8>,
+}
+
+/// Adds the organisations, stops, lines, day types and service journeys of a NeTEx PublicationDelivery. Objects are found wherever they are in the document's frames, and refer to each other by id, so also to objects in the other documents of the feed, as happens with stops and calendars kept in a shared document. Each Line becomes a route, and each ServiceJourney a trip at the stops its scheduled stop points are assigned to
+pub(super) fn convert(document: &Document, feed: &mut FeedBuilder) -> Result<(), String> {
+    let root = document.root_element();
+    let node_from_id = root
+        .descendants()
+        .filter(|node| node.is_element())
+        .filter_map(|node| Some((node.attribute("id")?, node)))
+        .collect::<HashMap<_, _>>();
+    let timezone = descendants(root, "TimeZone")
+        .find_map(|timezone| timezone.text())
+        .map_or_else(
+            || DEFAULT_NETEX_TIMEZONE.to_string(),
+            |timezone| timezone.trim().to_string(),
+        );
+    feed.timezone.get_or_insert_with(|| timezone.clone());
+
+    for organisation in descendants(root, "Operator").chain(descendants(root, "Authority")) {
+        if let Some(id) = organisation.attribute("id") {
+            let name = path_text(organisation, &["Name"])
+                .or_else(|| path_text(organisation, &["ShortName"]))
+                .unwrap_or_else(|| id.to_string());
+            feed.add_agency(
+                id.to_string(),
+                name,
+                path_text(organisation, &["ContactDetails", "Url"]).unwrap_or_default(),
+                timezone.clone(),
+            );
+        }
+    }
+
+    // quays and stop places are added wherever they are, as they may be used by journeys in another document, and those no journey visits are dropped once all are read
+    for stop in descendants(root, "Quay").chain(descendants(root, "StopPlace")) {
+        if let Some(id) = stop.attribute("id") {
+            let stop_place = stop
+                .ancestors()
+                .skip(1)
+                .find(|ancestor| ancestor.tag_name().name() == "StopPlace");
+            let name = path_text(stop, &["Name"])
+                .or_else(|| path_text(stop_place?, &["Name"]))
+                .unwrap_or_else(|| id.to_string());
+            feed.add_stop(
+                id.to_string(),
+                path_text(stop, &["PublicCode"]),
+                name,
+                stop_position(stop).or_else(|| stop_position(stop_place?)),
+            );
+        }
+    }
+    let mut stop_id_from_point_id = HashMap::new();
+    for assignment in descendants(root, "PassengerStopAssignment") {
+        if let (Some(point_id), Some(stop_id)) = (
+            reference(assignment, "ScheduledStopPointRef"),
+            reference(assignment, "QuayRef").or_else(|| reference(assignment, "StopPlaceRef")),
+        ) {
+            stop_id_from_point_id.insert(point_id, stop_id);
+        }
+    }
+    // points which aren't assigned to a stop are used as stops themselves
+    for point in descendants(root, "ScheduledStopPoint") {
+        if let Some(id) = point.attribute("id") {
+            if !stop_id_from_point_id.contains_key(id) {
+                feed.add_stop(
+                    id.to_string(),
+                    path_text(point, &["PublicCode"]),
+                    path_text(point, &["Name"]).unwrap_or_else(|| id.to_string()),
+                    stop_position(point),
+                );
+                stop_id_from_point_id.insert(id.to_string(), id.to_string());
+            }
+        }
+    }
+
+    for line in descendants(root, "Line").chain(descendants(root, "FlexibleLine")) {
+        if let Some(id) = line.attribute("id") {
+            feed.add_route(
+                id.to_string(),
+                // routes without a known operator are given one once all the documents are read
+                reference(line, "OperatorRef")
+                    .or_else(|| reference(line, "AuthorityRef"))
+                    .unwrap_or_default(),
+                path_text(line, &["PublicCode"])
+                    .or_else(|| path_text(line, &["ShortName"]))
+                    .unwrap_or_default(),
+                path_text(line, &["Name"]).unwrap_or_default(),
+                route_type(path_text(line, &["TransportMode"]).as_deref()),
+            );
+        }
+    }
+
+    let front_text = |display_id: Option<String>| {
+        path_text(*node_from_id.get(display_id?.as_str())?, &["FrontText"])
+    };
+    let mut journey_patterns = HashMap::new();
+    for pattern in
+        descendants(root, "ServiceJourneyPattern").chain(descendants(root, "JourneyPattern"))
+    {
+        let id = match pattern.attribute("id") {
+            Some(id) => id,
+            None => continue,
+        };
+        let mut points = path(pattern, &["pointsInSequence"])
+            .into_iter()
+            .flat_map(|points| children(points, "StopPointInJourneyPattern"))
+            .map(|point| {
+                let order = point
+                    .attribute("order")
+                    .and_then(|order| order.parse::<u32>().ok())
+                    .unwrap_or(0);
+                let stop_id = reference(point, "ScheduledStopPointRef")
+                    .and_then(|point_id| stop_id_from_point_id.get(&point_id).cloned())
+                    .ok_or_else(|| format!("journey pattern {} has a point with no stop", id))?;
+                Ok((order, point, stop_id))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        points.sort_by_key(|(order, _, _)| *order);
+        let route = reference(pattern, "RouteRef")
+            .and_then(|route_id| node_from_id.get(route_id.as_str()).copied());
+        journey_patterns.insert(
+            id.to_string(),
+            JourneyPattern {
+                headsign: front_text(reference(pattern, "DestinationDisplayRef"))
+                    .or_else(|| front_text(reference(points.first()?.1, "DestinationDisplayRef"))),
+                points: points
+                    .into_iter()
+                    .map(|(_, point, stop_id)| {
+                        (
+                            point.attribute("id").unwrap_or_default().to_string(),
+                            stop_id,
+                        )
+                    })
+                    .collect(),
+                line_id: route.and_then(|route| reference(route, "LineRef")),
+                direction_id: route.and_then(direction_id),
+            },
+        );
+    }
+
+    for day_type in descendants(root, "DayType") {
+        if let Some(id) = day_type.attribute("id") {
+            let days = &mut feed.day_types.entry(id.to_string()).or_default().days;
+            let days_of_week = path_text(day_type, &["properties", "PropertyOfDay", "DaysOfWeek"]);
+            for name in days_of_week
+                .iter()
+                .flat_map(|names| names.split_whitespace())
+            {
+                set_days(days, name);
+            }
+        }
+    }
+    // day types without days of the week run on the days of the week of their dates
+    let mut dates_from_day_type_id: HashMap<String, Vec<NaiveDate>> = HashMap::new();
+    for assignment in descendants(root, "DayTypeAssignment") {
+        if path_text(assignment, &["isAvailable"]).as_deref() == Some("false") {
+            continue;
+        }
+        let day_type_id = match reference(assignment, "DayTypeRef") {
+            Some(day_type_id) => day_type_id,
+            None => continue,
+        };
+        let (start_date, end_date) = if let Some(period) =
+            reference(assignment, "OperatingPeriodRef")
+                .and_then(|period_id| node_from_id.get(period_id.as_str()).copied())
+        {
+            (
+                path_text(period, &["FromDate"]).and_then(|date| parse_date(&date)),
+                path_text(period, &["ToDate"]).and_then(|date| parse_date(&date)),
+            )
+        } else {
+            let date = path_text(assignment, &["Date"])
+                .or_else(|| {
+                    path_text(
+                        *node_from_id.get(reference(assignment, "OperatingDayRef")?.as_str())?,
+                        &["CalendarDate"],
+                    )
+                })
+                .and_then(|date| parse_date(&date));
+            if let Some(date) = date {
+                dates_from_day_type_id
+                    .entry(day_type_id.clone())
+                    .or_default()
+                    .push(date);
+            }
+            (date, date)
+        };
+        if let Some(day_type) = feed.day_types.get_mut(&day_type_id) {
+            day_type.start_date = match (day_type.start_date, start_date) {
+                (Some(day_type_start_date), Some(start_date)) => {
+                    Some(day_type_start_date.min(start_date))
+                }
+                (day_type_start_date, start_date) => day_type_start_date.or(start_date),
+            };
+            day_type.end_date = day_type.end_date.max(end_date);
+        }
+    }
+    for (day_type_id, dates) in dates_from_day_type_id {
+        if let Some(day_type) = feed.day_types.get_mut(&day_type_id) {
+            if !day_type.days.contains(&true) {
+                for date in dates {
+                    day_type.days[date.weekday().num_days_from_monday() as usize] = true;
+                }
+            }
+        }
+    }
+
+    for journey in descendants(root, "ServiceJourney") {
+        let id = match journey.attribute("id") {
+            Some(id) => id,
+            None => continue,
+        };
+        let pattern = reference(journey, "ServiceJourneyPatternRef")
+            .or_else(|| reference(journey, "JourneyPatternRef"))
+            .and_then(|pattern_id| journey_patterns.get(&pattern_id))
+            .ok_or_else(|| format!("service journey {} has no journey pattern", id))?;
+        let route_id = reference(journey, "LineRef")
+            .or_else(|| reference(journey, "FlexibleLineRef"))
+            .or_else(|| pattern.line_id.clone())
+            .ok_or_else(|| format!("service journey {} has no line", id))?;
+        let stop_id_from_pattern_point_id = pattern
+            .points
+            .iter()
+            .map(|(point_id, stop_id)| (point_id.as_str(), stop_id))
+            .collect::<HashMap<_, _>>();
+        let mut visits = Vec::new();
+        for (i, passing_time) in path(journey, &["passingTimes"])
+            .into_iter()
+            .flat_map(|passing_times| children(passing_times, "TimetabledPassingTime"))
+            .enumerate()
+        {
+            // passing times without a point are in the same order as the pattern's points
+            let stop_id = match reference(passing_time, "StopPointInJourneyPatternRef") {
+                Some(point_id) => stop_id_from_pattern_point_id
+                    .get(point_id.as_str())
+                    .copied(),
+                None => pattern.points.get(i).map(|(_, stop_id)| stop_id),
+            }
+            .ok_or_else(|| format!("service journey {} has a passing time with no stop", id))?;
+            let arrival = time(passing_time, "ArrivalTime", "ArrivalDayOffset");
+            let departure = time(passing_time, "DepartureTime", "DepartureDayOffset");
+            let (arrival, departure) = match (arrival, departure) {
+                (Some(arrival), Some(departure)) => (arrival, departure),
+                (Some(time), None) | (None, Some(time)) => (time, time),
+                (None, None) => {
+                    return Err(format!(
+                        "service journey {} has a passing time with no time",
+                        id
+                    ))
+                }
+            };
+            visits.push(StopVisit {
+                stop_id: stop_id.clone(),
+                arrival,
+                departure,
+            });
+        }
+
+        let day_type_ids = path(journey, &["dayTypes"])
+            .into_iter()
+            .flat_map(|day_types| children(day_types, "DayTypeRef"))
+            .filter_map(|day_type_ref| day_type_ref.attribute("ref"))
+            .map(|day_type_id| day_type_id.to_string())
+            .collect::<Vec<_>>();
+        let service_id = feed.add_day_type_service(day_type_ids);
+        feed.add_trip(
+            id.to_string(),
+            route_id,
+            service_id,
+            pattern.headsign.clone(),
+            direction_id(journey).or(pattern.direction_id),
+            visits,
+        );
+    }
+    Ok(())
+}
+
+/// the ref of node's child with the given name, as NeTEx refers to other objects with elements such as <LineRef ref="..."/>
+fn reference(node: Node, name: &str) -> Option<String> {
+    child(node, name)?.attribute("ref").map(str::to_string)
+}
+
+/// stop places and quays have their position in a Centroid, scheduled stop points have it directly
+fn stop_position(node: Node) -> Option<(f64, f64)> {
+    path(node, &["Centroid", "Location"])
+        .or_else(|| child(node, "Location"))
+        .and_then(position)
+}
+
+/// seconds since the start of the service day of one of a passing time's times
+fn time(passing_time: Node, time_name: &str, day_offset_name: &str) -> Option<u32> {
+    let time = parse_time(&path_text(passing_time, &[time_name])?)?;
+    let day_offset = path_text(passing_time, &[day_offset_name])
+        .and_then(|day_offset| day_offset.parse::<u32>().ok())
+        .unwrap_or(0);
+    Some(time + day_offset * 24 * 3600)
+}
+
+/// the DirectionType of a route or journey
+fn direction_id(node: Node) -> Option<u8> {
+    match path_text(node, &["DirectionType"])?.as_str() {
+        "outbound" | "clockwise" => Some(0),
+        "inbound" | "anticlockwise" => Some(1),
+        _ => None,
+    }
+}
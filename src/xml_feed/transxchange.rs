@@ -0,0 +1,331 @@
+use chrono::NaiveDate;
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+
+use super::{
+    child, children, descendants, parse_date, parse_duration, parse_time, path, path_text,
+    position, route_type, set_days, FeedBuilder, StopVisit, TRANSXCHANGE_TIMEZONE,
+};
+
+/// A JourneyPatternTimingLink, with its times in seconds
+struct TimingLink {
+    from_stop: String,
+    to_stop: String,
+    run_time: u32,
+    from_wait: u32,
+    to_wait: u32,
+}
+
+/// A Service, whose Lines become routes
+struct Service {
+    route_id_from_line_id: HashMap<String, String>,
+    /// for journeys which don't say which line they are on
+    route_id: String,
+    days: [bool; 7],
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+}
+
+struct JourneyPattern {
+    section_ids: Vec<String>,
+    headsign: Option<String>,
+    direction_id: Option<u8>,
+}
+
+/// Adds the stops, operators, services and vehicle journeys of a TransXChange document. Each Line of a Service becomes a route, and each VehicleJourney a trip, timed from its departure time and the run and wait times of its journey pattern's timing links. Stops only have a position if the document gives their longitude and latitude. Operating profiles only keep their regular days, since there is nowhere to keep bank holidays and other special days
+pub(super) fn convert(document: &Document, feed: &mut FeedBuilder) -> Result<(), String> {
+    let root = document.root_element();
+    feed.timezone = Some(TRANSXCHANGE_TIMEZONE.to_string());
+
+    for stop in descendants(root, "AnnotatedStopPointRef") {
+        if let Some(id) = path_text(stop, &["StopPointRef"]) {
+            let name = path_text(stop, &["CommonName"]).unwrap_or_else(|| id.clone());
+            feed.add_stop(id, None, name, child(stop, "Location").and_then(location));
+        }
+    }
+    for stop in descendants(root, "StopPoint") {
+        if let Some(id) = path_text(stop, &["AtcoCode"]) {
+            let name = path_text(stop, &["Descriptor", "CommonName"]).unwrap_or_else(|| id.clone());
+            feed.add_stop(
+                id,
+                path_text(stop, &["NaptanCode"]),
+                name,
+                path(stop, &["Place", "Location"]).and_then(location),
+            );
+        }
+    }
+
+    // operators are referred to by an id local to the document, but the same operator is in many documents, so its national code is used as the agency id
+    let mut agency_id_from_operator_id = HashMap::new();
+    for operator in descendants(root, "Operator").chain(descendants(root, "LicensedOperator")) {
+        let id = operator.attribute("id").unwrap_or_default().to_string();
+        let agency_id = path_text(operator, &["NationalOperatorCode"])
+            .or_else(|| path_text(operator, &["OperatorCode"]))
+            .unwrap_or_else(|| id.clone());
+        let name = ["TradingName", "OperatorShortName", "OperatorNameOnLicence"]
+            .iter()
+            .find_map(|name| path_text(operator, &[*name]))
+            .unwrap_or_else(|| agency_id.clone());
+        feed.add_agency(
+            agency_id.clone(),
+            name,
+            String::new(),
+            TRANSXCHANGE_TIMEZONE.to_string(),
+        );
+        agency_id_from_operator_id.insert(id, agency_id);
+    }
+
+    let mut links = HashMap::new();
+    let mut link_ids_from_section_id = HashMap::new();
+    for section in descendants(root, "JourneyPatternSection") {
+        let mut link_ids = Vec::new();
+        for link in children(section, "JourneyPatternTimingLink") {
+            let id = link.attribute("id").unwrap_or_default().to_string();
+            let (from, to) = match (child(link, "From"), child(link, "To")) {
+                (Some(from), Some(to)) => (from, to),
+                _ => return Err(format!("timing link {} has no From or To", id)),
+            };
+            let stop = |node: Node| {
+                path_text(node, &["StopPointRef"])
+                    .ok_or_else(|| format!("timing link {} has no StopPointRef", id))
+            };
+            links.insert(
+                id.clone(),
+                TimingLink {
+                    from_stop: stop(from)?,
+                    to_stop: stop(to)?,
+                    run_time: duration(link, &["RunTime"]).unwrap_or(0),
+                    from_wait: duration(from, &["WaitTime"]).unwrap_or(0),
+                    to_wait: duration(to, &["WaitTime"]).unwrap_or(0),
+                },
+            );
+            link_ids.push(id);
+        }
+        link_ids_from_section_id.insert(
+            section.attribute("id").unwrap_or_default().to_string(),
+            link_ids,
+        );
+    }
+
+    let mut services = HashMap::new();
+    let mut journey_patterns = HashMap::new();
+    for service in descendants(root, "Service") {
+        let code = path_text(service, &["ServiceCode"]).ok_or("a Service has no ServiceCode")?;
+        // routes without a known operator are given one once all the documents are read
+        let agency_id = path_text(service, &["RegisteredOperatorRef"])
+            .and_then(|operator_id| agency_id_from_operator_id.get(&operator_id).cloned())
+            .unwrap_or_default();
+        let long_name = path_text(service, &["Description"])
+            .or_else(|| {
+                Some(format!(
+                    "{} - {}",
+                    path_text(service, &["StandardService", "Origin"])?,
+                    path_text(service, &["StandardService", "Destination"])?
+                ))
+            })
+            .unwrap_or_default();
+        let route_type = route_type(path_text(service, &["Mode"]).as_deref());
+
+        let mut route_id_from_line_id = HashMap::new();
+        let mut first_route_id = None;
+        for line in path(service, &["Lines"])
+            .into_iter()
+            .flat_map(|lines| children(lines, "Line"))
+        {
+            let line_id = line.attribute("id").unwrap_or_default().to_string();
+            // line ids are only unique within their service
+            let route_id = format!("{}:{}", code, line_id);
+            feed.add_route(
+                route_id.clone(),
+                agency_id.clone(),
+                path_text(line, &["LineName"]).unwrap_or_default(),
+                long_name.clone(),
+                route_type,
+            );
+            first_route_id.get_or_insert_with(|| route_id.clone());
+            route_id_from_line_id.insert(line_id, route_id);
+        }
+        let route_id = first_route_id.ok_or_else(|| format!("service {} has no lines", code))?;
+
+        for pattern in path(service, &["StandardService"])
+            .into_iter()
+            .flat_map(|standard_service| children(standard_service, "JourneyPattern"))
+        {
+            journey_patterns.insert(
+                pattern.attribute("id").unwrap_or_default().to_string(),
+                JourneyPattern {
+                    section_ids: children(pattern, "JourneyPatternSectionRefs")
+                        .filter_map(|section_ref| section_ref.text())
+                        .map(|section_id| section_id.trim().to_string())
+                        .collect(),
+                    headsign: path_text(pattern, &["DestinationDisplay"]),
+                    direction_id: path_text(pattern, &["Direction"])
+                        .and_then(|direction| direction_id(&direction)),
+                },
+            );
+        }
+
+        services.insert(
+            code,
+            Service {
+                route_id_from_line_id,
+                route_id,
+                days: child(service, "OperatingProfile").map_or([true; 7], operating_days),
+                start_date: path_text(service, &["OperatingPeriod", "StartDate"])
+                    .and_then(|date| parse_date(&date)),
+                end_date: path_text(service, &["OperatingPeriod", "EndDate"])
+                    .and_then(|date| parse_date(&date)),
+            },
+        );
+    }
+
+    let journeys = descendants(root, "VehicleJourney").collect::<Vec<_>>();
+    // journeys can repeat another journey's pattern by referring to it instead
+    let pattern_id_from_journey_code = journeys
+        .iter()
+        .filter_map(|journey| {
+            Some((
+                path_text(*journey, &["VehicleJourneyCode"])?,
+                path_text(*journey, &["JourneyPatternRef"])?,
+            ))
+        })
+        .collect::<HashMap<_, _>>();
+    for (i, journey) in journeys.into_iter().enumerate() {
+        let code =
+            path_text(journey, &["VehicleJourneyCode"]).unwrap_or_else(|| (i + 1).to_string());
+        let service_code = path_text(journey, &["ServiceRef"])
+            .ok_or_else(|| format!("vehicle journey {} has no ServiceRef", code))?;
+        let service = services
+            .get(&service_code)
+            .ok_or_else(|| format!("vehicle journey {} has an unknown service", code))?;
+        let pattern = path_text(journey, &["JourneyPatternRef"])
+            .or_else(|| {
+                pattern_id_from_journey_code
+                    .get(&path_text(journey, &["VehicleJourneyRef"])?)
+                    .cloned()
+            })
+            .and_then(|pattern_id| journey_patterns.get(&pattern_id))
+            .ok_or_else(|| format!("vehicle journey {} has no journey pattern", code))?;
+        let day_shift = path_text(journey, &["DepartureDayShift"])
+            .and_then(|day_shift| day_shift.parse::<u32>().ok())
+            .unwrap_or(0);
+        let mut time = path_text(journey, &["DepartureTime"])
+            .and_then(|time| parse_time(&time))
+            .ok_or_else(|| format!("vehicle journey {} has no DepartureTime", code))?
+            + day_shift * 24 * 3600;
+
+        // journeys can have their own run and wait times for some of the pattern's links
+        let journey_links = children(journey, "VehicleJourneyTimingLink")
+            .filter_map(|link| Some((path_text(link, &["JourneyPatternTimingLinkRef"])?, link)))
+            .collect::<HashMap<_, _>>();
+        let mut visits = Vec::new();
+        let mut last_stop = None;
+        // the wait at the end of the previous link, which is spent at the stop the next link starts from
+        let mut to_wait = 0;
+        for link_id in pattern
+            .section_ids
+            .iter()
+            .filter_map(|section_id| link_ids_from_section_id.get(section_id))
+            .flatten()
+        {
+            let link = &links[link_id];
+            let journey_link = journey_links.get(link_id).copied();
+            let time_of = |names: &[&str], default: u32| {
+                journey_link
+                    .and_then(|journey_link| duration(journey_link, names))
+                    .unwrap_or(default)
+            };
+            let departure = time + to_wait + time_of(&["From", "WaitTime"], link.from_wait);
+            visits.push(StopVisit {
+                stop_id: link.from_stop.clone(),
+                arrival: time,
+                departure,
+            });
+            time = departure + time_of(&["RunTime"], link.run_time);
+            to_wait = time_of(&["To", "WaitTime"], link.to_wait);
+            last_stop = Some(link.to_stop.clone());
+        }
+        if let Some(stop_id) = last_stop {
+            visits.push(StopVisit {
+                stop_id,
+                arrival: time,
+                departure: time + to_wait,
+            });
+        }
+
+        let route_id = path_text(journey, &["LineRef"])
+            .and_then(|line_id| service.route_id_from_line_id.get(&line_id))
+            .unwrap_or(&service.route_id)
+            .clone();
+        let days = child(journey, "OperatingProfile").map_or(service.days, operating_days);
+        let service_id = feed.add_service(days, service.start_date, service.end_date);
+        feed.add_trip(
+            format!("{}:{}", service_code, code),
+            route_id,
+            service_id,
+            path_text(journey, &["DestinationDisplay"]).or_else(|| pattern.headsign.clone()),
+            pattern.direction_id,
+            visits,
+        );
+    }
+    Ok(())
+}
+
+/// the position of a Location, which has Longitude and Latitude either directly or in a Translation
+fn location(node: Node) -> Option<(f64, f64)> {
+    position(node).or_else(|| position(child(node, "Translation")?))
+}
+
+fn duration(node: Node, names: &[&str]) -> Option<u32> {
+    parse_duration(&path_text(node, names)?)
+}
+
+/// the regular days of an OperatingProfile. Profiles which only run on holidays have no regular days
+fn operating_days(profile: Node) -> [bool; 7] {
+    let mut days = [false; 7];
+    if let Some(days_of_week) = path(profile, &["RegularDayType", "DaysOfWeek"]) {
+        for day in days_of_week.children().filter(|day| day.is_element()) {
+            set_days(&mut days, day.tag_name().name());
+        }
+    }
+    days
+}
+
+fn direction_id(direction: &str) -> Option<u8> {
+    match direction {
+        "outbound" | "clockwise" => Some(0),
+        "inbound" | "antiClockwise" => Some(1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn days(profile: &str) -> [bool; 7] {
+        let document = Document::parse(profile).unwrap();
+        operating_days(document.root_element())
+    }
+
+    #[test]
+    fn reads_operating_days() {
+        assert_eq!(
+            days("<OperatingProfile><RegularDayType><DaysOfWeek><MondayToFriday/><Sunday/></DaysOfWeek></RegularDayType></OperatingProfile>"),
+            [true, true, true, true, true, false, true]
+        );
+        assert_eq!(
+            days("<OperatingProfile><RegularDayType><DaysOfWeek>\n<NotMonday/>\n</DaysOfWeek></RegularDayType></OperatingProfile>"),
+            [false, true, true, true, true, true, true]
+        );
+        // namespaces are ignored
+        assert_eq!(
+            days("<OperatingProfile xmlns=\"http://www.transxchange.org.uk/\"><RegularDayType><DaysOfWeek><Saturday/></DaysOfWeek></RegularDayType></OperatingProfile>"),
+            [false, false, false, false, false, true, false]
+        );
+        assert_eq!(
+            days("<OperatingProfile><RegularDayType><HolidaysOnly/></RegularDayType></OperatingProfile>"),
+            [false; 7]
+        );
+    }
+}